@@ -0,0 +1,69 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Measures false sharing between adjacent counters.
+//
+// Each thread increments its own counter. When the counters are adjacent in
+// memory they share cache lines and every increment steals the line from the
+// other cores; with `CachePadded` each counter has a line of its own. The
+// difference only shows up with at least as many cores as threads.
+//
+// Run with `cargo bench --bench cache_padded`.
+
+extern crate atomic;
+
+use atomic::{Atomic, CachePadded, Ordering};
+use std::ops::Deref;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const INCREMENTS: usize = 1_000_000;
+
+fn run<C>(counters: &[C]) -> Duration
+where
+    C: Deref<Target = Atomic<u64>> + Sync,
+{
+    let start = Instant::now();
+    thread::scope(|s| {
+        for counter in counters {
+            s.spawn(move || {
+                for _ in 0..INCREMENTS {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+// `Atomic<u64>` doesn't implement `Deref`, so give the unpadded counters a
+// transparent wrapper which does.
+struct Plain(Atomic<u64>);
+
+impl Deref for Plain {
+    type Target = Atomic<u64>;
+
+    fn deref(&self) -> &Atomic<u64> {
+        &self.0
+    }
+}
+
+fn main() {
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    for threads in [2, 4, 8] {
+        let plain: Vec<Plain> = (0..threads).map(|_| Plain(Atomic::new(0))).collect();
+        let padded: Vec<CachePadded<Atomic<u64>>> = (0..threads)
+            .map(|_| CachePadded::new(Atomic::new(0)))
+            .collect();
+        let plain = run(&plain);
+        let padded = run(&padded);
+        println!(
+            "{} threads ({} cores): adjacent {:>10.2?}, padded {:>10.2?}",
+            threads, cores, plain, padded
+        );
+    }
+}
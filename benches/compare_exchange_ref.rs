@@ -0,0 +1,98 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Measures the copies saved by the reference-taking compare-exchanges on a
+// 64-byte value, which goes through the fallback lock.
+//
+// Threads increment a counter in a shared record with a compare-exchange
+// loop. `compare_exchange` copies `current` and `new` into the call and the
+// previous value out of it, 192 bytes for each attempt.
+// `compare_exchange_ref` copies `new` straight into the record and only the
+// previous value out, and `compare_exchange_in_place` compares the record in
+// place and only copies it out when the exchange fails.
+//
+// Run with `cargo bench --bench compare_exchange_ref`.
+
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+use std::hint::black_box;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Copy, Clone)]
+struct Record([u64; 8]);
+
+const UPDATES: usize = 1_000_000;
+const ROUNDS: usize = 10;
+
+fn measure<F: Fn(&Atomic<Record>) + Sync>(name: &str, threads: usize, update: F) {
+    let mut time = Duration::default();
+    for _ in 0..ROUNDS {
+        let a = Atomic::new(Record([0; 8]));
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    for _ in 0..UPDATES / threads {
+                        update(black_box(&a));
+                    }
+                });
+            }
+        });
+        time += start.elapsed();
+        assert_eq!(
+            a.load(Ordering::Relaxed).0[0],
+            (UPDATES / threads * threads) as u64
+        );
+    }
+    println!(
+        "{:>9} on {} thread(s): {:>8.2?}/update",
+        name,
+        threads,
+        time / (ROUNDS * UPDATES) as u32
+    );
+}
+
+fn next(mut record: Record) -> Record {
+    record.0[0] += 1;
+    record
+}
+
+fn main() {
+    for &threads in &[1, 4] {
+        measure("by value", threads, |a| {
+            let mut current = a.load(Ordering::Relaxed);
+            while let Err(x) =
+                a.compare_exchange(current, next(current), Ordering::AcqRel, Ordering::Relaxed)
+            {
+                current = x;
+            }
+        });
+        measure("ref", threads, |a| {
+            let mut current = a.load(Ordering::Relaxed);
+            while let Err(x) = a.compare_exchange_ref(
+                &current,
+                &next(current),
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                current = x;
+            }
+        });
+        measure("in place", threads, |a| {
+            let mut current = a.load(Ordering::Relaxed);
+            loop {
+                let new = next(current);
+                let (success, failure) = (Ordering::AcqRel, Ordering::Relaxed);
+                if a.compare_exchange_in_place(&mut current, &new, success, failure) {
+                    break;
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Measures the CPU time burnt by threads waiting for a contended fallback
+// lock.
+//
+// Four times as many threads as there are cores update a single
+// `Atomic<[u64; 4]>`, which takes the fallback lock, holding it for a while
+// each time. With the `std` feature the waiting threads park once their
+// backoff is completed, so the process uses far less CPU time than the wall
+// time multiplied by the number of cores. Without it they keep spinning.
+//
+// Run with `cargo bench --bench contended_fallback` and
+// `cargo bench --bench contended_fallback --features std` to compare.
+
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const UPDATES: usize = 200_000;
+
+#[cfg(unix)]
+fn cpu_time() -> Duration {
+    use std::os::raw::{c_int, c_long};
+
+    #[repr(C)]
+    struct Timeval {
+        tv_sec: c_long,
+        tv_usec: c_long,
+    }
+
+    // Only the user and system times at the start of `struct rusage` are
+    // read, the rest is room for the other fields.
+    #[repr(C)]
+    struct Rusage {
+        utime: Timeval,
+        stime: Timeval,
+        rest: [c_long; 14],
+    }
+
+    extern "C" {
+        fn getrusage(who: c_int, usage: *mut Rusage) -> c_int;
+    }
+
+    let mut usage: Rusage = unsafe { std::mem::zeroed() };
+    assert_eq!(unsafe { getrusage(0, &mut usage) }, 0);
+    let time = |t: &Timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    time(&usage.utime) + time(&usage.stime)
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Duration {
+    Duration::default()
+}
+
+fn main() {
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = cores * 4;
+    let value = Atomic::new([0u64; 4]);
+    let cpu = cpu_time();
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                for _ in 0..UPDATES / threads {
+                    value.replace_with(Ordering::AcqRel, Ordering::Acquire, |mut v| {
+                        // Keep the lock for long enough that the waiters
+                        // give up spinning.
+                        for x in v.iter_mut() {
+                            for _ in 0..100 {
+                                *x = std::hint::black_box(*x + 1);
+                            }
+                        }
+                        v
+                    });
+                }
+            });
+        }
+    });
+    let wall = start.elapsed();
+    let cpu = cpu_time() - cpu;
+    assert_eq!(
+        value.load(Ordering::Relaxed)[0],
+        (UPDATES / threads * threads * 100) as u64
+    );
+    println!(
+        "{} threads on {} cores: {:>8.2?} wall, {:>8.2?} CPU ({:.1} cores busy)",
+        threads,
+        cores,
+        wall,
+        cpu,
+        cpu.as_secs_f64() / wall.as_secs_f64()
+    );
+}
@@ -0,0 +1,55 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Measures contention on the fallback lock table for arrays of atomics.
+//
+// Each thread swaps its own elements of an array of atomics which aren't
+// lock-free, so the threads only contend when their elements hash to the same
+// lock. The difference only shows up with at least as many cores as threads.
+//
+// Run with `cargo bench --bench fallback_hash`.
+
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ELEMENTS: usize = 1024;
+const ROUNDS: usize = 1000;
+
+fn run<T: Copy + Default + Send + Sync>(threads: usize) -> Duration {
+    let array: Vec<Atomic<T>> = (0..ELEMENTS).map(|_| Atomic::default()).collect();
+    let start = Instant::now();
+    thread::scope(|s| {
+        for t in 0..threads {
+            let array = &array;
+            s.spawn(move || {
+                for _ in 0..ROUNDS {
+                    for a in array.iter().skip(t).step_by(threads) {
+                        a.swap(T::default(), Ordering::AcqRel);
+                    }
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn main() {
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    for threads in [1, 2, 4, 8] {
+        println!(
+            "{} threads ({} cores): stride 24 {:>10.2?}, stride 32 {:>10.2?}, stride 64 {:>10.2?}",
+            threads,
+            cores,
+            run::<[u8; 24]>(threads),
+            run::<[u64; 4]>(threads),
+            run::<[u64; 8]>(threads),
+        );
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Compares naive spinning with `spin_wait_for`.
+//
+// A token is passed around a ring of threads: each thread spins until the
+// token has its index and then hands it to the next thread. With more threads
+// than cores, naive spinning burns the waiting threads' whole time slices
+// while the thread holding the token may not even be running, whereas the
+// backoff eventually yields to it.
+//
+// Run with `cargo bench --bench spin_wait --features std`; without the `std`
+// feature the backoff never yields.
+
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+use std::hint;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn naive(turn: &Atomic<usize>, me: usize) {
+    while turn.load(Ordering::Acquire) != me {
+        hint::spin_loop();
+    }
+}
+
+fn backoff(turn: &Atomic<usize>, me: usize) {
+    turn.spin_wait_for(me, Ordering::Acquire);
+}
+
+fn ring(threads: usize, laps: usize, wait: fn(&Atomic<usize>, usize)) -> Duration {
+    let turn = Atomic::new(0);
+    let start = Instant::now();
+    thread::scope(|s| {
+        for me in 0..threads {
+            let turn = &turn;
+            s.spawn(move || {
+                for lap in 0..laps {
+                    wait(turn, lap * threads + me);
+                    turn.store(lap * threads + me + 1, Ordering::Release);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn main() {
+    for &(threads, laps) in &[(2, 2_000), (16, 100)] {
+        let handoffs = (threads * laps) as u32;
+        let naive = ring(threads, laps, naive);
+        let backoff = ring(threads, laps, backoff);
+        println!(
+            "{:>2} threads: naive {:>10.2?}/handoff, backoff {:>10.2?}/handoff",
+            threads,
+            naive / handoffs,
+            backoff / handoffs
+        );
+    }
+}
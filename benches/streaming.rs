@@ -0,0 +1,65 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Measures how much filling a large table evicts an unrelated working set.
+//
+// A small working set is warmed up, then a table much larger than the cache
+// is filled either with regular stores or with `fill_streaming`, and finally
+// the working set is scanned again. With streaming stores the working set
+// stays in the cache, so the second scan is faster.
+//
+// Run with `cargo bench --bench streaming`.
+
+extern crate atomic;
+
+use atomic::{slice, Atomic, Ordering};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const WORKING_SET: usize = 256 * 1024 / 8;
+const TABLE: usize = 64 * 1024 * 1024 / 8;
+const ROUNDS: usize = 20;
+
+fn scan(working_set: &[u64]) -> Duration {
+    let start = Instant::now();
+    let mut sum = 0u64;
+    for x in working_set {
+        sum = sum.wrapping_add(*x);
+    }
+    black_box(sum);
+    start.elapsed()
+}
+
+fn measure<F: FnMut(&mut [Atomic<u64>], u64)>(name: &str, mut fill: F) {
+    let working_set: Vec<u64> = (0..WORKING_SET as u64).collect();
+    let mut table: Vec<Atomic<u64>> = (0..TABLE).map(|_| Atomic::new(0)).collect();
+    let mut fill_time = Duration::default();
+    let mut scan_time = Duration::default();
+    for round in 0..ROUNDS {
+        scan(&working_set);
+        let start = Instant::now();
+        fill(black_box(&mut table), round as u64);
+        black_box(&table);
+        fill_time += start.elapsed();
+        scan_time += scan(&working_set);
+    }
+    println!(
+        "{:>10}: fill {:>10.2?}/round, working set scan after fill {:>10.2?}/round",
+        name,
+        fill_time / ROUNDS as u32,
+        scan_time / ROUNDS as u32
+    );
+}
+
+fn main() {
+    measure("regular", |table, val| {
+        for a in table.iter() {
+            a.store(val, Ordering::Relaxed);
+        }
+    });
+    measure("streaming", slice::fill_streaming);
+}
@@ -0,0 +1,65 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Measures the cost of reading values which are not lock-free once nothing
+// else can access them.
+//
+// Worker threads fill a large array of `Atomic<[u8; 32]>`, and after they
+// have been joined the array is summed either with `load`, which takes the
+// fallback lock for every element, or with `load_unsynchronized`, which reads
+// the elements directly.
+//
+// Run with `cargo bench --bench unsynchronized`.
+
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+use std::hint::black_box;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ELEMS: usize = 1024 * 1024;
+const THREADS: usize = 4;
+const ROUNDS: usize = 10;
+
+fn sum(bytes: [u8; 32]) -> u64 {
+    bytes.iter().map(|&b| b as u64).sum()
+}
+
+fn measure<F: Fn(&[Atomic<[u8; 32]>]) -> u64>(name: &str, total: F) {
+    let table: Vec<Atomic<[u8; 32]>> = (0..ELEMS).map(|_| Atomic::new([0; 32])).collect();
+    let mut time = Duration::default();
+    for round in 0..ROUNDS {
+        thread::scope(|s| {
+            for chunk in table.chunks(ELEMS / THREADS) {
+                s.spawn(move || {
+                    for a in chunk {
+                        a.store([round as u8; 32], Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        let start = Instant::now();
+        let result = total(black_box(&table));
+        time += start.elapsed();
+        assert_eq!(result, (ELEMS * 32 * round) as u64);
+    }
+    println!("{:>16}: {:>10.2?}/round", name, time / ROUNDS as u32);
+}
+
+fn main() {
+    measure("load", |table| {
+        table.iter().map(|a| sum(a.load(Ordering::Relaxed))).sum()
+    });
+    // SAFETY: the workers were joined before the table is summed.
+    measure("unsynchronized", |table| {
+        table
+            .iter()
+            .map(|a| sum(unsafe { a.load_unsynchronized() }))
+            .sum()
+    });
+}
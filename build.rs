@@ -0,0 +1,349 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Detects which atomic widths are natively supported by the target and emits
+// a `has_atomic_N` cfg for each of them. The ops module only ever looks at
+// these cfgs, so the dispatch and `is_lock_free` agree on every target
+// regardless of which compiler is used.
+
+use std::env;
+
+const WIDTHS: &[&str] = &["8", "16", "32", "64", "128"];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    for width in WIDTHS {
+        println!("cargo:rustc-check-cfg=cfg(has_atomic_{})", width);
+    }
+
+    println!("cargo:rustc-check-cfg=cfg(has_atomic_ptr)");
+    for width in WIDTHS.iter().chain(&["ptr"]) {
+        println!("cargo:rustc-check-cfg=cfg(has_atomic_load_store_{})", width);
+    }
+    println!("cargo:rustc-check-cfg=cfg(no_atomic_cas)");
+    println!("cargo:rustc-check-cfg=cfg(force_fallback)");
+    for width in WIDTHS.iter().chain(&["ptr"]) {
+        println!("cargo:rustc-check-cfg=cfg(native_{})", width);
+    }
+    println!("cargo:rustc-check-cfg=cfg(native_min_max)");
+    println!("cargo:rustc-check-cfg=cfg(detect_cmpxchg16b)");
+    println!("cargo:rustc-check-cfg=cfg(zacas_64)");
+    println!("cargo:rustc-check-cfg=cfg(single_threaded)");
+    println!("cargo:rustc-check-cfg=cfg(volatile_asm)");
+    for width in WIDTHS.iter().chain(&["ptr"]) {
+        println!("cargo:rustc-check-cfg=cfg(has_atomic_equal_alignment_{})", width);
+    }
+    println!("cargo:rustc-check-cfg=cfg(loom)");
+    println!("cargo:rustc-check-cfg=cfg(shuttle)");
+    println!("cargo:rustc-check-cfg=cfg(sanitize_thread)");
+
+    // `--cfg no_atomic_cas` makes the target look like one without any
+    // compare-exchange, which lets the tests of that configuration run on the
+    // host.
+    let mut widths = target_atomic_widths();
+    if env::var_os("CARGO_CFG_NO_ATOMIC_CAS").is_some() {
+        widths.clear();
+    }
+    for width in &widths {
+        println!("cargo:rustc-cfg=has_atomic_{}", width);
+    }
+
+    // `AtomicPtr` is available whenever integers of pointer width are.
+    let pointer_width = env::var("CARGO_CFG_TARGET_POINTER_WIDTH").unwrap_or_default();
+    if widths.contains(&pointer_width) {
+        println!("cargo:rustc-cfg=has_atomic_ptr");
+    }
+
+    // The `portable-atomic` feature replaces the standard atomic types in the
+    // ops module by those of the `portable-atomic` crate, which exist for every
+    // width on every target. Which of them are lock-free is only known to that
+    // crate, so the ops module asks it. The model checkers replace them with
+    // their own types again.
+    let model_checked =
+        env::var_os("CARGO_CFG_LOOM").is_some() || env::var_os("CARGO_CFG_SHUTTLE").is_some();
+    let portable = env::var_os("CARGO_FEATURE_PORTABLE_ATOMIC").is_some() && !model_checked;
+
+    // The widths which the native types of the ops module have every
+    // operation for. 128-bit atomics have no stable type, but the generic
+    // intrinsics used by the `nightly` feature support them on targets which
+    // have them.
+    let mut native_widths: Vec<String> = widths.iter().filter(|w| *w != "128").cloned().collect();
+    if env::var_os("CARGO_FEATURE_NIGHTLY").is_some()
+        && widths.iter().any(|w| w == "128")
+        && !model_checked
+    {
+        native_widths.push("128".to_string());
+    }
+    if portable {
+        native_widths = WIDTHS.iter().map(|w| w.to_string()).collect();
+    }
+    // `--cfg force_fallback` takes away every native type, so that all
+    // operations go through the fallback lock. It is only meant for tests
+    // which compare the fallback with the native instructions on the same
+    // types.
+    let force_fallback = env::var_os("CARGO_CFG_FORCE_FALLBACK").is_some();
+
+    // riscv32 has no 64-bit atomic types, but the Zacas extension adds a
+    // 64-bit compare-exchange, `amocas.d`, which the other operations can be
+    // built on. Bare-metal targets can't detect it, so it is only used when it
+    // is enabled at compile time. `portable-atomic` and the model checkers
+    // bring their own types, and Miri can't run the inline assembly.
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let target_features = env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    let zacas = arch == "riscv32"
+        && target_features.split(',').any(|f| f == "zacas")
+        && widths.iter().any(|w| w == "32")
+        && !widths.iter().any(|w| w == "64")
+        && !portable
+        && !model_checked
+        && !force_fallback
+        && env::var_os("CARGO_CFG_MIRI").is_none();
+    if zacas {
+        println!("cargo:rustc-cfg=zacas_64");
+        native_widths.push("64".to_string());
+    }
+    if force_fallback {
+        native_widths.clear();
+    }
+    for width in &native_widths {
+        println!("cargo:rustc-cfg=native_{}", width);
+    }
+    if native_widths.contains(&pointer_width) {
+        println!("cargo:rustc-cfg=native_ptr");
+    }
+    // The intrinsics of the `nightly` feature and the types of
+    // `portable-atomic` also have `fetch_min` and `fetch_max` for signed
+    // integers.
+    if (env::var_os("CARGO_FEATURE_NIGHTLY").is_some() || portable)
+        && !model_checked
+        && !force_fallback
+    {
+        println!("cargo:rustc-cfg=native_min_max");
+    }
+
+    // Without native 128-bit atomics, x86_64 can still use `cmpxchg16b` for
+    // 16-byte values if the processor has it, which is detected at runtime.
+    // Neither the model checkers, Miri nor ThreadSanitizer can see the inline
+    // assembly, and SGX enclaves can't run `cpuid`. `--cfg no_atomic_cas`
+    // also turns it off, since it pretends there is no compare-exchange.
+    let sanitizers = env::var("CARGO_CFG_SANITIZE").unwrap_or_default();
+    if env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default() == "x86_64"
+        && !widths.is_empty()
+        && !native_widths.iter().any(|w| w == "128")
+        && !force_fallback
+        && !model_checked
+        && env::var_os("CARGO_CFG_MIRI").is_none()
+        && !sanitizers.split(',').any(|s| s == "thread")
+        && env::var("CARGO_CFG_TARGET_ENV").unwrap_or_default() != "sgx"
+    {
+        println!("cargo:rustc-cfg=detect_cmpxchg16b");
+    }
+
+    // wasm without the `atomics` target feature can't start a second thread,
+    // and the compiler already lowers the native atomics to plain loads and
+    // stores there. The fallback then doesn't need a lock either, which keeps
+    // the lock table and its spinning out of the binary, and every size is
+    // lock-free. The model checkers and `force_fallback` want the lock.
+    let single_threaded = env::var("CARGO_CFG_TARGET_FAMILY")
+        .unwrap_or_default()
+        .split(',')
+        .any(|f| f == "wasm")
+        && !target_features.split(',').any(|f| f == "atomics")
+        && !widths.is_empty()
+        && !model_checked
+        && !force_fallback;
+    if single_threaded {
+        println!("cargo:rustc-cfg=single_threaded");
+    }
+
+    // `load_volatile` and `store_volatile` are inline assembly for the
+    // architectures with a single load and store instruction for each width up
+    // to the pointer size, and barriers to go with them. On ARM those are
+    // ARMv6-M and ARMv7 or later, whose target features stable compilers don't
+    // pass on, so they are recognized by the name of the target too.
+    let target = env::var("TARGET").unwrap_or_default();
+    let volatile_asm = match &*arch {
+        "x86" | "x86_64" | "aarch64" | "riscv32" | "riscv64" => true,
+        "arm" => {
+            target_features
+                .split(',')
+                .any(|f| f == "v7" || f == "mclass")
+                || ["armv7", "armv8", "thumbv6m", "thumbv7", "thumbv8"]
+                    .iter()
+                    .any(|p| target.starts_with(p))
+        }
+        _ => false,
+    };
+    if volatile_asm {
+        println!("cargo:rustc-cfg=volatile_asm");
+    }
+
+    // Targets without any compare-exchange, like Cortex-M0, can still load
+    // and store atomically. The read-modify-write operations then run in a
+    // critical section, which keeps out everything else on a single core,
+    // including native stores. Where there is a compare-exchange the fallback
+    // is a spinlock, which doesn't keep out native stores, so there loads and
+    // stores only use native instructions for the widths which have a
+    // compare-exchange.
+    //
+    // MSP430 has no atomics at all, so there loads and stores take the
+    // critical section as well. It is the only lock a target without a
+    // compare-exchange can have, and unlike a spinlock it can't deadlock
+    // against the code an interrupt handler interrupted.
+    //
+    // With `portable-atomic` the fallback is a spinlock on its types, which
+    // on these targets are made atomic by masking interrupts. An interrupt
+    // handler which found the spinlock held would spin forever, so the
+    // critical section is used instead when the `critical-section` feature
+    // is enabled.
+    let load_store_widths = if force_fallback {
+        Vec::new()
+    } else if portable {
+        if widths.is_empty() && env::var_os("CARGO_FEATURE_CRITICAL_SECTION").is_some() {
+            println!("cargo:rustc-cfg=no_atomic_cas");
+        }
+        native_widths.clone()
+    } else if widths.is_empty() {
+        println!("cargo:rustc-cfg=no_atomic_cas");
+        target_load_store_widths()
+    } else if zacas {
+        native_widths.clone()
+    } else {
+        widths.clone()
+    };
+    for width in load_store_widths.iter().filter(|w| *w != "128") {
+        println!("cargo:rustc-cfg=has_atomic_load_store_{}", width);
+    }
+    if load_store_widths.contains(&pointer_width) {
+        println!("cargo:rustc-cfg=has_atomic_load_store_ptr");
+    }
+
+    for width in equal_alignment_widths(&widths, &pointer_width) {
+        println!("cargo:rustc-cfg=has_atomic_equal_alignment_{}", width);
+    }
+
+    // The number of slots in the fallback lock table. It is checked to be a
+    // power of two when the crate is compiled.
+    println!("cargo:rerun-if-env-changed=ATOMIC_FALLBACK_LOCKS");
+    let locks = env::var("ATOMIC_FALLBACK_LOCKS").unwrap_or_else(|_| "64".to_string());
+    println!("cargo:rustc-env=ATOMIC_FALLBACK_LOCKS={}", locks.trim());
+
+    // `cfg(sanitize)` is unstable, but build scripts see it regardless of the
+    // compiler channel, so pass it on as a cfg of our own.
+    if sanitizers.split(',').any(|s| s == "thread") {
+        println!("cargo:rustc-cfg=sanitize_thread");
+    }
+}
+
+fn target_atomic_widths() -> Vec<String> {
+    // Compilers which support the `target_has_atomic` cfg pass it on to build
+    // scripts, which gives us an exact answer.
+    if let Ok(widths) = env::var("CARGO_CFG_TARGET_HAS_ATOMIC") {
+        return widths
+            .split(',')
+            .filter(|w| WIDTHS.contains(w))
+            .map(String::from)
+            .collect();
+    }
+
+    // Older compilers don't expose it, so fall back to a table of known
+    // targets. This errs on the side of using the fallback lock.
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let features = env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    let has_feature = |f: &str| features.split(',').any(|x| x == f);
+    let max_width = match &*arch {
+        "x86" | "x86_64" | "aarch64" | "mips64" | "powerpc64" | "s390x" | "sparc64" | "riscv64"
+        | "loongarch64" => 64,
+        "arm" if has_feature("v7") && !has_feature("mclass") => 64,
+        // ARMv6-M has no exclusive loads and stores.
+        "arm" if has_feature("mclass") && !has_feature("v7") => 0,
+        "arm" if has_feature("v6") || has_feature("v7") => 32,
+        "mips" | "powerpc" | "sparc" | "hexagon" => 32,
+        "riscv32" if has_feature("a") => 32,
+        _ => 0,
+    };
+    WIDTHS
+        .iter()
+        .filter(|w| w.parse::<u32>().unwrap() <= max_width)
+        .map(|w| w.to_string())
+        .collect()
+}
+
+// The widths which can be loaded and stored atomically on a target without
+// any compare-exchange.
+fn target_load_store_widths() -> Vec<String> {
+    // Nightly compilers pass this on to build scripts.
+    if let Ok(widths) = env::var("CARGO_CFG_TARGET_HAS_ATOMIC_LOAD_STORE") {
+        return widths
+            .split(',')
+            .filter(|w| WIDTHS.contains(w))
+            .map(String::from)
+            .collect();
+    }
+
+    // Stable compilers don't, so fall back to the targets we know of. With
+    // `--cfg no_atomic_cas` on the host, this is every width up to the pointer
+    // width.
+    let target = env::var("TARGET").unwrap_or_default();
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let features = env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    let has_feature = |f: &str| features.split(',').any(|x| x == f);
+    let max_width = if env::var_os("CARGO_CFG_NO_ATOMIC_CAS").is_some() {
+        let pointer_width = env::var("CARGO_CFG_TARGET_POINTER_WIDTH").unwrap_or_default();
+        pointer_width.parse().unwrap_or(0)
+    } else if target.starts_with("thumbv6m-") || (arch == "riscv32" && !has_feature("a")) {
+        32
+    } else if arch == "avr" {
+        // AVR has 8-bit accesses and 16-bit ones which the compiler makes
+        // atomic by masking interrupts around them. MSP430 has no atomic
+        // types at all.
+        16
+    } else {
+        0
+    };
+    WIDTHS
+        .iter()
+        .filter(|w| w.parse::<u32>().unwrap() <= max_width)
+        .map(|w| w.to_string())
+        .collect()
+}
+
+// The widths whose standard atomic type has the same alignment as the integer,
+// so that references to an `Atomic` can be turned into references to it.
+fn equal_alignment_widths(widths: &[String], pointer_width: &str) -> Vec<String> {
+    // Nightly compilers pass this on to build scripts; it was called
+    // `target_has_atomic_equal_alignment` before being renamed.
+    for var in &[
+        "CARGO_CFG_TARGET_HAS_ATOMIC_PRIMITIVE_ALIGNMENT",
+        "CARGO_CFG_TARGET_HAS_ATOMIC_EQUAL_ALIGNMENT",
+    ] {
+        if let Ok(aligned) = env::var(var) {
+            return aligned.split(',').map(String::from).collect();
+        }
+    }
+
+    // Otherwise integers are aligned to their size, except for 64-bit ones on
+    // 32-bit x86 (other than Windows and UEFI), anything wider than 16 bits
+    // on m68k, and anything wider than 8 bits on AVR.
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let max_aligned = match &*arch {
+        "x86" if os != "windows" && os != "uefi" => 32,
+        "m68k" => 16,
+        "avr" => 8,
+        _ => 128,
+    };
+    let mut aligned: Vec<String> = widths
+        .iter()
+        .filter(|w| w.parse::<u32>().unwrap() <= max_aligned)
+        .cloned()
+        .collect();
+    if aligned.iter().any(|w| w == pointer_width) {
+        aligned.push("ptr".to_string());
+    }
+    aligned
+}
@@ -0,0 +1,49 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Functions whose generated assembly is checked by `tests/codegen.rs`.
+
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+
+#[no_mangle]
+pub fn load_relaxed_u32(a: &Atomic<u32>) -> u32 {
+    a.load(Ordering::Relaxed)
+}
+
+#[no_mangle]
+pub fn store_release_u32(a: &Atomic<u32>, val: u32) {
+    a.store(val, Ordering::Release)
+}
+
+#[no_mangle]
+pub fn swap_acq_rel_u64(a: &Atomic<u64>, val: u64) -> u64 {
+    a.swap(val, Ordering::AcqRel)
+}
+
+#[no_mangle]
+pub fn compare_exchange_u32(a: &Atomic<u32>, current: u32, new: u32) -> Result<u32, u32> {
+    a.compare_exchange(current, new, Ordering::AcqRel, Ordering::Acquire)
+}
+
+#[no_mangle]
+pub fn fetch_add_relaxed_u64(a: &Atomic<u64>, val: u64) -> u64 {
+    a.fetch_add(val, Ordering::Relaxed)
+}
+
+#[no_mangle]
+pub fn load_volatile_twice_u32(a: &Atomic<u32>) -> u32 {
+    a.load_volatile(Ordering::Acquire)
+        .wrapping_add(a.load_volatile(Ordering::Acquire))
+}
+
+#[no_mangle]
+pub fn store_volatile_twice_u64(a: &Atomic<u64>, val: u64) {
+    a.store_volatile(val, Ordering::Relaxed);
+    a.store_volatile(val, Ordering::Relaxed);
+}
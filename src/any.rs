@@ -0,0 +1,255 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::any;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{self, MaybeUninit};
+use core::ptr;
+use Atomic;
+use Ordering;
+
+// The erased operations for one `T`. Each function takes a pointer to an
+// `Atomic<T>` and calls the monomorphized method, so the choice between the
+// lock-free and the fallback implementation is made when the vtable is
+// instantiated rather than when it is called.
+struct VTable {
+    size: usize,
+    align: usize,
+    lock_free: fn() -> bool,
+    type_name: fn() -> &'static str,
+    load: unsafe fn(*const (), &mut [MaybeUninit<u8>], Ordering),
+    store: unsafe fn(*const (), &[u8], Ordering),
+    fmt: unsafe fn(*const (), &mut fmt::Formatter) -> fmt::Result,
+}
+
+struct VTableFor<T>(PhantomData<T>);
+
+impl<T: Copy + fmt::Debug> VTableFor<T> {
+    const VTABLE: VTable = VTable {
+        size: mem::size_of::<T>(),
+        align: mem::align_of::<T>(),
+        lock_free: Atomic::<T>::is_lock_free_runtime,
+        type_name: any::type_name::<T>,
+        load: Self::load,
+        store: Self::store,
+        fmt: Self::fmt,
+    };
+
+    unsafe fn load(data: *const (), buf: &mut [MaybeUninit<u8>], order: Ordering) {
+        let val = (*(data as *const Atomic<T>)).load(order);
+        ptr::copy_nonoverlapping(
+            &val as *const T as *const MaybeUninit<u8>,
+            buf.as_mut_ptr(),
+            mem::size_of::<T>(),
+        );
+    }
+
+    unsafe fn store(data: *const (), buf: &[u8], order: Ordering) {
+        let val = ptr::read_unaligned(buf.as_ptr() as *const T);
+        (*(data as *const Atomic<T>)).store(val, order);
+    }
+
+    unsafe fn fmt(data: *const (), f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&(*(data as *const Atomic<T>)).load(Ordering::SeqCst), f)
+    }
+}
+
+/// A type-erased reference to an `Atomic<T>`.
+///
+/// An `AnyAtomic` allows atomics of different types to be stored in the same
+/// collection, for example in a registry of values to report in diagnostics.
+/// It gives access to the value as raw bytes and through its `Debug`
+/// implementation.
+///
+/// Every operation uses the same implementation as the original `Atomic<T>`,
+/// including the choice between native atomic instructions and the fallback
+/// lock, so erased and typed accesses to the same atomic are atomic with
+/// respect to each other.
+#[derive(Copy, Clone)]
+pub struct AnyAtomic {
+    data: *const (),
+    vtable: &'static VTable,
+}
+
+// An `AnyAtomic` can only be created from a `&'static Atomic<T>` where `T` is
+// `Send`, which is itself `Send` and `Sync`.
+unsafe impl Send for AnyAtomic {}
+unsafe impl Sync for AnyAtomic {}
+
+impl AnyAtomic {
+    /// Erases the type of an `Atomic`.
+    #[inline]
+    pub fn erase<T: Copy + Send + fmt::Debug>(atomic: &'static Atomic<T>) -> AnyAtomic {
+        AnyAtomic {
+            data: atomic as *const Atomic<T> as *const (),
+            vtable: &VTableFor::<T>::VTABLE,
+        }
+    }
+
+    /// Returns the size of the value in bytes.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.vtable.size
+    }
+
+    /// Returns the alignment of the value in bytes.
+    #[inline]
+    pub fn align(&self) -> usize {
+        self.vtable.align
+    }
+
+    /// Returns whether operations on the original `Atomic` are lock-free.
+    #[inline]
+    pub fn is_lock_free(&self) -> bool {
+        (self.vtable.lock_free)()
+    }
+
+    /// Returns the name of the type of the value, as given by
+    /// `core::any::type_name`.
+    #[inline]
+    pub fn type_name(&self) -> &'static str {
+        (self.vtable.type_name)()
+    }
+
+    /// Loads the value and copies its bytes into `buf`.
+    ///
+    /// The bytes are left uninitialized where the value has padding.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of `buf` is not `size()`, or if `order` is
+    /// `Release` or `AcqRel`.
+    #[inline]
+    pub fn load_bytes(&self, buf: &mut [MaybeUninit<u8>], order: Ordering) {
+        assert_eq!(buf.len(), self.size(), "buffer size mismatch");
+        unsafe { (self.vtable.load)(self.data, buf, order) }
+    }
+
+    /// Stores a value given as raw bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of `buf` is not `size()`, or if `order` is
+    /// `Acquire` or `AcqRel`.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must contain a valid value of the erased type.
+    #[inline]
+    pub unsafe fn store_bytes(&self, buf: &[u8], order: Ordering) {
+        assert_eq!(buf.len(), self.size(), "buffer size mismatch");
+        (self.vtable.store)(self.data, buf, order)
+    }
+}
+
+impl fmt::Debug for AnyAtomic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        unsafe { (self.vtable.fmt)(self.data, f) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![cfg_attr(feature = "no-fallback", allow(dead_code, unused_imports))]
+
+    use super::AnyAtomic;
+    use core::mem::MaybeUninit;
+    use std::boxed::Box;
+    use std::vec::Vec;
+    use Atomic;
+    use Ordering::*;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Point {
+        x: i32,
+        y: i32,
+        z: i32,
+    }
+
+    fn bytes(a: &AnyAtomic) -> Vec<u8> {
+        let mut buf = vec![MaybeUninit::uninit(); a.size()];
+        a.load_bytes(&mut buf, SeqCst);
+        // None of the types in these tests have padding.
+        buf.iter().map(|b| unsafe { b.assume_init() }).collect()
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn heterogeneous_registry() {
+        let a: &'static Atomic<u8> = Box::leak(Box::new(Atomic::new(1)));
+        let b: &'static Atomic<u64> = Box::leak(Box::new(Atomic::new(2)));
+        let c: &'static Atomic<f32> = Box::leak(Box::new(Atomic::new(0.5)));
+        let d: &'static Atomic<Point> =
+            Box::leak(Box::new(Atomic::new(Point { x: 1, y: 2, z: 3 })));
+        let registry: Vec<AnyAtomic> = Vec::from([
+            AnyAtomic::erase(a),
+            AnyAtomic::erase(b),
+            AnyAtomic::erase(c),
+            AnyAtomic::erase(d),
+        ]);
+
+        let sizes: Vec<_> = registry.iter().map(|x| (x.size(), x.align())).collect();
+        assert_eq!(sizes, [(1, 1), (8, 8), (4, 4), (12, 4)]);
+        let lock_free: Vec<_> = registry.iter().map(|x| x.is_lock_free()).collect();
+        assert_eq!(
+            lock_free,
+            [
+                Atomic::<u8>::is_lock_free(),
+                Atomic::<u64>::is_lock_free(),
+                Atomic::<f32>::is_lock_free(),
+                Atomic::<Point>::is_lock_free(),
+            ]
+        );
+        assert_eq!(registry[2].type_name(), "f32");
+        assert!(registry[3].type_name().ends_with("Point"));
+
+        let formatted: Vec<_> = registry.iter().map(|x| format!("{:?}", x)).collect();
+        assert_eq!(formatted, ["1", "2", "0.5", "Point { x: 1, y: 2, z: 3 }"]);
+
+        a.store(200, SeqCst);
+        b.fetch_add(40, SeqCst);
+        c.store(-1.25, SeqCst);
+        d.store(Point { x: 4, y: 5, z: 6 }, SeqCst);
+        let formatted: Vec<_> = registry.iter().map(|x| format!("{:?}", x)).collect();
+        assert_eq!(
+            formatted,
+            ["200", "42", "-1.25", "Point { x: 4, y: 5, z: 6 }"]
+        );
+        assert_eq!(bytes(&registry[0]), [200]);
+        assert_eq!(bytes(&registry[1]), 42u64.to_ne_bytes());
+        assert_eq!(bytes(&registry[2]), (-1.25f32).to_ne_bytes());
+
+        unsafe {
+            registry[1].store_bytes(&7u64.to_ne_bytes(), SeqCst);
+            registry[2].store_bytes(&3.5f32.to_ne_bytes(), SeqCst);
+        }
+        assert_eq!(b.load(SeqCst), 7);
+        assert_eq!(c.load(SeqCst), 3.5);
+
+        // Keep Miri's leak checker happy.
+        drop(registry);
+        unsafe {
+            drop(Box::from_raw(a as *const _ as *mut Atomic<u8>));
+            drop(Box::from_raw(b as *const _ as *mut Atomic<u64>));
+            drop(Box::from_raw(c as *const _ as *mut Atomic<f32>));
+            drop(Box::from_raw(d as *const _ as *mut Atomic<Point>));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "buffer size mismatch")]
+    fn wrong_buffer_size() {
+        let a: &'static Atomic<u32> = Box::leak(Box::new(Atomic::new(1)));
+        let erased = AnyAtomic::erase(a);
+        // The size check comes before the atomic is accessed, so it can be
+        // freed right away.
+        unsafe { drop(Box::from_raw(a as *const _ as *mut Atomic<u32>)) };
+        let mut buf = [MaybeUninit::uninit(); 8];
+        erased.load_bytes(&mut buf, SeqCst);
+    }
+}
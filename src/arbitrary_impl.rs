@@ -0,0 +1,68 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// `arbitrary::Arbitrary` implementations, so that structures with atomic
+// fields can be generated by fuzzers.
+//
+// Each wrapper is built from an arbitrary value of the type it wraps, and
+// consumes exactly the same input, so the size hints are those of the inner
+// type. Pointer types such as `AtomicTaggedPtr` and `RelPtr` are left out,
+// since a pointer made up from fuzzer input can't be used anyway.
+
+use arbitrary::{Arbitrary, MaxRecursionReached, Result, Unstructured};
+use core::sync::atomic::Ordering;
+#[cfg(has_atomic_8)]
+use AtomicFlag;
+#[cfg(not(feature = "no-fallback"))]
+use AtomicSlot;
+use {Atomic, AtomicArray, CachePadded};
+
+macro_rules! arbitrary_wrapper {
+    ([$($params:tt)*] $wrapper:ty, $inner:ty, $new:expr) => {
+        impl<'a, $($params)*> Arbitrary<'a> for $wrapper {
+            #[inline]
+            fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+                <$inner>::arbitrary(u).map($new)
+            }
+
+            #[inline]
+            fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+                <$inner>::arbitrary_take_rest(u).map($new)
+            }
+
+            #[inline]
+            fn size_hint(depth: usize) -> (usize, Option<usize>) {
+                <$inner>::size_hint(depth)
+            }
+
+            #[inline]
+            fn try_size_hint(
+                depth: usize,
+            ) -> core::result::Result<(usize, Option<usize>), MaxRecursionReached> {
+                <$inner>::try_size_hint(depth)
+            }
+        }
+    };
+}
+
+arbitrary_wrapper!([T: Arbitrary<'a> + Copy] Atomic<T>, T, Atomic::new);
+arbitrary_wrapper!(
+    [T: Arbitrary<'a> + Copy, const N: usize] AtomicArray<T, N>,
+    [T; N],
+    AtomicArray::new
+);
+arbitrary_wrapper!([T: Arbitrary<'a>] CachePadded<T>, T, CachePadded::new);
+#[cfg(not(feature = "no-fallback"))]
+arbitrary_wrapper!([T: Arbitrary<'a>] AtomicSlot<T>, T, AtomicSlot::new);
+#[cfg(has_atomic_8)]
+arbitrary_wrapper!([] AtomicFlag, bool, |set| {
+    let flag = AtomicFlag::new();
+    if set {
+        flag.test_and_set(Ordering::Relaxed);
+    }
+    flag
+});
@@ -0,0 +1,312 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+#[cfg(not(any(loom, shuttle)))]
+use core::mem::ManuallyDrop;
+use core::sync::atomic::Ordering;
+use Atomic;
+
+/// A fixed-size array of atomics.
+///
+/// Every element is an independent `Atomic<T>`, so this is only a more
+/// convenient way of writing `[Atomic<T>; N]`: operations take the index of
+/// the element they apply to, and there are a few operations on the whole
+/// array. The elements themselves are available through `as_slice`.
+///
+/// Operations on the whole array, such as `iter_load` and `fill`, access the
+/// elements one after the other. They are not atomic as a whole, so other
+/// threads can observe (or make) changes to some elements in the middle of
+/// them.
+///
+/// # Examples
+///
+/// A sharded counter, where each thread increments its own element:
+///
+/// ```
+/// use atomic::{AtomicArray, Ordering};
+/// use std::thread;
+///
+/// static HITS: AtomicArray<u64, 4> = AtomicArray::<u64, 4>::zeroed();
+///
+/// thread::scope(|s| {
+///     for shard in 0..4 {
+///         s.spawn(move || {
+///             for _ in 0..100 {
+///                 HITS.fetch_add(shard, 1, Ordering::Relaxed);
+///             }
+///         });
+///     }
+/// });
+/// let total: u64 = HITS.iter_load(Ordering::Relaxed).iter().sum();
+/// assert_eq!(total, 400);
+/// ```
+#[cfg_attr(
+    all(feature = "zerocopy", not(any(loom, shuttle))),
+    derive(zerocopy::FromBytes, zerocopy::KnownLayout)
+)]
+pub struct AtomicArray<T: Copy, const N: usize> {
+    elems: [Atomic<T>; N],
+}
+
+#[cfg(not(any(loom, shuttle)))]
+union Transmute<T: Copy, const N: usize> {
+    values: [T; N],
+    atomics: ManuallyDrop<[Atomic<T>; N]>,
+}
+
+impl<T: Copy, const N: usize> AtomicArray<T, N> {
+    /// Creates a new `AtomicArray` holding the given values.
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub const fn new(values: [T; N]) -> AtomicArray<T, N> {
+        // `Atomic<T>` is `repr(transparent)` over `T`, so the arrays have the
+        // same layout.
+        let t = Transmute { values };
+        AtomicArray {
+            elems: ManuallyDrop::into_inner(unsafe { t.atomics }),
+        }
+    }
+
+    /// Creates a new `AtomicArray` holding the given values.
+    ///
+    /// Under loom or shuttle this isn't a `const fn`, like `Atomic::new`.
+    #[inline]
+    #[cfg(any(loom, shuttle))]
+    pub fn new(values: [T; N]) -> AtomicArray<T, N> {
+        AtomicArray {
+            elems: core::array::from_fn(|i| Atomic::new(values[i])),
+        }
+    }
+
+    /// Returns the elements of the array.
+    #[inline]
+    pub fn as_slice(&self) -> &[Atomic<T>; N] {
+        &self.elems
+    }
+
+    /// Returns the number of elements in the array.
+    #[inline]
+    pub const fn len(&self) -> usize {
+        N
+    }
+
+    /// Returns `true` if the array has no elements.
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    /// Consumes the array and returns the contained values.
+    #[inline]
+    pub fn into_inner(self) -> [T; N] {
+        self.elems.map(Atomic::into_inner)
+    }
+
+    /// Loads the element at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds, or if `order` is `Release` or
+    /// `AcqRel`.
+    #[inline]
+    pub fn load(&self, index: usize, order: Ordering) -> T {
+        self.elems[index].load(order)
+    }
+
+    /// Stores a value into the element at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds, or if `order` is `Acquire` or
+    /// `AcqRel`.
+    #[inline]
+    pub fn store(&self, index: usize, val: T, order: Ordering) {
+        self.elems[index].store(val, order)
+    }
+
+    /// Stores a value into the element at `index`, returning the old value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    #[inline]
+    pub fn swap(&self, index: usize, val: T, order: Ordering) -> T {
+        self.elems[index].swap(val, order)
+    }
+
+    /// Loads every element, in order.
+    ///
+    /// Each element is loaded separately, so the result is not a consistent
+    /// snapshot of the array: it can mix values from before and after a
+    /// concurrent update of several elements. Writes which happen-before the
+    /// call are always reflected in the result.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn iter_load(&self, order: Ordering) -> [T; N] {
+        core::array::from_fn(|i| self.elems[i].load(order))
+    }
+
+    /// Stores `val` into every element, in order.
+    ///
+    /// Like `iter_load`, this is a sequence of separate stores, so other
+    /// threads can observe some of the elements changed and others not.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn fill(&self, val: T, order: Ordering) {
+        for a in &self.elems {
+            a.store(val, order);
+        }
+    }
+}
+
+impl<T: Copy + Default, const N: usize> Default for AtomicArray<T, N> {
+    #[inline]
+    fn default() -> Self {
+        Self::new([T::default(); N])
+    }
+}
+
+impl<T: Copy + fmt::Debug, const N: usize> fmt::Debug for AtomicArray<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("AtomicArray")
+            .field(&self.iter_load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+macro_rules! atomic_array_arith {
+    ($($t:ident $zero:expr),*) => ($(
+        impl<const N: usize> AtomicArray<$t, N> {
+            /// Creates a new `AtomicArray` with every element set to zero.
+            #[inline]
+            #[cfg(not(any(loom, shuttle)))]
+            pub const fn zeroed() -> AtomicArray<$t, N> {
+                AtomicArray::new([$zero; N])
+            }
+
+            /// Creates a new `AtomicArray` with every element set to zero.
+            #[inline]
+            #[cfg(any(loom, shuttle))]
+            pub fn zeroed() -> AtomicArray<$t, N> {
+                AtomicArray::new([$zero; N])
+            }
+
+            /// Adds to the element at `index`, returning the previous value.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `index` is out of bounds.
+            #[inline]
+            pub fn fetch_add(&self, index: usize, val: $t, order: Ordering) -> $t {
+                self.elems[index].fetch_add(val, order)
+            }
+
+            /// Subtracts from the element at `index`, returning the previous
+            /// value.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `index` is out of bounds.
+            #[inline]
+            pub fn fetch_sub(&self, index: usize, val: $t, order: Ordering) -> $t {
+                self.elems[index].fetch_sub(val, order)
+            }
+        }
+    )*);
+}
+atomic_array_arith! {
+    i8 0, i16 0, i32 0, i64 0, isize 0, i128 0,
+    u8 0, u16 0, u32 0, u64 0, usize 0, u128 0,
+    f32 0.0, f64 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AtomicArray;
+    use core::sync::atomic::Ordering::*;
+    use std::thread;
+
+    static TABLE: AtomicArray<u32, 3> = AtomicArray::new([1, 2, 3]);
+
+    #[test]
+    fn basic() {
+        assert_eq!(TABLE.len(), 3);
+        assert_eq!(TABLE.load(1, SeqCst), 2);
+        let a = AtomicArray::new([1u8, 2, 3]);
+        a.store(0, 10, SeqCst);
+        assert_eq!(a.swap(2, 30, SeqCst), 3);
+        assert_eq!(a.fetch_add(1, 18, SeqCst), 2);
+        assert_eq!(a.as_slice()[1].load(SeqCst), 20);
+        assert_eq!(format!("{:?}", a), "AtomicArray([10, 20, 30])");
+        a.fill(7, SeqCst);
+        assert_eq!(a.into_inner(), [7; 3]);
+        let e = AtomicArray::<f64, 0>::zeroed();
+        assert!(e.is_empty());
+        assert_eq!(e.iter_load(SeqCst), []);
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_bounds() {
+        TABLE.load(3, SeqCst);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn fallback_elements() {
+        let a = AtomicArray::new([[0u8; 24]; 2]);
+        a.store(1, [5; 24], SeqCst);
+        assert_eq!(a.iter_load(SeqCst), [[0; 24], [5; 24]]);
+    }
+
+    #[test]
+    fn concurrent_histogram() {
+        const BUCKETS: usize = 8;
+        const THREADS: u64 = 4;
+        const SAMPLES: u64 = 1000;
+        let hist = AtomicArray::<u64, BUCKETS>::zeroed();
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let hist = &hist;
+                s.spawn(move || {
+                    for i in 0..SAMPLES {
+                        let bucket = ((t * SAMPLES + i) % BUCKETS as u64) as usize;
+                        hist.fetch_add(bucket, 1, Relaxed);
+                    }
+                });
+            }
+        });
+        let counts = hist.iter_load(Relaxed);
+        assert_eq!(counts, [THREADS * SAMPLES / BUCKETS as u64; BUCKETS]);
+    }
+
+    #[test]
+    fn iter_load_sees_completed_writes() {
+        let a = AtomicArray::<u32, 16>::zeroed();
+        let ready = AtomicArray::<u32, 1>::zeroed();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..16 {
+                    a.store(i, i as u32 + 1, Relaxed);
+                }
+                ready.store(0, 1, Release);
+            });
+            while ready.load(0, Acquire) == 0 {
+                thread::yield_now();
+            }
+            let expected: [u32; 16] = core::array::from_fn(|i| i as u32 + 1);
+            assert_eq!(a.iter_load(Relaxed), expected);
+        });
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// `atomic_traits` implementations, so that `Atomic<T>` can be used in place of
+// the standard atomic types by code which is generic over them.
+//
+// The traits which exist depend on the compiler version atomic-traits is built
+// with; everything up to our minimum supported version is implemented.
+// `FromPtr` is left out under loom and shuttle, like `Atomic::from_ptr`.
+// Like in `radium_impl`, integers get `fetch_nand` from the usual update loop
+// since they don't have an inherent one.
+
+#[cfg(not(any(loom, shuttle)))]
+use atomic_traits::FromPtr;
+use atomic_traits::{self, fetch, AsPtr, Bitwise, NumOps};
+use core::sync::atomic::Ordering;
+use ops;
+use Atomic;
+
+macro_rules! atomic_traits_common {
+    ($t:ty, [$($params:tt)*]) => {
+        impl<$($params)*> atomic_traits::Atomic for Atomic<$t> {
+            type Type = $t;
+
+            #[inline]
+            fn new(v: $t) -> Self {
+                Atomic::new(v)
+            }
+
+            #[inline]
+            fn get_mut(&mut self) -> &mut $t {
+                Atomic::<$t>::get_mut(self)
+            }
+
+            #[inline]
+            fn into_inner(self) -> $t {
+                Atomic::<$t>::into_inner(self)
+            }
+
+            #[inline]
+            fn load(&self, order: Ordering) -> $t {
+                Atomic::<$t>::load(self, order)
+            }
+
+            #[inline]
+            fn store(&self, val: $t, order: Ordering) {
+                Atomic::<$t>::store(self, val, order)
+            }
+
+            #[inline]
+            fn swap(&self, val: $t, order: Ordering) -> $t {
+                Atomic::<$t>::swap(self, val, order)
+            }
+
+            #[inline]
+            #[allow(deprecated)]
+            fn compare_and_swap(&self, current: $t, new: $t, order: Ordering) -> $t {
+                Atomic::<$t>::compare_and_swap(self, current, new, order)
+            }
+
+            #[inline]
+            fn compare_exchange(
+                &self,
+                current: $t,
+                new: $t,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$t, $t> {
+                Atomic::<$t>::compare_exchange(self, current, new, success, failure)
+            }
+
+            #[inline]
+            fn compare_exchange_weak(
+                &self,
+                current: $t,
+                new: $t,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$t, $t> {
+                Atomic::<$t>::compare_exchange_weak(self, current, new, success, failure)
+            }
+        }
+
+        impl<$($params)*> fetch::Update for Atomic<$t> {
+            type Type = $t;
+
+            #[inline]
+            fn fetch_update<F>(
+                &self,
+                set_order: Ordering,
+                fetch_order: Ordering,
+                f: F,
+            ) -> Result<$t, $t>
+            where
+                F: FnMut($t) -> Option<$t>,
+            {
+                unsafe { ops::atomic_fetch_update(self.v.get(), set_order, fetch_order, f) }
+            }
+        }
+
+        impl<$($params)*> AsPtr for Atomic<$t> {
+            #[inline]
+            fn as_ptr(&self) -> *mut $t {
+                Atomic::<$t>::as_ptr(self)
+            }
+        }
+
+        #[cfg(not(any(loom, shuttle)))]
+        impl<$($params)*> FromPtr for Atomic<$t> {
+            #[inline]
+            unsafe fn from_ptr<'a>(ptr: *mut $t) -> &'a Self {
+                Atomic::<$t>::from_ptr(ptr)
+            }
+        }
+    };
+}
+
+macro_rules! atomic_traits_fetch {
+    ($t:ty, $($name:ident $method:ident)*) => {
+        $(
+            impl fetch::$name for Atomic<$t> {
+                type Type = $t;
+
+                #[inline]
+                fn $method(&self, val: $t, order: Ordering) -> $t {
+                    Atomic::<$t>::$method(self, val, order)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! atomic_traits_int {
+    ($($t:ty)*) => ($(
+        atomic_traits_common!($t, []);
+        atomic_traits_fetch!($t, And fetch_and Or fetch_or Xor fetch_xor);
+        atomic_traits_fetch!($t, Add fetch_add Sub fetch_sub Max fetch_max Min fetch_min);
+
+        impl fetch::Nand for Atomic<$t> {
+            type Type = $t;
+
+            #[inline]
+            fn fetch_nand(&self, val: $t, order: Ordering) -> $t {
+                let f = |x: $t| Some(!(x & val));
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+        }
+
+        impl Bitwise for Atomic<$t> {}
+        impl NumOps for Atomic<$t> {}
+    )*);
+}
+atomic_traits_int! { i8 i16 i32 i64 i128 isize u8 u16 u32 u64 u128 usize }
+
+atomic_traits_common!(bool, []);
+atomic_traits_fetch!(bool, And fetch_and Nand fetch_nand Or fetch_or Xor fetch_xor);
+impl Bitwise for Atomic<bool> {}
+
+atomic_traits_common!(*mut T, [T]);
@@ -0,0 +1,187 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use core::hint;
+
+const SPIN_LIMIT: u32 = 6;
+const YIELD_LIMIT: u32 = 10;
+
+/// Exponential backoff for spin loops.
+///
+/// Each step doubles the number of `spin_loop` hints, which keeps a waiting
+/// thread from hammering a cache line which another thread is about to
+/// release. This is the policy used by the fallback lock and by
+/// `Atomic::spin_wait_until`.
+///
+/// `spin` is meant for retrying a failed compare-exchange, where another
+/// thread made progress and retrying soon is likely to succeed. `snooze` is
+/// meant for waiting for another thread to do something: once the pauses get
+/// long enough that the other thread was probably preempted, it yields the
+/// time slice instead (with the `std` feature), so that the other thread can
+/// run even if there are more threads than cores. `is_completed` then tells
+/// that it is time to block instead, if there is a way to.
+///
+/// Under loom and shuttle both of them yield to the model checker, which
+/// otherwise keeps scheduling the spinning thread.
+///
+/// # Examples
+///
+/// ```
+/// use atomic::{Atomic, Backoff, Ordering};
+///
+/// fn fetch_mul(a: &Atomic<u64>, val: u64) -> u64 {
+///     let mut backoff = Backoff::new();
+///     let mut current = a.load(Ordering::Relaxed);
+///     loop {
+///         let new = current.wrapping_mul(val);
+///         match a.compare_exchange_weak(current, new, Ordering::AcqRel, Ordering::Relaxed) {
+///             Ok(x) => return x,
+///             Err(x) => {
+///                 current = x;
+///                 backoff.spin();
+///             }
+///         }
+///     }
+/// }
+///
+/// let a = Atomic::new(3);
+/// assert_eq!(fetch_mul(&a, 5), 3);
+/// assert_eq!(a.load(Ordering::Relaxed), 15);
+/// ```
+pub struct Backoff {
+    step: u32,
+}
+
+impl Default for Backoff {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Backoff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Backoff")
+            .field("step", &self.step)
+            .field("is_completed", &self.is_completed())
+            .finish()
+    }
+}
+
+impl Backoff {
+    /// Creates a new `Backoff`.
+    #[inline]
+    pub const fn new() -> Backoff {
+        Backoff { step: 0 }
+    }
+
+    /// Starts over from the shortest pause.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.step = 0;
+    }
+
+    /// Backs off in a loop which retries a failed compare-exchange.
+    ///
+    /// This only ever spins, doubling the number of `spin_loop` hints on each
+    /// call up to 64.
+    #[inline]
+    pub fn spin(&mut self) {
+        if cfg!(any(loom, shuttle)) {
+            yield_now();
+        } else {
+            for _ in 0..1 << self.step.min(SPIN_LIMIT) {
+                hint::spin_loop();
+            }
+        }
+        if self.step <= SPIN_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    /// Backs off in a loop which waits for another thread.
+    ///
+    /// This spins like `spin` at first, and then yields to other threads with
+    /// the `std` feature, or keeps spinning without it.
+    #[inline]
+    pub fn snooze(&mut self) {
+        if self.step <= SPIN_LIMIT && !cfg!(any(loom, shuttle)) {
+            for _ in 0..1 << self.step {
+                hint::spin_loop();
+            }
+        } else {
+            yield_now();
+        }
+        if self.step <= YIELD_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    /// Returns `true` once `snooze` has backed off for long enough that the
+    /// thread should block rather than keep waiting.
+    ///
+    /// `spin` alone never completes the backoff.
+    #[inline]
+    pub fn is_completed(&self) -> bool {
+        self.step > YIELD_LIMIT
+    }
+}
+
+#[inline]
+fn yield_now() {
+    #[cfg(any(loom, shuttle))]
+    ::shadow::yield_now();
+    #[cfg(all(feature = "std", not(any(loom, shuttle))))]
+    ::std::thread::yield_now();
+    #[cfg(not(any(feature = "std", loom, shuttle)))]
+    for _ in 0..1 << SPIN_LIMIT {
+        hint::spin_loop();
+    }
+}
+
+#[cfg(all(test, not(any(loom, shuttle))))]
+mod tests {
+    use super::{Backoff, SPIN_LIMIT, YIELD_LIMIT};
+
+    #[test]
+    fn snooze_completes() {
+        let mut backoff = Backoff::new();
+        let mut steps = 0;
+        while !backoff.is_completed() {
+            backoff.snooze();
+            steps += 1;
+        }
+        assert_eq!(steps, YIELD_LIMIT + 1);
+        backoff.snooze();
+        assert!(backoff.is_completed());
+
+        backoff.reset();
+        assert!(!backoff.is_completed());
+        assert_eq!(backoff.step, 0);
+    }
+
+    #[test]
+    fn spin_never_completes() {
+        let mut backoff = Backoff::default();
+        for _ in 0..100 {
+            backoff.spin();
+            assert!(!backoff.is_completed());
+        }
+        assert_eq!(backoff.step, SPIN_LIMIT + 1);
+
+        // Snoozing carries on from where spinning left off.
+        for _ in SPIN_LIMIT + 1..=YIELD_LIMIT {
+            backoff.snooze();
+        }
+        assert!(backoff.is_completed());
+        assert_eq!(
+            format!("{:?}", backoff),
+            "Backoff { step: 11, is_completed: true }"
+        );
+    }
+}
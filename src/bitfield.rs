@@ -0,0 +1,298 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use core::sync::atomic::Ordering;
+use ops;
+use Atomic;
+
+/// A view of a range of bits inside an unsigned integer `Atomic`.
+///
+/// The field holds the `WIDTH` bits of the integer starting at bit `OFFSET`,
+/// counted from the least significant bit. It is created by `Atomic::field`,
+/// which checks at compile time that the range is not empty and fits in the
+/// integer.
+///
+/// Values of the field are passed and returned shifted down to bit 0. Every
+/// update is a compare-exchange loop on the whole integer which only changes
+/// the bits of the field, so fields which don't overlap can be updated
+/// concurrently without affecting each other. Reading a field is a plain
+/// load.
+///
+/// # Examples
+///
+/// A 48-bit index, an 8-bit state and 8 bits of flags packed in a `u64`:
+///
+/// ```
+/// use atomic::{Atomic, Ordering};
+///
+/// let word = Atomic::new(0u64);
+/// let index = word.field::<0, 48>();
+/// let state = word.field::<48, 8>();
+///
+/// index.store_field(0x1234_5678_9abc, Ordering::Relaxed);
+/// assert_eq!(state.compare_exchange_field(0, 3, Ordering::AcqRel, Ordering::Acquire), Ok(0));
+/// assert_eq!(index.load_field(Ordering::Relaxed), 0x1234_5678_9abc);
+/// assert_eq!(word.load(Ordering::Relaxed), 0x0003_1234_5678_9abc);
+/// ```
+///
+/// A range which doesn't fit in the integer is rejected:
+///
+/// ```compile_fail
+/// use atomic::Atomic;
+///
+/// let word = Atomic::new(0u32);
+/// word.field::<24, 16>();
+/// ```
+pub struct BitField<'a, T: Copy, const OFFSET: u32, const WIDTH: u32> {
+    atomic: &'a Atomic<T>,
+}
+
+impl<'a, T: Copy, const OFFSET: u32, const WIDTH: u32> Clone for BitField<'a, T, OFFSET, WIDTH> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T: Copy, const OFFSET: u32, const WIDTH: u32> Copy for BitField<'a, T, OFFSET, WIDTH> {}
+
+macro_rules! bitfield {
+    ($($t:ident)*) => ($(
+        impl<'a, const OFFSET: u32, const WIDTH: u32> BitField<'a, $t, OFFSET, WIDTH> {
+            const CHECK: () = assert!(
+                WIDTH > 0 && OFFSET < <$t>::BITS && WIDTH <= <$t>::BITS - OFFSET,
+                "bit field out of range"
+            );
+
+            // The bits of the field, shifted down to bit 0.
+            const MASK: $t = if WIDTH == <$t>::BITS { !0 } else { (1 << WIDTH) - 1 };
+
+            #[inline]
+            fn get(word: $t) -> $t {
+                (word >> OFFSET) & Self::MASK
+            }
+
+            #[inline]
+            fn set(word: $t, val: $t) -> $t {
+                assert!(
+                    val <= Self::MASK,
+                    "value {:#x} doesn't fit in a {}-bit field",
+                    val,
+                    WIDTH
+                );
+                (word & !(Self::MASK << OFFSET)) | (val << OFFSET)
+            }
+
+            /// Returns the `Atomic` the field is part of.
+            #[inline]
+            pub fn atomic(&self) -> &'a Atomic<$t> {
+                self.atomic
+            }
+
+            /// Loads the value of the field.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `order` is `Release` or `AcqRel`.
+            #[inline]
+            pub fn load_field(&self, order: Ordering) -> $t {
+                Self::get(self.atomic.load(order))
+            }
+
+            /// Stores a value into the field, leaving the other bits unchanged.
+            ///
+            /// `order` is the ordering of the update; the loads of the
+            /// compare-exchange loop use the strongest failure ordering
+            /// allowed for it.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `val` doesn't fit in `WIDTH` bits.
+            #[inline]
+            pub fn store_field(&self, val: $t, order: Ordering) {
+                let f = |word| Some(Self::set(word, val));
+                let fetch_order = ops::failure_ordering(order);
+                let _ = unsafe {
+                    ops::atomic_fetch_update(self.atomic.as_ptr(), order, fetch_order, f)
+                };
+            }
+
+            /// Fetches the value of the field, and applies a function to it
+            /// that returns an optional new value for the field.
+            ///
+            /// Returns `Ok` with the previous value of the field if `f`
+            /// returned `Some`, or `Err` with the value passed to `f` if it
+            /// returned `None`. Like `fetch_update`, `f` may be called several
+            /// times if the integer is changed by other threads in the
+            /// meantime, including in bits outside the field.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `f` returns a value which doesn't fit in `WIDTH`
+            /// bits, or if `fetch_order` is `Release` or `AcqRel`.
+            #[inline]
+            pub fn fetch_update_field<F: FnMut($t) -> Option<$t>>(
+                &self,
+                set_order: Ordering,
+                fetch_order: Ordering,
+                mut f: F,
+            ) -> Result<$t, $t> {
+                let f = |word| f(Self::get(word)).map(|val| Self::set(word, val));
+                let result = unsafe {
+                    ops::atomic_fetch_update(self.atomic.as_ptr(), set_order, fetch_order, f)
+                };
+                result.map(Self::get).map_err(Self::get)
+            }
+
+            /// Stores a value into the field if its current value is
+            /// `current`, leaving the other bits unchanged.
+            ///
+            /// Returns `Ok` with the previous value of the field if `new` was
+            /// stored, or `Err` with the value of the field if it wasn't
+            /// `current`. Changes to other bits don't make this fail; the
+            /// exchange is retried with them. The orderings are the same as
+            /// for `compare_exchange`.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `new` doesn't fit in `WIDTH` bits.
+            #[inline]
+            pub fn compare_exchange_field(
+                &self,
+                current: $t,
+                new: $t,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$t, $t> {
+                let f = |val| if val == current { Some(new) } else { None };
+                self.fetch_update_field(success, failure, f)
+            }
+        }
+
+        impl<'a, const OFFSET: u32, const WIDTH: u32> fmt::Debug for BitField<'a, $t, OFFSET, WIDTH> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_tuple("BitField")
+                    .field(&self.load_field(Ordering::SeqCst))
+                    .finish()
+            }
+        }
+
+        impl Atomic<$t> {
+            /// Returns a view of the `WIDTH` bits starting at bit `OFFSET`.
+            ///
+            /// Fails to compile if `WIDTH` is zero or the bits don't fit in
+            #[doc = concat!("a `", stringify!($t), "`.")]
+            /// See [`BitField`] for the operations on the field.
+            #[inline]
+            pub fn field<const OFFSET: u32, const WIDTH: u32>(
+                &self,
+            ) -> BitField<'_, $t, OFFSET, WIDTH> {
+                #[allow(clippy::let_unit_value)]
+                let () = BitField::<$t, OFFSET, WIDTH>::CHECK;
+                BitField { atomic: self }
+            }
+        }
+    )*);
+}
+bitfield! { u8 u16 u32 u64 usize u128 }
+
+#[cfg(all(test, not(any(loom, shuttle))))]
+mod tests {
+    use core::sync::atomic::Ordering::*;
+    use std::thread;
+    use Atomic;
+
+    #[test]
+    fn fields() {
+        let a = Atomic::new(0u32);
+        let lo = a.field::<0, 4>();
+        let mid = a.field::<4, 12>();
+        let hi = a.field::<16, 16>();
+        lo.store_field(0xf, Relaxed);
+        mid.store_field(0xabc, Relaxed);
+        hi.store_field(0x1234, Relaxed);
+        assert_eq!(a.load(Relaxed), 0x1234_abcf);
+        assert_eq!(mid.load_field(Relaxed), 0xabc);
+        assert_eq!(
+            mid.compare_exchange_field(0xabd, 0, AcqRel, Acquire),
+            Err(0xabc)
+        );
+        assert_eq!(
+            mid.compare_exchange_field(0xabc, 0, AcqRel, Acquire),
+            Ok(0xabc)
+        );
+        assert_eq!(
+            lo.fetch_update_field(AcqRel, Acquire, |x| Some(x - 1)),
+            Ok(0xf)
+        );
+        assert_eq!(lo.fetch_update_field(AcqRel, Acquire, |_| None), Err(0xe));
+        assert_eq!(a.load(Relaxed), 0x1234_000e);
+        assert_eq!(format!("{:?}", hi), "BitField(4660)");
+        assert!(core::ptr::eq(hi.atomic(), &a));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn full_width() {
+        let a = Atomic::new(5u64);
+        let all = a.field::<0, 64>();
+        assert_eq!(all.load_field(Relaxed), 5);
+        all.store_field(!0, Relaxed);
+        assert_eq!(a.load(Relaxed), !0);
+        let top = a.field::<63, 1>();
+        assert_eq!(top.compare_exchange_field(1, 0, AcqRel, Acquire), Ok(1));
+        assert_eq!(a.load(Relaxed), !0 >> 1);
+
+        let w = Atomic::new(0u128);
+        w.field::<64, 64>().store_field(3, Relaxed);
+        assert_eq!(w.load(Relaxed), 3 << 64);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't fit in a 4-bit field")]
+    fn value_too_wide() {
+        Atomic::new(0u8).field::<2, 4>().store_field(16, Relaxed);
+    }
+
+    #[test]
+    fn disjoint_fields_concurrently() {
+        const THREADS: u64 = 4;
+        const ITERS: u64 = 1000;
+        let word = Atomic::new(0u64);
+        let count = word.field::<0, 48>();
+        let state = word.field::<48, 8>();
+        let flags = word.field::<56, 8>();
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                s.spawn(move || {
+                    for _ in 0..ITERS {
+                        count
+                            .fetch_update_field(Relaxed, Relaxed, |x| Some(x + 1))
+                            .unwrap();
+                    }
+                });
+                s.spawn(move || {
+                    for i in 0..ITERS {
+                        // Each thread of this group owns one bit of `flags`
+                        // and adds to `state`, which wraps around.
+                        let bit = 1 << t;
+                        let f = |x: u64| Some(x ^ bit);
+                        flags.fetch_update_field(Relaxed, Relaxed, f).unwrap();
+                        let add = |x: u64| Some((x + i) & 0xff);
+                        state.fetch_update_field(Relaxed, Relaxed, add).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(count.load_field(Relaxed), THREADS * ITERS);
+        // Every flag was toggled an even number of times.
+        assert_eq!(flags.load_field(Relaxed), 0);
+        let sum: u64 = (0..ITERS).sum::<u64>() * THREADS;
+        assert_eq!(state.load_field(Relaxed), sum & 0xff);
+    }
+}
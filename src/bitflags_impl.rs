@@ -0,0 +1,95 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Operations on sets of `bitflags` flags.
+//
+// `Flags` is a safe trait, so nothing says that a type implementing it is laid
+// out as its `Bits`, even though the ones generated by `bitflags!` are. The
+// updates are therefore compare-exchange loops on the value itself, which are
+// lock-free whenever `Atomic<F>` is, and they go through the `Flags` methods,
+// so unknown bits are kept exactly like `insert`, `remove` and `toggle` keep
+// them.
+
+use bitflags::Flags;
+use core::sync::atomic::Ordering;
+use ops;
+use Atomic;
+
+impl<F: Flags + Copy> Atomic<F> {
+    #[inline]
+    fn fetch_flags<U: Fn(&mut F)>(&self, update: U, order: Ordering) -> F {
+        let f = |mut x: F| {
+            update(&mut x);
+            Some(x)
+        };
+        let fetch_order = ops::failure_ordering(order);
+        match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+            Ok(x) | Err(x) => x,
+        }
+    }
+
+    /// Sets the bits of `flags` in the current value, returning the previous
+    /// value.
+    ///
+    /// Like [`Flags::insert`], bits which aren't defined flags are kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate bitflags;
+    /// extern crate atomic;
+    /// use atomic::{Atomic, Ordering};
+    ///
+    /// bitflags! {
+    ///     #[derive(Clone, Copy, Debug, PartialEq)]
+    ///     struct State: u8 {
+    ///         const READY = 1;
+    ///         const CLOSED = 2;
+    ///     }
+    /// }
+    ///
+    /// # fn main() {
+    /// let state = Atomic::new(State::READY);
+    /// assert_eq!(state.fetch_insert(State::CLOSED, Ordering::AcqRel), State::READY);
+    /// assert_eq!(state.fetch_remove(State::READY, Ordering::AcqRel), State::all());
+    /// assert!(state.load_contains(State::CLOSED, Ordering::Acquire));
+    /// # }
+    /// ```
+    #[inline]
+    pub fn fetch_insert(&self, flags: F, order: Ordering) -> F {
+        self.fetch_flags(|x| x.insert(flags), order)
+    }
+
+    /// Clears the bits of `flags` in the current value, returning the previous
+    /// value.
+    ///
+    /// Like [`Flags::remove`], bits which aren't defined flags are kept.
+    #[inline]
+    pub fn fetch_remove(&self, flags: F, order: Ordering) -> F {
+        self.fetch_flags(|x| x.remove(flags), order)
+    }
+
+    /// Flips the bits of `flags` in the current value, returning the previous
+    /// value.
+    ///
+    /// Like [`Flags::toggle`], bits which aren't defined flags are kept.
+    #[inline]
+    pub fn fetch_toggle(&self, flags: F, order: Ordering) -> F {
+        self.fetch_flags(|x| x.toggle(flags), order)
+    }
+
+    /// Loads the current value and returns whether it contains all of `flags`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load_contains(&self, flags: F, order: Ordering) -> bool {
+        self.load(order).contains(flags)
+    }
+}
@@ -0,0 +1,277 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use core::sync::atomic::Ordering;
+use AtomicArray;
+
+const WORD_BITS: usize = usize::BITS as usize;
+
+/// A fixed-capacity set of bits which can be changed concurrently.
+///
+/// The bits are stored in `WORDS` words of `Atomic<usize>`, so the set holds
+/// `WORDS * usize::BITS` bits. (The capacity is a whole number of words since
+/// the number of words can't be computed from a number of bits in a const
+/// generic parameter.)
+///
+/// Each operation on a single bit is atomic. Operations which look at several
+/// words, such as `find_and_set_first_zero` and `count_ones`, go through them
+/// one after the other and don't see a consistent snapshot of the whole set.
+///
+/// # Examples
+///
+/// Allocating slots in a table shared by several threads:
+///
+/// ```
+/// use atomic::{AtomicBitSet, Ordering};
+/// use std::thread;
+///
+/// static SLOTS: AtomicBitSet<2> = AtomicBitSet::new();
+///
+/// thread::scope(|s| {
+///     for _ in 0..4 {
+///         s.spawn(|| {
+///             let slot = SLOTS.find_and_set_first_zero(Ordering::Acquire).unwrap();
+///             // ... use the slot ...
+///             assert!(SLOTS.clear(slot, Ordering::Release));
+///         });
+///     }
+/// });
+/// assert_eq!(SLOTS.count_ones(Ordering::Relaxed), 0);
+/// ```
+pub struct AtomicBitSet<const WORDS: usize> {
+    words: AtomicArray<usize, WORDS>,
+}
+
+impl<const WORDS: usize> AtomicBitSet<WORDS> {
+    /// Creates a new `AtomicBitSet` with all bits cleared.
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub const fn new() -> AtomicBitSet<WORDS> {
+        AtomicBitSet {
+            words: AtomicArray::<usize, WORDS>::zeroed(),
+        }
+    }
+
+    /// Creates a new `AtomicBitSet` with all bits cleared.
+    ///
+    /// Under loom or shuttle this isn't a `const fn`, like `Atomic::new`.
+    #[inline]
+    #[cfg(any(loom, shuttle))]
+    pub fn new() -> AtomicBitSet<WORDS> {
+        AtomicBitSet {
+            words: AtomicArray::<usize, WORDS>::zeroed(),
+        }
+    }
+
+    /// Returns the number of bits in the set.
+    #[inline]
+    pub const fn capacity(&self) -> usize {
+        WORDS * WORD_BITS
+    }
+
+    /// Returns the words the bits are stored in.
+    ///
+    /// Bit `i` is bit `i % usize::BITS` of word `i / usize::BITS`.
+    #[inline]
+    pub fn as_words(&self) -> &AtomicArray<usize, WORDS> {
+        &self.words
+    }
+
+    #[inline]
+    fn locate(&self, bit: usize) -> (usize, usize) {
+        assert!(
+            bit < self.capacity(),
+            "bit {} out of range for AtomicBitSet of {} bits",
+            bit,
+            self.capacity()
+        );
+        (bit / WORD_BITS, 1 << (bit % WORD_BITS))
+    }
+
+    /// Sets a bit, returning whether it was already set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bit` is out of range.
+    #[inline]
+    pub fn set(&self, bit: usize, order: Ordering) -> bool {
+        let (word, mask) = self.locate(bit);
+        self.words.as_slice()[word].fetch_or(mask, order) & mask != 0
+    }
+
+    /// Clears a bit, returning whether it was set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bit` is out of range.
+    #[inline]
+    pub fn clear(&self, bit: usize, order: Ordering) -> bool {
+        let (word, mask) = self.locate(bit);
+        self.words.as_slice()[word].fetch_and(!mask, order) & mask != 0
+    }
+
+    /// Returns whether a bit is set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bit` is out of range, or if `order` is `Release` or
+    /// `AcqRel`.
+    #[inline]
+    pub fn test(&self, bit: usize, order: Ordering) -> bool {
+        let (word, mask) = self.locate(bit);
+        self.words.load(word, order) & mask != 0
+    }
+
+    /// Sets the lowest bit which is clear, and returns its index.
+    ///
+    /// Returns `None` if every bit was set. Since the words are searched one
+    /// after the other, this can return `None` while another thread clears a
+    /// bit in a word which was already searched.
+    ///
+    /// `order` is the ordering of the successful update; the bits which are
+    /// looked at while searching are loaded with `Relaxed`.
+    pub fn find_and_set_first_zero(&self, order: Ordering) -> Option<usize> {
+        for (i, word) in self.words.as_slice().iter().enumerate() {
+            let mut current = word.load(Ordering::Relaxed);
+            // Full words are skipped without writing to them.
+            while current != !0 {
+                let bit = (!current).trailing_zeros() as usize;
+                let new = current | 1 << bit;
+                match word.compare_exchange_weak(current, new, order, Ordering::Relaxed) {
+                    Ok(_) => return Some(i * WORD_BITS + bit),
+                    Err(x) => current = x,
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the number of bits which are set.
+    ///
+    /// The words are loaded one after the other, so this isn't a consistent
+    /// count if other threads are changing the set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    pub fn count_ones(&self, order: Ordering) -> usize {
+        self.words
+            .as_slice()
+            .iter()
+            .map(|w| w.load(order).count_ones() as usize)
+            .sum()
+    }
+}
+
+impl<const WORDS: usize> Default for AtomicBitSet<WORDS> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const WORDS: usize> fmt::Debug for AtomicBitSet<WORDS> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let set = (0..self.capacity()).filter(|&i| self.test(i, Ordering::SeqCst));
+        f.debug_set().entries(set).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AtomicBitSet, WORD_BITS};
+    use core::sync::atomic::Ordering::*;
+    use std::thread;
+    use std::vec::Vec;
+
+    #[test]
+    fn set_clear_test() {
+        let s = AtomicBitSet::<2>::new();
+        assert_eq!(s.capacity(), 2 * WORD_BITS);
+        assert!(!s.set(3, AcqRel));
+        assert!(s.set(3, AcqRel));
+        assert!(!s.set(WORD_BITS + 1, AcqRel));
+        assert!(s.test(3, Acquire));
+        assert!(!s.test(4, Acquire));
+        assert_eq!(s.count_ones(Relaxed), 2);
+        assert_eq!(format!("{:?}", s), format!("{{3, {}}}", WORD_BITS + 1));
+        assert!(s.clear(3, AcqRel));
+        assert!(!s.clear(3, AcqRel));
+        assert_eq!(s.as_words().load(1, Relaxed), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn out_of_range() {
+        AtomicBitSet::<1>::new().set(WORD_BITS, Relaxed);
+    }
+
+    #[test]
+    fn find_skips_full_words() {
+        let s = AtomicBitSet::<3>::new();
+        s.as_words().store(0, !0, Relaxed);
+        s.as_words().store(1, !0 ^ 1 << 5, Relaxed);
+        assert_eq!(s.find_and_set_first_zero(AcqRel), Some(WORD_BITS + 5));
+        assert_eq!(s.find_and_set_first_zero(AcqRel), Some(2 * WORD_BITS));
+        s.as_words().fill(!0, Relaxed);
+        assert_eq!(s.find_and_set_first_zero(AcqRel), None);
+        assert_eq!(
+            AtomicBitSet::<0>::new().find_and_set_first_zero(AcqRel),
+            None
+        );
+    }
+
+    #[test]
+    fn concurrent_allocation_is_distinct() {
+        const THREADS: usize = 4;
+        let s = AtomicBitSet::<2>::new();
+        let per_thread = s.capacity() / THREADS;
+        let mut slots: Vec<usize> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..per_thread)
+                            .map(|_| s.find_and_set_first_zero(Acquire).unwrap())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        slots.sort_unstable();
+        assert_eq!(slots, (0..s.capacity()).collect::<Vec<_>>());
+        assert_eq!(s.find_and_set_first_zero(Acquire), None);
+    }
+
+    #[test]
+    fn release_and_reacquire() {
+        const THREADS: usize = 4;
+        const CYCLES: usize = 1000;
+        // Fewer slots than threads can hold at once, so they get reused.
+        let s = AtomicBitSet::<1>::new();
+        let owners = [0u32; WORD_BITS].map(::Atomic::new);
+        thread::scope(|scope| {
+            for t in 0..THREADS as u32 {
+                let (s, owners) = (&s, &owners);
+                scope.spawn(move || {
+                    for _ in 0..CYCLES {
+                        let slot = s.find_and_set_first_zero(Acquire).unwrap();
+                        // Nobody else may own the slot until it's released.
+                        assert_eq!(owners[slot].swap(t + 1, Relaxed), 0);
+                        assert_eq!(owners[slot].swap(0, Relaxed), t + 1);
+                        assert!(s.clear(slot, Release));
+                    }
+                });
+            }
+        });
+        assert_eq!(s.count_ones(Relaxed), 0);
+    }
+}
@@ -0,0 +1,27 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// `bytemuck::Zeroable` implementations, so that tables of atomics can be
+// allocated zeroed with `bytemuck::zeroed_box` and friends.
+//
+// An all-zero `Atomic<T>` is an all-zero `T`, since `Atomic<T>` has the layout
+// of `T`. The other bytemuck traits aren't implemented on purpose:
+// `TransparentWrapper<T>` would allow safe code to turn a `&T` into a
+// `&Atomic<T>` and write through it, or to read a `&T` peeled off an `Atomic`
+// which another thread is writing to, and `Pod` is ruled out by the interior
+// mutability. `Atomic::from_mut_slice`, `Atomic::get_mut_slice` and
+// `Atomic::from_ptr` are the sound ways to reinterpret memory instead.
+
+use bytemuck::Zeroable;
+use {Atomic, AtomicArray, CachePadded};
+
+unsafe impl<T: Copy + Zeroable> Zeroable for Atomic<T> {}
+
+unsafe impl<T: Copy + Zeroable, const N: usize> Zeroable for AtomicArray<T, N> {}
+
+// The padding only changes the alignment.
+unsafe impl<T: Zeroable> Zeroable for CachePadded<T> {}
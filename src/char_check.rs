@@ -0,0 +1,208 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Checks of the `char`s returned by loads, swaps, compare-exchanges and
+// updates, for debug builds without the `validate` feature, which checks them
+// along with the other types it knows.
+//
+// An invalid scalar value written through `as_ptr` or shared memory would
+// otherwise go unnoticed until it causes undefined behavior. Like in
+// `validate`, the operations are performed on the memory as a
+// `MaybeUninit<T>`, and the bits are checked before they become a `T`.
+//
+// There is no way to specialize the operations for `char` on stable, so the
+// type is recognized by its `TypeId`. `TypeId::of` needs a `'static` type,
+// which `T` isn't known to be, so it is called through a trait object whose
+// lifetime is erased: the id of a type doesn't depend on its lifetimes.
+
+use core::any::TypeId;
+use core::marker::PhantomData;
+use core::mem::{self, MaybeUninit};
+use core::ptr;
+use ops;
+use Ordering;
+
+trait NonStaticAny {
+    fn type_id(&self) -> TypeId
+    where
+        Self: 'static;
+}
+
+impl<T: ?Sized> NonStaticAny for PhantomData<T> {
+    fn type_id(&self) -> TypeId
+    where
+        Self: 'static,
+    {
+        TypeId::of::<T>()
+    }
+}
+
+#[inline]
+fn is_char<T>() -> bool {
+    let marker = PhantomData::<T>;
+    let marker: &dyn NonStaticAny = &marker;
+    let marker = unsafe {
+        mem::transmute::<&dyn NonStaticAny, &(dyn NonStaticAny + 'static)>(marker)
+    };
+    marker.type_id() == TypeId::of::<char>()
+}
+
+#[inline]
+#[track_caller]
+fn check<T>(val: MaybeUninit<T>) -> T {
+    if is_char::<T>() {
+        let bits = unsafe { ptr::read(val.as_ptr() as *const u32) };
+        assert!(
+            char::from_u32(bits).is_some(),
+            "Atomic<char> contains an invalid char: {:#x}",
+            bits
+        );
+    }
+    unsafe { val.assume_init() }
+}
+
+#[inline]
+fn uninit<T>(dst: *mut T) -> *mut MaybeUninit<T> {
+    dst as *mut MaybeUninit<T>
+}
+
+// The operations of `ops` which return values, with the values checked.
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_load<T>(dst: *mut T, order: Ordering) -> T {
+    check(ops::atomic_load(uninit(dst), order))
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_load_consume<T>(dst: *mut T) -> T {
+    check(ops::atomic_load_consume(uninit(dst)))
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_try_load<T>(dst: *mut T, order: Ordering) -> Option<T> {
+    ops::atomic_try_load(uninit(dst), order).map(check)
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_swap<T>(dst: *mut T, val: T, order: Ordering) -> T {
+    check(ops::atomic_swap(uninit(dst), MaybeUninit::new(val), order))
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_try_swap<T>(dst: *mut T, val: T, order: Ordering) -> Result<T, T> {
+    match ops::atomic_try_swap(uninit(dst), MaybeUninit::new(val), order) {
+        Ok(prev) => Ok(check(prev)),
+        // The value which couldn't be stored is the caller's own.
+        Err(val) => Err(val.assume_init()),
+    }
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_compare_exchange<T>(
+    dst: *mut T,
+    current: T,
+    new: T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    match ops::atomic_compare_exchange(
+        uninit(dst),
+        MaybeUninit::new(current),
+        MaybeUninit::new(new),
+        success,
+        failure,
+    ) {
+        Ok(prev) => Ok(check(prev)),
+        Err(prev) => Err(check(prev)),
+    }
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_compare_exchange_weak<T>(
+    dst: *mut T,
+    current: T,
+    new: T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    match ops::atomic_compare_exchange_weak(
+        uninit(dst),
+        MaybeUninit::new(current),
+        MaybeUninit::new(new),
+        success,
+        failure,
+    ) {
+        Ok(prev) => Ok(check(prev)),
+        Err(prev) => Err(check(prev)),
+    }
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_compare_exchange_ref<T: Copy>(
+    dst: *mut T,
+    current: &T,
+    new: &T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    match ops::atomic_compare_exchange_ref(
+        uninit(dst),
+        &MaybeUninit::new(*current),
+        &MaybeUninit::new(*new),
+        success,
+        failure,
+    ) {
+        Ok(prev) => Ok(check(prev)),
+        Err(prev) => Err(check(prev)),
+    }
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_compare_exchange_in_place<T: Copy>(
+    dst: *mut T,
+    current: &mut T,
+    new: &T,
+    success: Ordering,
+    failure: Ordering,
+) -> bool {
+    let mut prev = MaybeUninit::new(*current);
+    let stored = ops::atomic_compare_exchange_in_place(
+        uninit(dst),
+        &mut prev,
+        &MaybeUninit::new(*new),
+        success,
+        failure,
+    );
+    *current = check(prev);
+    stored
+}
+
+// Every value which is returned has been passed to `f` first, so it has
+// already been checked.
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_fetch_update<T: Copy, F: FnMut(T) -> Option<T>>(
+    dst: *mut T,
+    set_order: Ordering,
+    fetch_order: Ordering,
+    mut f: F,
+) -> Result<T, T> {
+    let f = |prev| f(check(prev)).map(MaybeUninit::new);
+    match ops::atomic_fetch_update(uninit(dst), set_order, fetch_order, f) {
+        Ok(prev) => Ok(prev.assume_init()),
+        Err(prev) => Err(prev.assume_init()),
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// 16-byte atomics on x86_64 through `cmpxchg16b`, for the builds whose native
+// types have no 128-bit atomics.
+//
+// The instruction isn't part of the baseline x86_64, so unless the target
+// feature is enabled at compile time, it is detected with `cpuid` the first
+// time a 16-byte value is accessed, and the answer is cached. The ops module
+// checks `detected()` in the dispatch arm for 16-byte values, which costs a
+// load and a predictable branch, and takes the fallback lock on processors
+// without it.
+//
+// The types have the same interface as the native types of the ops module.
+// `cmpxchg16b` is the only 16-byte atomic instruction: loads are a
+// compare-exchange which writes back the value it found, and everything else
+// is a compare-exchange loop. It is a full barrier, so it satisfies every
+// ordering, but the orderings are still checked like the standard atomic
+// types do.
+//
+// `rbx` is reserved by LLVM, so the low half of the new value is swapped into
+// it around the instruction.
+
+use core::arch::asm;
+use core::arch::x86_64 as arch;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const UNKNOWN: u8 = 0;
+const ABSENT: u8 = 1;
+const PRESENT: u8 = 2;
+
+static STATE: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+// Tests force either path on the current thread, so that the values they use
+// are never accessed both ways.
+#[cfg(test)]
+thread_local! {
+    pub static FORCE: ::core::cell::Cell<Option<bool>> = const { ::core::cell::Cell::new(None) };
+}
+
+/// Whether the processor has `cmpxchg16b`.
+#[inline]
+pub fn detected() -> bool {
+    if cfg!(target_feature = "cmpxchg16b") {
+        return true;
+    }
+    #[cfg(test)]
+    {
+        if let Some(force) = FORCE.with(|f| f.get()) {
+            return force;
+        }
+    }
+    // The answer never changes, so a relaxed load of it is enough.
+    let state = STATE.load(Ordering::Relaxed);
+    if state == PRESENT {
+        return true;
+    }
+    state == UNKNOWN && detect()
+}
+
+#[cold]
+fn detect() -> bool {
+    // Leaf 1 reports `cmpxchg16b` in bit 13 of `ecx`.
+    #[allow(unused_unsafe)]
+    let present = unsafe { arch::__cpuid(1) }.ecx & (1 << 13) != 0;
+    STATE.store(if present { PRESENT } else { ABSENT }, Ordering::Relaxed);
+    present
+}
+
+// Replaces the value at `dst` with `new` if it is `current`, and returns the
+// value it found.
+#[inline]
+unsafe fn cmpxchg16b(dst: *mut u128, current: u128, new: u128) -> u128 {
+    let (lo, hi): (u64, u64);
+    asm!(
+        "xchg {rbx}, rbx",
+        "lock cmpxchg16b xmmword ptr [{dst}]",
+        "mov rbx, {rbx}",
+        dst = in(reg) dst,
+        rbx = inout(reg) new as u64 => _,
+        in("rcx") (new >> 64) as u64,
+        inout("rax") current as u64 => lo,
+        inout("rdx") (current >> 64) as u64 => hi,
+        options(nostack),
+    );
+    u128::from(lo) | (u128::from(hi) << 64)
+}
+
+#[inline]
+fn check_load_ordering(order: Ordering, what: &str) {
+    match order {
+        Ordering::Release => panic!("there is no such thing as a release {}", what),
+        Ordering::AcqRel => panic!("there is no such thing as an acquire-release {}", what),
+        _ => {}
+    }
+}
+
+macro_rules! atomic_int {
+    ($($name:ident $t:ident)*) => ($(
+        #[repr(transparent)]
+        pub struct $name(UnsafeCell<$t>);
+
+        // The ops module only uses `AtomicI128` for its minimum and maximum.
+        #[allow(dead_code)]
+        impl $name {
+            #[inline]
+            pub unsafe fn from_ptr<'a>(p: *mut $t) -> &'a $name {
+                &*(p as *const $name)
+            }
+
+            #[inline]
+            fn compare_exchange_raw(&self, current: $t, new: $t) -> $t {
+                let p = self.0.get() as *mut u128;
+                unsafe { cmpxchg16b(p, current as u128, new as u128) as $t }
+            }
+
+            // Starts from a guess of zero, since a failed compare-exchange
+            // returns the current value anyway.
+            #[inline]
+            fn update<F: Fn($t) -> $t>(&self, f: F) -> $t {
+                let mut prev = 0;
+                loop {
+                    let found = self.compare_exchange_raw(prev, f(prev));
+                    if found == prev {
+                        return prev;
+                    }
+                    prev = found;
+                }
+            }
+
+            #[inline]
+            pub fn load(&self, order: Ordering) -> $t {
+                check_load_ordering(order, "load");
+                self.compare_exchange_raw(0, 0)
+            }
+
+            #[inline]
+            pub fn store(&self, val: $t, order: Ordering) {
+                match order {
+                    Ordering::Acquire => panic!("there is no such thing as an acquire store"),
+                    Ordering::AcqRel => panic!("there is no such thing as an acquire-release store"),
+                    _ => {}
+                }
+                self.update(|_| val);
+            }
+
+            #[inline]
+            pub fn swap(&self, val: $t, _: Ordering) -> $t {
+                self.update(|_| val)
+            }
+
+            #[inline]
+            pub fn compare_exchange(
+                &self,
+                current: $t,
+                new: $t,
+                _: Ordering,
+                failure: Ordering,
+            ) -> Result<$t, $t> {
+                check_load_ordering(failure, "failure ordering");
+                let found = self.compare_exchange_raw(current, new);
+                if found == current {
+                    Ok(found)
+                } else {
+                    Err(found)
+                }
+            }
+
+            // `cmpxchg16b` never fails spuriously.
+            #[inline]
+            pub fn compare_exchange_weak(
+                &self,
+                current: $t,
+                new: $t,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$t, $t> {
+                self.compare_exchange(current, new, success, failure)
+            }
+
+            #[inline]
+            pub fn fetch_add(&self, val: $t, _: Ordering) -> $t {
+                self.update(|x| x.wrapping_add(val))
+            }
+
+            #[inline]
+            pub fn fetch_sub(&self, val: $t, _: Ordering) -> $t {
+                self.update(|x| x.wrapping_sub(val))
+            }
+
+            #[inline]
+            pub fn fetch_and(&self, val: $t, _: Ordering) -> $t {
+                self.update(|x| x & val)
+            }
+
+            #[inline]
+            pub fn fetch_or(&self, val: $t, _: Ordering) -> $t {
+                self.update(|x| x | val)
+            }
+
+            #[inline]
+            pub fn fetch_xor(&self, val: $t, _: Ordering) -> $t {
+                self.update(|x| x ^ val)
+            }
+
+            #[inline]
+            pub fn fetch_min(&self, val: $t, _: Ordering) -> $t {
+                self.update(|x| x.min(val))
+            }
+
+            #[inline]
+            pub fn fetch_max(&self, val: $t, _: Ordering) -> $t {
+                self.update(|x| x.max(val))
+            }
+        }
+    )*);
+}
+
+atomic_int! {
+    AtomicU128 u128
+    AtomicI128 i128
+}
+
+#[cfg(all(test, not(feature = "no-fallback")))]
+mod tests {
+    use super::{detected, AtomicI128, AtomicU128, FORCE};
+    use core::sync::atomic::Ordering::*;
+    use std::thread;
+    use std::vec::Vec;
+    use Atomic;
+
+    #[repr(align(16))]
+    struct Cell(u128);
+
+    // The same operations on `Atomic<u128>` and `Atomic<i128>` give the same
+    // results whether they use `cmpxchg16b` or the fallback lock.
+    fn run(force: Option<bool>) -> Vec<u128> {
+        FORCE.with(|f| f.set(force));
+        let lock_free = Atomic::<u128>::is_lock_free_runtime();
+        let a = Atomic::new(u128::MAX - 1);
+        let mut results = vec![
+            a.load(SeqCst),
+            a.fetch_add(3, AcqRel),
+            a.fetch_sub(1 << 64, Release),
+            a.swap(7, SeqCst),
+            a.fetch_or(1 << 100, Relaxed),
+            a.fetch_and(!1, Acquire),
+            a.fetch_xor(0xff, SeqCst),
+            a.fetch_max(u128::MAX / 2, SeqCst),
+            a.fetch_min(5, SeqCst),
+            a.compare_exchange(6, 8, SeqCst, SeqCst)
+                .unwrap_or_else(|x| x),
+            a.compare_exchange(5, 8, SeqCst, SeqCst)
+                .unwrap_or_else(|x| x),
+            a.compare_exchange_weak(8, 0, SeqCst, Relaxed)
+                .unwrap_or_else(|x| x),
+            a.load(Acquire),
+        ];
+        a.store(1 << 127, Release);
+        results.push(a.load(Relaxed));
+
+        let b = Atomic::new(-1i128);
+        results.push(b.fetch_min(i128::MIN, SeqCst) as u128);
+        results.push(b.fetch_max(3, SeqCst) as u128);
+        results.push(b.load(SeqCst) as u128);
+        results.push(lock_free as u128);
+        FORCE.with(|f| f.set(None));
+        results
+    }
+
+    #[test]
+    fn both_paths_agree() {
+        let fallback = run(Some(false));
+        // The target feature skips the detection, so nothing can be forced.
+        let forced = !cfg!(target_feature = "cmpxchg16b");
+        assert_eq!(fallback.last(), Some(&(!forced as u128)));
+        let default = run(None);
+        assert_eq!(default.last(), Some(&(detected() as u128)));
+        assert_eq!(default[..default.len() - 1], fallback[..fallback.len() - 1]);
+        if detected() {
+            let native = run(Some(true));
+            assert_eq!(native, default);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "there is no such thing as a release load")]
+    fn release_load() {
+        let mut x = Cell(0);
+        unsafe { AtomicU128::from_ptr(&mut x.0).load(Release) };
+    }
+
+    #[test]
+    fn contention() {
+        if !detected() {
+            return;
+        }
+        let mut x = Cell(0);
+        let p = &mut x.0 as *mut u128 as usize;
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(move || {
+                    let a = unsafe { AtomicU128::from_ptr(p as *mut u128) };
+                    for _ in 0..1000 {
+                        a.fetch_add((1 << 64) | 1, Relaxed);
+                    }
+                });
+            }
+        });
+        assert_eq!(x.0, (4000 << 64) | 4000);
+
+        let mut y = Cell(0);
+        let b = unsafe { AtomicI128::from_ptr(&mut y.0 as *mut u128 as *mut i128) };
+        assert_eq!(b.fetch_sub(1, SeqCst), 0);
+        assert_eq!(b.fetch_min(-5, SeqCst), -1);
+        assert_eq!(b.fetch_max(i128::MAX, SeqCst), -5);
+        assert_eq!(b.load(SeqCst), i128::MAX);
+    }
+}
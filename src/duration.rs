@@ -0,0 +1,257 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use core::time::Duration;
+use ops;
+use Atomic;
+use Ordering;
+
+/// A `Duration` which can be safely shared between threads, for example to
+/// accumulate the time spent by several workers.
+///
+/// The duration is stored as a number of nanoseconds in an `Atomic<u64>`, so
+/// it is lock-free wherever 64-bit atomics are. That covers about 584 years:
+/// durations longer than `u64::MAX` nanoseconds are clamped to it when they
+/// are stored, and `fetch_add` saturates at it rather than wrapping around.
+/// Nanoseconds are the smallest unit of a `Duration`, so nothing else is lost.
+///
+/// # Examples
+///
+/// ```
+/// use atomic::{AtomicDuration, Ordering};
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let busy = AtomicDuration::new(Duration::ZERO);
+/// thread::scope(|s| {
+///     for i in 1..=4 {
+///         let busy = &busy;
+///         s.spawn(move || busy.fetch_add(Duration::from_millis(i), Ordering::Relaxed));
+///     }
+/// });
+/// assert_eq!(busy.load(Ordering::Relaxed), Duration::from_millis(10));
+/// ```
+pub struct AtomicDuration {
+    nanos: Atomic<u64>,
+}
+
+#[inline]
+const fn to_nanos(d: Duration) -> u64 {
+    let nanos = d.as_nanos();
+    if nanos > u64::MAX as u128 {
+        u64::MAX
+    } else {
+        nanos as u64
+    }
+}
+
+impl Default for AtomicDuration {
+    #[inline]
+    fn default() -> Self {
+        Self::new(Duration::ZERO)
+    }
+}
+
+impl fmt::Debug for AtomicDuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("AtomicDuration")
+            .field(&self.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+impl AtomicDuration {
+    /// Creates a new `AtomicDuration`.
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub const fn new(d: Duration) -> AtomicDuration {
+        AtomicDuration {
+            nanos: Atomic::new(to_nanos(d)),
+        }
+    }
+
+    /// Creates a new `AtomicDuration`.
+    ///
+    /// Under loom or shuttle this isn't a `const fn`, like `Atomic::new`.
+    #[inline]
+    #[cfg(any(loom, shuttle))]
+    pub fn new(d: Duration) -> AtomicDuration {
+        AtomicDuration {
+            nanos: Atomic::new(to_nanos(d)),
+        }
+    }
+
+    /// Checks if the operations on this type are lock-free.
+    #[inline]
+    pub fn is_lock_free() -> bool {
+        Atomic::<u64>::is_lock_free()
+    }
+
+    /// Loads the duration.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> Duration {
+        Duration::from_nanos(self.nanos.load(order))
+    }
+
+    /// Stores a duration, clamped to `u64::MAX` nanoseconds.
+    #[inline]
+    pub fn store(&self, d: Duration, order: Ordering) {
+        self.nanos.store(to_nanos(d), order)
+    }
+
+    /// Stores a duration, clamped to `u64::MAX` nanoseconds, and returns the
+    /// previous one.
+    #[inline]
+    pub fn swap(&self, d: Duration, order: Ordering) -> Duration {
+        Duration::from_nanos(self.nanos.swap(to_nanos(d), order))
+    }
+
+    /// Adds to the current duration, saturating at `u64::MAX` nanoseconds, and
+    /// returns the previous duration.
+    ///
+    /// This is a compare-exchange loop, since the native `fetch_add` would
+    /// wrap around. The loads use the strongest failure ordering allowed for
+    /// `order`.
+    #[inline]
+    pub fn fetch_add(&self, d: Duration, order: Ordering) -> Duration {
+        let d = to_nanos(d);
+        let f = |nanos: u64| Some(nanos.saturating_add(d));
+        match self
+            .nanos
+            .fetch_update(order, ops::failure_ordering(order), f)
+        {
+            Ok(nanos) | Err(nanos) => Duration::from_nanos(nanos),
+        }
+    }
+
+    /// Stores the maximum of the current duration and `d`, and returns the
+    /// previous duration.
+    #[inline]
+    pub fn fetch_max(&self, d: Duration, order: Ordering) -> Duration {
+        Duration::from_nanos(self.nanos.fetch_max(to_nanos(d), order))
+    }
+
+    /// Fetches the duration, and applies a function to it that returns an
+    /// optional new duration.
+    ///
+    /// Returns `Ok(previous)` if the function returned `Some`, and
+    /// `Err(previous)` if it returned `None`. New durations are clamped to
+    /// `u64::MAX` nanoseconds. The orderings are the same as for
+    /// `Atomic::fetch_update`.
+    #[inline]
+    pub fn fetch_update<F: FnMut(Duration) -> Option<Duration>>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<Duration, Duration> {
+        let f = |nanos| f(Duration::from_nanos(nanos)).map(to_nanos);
+        self.nanos
+            .fetch_update(set_order, fetch_order, f)
+            .map(Duration::from_nanos)
+            .map_err(Duration::from_nanos)
+    }
+
+    /// Consumes the atomic and returns the contained duration.
+    #[inline]
+    pub fn into_inner(self) -> Duration {
+        Duration::from_nanos(self.nanos.into_inner())
+    }
+}
+
+#[cfg(all(test, not(any(loom, shuttle))))]
+mod tests {
+    use super::AtomicDuration;
+    use core::time::Duration;
+    use std::thread;
+    use std::vec::Vec;
+    use Ordering::*;
+
+    const MAX: Duration = Duration::from_nanos(u64::MAX);
+
+    #[test]
+    fn duration() {
+        let a = AtomicDuration::default();
+        assert_eq!(format!("{:?}", a), "AtomicDuration(0ns)");
+        a.store(Duration::from_millis(1500), Relaxed);
+        assert_eq!(a.load(Relaxed), Duration::from_millis(1500));
+        assert_eq!(
+            a.swap(Duration::from_secs(1), Relaxed),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(
+            a.fetch_max(Duration::from_millis(999), Relaxed),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            a.fetch_max(Duration::from_secs(2), Relaxed),
+            Duration::from_secs(1)
+        );
+        let halve = |d: Duration| Some(d / 2);
+        assert_eq!(
+            a.fetch_update(AcqRel, Acquire, halve),
+            Ok(Duration::from_secs(2))
+        );
+        assert_eq!(
+            a.fetch_update(AcqRel, Acquire, |_| None),
+            Err(Duration::from_secs(1))
+        );
+        assert_eq!(a.into_inner(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn saturation() {
+        let a = AtomicDuration::new(MAX - Duration::from_nanos(10));
+        assert_eq!(
+            a.fetch_add(Duration::from_nanos(4), SeqCst),
+            MAX - Duration::from_nanos(10)
+        );
+        assert_eq!(
+            a.fetch_add(Duration::from_nanos(7), SeqCst),
+            MAX - Duration::from_nanos(6)
+        );
+        assert_eq!(a.load(SeqCst), MAX);
+        assert_eq!(a.fetch_add(Duration::from_secs(1), SeqCst), MAX);
+        assert_eq!(a.load(SeqCst), MAX);
+
+        // Durations which don't fit are clamped.
+        let a = AtomicDuration::new(Duration::MAX);
+        assert_eq!(a.load(SeqCst), MAX);
+        a.store(Duration::ZERO, SeqCst);
+        assert_eq!(a.fetch_add(Duration::MAX, SeqCst), Duration::ZERO);
+        assert_eq!(a.swap(MAX + Duration::from_nanos(1), SeqCst), MAX);
+        assert_eq!(a.fetch_max(Duration::MAX, SeqCst), MAX);
+        let double = |d: Duration| d.checked_mul(2);
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, double), Ok(MAX));
+        assert_eq!(a.load(SeqCst), MAX);
+    }
+
+    #[test]
+    fn accumulate() {
+        let rounds = if cfg!(miri) { 10 } else { 1000 };
+        let durations: Vec<Duration> = (0..4u64)
+            .map(|t| Duration::new(t, t as u32 * 1_000_003 + 17))
+            .collect();
+        let total = AtomicDuration::new(Duration::ZERO);
+        let longest = AtomicDuration::new(Duration::ZERO);
+        thread::scope(|s| {
+            for &d in &durations {
+                let (total, longest) = (&total, &longest);
+                s.spawn(move || {
+                    for i in 0..rounds {
+                        total.fetch_add(d, Relaxed);
+                        longest.fetch_max(d * i, Relaxed);
+                    }
+                });
+            }
+        });
+        let sum: Duration = durations.iter().sum::<Duration>() * rounds;
+        assert_eq!(total.load(Relaxed), sum);
+        assert_eq!(longest.load(Relaxed), durations[3] * (rounds - 1));
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::error;
+use core::fmt;
+
+/// The kind of failure reported by a fallible operation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The operation could not complete without blocking or retrying.
+    WouldBlock,
+    /// The operation did not complete before its deadline.
+    TimedOut,
+    /// The value observed by the operation was out of date.
+    Stale,
+    /// The memory ordering is not valid for the operation.
+    InvalidOrdering,
+    /// A fixed-capacity resource is full.
+    CapacityExhausted,
+    /// A lock was poisoned by a panic.
+    Poisoned,
+    /// The value does not fit in the available representation.
+    ValueTooLarge,
+    /// The value has no encoding in the available representation.
+    Unrepresentable,
+}
+
+impl ErrorKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::WouldBlock => "operation would block",
+            ErrorKind::TimedOut => "operation timed out",
+            ErrorKind::Stale => "value is stale",
+            ErrorKind::InvalidOrdering => "invalid memory ordering for this operation",
+            ErrorKind::CapacityExhausted => "capacity exhausted",
+            ErrorKind::Poisoned => "lock poisoned",
+            ErrorKind::ValueTooLarge => "value too large",
+            ErrorKind::Unrepresentable => "value cannot be represented",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error type for fallible operations in this crate.
+///
+/// This never allocates. Operations which need to hand a value back to the
+/// caller on failure (for example a value which could not be stored) carry it
+/// in the payload `P`; all other operations use `Error<()>`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Error<P = ()> {
+    kind: ErrorKind,
+    payload: P,
+}
+
+impl Error {
+    /// Creates an error without a payload.
+    #[inline]
+    pub const fn new(kind: ErrorKind) -> Error {
+        Error { kind, payload: () }
+    }
+}
+
+impl<P> Error<P> {
+    /// Creates an error carrying a payload.
+    #[inline]
+    pub const fn with_payload(kind: ErrorKind, payload: P) -> Error<P> {
+        Error { kind, payload }
+    }
+
+    /// Returns the kind of this error.
+    #[inline]
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns a reference to the payload of this error.
+    #[inline]
+    pub fn payload(&self) -> &P {
+        &self.payload
+    }
+
+    /// Consumes the error and returns its payload.
+    #[inline]
+    pub fn into_payload(self) -> P {
+        self.payload
+    }
+
+    /// Discards the payload of this error.
+    #[inline]
+    pub fn without_payload(self) -> Error {
+        Error::new(self.kind)
+    }
+}
+
+impl From<ErrorKind> for Error {
+    #[inline]
+    fn from(kind: ErrorKind) -> Error {
+        Error::new(kind)
+    }
+}
+
+impl<P> From<Error<P>> for ErrorKind {
+    #[inline]
+    fn from(err: Error<P>) -> ErrorKind {
+        err.kind
+    }
+}
+
+impl<P> fmt::Display for Error<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.kind, f)
+    }
+}
+
+impl<P: fmt::Debug> error::Error for Error<P> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, ErrorKind};
+    use std::boxed::Box;
+    use std::error::Error as StdError;
+    use std::string::ToString;
+
+    #[test]
+    fn display() {
+        let cases = [
+            (ErrorKind::WouldBlock, "operation would block"),
+            (ErrorKind::TimedOut, "operation timed out"),
+            (ErrorKind::Stale, "value is stale"),
+            (
+                ErrorKind::InvalidOrdering,
+                "invalid memory ordering for this operation",
+            ),
+            (ErrorKind::CapacityExhausted, "capacity exhausted"),
+            (ErrorKind::Poisoned, "lock poisoned"),
+            (ErrorKind::ValueTooLarge, "value too large"),
+            (ErrorKind::Unrepresentable, "value cannot be represented"),
+        ];
+        for &(kind, msg) in cases.iter() {
+            // Make sure every kind is covered by this test.
+            match kind {
+                ErrorKind::WouldBlock
+                | ErrorKind::TimedOut
+                | ErrorKind::Stale
+                | ErrorKind::InvalidOrdering
+                | ErrorKind::CapacityExhausted
+                | ErrorKind::Poisoned
+                | ErrorKind::ValueTooLarge
+                | ErrorKind::Unrepresentable => {}
+            }
+            assert_eq!(format!("{}", kind), msg);
+            assert_eq!(format!("{}", Error::new(kind)), msg);
+            assert_eq!(format!("{}", Error::with_payload(kind, 42u8)), msg);
+        }
+    }
+
+    #[test]
+    fn payload_and_conversions() {
+        let err = Error::with_payload(ErrorKind::ValueTooLarge, 7u64);
+        assert_eq!(err.kind(), ErrorKind::ValueTooLarge);
+        assert_eq!(*err.payload(), 7);
+        assert_eq!(err.without_payload(), Error::new(ErrorKind::ValueTooLarge));
+        assert_eq!(ErrorKind::from(err), ErrorKind::ValueTooLarge);
+        assert_eq!(err.into_payload(), 7);
+        assert_eq!(
+            Error::from(ErrorKind::WouldBlock),
+            Error::new(ErrorKind::WouldBlock)
+        );
+
+        let boxed: Box<dyn StdError> = Box::new(err);
+        assert_eq!(boxed.to_string(), "value too large");
+    }
+}
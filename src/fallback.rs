@@ -5,142 +5,700 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+// Under loom or shuttle the lock table is replaced by per-`Atomic` mutexes,
+// on targets without a compare-exchange by a critical section, and on
+// single-threaded wasm by nothing at all.
+#![cfg_attr(any(loom, shuttle, no_atomic_cas, single_threaded), allow(dead_code))]
+
+#[cfg(not(any(no_atomic_cas, single_threaded)))]
+use backoff::Backoff;
 use core::cmp;
 use core::mem;
 use core::num::Wrapping;
 use core::ops;
+#[cfg(not(sanitize_thread))]
 use core::ptr;
+#[cfg(not(sanitize_thread))]
 use core::slice;
-use core::sync::atomic::{self, AtomicUsize, Ordering};
+#[cfg(sanitize_thread)]
+use core::sync::atomic::AtomicU8;
+#[cfg(all(
+    any(not(any(no_atomic_cas, single_threaded)), feature = "fallback-stats"),
+    not(feature = "portable-atomic")
+))]
+use core::sync::atomic::AtomicUsize;
+#[cfg(any(not(any(no_atomic_cas, single_threaded)), sanitize_thread))]
+use core::sync::atomic::Ordering;
+#[cfg(all(
+    any(not(any(no_atomic_cas, single_threaded)), feature = "fallback-stats"),
+    feature = "portable-atomic"
+))]
+use portable_atomic::AtomicUsize;
+#[cfg(any(loom, shuttle))]
+use shadow;
+#[cfg(all(
+    feature = "std",
+    not(any(loom, shuttle, no_atomic_cas, single_threaded))
+))]
+use wait;
 
 // We use an AtomicUsize instead of an AtomicBool because it performs better
 // on architectures that don't have byte-sized atomics.
 //
-// We give each spinlock its own cache line to avoid false sharing.
+// With the `portable-atomic` feature it is that crate's, so that the lock also
+// works on targets where only it provides a compare-exchange.
+//
+// We give each spinlock its own cache line to avoid false sharing. The
+// contention counters live in the same cache line as the lock word, which is
+// already being written to whenever they are updated.
+//
+// With the `std` feature a thread which is still waiting once its backoff is
+// completed parks instead of spinning on. The lock word is then 2 rather than
+// 1, which tells the unlocking thread to wake the parked threads, and a thread
+// which has parked once takes the lock with 2 until it gets it, since there
+// may be other threads parked behind it. Without `std` there is nothing to
+// park on, and the lock only spins and yields.
 #[repr(align(64))]
-struct SpinLock(AtomicUsize);
+#[cfg(not(any(no_atomic_cas, single_threaded)))]
+struct SpinLock(AtomicUsize, LockCounters);
 
+#[cfg(not(any(no_atomic_cas, single_threaded)))]
 impl SpinLock {
-    fn lock(&self) {
-        while self
-            .0
+    const fn new() -> SpinLock {
+        SpinLock(AtomicUsize::new(0), LockCounters::new())
+    }
+
+    #[inline]
+    fn try_lock(&self) -> bool {
+        self.0
             .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Relaxed)
-            .is_err()
-        {
+            .is_ok()
+    }
+
+    fn lock(&self) {
+        self.1.record_acquisition();
+        if self.try_lock() {
+            return;
+        }
+        self.1.record_contention();
+        let mut backoff = Backoff::new();
+        loop {
             while self.0.load(Ordering::Relaxed) != 0 {
-                atomic::spin_loop_hint();
+                #[cfg(all(feature = "std", not(any(loom, shuttle))))]
+                {
+                    if backoff.is_completed() {
+                        return self.lock_parked();
+                    }
+                }
+                self.1.record_spin();
+                backoff.snooze();
+            }
+            if self.try_lock() {
+                return;
             }
         }
     }
 
+    #[cold]
+    #[cfg(all(feature = "std", not(any(loom, shuttle))))]
+    fn lock_parked(&self) {
+        while self.0.swap(2, Ordering::Acquire) != 0 {
+            wait::park_while(self.park_key(), || self.0.load(Ordering::Relaxed) == 2);
+        }
+    }
+
+    // Consecutive slots get consecutive keys, which spreads them over the
+    // parking buckets.
+    #[inline]
+    #[cfg(all(feature = "std", not(any(loom, shuttle))))]
+    fn park_key(&self) -> usize {
+        self as *const SpinLock as usize / mem::size_of::<SpinLock>()
+    }
+
+    // Unlike `try_lock`, this never fails spuriously: it only fails if the
+    // lock is held.
+    #[inline]
+    fn try_lock_once(&self) -> bool {
+        let locked = self
+            .0
+            .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if locked {
+            self.1.record_acquisition();
+        }
+        locked
+    }
+
+    #[cfg(not(all(feature = "std", not(any(loom, shuttle)))))]
     fn unlock(&self) {
         self.0.store(0, Ordering::Release);
     }
+
+    #[cfg(all(feature = "std", not(any(loom, shuttle))))]
+    fn unlock(&self) {
+        if self.0.swap(0, Ordering::Release) == 2 {
+            wait::unpark_all(self.park_key());
+        }
+    }
+}
+
+// Per-lock contention counters. These are only updated with relaxed atomics
+// and are compiled down to nothing when the `fallback-stats` feature is
+// disabled.
+#[cfg(feature = "fallback-stats")]
+struct LockCounters {
+    acquisitions: AtomicUsize,
+    contended: AtomicUsize,
+    spins: AtomicUsize,
+}
+
+#[cfg(feature = "fallback-stats")]
+impl LockCounters {
+    const fn new() -> LockCounters {
+        LockCounters {
+            acquisitions: AtomicUsize::new(0),
+            contended: AtomicUsize::new(0),
+            spins: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline]
+    fn record_acquisition(&self) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn record_contention(&self) {
+        self.contended.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn record_spin(&self) {
+        self.spins.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        self.acquisitions.store(0, Ordering::Relaxed);
+        self.contended.store(0, Ordering::Relaxed);
+        self.spins.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(not(feature = "fallback-stats"))]
+struct LockCounters;
+
+#[cfg(not(feature = "fallback-stats"))]
+impl LockCounters {
+    const fn new() -> LockCounters {
+        LockCounters
+    }
+
+    #[inline]
+    fn record_acquisition(&self) {}
+
+    #[inline]
+    fn record_contention(&self) {}
+
+    #[inline]
+    fn record_spin(&self) {}
 }
 
 // A big array of spinlocks which we use to guard atomic accesses. A spinlock is
 // chosen based on a hash of the address of the atomic object, which helps to
 // reduce contention compared to a single global lock.
-macro_rules! array {
-    (@accum (0, $($_es:expr),*) -> ($($body:tt)*))
-        => {array!(@as_expr [$($body)*])};
-    (@accum (1, $($es:expr),*) -> ($($body:tt)*))
-        => {array!(@accum (0, $($es),*) -> ($($body)* $($es,)*))};
-    (@accum (2, $($es:expr),*) -> ($($body:tt)*))
-        => {array!(@accum (0, $($es),*) -> ($($body)* $($es,)* $($es,)*))};
-    (@accum (4, $($es:expr),*) -> ($($body:tt)*))
-        => {array!(@accum (2, $($es,)* $($es),*) -> ($($body)*))};
-    (@accum (8, $($es:expr),*) -> ($($body:tt)*))
-        => {array!(@accum (4, $($es,)* $($es),*) -> ($($body)*))};
-    (@accum (16, $($es:expr),*) -> ($($body:tt)*))
-        => {array!(@accum (8, $($es,)* $($es),*) -> ($($body)*))};
-    (@accum (32, $($es:expr),*) -> ($($body:tt)*))
-        => {array!(@accum (16, $($es,)* $($es),*) -> ($($body)*))};
-    (@accum (64, $($es:expr),*) -> ($($body:tt)*))
-        => {array!(@accum (32, $($es,)* $($es),*) -> ($($body)*))};
-
-    (@as_expr $e:expr) => {$e};
-
-    [$e:expr; $n:tt] => { array!(@accum ($n, $e) -> ()) };
-}
-static SPINLOCKS: [SpinLock; 64] = array![SpinLock(AtomicUsize::new(0)); 64];
-
-// Spinlock pointer hashing function from compiler-rt
+//
+// The number of slots is set by the `ATOMIC_FALLBACK_LOCKS` environment
+// variable at build time (see build.rs), and must be a power of two so that
+// the index can be taken from the top bits of the hash. Each slot takes a cache
+// line, 64 bytes, so the default of 64 slots takes 4 KiB.
+const LOCKS: usize = parse_lock_count(env!("ATOMIC_FALLBACK_LOCKS"));
+
+const _: () = assert!(
+    LOCKS.is_power_of_two(),
+    "ATOMIC_FALLBACK_LOCKS must be a power of two"
+);
+
+const fn parse_lock_count(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    assert!(!bytes.is_empty(), "ATOMIC_FALLBACK_LOCKS must be a number");
+    let mut n: usize = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let digit = bytes[i];
+        assert!(
+            digit.is_ascii_digit(),
+            "ATOMIC_FALLBACK_LOCKS must be a number"
+        );
+        n = match n.checked_mul(10) {
+            Some(n) => n + (digit - b'0') as usize,
+            None => panic!("ATOMIC_FALLBACK_LOCKS is too large"),
+        };
+        i += 1;
+    }
+    n
+}
+
+#[cfg(not(any(no_atomic_cas, single_threaded)))]
+static SPINLOCKS: [SpinLock; LOCKS] = [const { SpinLock::new() }; LOCKS];
+
+// Fibonacci hashing: multiplying by 2^N / φ mixes every bit of the address
+// into the high bits of the product, which are taken as the index. This
+// spreads arrays of atomics over the table whatever their stride, while
+// masking the low bits of the address would only ever use a few of the slots
+// for strides which are a multiple of a large power of two.
+const FIBONACCI: usize = (0x9e37_79b9_7f4a_7c15u64 >> (64 - usize::BITS)) as usize;
+
 #[inline]
+fn lock_index(addr: usize) -> usize {
+    if LOCKS == 1 {
+        // The shift below would overflow.
+        return 0;
+    }
+    addr.wrapping_mul(FIBONACCI) >> (usize::BITS - LOCKS.trailing_zeros())
+}
+
+#[inline]
+#[cfg(not(any(no_atomic_cas, single_threaded)))]
 fn lock_for_addr(addr: usize) -> &'static SpinLock {
-    // Disregard the lowest 4 bits.  We want all values that may be part of the
-    // same memory operation to hash to the same value and therefore use the same
-    // lock.
-    let mut hash = addr >> 4;
-    // Use the next bits as the basis for the hash
-    let low = hash & (SPINLOCKS.len() - 1);
-    // Now use the high(er) set of bits to perturb the hash, so that we don't
-    // get collisions from atomic fields in a single object
-    hash >>= 16;
-    hash ^= low;
-    // Return a pointer to the lock to use
-    &SPINLOCKS[hash & (SPINLOCKS.len() - 1)]
+    &SPINLOCKS[lock_index(addr)]
+}
+
+/// Contention statistics for a single slot of the fallback lock table.
+///
+/// The counters are updated with relaxed atomic operations, so a snapshot
+/// taken while other threads are using the lock may be slightly inconsistent.
+/// Counters wrap around on overflow.
+#[cfg(feature = "fallback-stats")]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LockStats {
+    /// Index of the lock slot in the fallback lock table.
+    pub slot: usize,
+    /// Number of times the lock was acquired.
+    pub acquisitions: usize,
+    /// Number of acquisitions which found the lock already held.
+    pub contended: usize,
+    /// Total number of spin iterations spent waiting for the lock.
+    pub spins: usize,
+}
+
+/// Returns the contention statistics of every slot in the fallback lock
+/// table, in slot order.
+///
+/// Only atomic types which are not lock-free go through the fallback lock, so
+/// this can be used to find out which `Atomic<T>` is secretly a hot lock.
+#[cfg(feature = "fallback-stats")]
+pub fn fallback_stats() -> impl Iterator<Item = LockStats> {
+    SPINLOCKS.iter().enumerate().map(|(slot, lock)| LockStats {
+        slot,
+        acquisitions: lock.1.acquisitions.load(Ordering::Relaxed),
+        contended: lock.1.contended.load(Ordering::Relaxed),
+        spins: lock.1.spins.load(Ordering::Relaxed),
+    })
+}
+
+/// Resets the contention statistics of every slot in the fallback lock table
+/// to zero.
+#[cfg(feature = "fallback-stats")]
+pub fn reset_fallback_stats() {
+    for lock in SPINLOCKS.iter() {
+        lock.1.reset();
+    }
+}
+
+/// Returns the index of the fallback lock slot used for the given address.
+#[cfg(feature = "fallback-stats")]
+pub fn fallback_slot(addr: usize) -> usize {
+    lock_index(addr)
 }
 
 #[inline]
+#[cfg(not(any(loom, shuttle, no_atomic_cas, single_threaded)))]
 fn lock(addr: usize) -> LockGuard {
+    #[cfg(all(debug_assertions, feature = "std"))]
+    held::insert(lock_index(addr));
     let lock = lock_for_addr(addr);
     lock.lock();
-    LockGuard(lock)
+    LockGuard {
+        lock,
+        #[cfg(all(debug_assertions, feature = "std"))]
+        held: true,
+    }
+}
+
+// Takes the lock for `addr` if it is free, without waiting. This never spins,
+// so it can't deadlock against a lock held by the code it interrupted, as in a
+// signal handler. For the same reason it doesn't record the slot in `held`.
+#[inline]
+#[cfg(not(any(loom, shuttle, no_atomic_cas, single_threaded)))]
+fn try_lock(addr: usize) -> Option<LockGuard> {
+    let lock = lock_for_addr(addr);
+    if lock.try_lock_once() {
+        Some(LockGuard {
+            lock,
+            #[cfg(all(debug_assertions, feature = "std"))]
+            held: false,
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(not(any(no_atomic_cas, single_threaded)))]
+struct LockGuard {
+    lock: &'static SpinLock,
+    // Whether the slot was recorded in `held`.
+    #[cfg(all(debug_assertions, feature = "std", not(any(loom, shuttle))))]
+    held: bool,
+}
+#[cfg(not(any(no_atomic_cas, single_threaded)))]
+impl Drop for LockGuard {
+    #[inline]
+    fn drop(&mut self) {
+        self.lock.unlock();
+        #[cfg(all(debug_assertions, feature = "std", not(any(loom, shuttle))))]
+        {
+            if self.held {
+                held::remove(self.index());
+            }
+        }
+    }
+}
+
+#[cfg(all(
+    debug_assertions,
+    feature = "std",
+    not(any(loom, shuttle, no_atomic_cas, single_threaded))
+))]
+impl LockGuard {
+    #[inline]
+    fn index(&self) -> usize {
+        let offset = self.lock as *const SpinLock as usize - SPINLOCKS.as_ptr() as usize;
+        offset / mem::size_of::<SpinLock>()
+    }
+}
+
+// The slots of the lock table held by the current thread, in debug builds.
+//
+// The locks aren't reentrant, so a thread which takes a lock it already holds
+// spins forever. That happens when the closure of a `fetch_update` accesses
+// another value which isn't lock-free and happens to use the same slot, or
+// the same value. Debug builds panic instead, before spinning.
+//
+// `try_lock` fails rather than spinning, so it doesn't need the check. It
+// doesn't record the slot it takes either: `try_load` and friends must stay
+// async-signal-safe, and a signal handler which borrows the `RefCell` or
+// grows the `Vec` while the interrupted code is doing the same would panic or
+// reenter the allocator.
+#[cfg(all(
+    debug_assertions,
+    feature = "std",
+    not(any(loom, shuttle, no_atomic_cas, single_threaded))
+))]
+mod held {
+    use std::cell::RefCell;
+    use std::vec::Vec;
+
+    thread_local! {
+        static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub fn insert(index: usize) {
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if held.contains(&index) {
+                // Unwinding drops the guards of the locks which are held.
+                drop(held);
+                panic!(
+                    "fallback lock {} acquired reentrantly: two Atomic values share a lock slot",
+                    index
+                );
+            }
+            held.push(index);
+        })
+    }
+
+    pub fn remove(index: usize) {
+        // The thread-local may already be gone if a guard is dropped by
+        // another thread-local's destructor.
+        let _ = HELD.try_with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(i) = held.iter().rposition(|&x| x == index) {
+                held.swap_remove(i);
+            }
+        });
+    }
+}
+
+// Targets without a compare-exchange, like Cortex-M0, can't have a spinlock,
+// so every value shares a single critical section from the `critical-section`
+// crate instead. On a single core, where it masks interrupts, it excludes
+// everything else that could touch the value, including the native loads and
+// stores which are still used for the lock-free sizes. Implementations for
+// multi-core chips only exclude other critical sections, so there a native
+// store from another core isn't ordered with an operation in here.
+#[inline]
+#[cfg(all(no_atomic_cas, not(any(loom, shuttle))))]
+fn lock(_addr: usize) -> LockGuard {
+    LockGuard(unsafe { critical_section::acquire() })
+}
+
+// Masking interrupts never waits, so this always succeeds.
+#[inline]
+#[cfg(all(no_atomic_cas, not(any(loom, shuttle))))]
+fn try_lock(addr: usize) -> Option<LockGuard> {
+    Some(lock(addr))
 }
 
-struct LockGuard(&'static SpinLock);
+#[cfg(no_atomic_cas)]
+struct LockGuard(critical_section::RestoreState);
+#[cfg(no_atomic_cas)]
 impl Drop for LockGuard {
     #[inline]
     fn drop(&mut self) {
-        self.0.unlock();
+        unsafe { critical_section::release(self.0) }
     }
 }
 
+// wasm without the `atomics` target feature only ever runs a single thread,
+// and nothing can interrupt it in the middle of an operation, so there is
+// nothing to exclude. The operations are plain reads and writes of the value.
+#[inline]
+#[cfg(single_threaded)]
+fn lock(_addr: usize) -> LockGuard {
+    LockGuard
+}
+
+#[inline]
+#[cfg(single_threaded)]
+fn try_lock(_addr: usize) -> Option<LockGuard> {
+    Some(LockGuard)
+}
+
+#[cfg(single_threaded)]
+struct LockGuard;
+
+// Under loom or shuttle every `Atomic` has its own mutex instead, so that the
+// model checker sees the synchronization provided by the lock.
+#[inline]
+#[cfg(any(loom, shuttle))]
+fn lock(addr: usize) -> shadow::LockGuard {
+    shadow::lock(addr)
+}
+
+#[inline]
+#[cfg(any(loom, shuttle))]
+fn try_lock(addr: usize) -> Option<shadow::LockGuard> {
+    shadow::try_lock(addr)
+}
+
+// Holds the lock for `addr` while running `f`, so that tests can make the
+// lock look busy to another operation.
+#[cfg(all(test, not(any(loom, shuttle))))]
+pub fn with_lock_held<R, F: FnOnce() -> R>(addr: usize, f: F) -> R {
+    let _l = lock(addr);
+    f()
+}
+
+// The data is only ever accessed with the lock held, so plain copies are
+// enough. ThreadSanitizer doesn't reliably pick up the happens-before edges of
+// the spinlock in every interleaving though, and reports these copies as data
+// races. Under TSan they are therefore made with relaxed atomic operations,
+// which it never reports, while the lock still makes the operation as a whole
+// atomic.
+#[inline]
+#[cfg(not(sanitize_thread))]
+unsafe fn read<T>(src: *const T) -> T {
+    ptr::read(src)
+}
+
+#[inline]
+#[cfg(not(sanitize_thread))]
+unsafe fn write<T>(dst: *mut T, val: T) {
+    ptr::write(dst, val)
+}
+
+#[inline]
+#[cfg(not(sanitize_thread))]
+unsafe fn copy<T>(src: *const T, dst: *mut T) {
+    ptr::copy_nonoverlapping(src, dst, 1)
+}
+
+// compare_exchange compares with memcmp instead of Eq
+#[inline]
+#[cfg(not(sanitize_thread))]
+unsafe fn bytes_eq<T>(a: *const T, b: *const T) -> bool {
+    let a = slice::from_raw_parts(a as *const u8, mem::size_of::<T>());
+    let b = slice::from_raw_parts(b as *const u8, mem::size_of::<T>());
+    a == b
+}
+
+#[cfg(sanitize_thread)]
+unsafe fn read<T>(src: *const T) -> T {
+    let mut val = mem::MaybeUninit::<T>::uninit();
+    let bytes = val.as_mut_ptr() as *mut u8;
+    for i in 0..mem::size_of::<T>() {
+        let byte = AtomicU8::from_ptr((src as *mut u8).add(i));
+        *bytes.add(i) = byte.load(Ordering::Relaxed);
+    }
+    val.assume_init()
+}
+
+#[cfg(sanitize_thread)]
+unsafe fn write<T>(dst: *mut T, val: T) {
+    let bytes = &val as *const T as *const u8;
+    for i in 0..mem::size_of::<T>() {
+        let byte = AtomicU8::from_ptr((dst as *mut u8).add(i));
+        byte.store(*bytes.add(i), Ordering::Relaxed);
+    }
+    mem::forget(val);
+}
+
+#[cfg(sanitize_thread)]
+unsafe fn copy<T>(src: *const T, dst: *mut T) {
+    for i in 0..mem::size_of::<T>() {
+        let from = AtomicU8::from_ptr((src as *mut u8).add(i));
+        let to = AtomicU8::from_ptr((dst as *mut u8).add(i));
+        to.store(from.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+#[cfg(sanitize_thread)]
+unsafe fn bytes_eq<T>(a: *const T, b: *const T) -> bool {
+    (0..mem::size_of::<T>()).all(|i| {
+        let a = AtomicU8::from_ptr((a as *mut u8).add(i));
+        let b = AtomicU8::from_ptr((b as *mut u8).add(i));
+        a.load(Ordering::Relaxed) == b.load(Ordering::Relaxed)
+    })
+}
+
 #[inline]
 pub unsafe fn atomic_load<T>(dst: *mut T) -> T {
     let _l = lock(dst as usize);
-    ptr::read(dst)
+    read(dst)
 }
 
 #[inline]
 pub unsafe fn atomic_store<T>(dst: *mut T, val: T) {
     let _l = lock(dst as usize);
-    ptr::write(dst, val);
+    write(dst, val);
 }
 
 #[inline]
 pub unsafe fn atomic_swap<T>(dst: *mut T, val: T) -> T {
     let _l = lock(dst as usize);
-    ptr::replace(dst, val)
+    let result = read(dst);
+    write(dst, val);
+    result
+}
+
+// Clones the value in place under the lock. If `clone` panics the lock is
+// released while unwinding, and the value is left as it was.
+#[inline]
+pub unsafe fn atomic_clone<T: Clone>(dst: *mut T) -> T {
+    let _l = lock(dst as usize);
+    (*dst).clone()
+}
+
+#[inline]
+pub unsafe fn atomic_try_load<T>(dst: *mut T) -> Option<T> {
+    let _l = try_lock(dst as usize)?;
+    Some(read(dst))
+}
+
+#[inline]
+pub unsafe fn atomic_try_store<T>(dst: *mut T, val: T) -> Result<(), T> {
+    match try_lock(dst as usize) {
+        Some(_l) => {
+            write(dst, val);
+            Ok(())
+        }
+        None => Err(val),
+    }
+}
+
+#[inline]
+pub unsafe fn atomic_try_swap<T>(dst: *mut T, val: T) -> Result<T, T> {
+    match try_lock(dst as usize) {
+        Some(_l) => {
+            let result = read(dst);
+            write(dst, val);
+            Ok(result)
+        }
+        None => Err(val),
+    }
 }
 
 #[inline]
 pub unsafe fn atomic_compare_exchange<T>(dst: *mut T, current: T, new: T) -> Result<T, T> {
     let _l = lock(dst as usize);
-    let result = ptr::read(dst);
-    // compare_exchange compares with memcmp instead of Eq
-    let a = slice::from_raw_parts(&result as *const _ as *const u8, mem::size_of_val(&result));
-    let b = slice::from_raw_parts(
-        &current as *const _ as *const u8,
-        mem::size_of_val(&current),
-    );
-    if a == b {
-        ptr::write(dst, new);
+    let result = read(dst);
+    if bytes_eq(&result, &current) {
+        write(dst, new);
         Ok(result)
     } else {
         Err(result)
     }
 }
 
+// The reference-taking compare-exchanges copy `new` straight from the caller's
+// value into `dst`, instead of moving it through the arguments first.
+#[inline]
+pub unsafe fn atomic_compare_exchange_ref<T: Copy>(
+    dst: *mut T,
+    current: &T,
+    new: &T,
+) -> Result<T, T> {
+    let _l = lock(dst as usize);
+    let result = read(dst);
+    if bytes_eq(&result, current) {
+        copy(new, dst);
+        Ok(result)
+    } else {
+        Err(result)
+    }
+}
+
+// This one compares `dst` in place and only copies it out on failure.
+#[inline]
+pub unsafe fn atomic_compare_exchange_in_place<T: Copy>(
+    dst: *mut T,
+    current: &mut T,
+    new: &T,
+) -> bool {
+    let _l = lock(dst as usize);
+    if bytes_eq(dst, current) {
+        copy(new, dst);
+        true
+    } else {
+        copy(dst, current);
+        false
+    }
+}
+
+#[inline]
+pub unsafe fn atomic_fetch_update<T: Copy, F: FnOnce(T) -> Option<T>>(
+    dst: *mut T,
+    f: F,
+) -> Result<T, T> {
+    let _l = lock(dst as usize);
+    let result = read(dst);
+    match f(result) {
+        Some(new) => {
+            write(dst, new);
+            Ok(result)
+        }
+        None => Err(result),
+    }
+}
+
 #[inline]
 pub unsafe fn atomic_add<T: Copy>(dst: *mut T, val: T) -> T
 where
     Wrapping<T>: ops::Add<Output = Wrapping<T>>,
 {
     let _l = lock(dst as usize);
-    let result = ptr::read(dst);
-    ptr::write(dst, (Wrapping(result) + Wrapping(val)).0);
+    let result = read(dst);
+    write(dst, (Wrapping(result) + Wrapping(val)).0);
     result
 }
 
@@ -150,47 +708,259 @@ where
     Wrapping<T>: ops::Sub<Output = Wrapping<T>>,
 {
     let _l = lock(dst as usize);
-    let result = ptr::read(dst);
-    ptr::write(dst, (Wrapping(result) - Wrapping(val)).0);
+    let result = read(dst);
+    write(dst, (Wrapping(result) - Wrapping(val)).0);
     result
 }
 
 #[inline]
 pub unsafe fn atomic_and<T: Copy + ops::BitAnd<Output = T>>(dst: *mut T, val: T) -> T {
     let _l = lock(dst as usize);
-    let result = ptr::read(dst);
-    ptr::write(dst, result & val);
+    let result = read(dst);
+    write(dst, result & val);
     result
 }
 
 #[inline]
 pub unsafe fn atomic_or<T: Copy + ops::BitOr<Output = T>>(dst: *mut T, val: T) -> T {
     let _l = lock(dst as usize);
-    let result = ptr::read(dst);
-    ptr::write(dst, result | val);
+    let result = read(dst);
+    write(dst, result | val);
     result
 }
 
 #[inline]
 pub unsafe fn atomic_xor<T: Copy + ops::BitXor<Output = T>>(dst: *mut T, val: T) -> T {
     let _l = lock(dst as usize);
-    let result = ptr::read(dst);
-    ptr::write(dst, result ^ val);
+    let result = read(dst);
+    write(dst, result ^ val);
     result
 }
 
 #[inline]
 pub unsafe fn atomic_min<T: Copy + cmp::Ord>(dst: *mut T, val: T) -> T {
     let _l = lock(dst as usize);
-    let result = ptr::read(dst);
-    ptr::write(dst, cmp::min(result, val));
+    let result = read(dst);
+    write(dst, cmp::min(result, val));
     result
 }
 
 #[inline]
 pub unsafe fn atomic_max<T: Copy + cmp::Ord>(dst: *mut T, val: T) -> T {
     let _l = lock(dst as usize);
-    let result = ptr::read(dst);
-    ptr::write(dst, cmp::max(result, val));
+    let result = read(dst);
+    write(dst, cmp::max(result, val));
     result
 }
+
+#[cfg(all(test, not(any(loom, shuttle, no_atomic_cas, single_threaded))))]
+mod tests {
+    use super::{lock_index, LOCKS};
+    use std::vec::Vec;
+
+    // Checks that no slot is used by more than 4 times its share of the
+    // elements of an array with the given stride.
+    fn check_distribution(stride: usize) {
+        const ELEMENTS: usize = 1024;
+        let array: Vec<u8> = vec![0; ELEMENTS * stride];
+        let mut counts = [0; LOCKS];
+        for x in array.chunks(stride) {
+            counts[lock_index(x.as_ptr() as usize)] += 1;
+        }
+        let average = (ELEMENTS / LOCKS).max(1);
+        let max = *counts.iter().max().unwrap();
+        assert!(
+            max <= 4 * average,
+            "stride {}: {} elements in one slot, {} on average",
+            stride,
+            max,
+            average
+        );
+    }
+
+    #[test]
+    fn strided_arrays_are_spread() {
+        for &stride in &[16, 24, 32, 64, 4096] {
+            check_distribution(stride);
+        }
+    }
+
+    // Threads waiting for a lock which is held for a long time park, and are
+    // woken when it is released, while lock-free atomics keep working.
+    #[test]
+    #[cfg(feature = "std")]
+    fn parked_waiters_wake_up() {
+        use super::{lock_for_addr, with_lock_held};
+        use std::sync::atomic::{AtomicUsize, Ordering::*};
+        use std::{thread, time};
+        use Atomic;
+
+        let value = Atomic::new([0u64; 3]);
+        let counters: Vec<Atomic<u32>> = (0..4).map(|_| Atomic::new(0)).collect();
+        let done = AtomicUsize::new(0);
+        let addr = value.as_ptr() as usize;
+        thread::scope(|s| {
+            with_lock_held(addr, || {
+                for _ in 0..8 {
+                    s.spawn(|| {
+                        let f = |[a, b, c]: [u64; 3]| Some([a + 1, b + 1, c + 1]);
+                        value.fetch_update_both(AcqRel, Acquire, f).unwrap();
+                        done.fetch_add(1, Relaxed);
+                    });
+                }
+                for c in &counters {
+                    s.spawn(move || {
+                        for _ in 0..1000 {
+                            c.fetch_add(1, Relaxed);
+                        }
+                    });
+                }
+                // The waiters mark the lock once they park.
+                let start = time::Instant::now();
+                while lock_for_addr(addr).0.load(Relaxed) != 2 {
+                    assert!(start.elapsed() < time::Duration::from_secs(10));
+                    thread::yield_now();
+                }
+                assert_eq!(done.load(Relaxed), 0);
+            });
+        });
+        assert_eq!(value.load(Relaxed), [8; 3]);
+        assert!(counters.iter().all(|c| c.load(Relaxed) == 1000));
+        assert_eq!(lock_for_addr(addr).0.load(Relaxed), 0);
+    }
+
+    // More threads than cores contending on one value which takes the lock,
+    // mixed with lock-free operations on other atomics.
+    #[test]
+    #[cfg(feature = "std")]
+    fn oversubscribed() {
+        use std::thread;
+        use Atomic;
+        use Ordering::*;
+
+        let threads = if cfg!(miri) {
+            4
+        } else {
+            thread::available_parallelism().map_or(4, |n| n.get()) * 4
+        };
+        let rounds = if cfg!(miri) { 20 } else { 1000 };
+        let value = Atomic::new([0u64; 2]);
+        let counter = Atomic::new(0u64);
+        thread::scope(|s| {
+            for i in 0..threads {
+                let (value, counter) = (&value, &counter);
+                s.spawn(move || {
+                    for _ in 0..rounds {
+                        if i % 2 == 0 {
+                            let f = |[a, b]: [u64; 2]| Some([a + 1, b + 2]);
+                            value.fetch_update_both(AcqRel, Acquire, f).unwrap();
+                        } else {
+                            counter.fetch_add(1, Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        // Half of the threads update each of them.
+        let updates = (threads / 2 * rounds) as u64;
+        assert_eq!(value.load(Relaxed), [updates, 2 * updates]);
+        assert_eq!(counter.load(Relaxed), updates);
+    }
+
+    // The same with a pair, which only takes the lock on processors without
+    // `cmpxchg16b`.
+    #[test]
+    #[cfg(feature = "std")]
+    fn oversubscribed_pair() {
+        use std::thread;
+        use AtomicPair;
+        use Ordering::*;
+
+        let threads = if cfg!(miri) {
+            4
+        } else {
+            thread::available_parallelism().map_or(4, |n| n.get()) * 4
+        };
+        let rounds = if cfg!(miri) { 20 } else { 1000 };
+        let value = AtomicPair::new(0u64, 0u64);
+        thread::scope(|s| {
+            for _ in 0..threads {
+                let value = &value;
+                s.spawn(move || {
+                    for _ in 0..rounds {
+                        let f = |(a, b): (u64, u64)| Some((a + 1, b + 2));
+                        value.fetch_update(AcqRel, Acquire, f).unwrap();
+                    }
+                });
+            }
+        });
+        let updates = (threads * rounds) as u64;
+        assert_eq!(value.load(Relaxed), (updates, 2 * updates));
+    }
+
+    // Returns the indices of two of the values which use the same slot. There
+    // are more values than slots, so there always are some.
+    #[cfg(feature = "std")]
+    fn sharing_a_slot<T: Copy>(values: &[::Atomic<T>]) -> (usize, usize) {
+        assert!(values.len() > LOCKS);
+        let mut owners: Vec<Option<usize>> = vec![None; LOCKS];
+        for (i, v) in values.iter().enumerate() {
+            let slot = lock_index(v.as_ptr() as usize);
+            if let Some(j) = owners[slot] {
+                return (j, i);
+            }
+            owners[slot] = Some(i);
+        }
+        unreachable!()
+    }
+
+    // Taking a lock which the thread already holds panics in debug builds,
+    // rather than spinning forever. The attempt runs on another thread, so that
+    // a deadlock fails the test instead of hanging it.
+    #[test]
+    #[cfg(all(debug_assertions, feature = "std"))]
+    fn reentrancy_panics() {
+        use super::lock_for_addr;
+        use std::string::String;
+        use std::sync::atomic::Ordering::Relaxed;
+        use std::sync::{mpsc, Arc};
+        use std::time::Duration;
+        use std::{panic, thread};
+        use Atomic;
+        use Ordering::*;
+
+        let values: Arc<Vec<_>> = Arc::new((0..=LOCKS).map(|_| Atomic::new([0u64; 3])).collect());
+        let (i, j) = sharing_a_slot(&values);
+        let (done, finished) = mpsc::channel();
+        let thread = {
+            let values = values.clone();
+            thread::spawn(move || {
+                let (a, b) = (&values[i], &values[j]);
+                let result = panic::catch_unwind(|| {
+                    a.fetch_update_both(AcqRel, Acquire, |x| {
+                        b.store([1; 3], Release);
+                        Some(x)
+                    })
+                });
+                let message = result.map_err(|e| *e.downcast::<String>().unwrap());
+                done.send(message).unwrap();
+            })
+        };
+        let result = finished.recv_timeout(Duration::from_secs(10));
+        let message = result.expect("deadlocked").unwrap_err();
+        thread.join().unwrap();
+        let (a, b) = (&values[i], &values[j]);
+        let slot = lock_index(a.as_ptr() as usize);
+        assert_eq!(
+            message,
+            format!(
+                "fallback lock {} acquired reentrantly: two Atomic values share a lock slot",
+                slot
+            )
+        );
+        // Unwinding released the lock.
+        assert_eq!(lock_for_addr(a.as_ptr() as usize).0.load(Relaxed), 0);
+        assert_eq!(a.load(SeqCst), [0; 3]);
+        assert_eq!(b.load(SeqCst), [0; 3]);
+    }
+}
@@ -0,0 +1,359 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Interoperability with C11 atomics.
+//!
+//! `Atomic<u8>`, `Atomic<u16>`, `Atomic<u32>` and `Atomic<u64>` have the same
+//! size, alignment and representation as C's `_Atomic uint8_t`,
+//! `_Atomic uint16_t`, `_Atomic uint32_t` and `_Atomic uint64_t`, and always
+//! use the target's native atomic instructions, like C compilers do. A
+//! `#[repr(C)]` struct with such fields can therefore be shared with C code
+//! which declares them as `_Atomic`, and both sides can operate on them
+//! concurrently. This module only exists where that holds for the 8, 16 and
+//! 32-bit types. The 64-bit functions are only available where 64-bit atomics
+//! are lock-free and `u64` is aligned to 8 bytes: on 32-bit x86 Linux, for
+//! example, `u64` is only 4-byte aligned while `_Atomic uint64_t` is 8-byte
+//! aligned, so the two don't have the same layout.
+//!
+//! The module also exports `extern "C"` functions, like `atomic_u32_load` and
+//! `atomic_u32_fetch_add`, which C code can call on the same memory. They
+//! mirror the `_explicit` functions of `<stdatomic.h>` and take a
+//! `memory_order`, whose values are those of the `MEMORY_ORDER_*` constants,
+//! as they are with GCC, Clang and MSVC. `memory_order_consume` is treated as
+//! `memory_order_acquire`, as C compilers do. An invalid order, or one which
+//! isn't valid for the operation, such as a release load, aborts the process.
+//! [`C_HEADER`] holds their prototypes.
+//!
+//! The functions are only exported with the `ffi` feature, since their
+//! unmangled names would clash if two versions of this crate were linked
+//! into the same program.
+
+use core::ffi::c_int;
+use core::mem;
+use Atomic;
+use Ordering;
+
+/// `memory_order_relaxed`.
+pub const MEMORY_ORDER_RELAXED: c_int = 0;
+/// `memory_order_consume`, which is treated as `memory_order_acquire`.
+pub const MEMORY_ORDER_CONSUME: c_int = 1;
+/// `memory_order_acquire`.
+pub const MEMORY_ORDER_ACQUIRE: c_int = 2;
+/// `memory_order_release`.
+pub const MEMORY_ORDER_RELEASE: c_int = 3;
+/// `memory_order_acq_rel`.
+pub const MEMORY_ORDER_ACQ_REL: c_int = 4;
+/// `memory_order_seq_cst`.
+pub const MEMORY_ORDER_SEQ_CST: c_int = 5;
+
+/// Returns the `Ordering` which corresponds to a C11 `memory_order`, or
+/// `None` if `order` isn't one.
+#[inline]
+pub fn memory_order(order: c_int) -> Option<Ordering> {
+    match order {
+        MEMORY_ORDER_RELAXED => Some(Ordering::Relaxed),
+        MEMORY_ORDER_CONSUME | MEMORY_ORDER_ACQUIRE => Some(Ordering::Acquire),
+        MEMORY_ORDER_RELEASE => Some(Ordering::Release),
+        MEMORY_ORDER_ACQ_REL => Some(Ordering::AcqRel),
+        MEMORY_ORDER_SEQ_CST => Some(Ordering::SeqCst),
+        _ => None,
+    }
+}
+
+// A panic can't unwind out of an `extern "C"` function, so it aborts.
+#[inline]
+fn ordering(order: c_int) -> Ordering {
+    match memory_order(order) {
+        Some(order) => order,
+        None => panic!("invalid memory_order: {}", order),
+    }
+}
+
+// The prototype of the function for `$t` which does `$op`, whose first
+// parameter is `obj`.
+macro_rules! prototype {
+    ($ret:expr, $t:ident, $op:ident, $c:literal, $obj:literal, $($params:expr),*) => {
+        concat!(
+            $ret, " atomic_", stringify!($t), "_", stringify!($op), "(", $obj, " _Atomic ", $c,
+            " *obj, ", $($params,)* ");\n"
+        )
+    };
+}
+
+// rustfmt leaves the braced `concat!` alone, which keeps one prototype per
+// line.
+macro_rules! prototypes {
+    ($t:ident $c:literal) => {
+        concat! {
+            prototype!($c, $t, load, $c, "const volatile", "memory_order order"),
+            prototype!("void", $t, store, $c, "volatile", $c, " desired, memory_order order"),
+            prototype!($c, $t, exchange, $c, "volatile", $c, " desired, memory_order order"),
+            prototype!("bool", $t, compare_exchange_strong, $c, "volatile", $c, " *expected, ",
+                       $c, " desired, memory_order success, memory_order failure"),
+            prototype!("bool", $t, compare_exchange_weak, $c, "volatile", $c, " *expected, ",
+                       $c, " desired, memory_order success, memory_order failure"),
+            prototype!($c, $t, fetch_add, $c, "volatile", $c, " arg, memory_order order"),
+            prototype!($c, $t, fetch_sub, $c, "volatile", $c, " arg, memory_order order"),
+            prototype!($c, $t, fetch_and, $c, "volatile", $c, " arg, memory_order order"),
+            prototype!($c, $t, fetch_or, $c, "volatile", $c, " arg, memory_order order"),
+            prototype!($c, $t, fetch_xor, $c, "volatile", $c, " arg, memory_order order"),
+        }
+    };
+}
+
+macro_rules! header {
+    ($defines:literal; $($t:ident $c:literal)*) => {
+        concat!(
+            "#ifndef ATOMIC_RS_H\n",
+            "#define ATOMIC_RS_H\n",
+            "\n",
+            $defines,
+            "#include <stdatomic.h>\n",
+            "#include <stdbool.h>\n",
+            "#include <stdint.h>\n",
+            "\n",
+            "#ifdef __cplusplus\n",
+            "extern \"C\" {\n",
+            "#endif\n",
+            $("\n", prototypes!($t $c),)*
+            "\n",
+            "#ifdef __cplusplus\n",
+            "}\n",
+            "#endif\n",
+            "\n",
+            "#endif\n",
+        )
+    };
+}
+
+/// The C prototypes of the functions exported by this module, as a header
+/// file.
+///
+/// It only declares the functions which exist on the target, so it should be
+/// generated for the target the C code is compiled for, for example by a
+/// build script which writes it to a file. The 64-bit ones are declared along
+/// with an `ATOMIC_RS_U64` macro.
+#[cfg(all(has_atomic_64, has_atomic_equal_alignment_64))]
+pub const C_HEADER: &str = header!(
+    "#define ATOMIC_RS_U64 1\n\n";
+    u8 "uint8_t" u16 "uint16_t" u32 "uint32_t" u64 "uint64_t"
+);
+/// The C prototypes of the functions exported by this module, as a header
+/// file.
+///
+/// It only declares the functions which exist on the target, so it should be
+/// generated for the target the C code is compiled for, for example by a
+/// build script which writes it to a file. The 64-bit ones are declared along
+/// with an `ATOMIC_RS_U64` macro.
+#[cfg(not(all(has_atomic_64, has_atomic_equal_alignment_64)))]
+pub const C_HEADER: &str = header!(""; u8 "uint8_t" u16 "uint16_t" u32 "uint32_t");
+
+macro_rules! ffi {
+    ($t:ident $c:literal $load:ident $store:ident $exchange:ident $cas:ident $cas_weak:ident
+     $add:ident $sub:ident $and:ident $or:ident $xor:ident) => {
+        // These never take the fallback lock, which C code knows nothing
+        // about, and match the layout of the C type.
+        const _: () = assert!(
+            Atomic::<$t>::IS_LOCK_FREE
+                && mem::size_of::<Atomic<$t>>() == mem::size_of::<$t>()
+                && mem::align_of::<Atomic<$t>>() == mem::size_of::<$t>()
+        );
+
+        #[doc = concat!("C11 `atomic_load_explicit` on `_Atomic ", $c, "`.")]
+        ///
+        /// # Safety
+        ///
+        /// `obj` must point to a live `Atomic` which is only accessed
+        /// atomically.
+        #[no_mangle]
+        pub unsafe extern "C" fn $load(obj: *const Atomic<$t>, order: c_int) -> $t {
+            (*obj).load(ordering(order))
+        }
+
+        #[doc = concat!("C11 `atomic_store_explicit` on `_Atomic ", $c, "`.")]
+        ///
+        /// # Safety
+        ///
+        /// `obj` must point to a live `Atomic` which is only accessed
+        /// atomically.
+        #[no_mangle]
+        pub unsafe extern "C" fn $store(obj: *const Atomic<$t>, desired: $t, order: c_int) {
+            (*obj).store(desired, ordering(order))
+        }
+
+        #[doc = concat!("C11 `atomic_exchange_explicit` on `_Atomic ", $c, "`.")]
+        ///
+        /// # Safety
+        ///
+        /// `obj` must point to a live `Atomic` which is only accessed
+        /// atomically.
+        #[no_mangle]
+        pub unsafe extern "C" fn $exchange(obj: *const Atomic<$t>, desired: $t, order: c_int) -> $t {
+            (*obj).swap(desired, ordering(order))
+        }
+
+        #[doc = concat!(
+            "C11 `atomic_compare_exchange_strong_explicit` on `_Atomic ", $c, "`."
+        )]
+        ///
+        /// Returns whether `desired` was stored. Otherwise the current value
+        /// is written to `*expected`.
+        ///
+        /// # Safety
+        ///
+        /// `obj` must point to a live `Atomic` which is only accessed
+        /// atomically, and `expected` must be valid for reads and writes.
+        #[no_mangle]
+        pub unsafe extern "C" fn $cas(
+            obj: *const Atomic<$t>,
+            expected: *mut $t,
+            desired: $t,
+            success: c_int,
+            failure: c_int,
+        ) -> bool {
+            match (*obj).compare_exchange(*expected, desired, ordering(success), ordering(failure)) {
+                Ok(_) => true,
+                Err(current) => {
+                    *expected = current;
+                    false
+                }
+            }
+        }
+
+        #[doc = concat!(
+            "C11 `atomic_compare_exchange_weak_explicit` on `_Atomic ", $c, "`."
+        )]
+        ///
+        /// Like the strong version, except that it may fail spuriously.
+        ///
+        /// # Safety
+        ///
+        /// `obj` must point to a live `Atomic` which is only accessed
+        /// atomically, and `expected` must be valid for reads and writes.
+        #[no_mangle]
+        pub unsafe extern "C" fn $cas_weak(
+            obj: *const Atomic<$t>,
+            expected: *mut $t,
+            desired: $t,
+            success: c_int,
+            failure: c_int,
+        ) -> bool {
+            match (*obj).compare_exchange_weak(
+                *expected,
+                desired,
+                ordering(success),
+                ordering(failure),
+            ) {
+                Ok(_) => true,
+                Err(current) => {
+                    *expected = current;
+                    false
+                }
+            }
+        }
+
+        ffi!(@rmw $t $c $add fetch_add "atomic_fetch_add_explicit");
+        ffi!(@rmw $t $c $sub fetch_sub "atomic_fetch_sub_explicit");
+        ffi!(@rmw $t $c $and fetch_and "atomic_fetch_and_explicit");
+        ffi!(@rmw $t $c $or fetch_or "atomic_fetch_or_explicit");
+        ffi!(@rmw $t $c $xor fetch_xor "atomic_fetch_xor_explicit");
+    };
+    (@rmw $t:ident $c:literal $name:ident $method:ident $op:literal) => {
+        #[doc = concat!("C11 `", $op, "` on `_Atomic ", $c, "`.")]
+        ///
+        /// # Safety
+        ///
+        /// `obj` must point to a live `Atomic` which is only accessed
+        /// atomically.
+        #[no_mangle]
+        pub unsafe extern "C" fn $name(obj: *const Atomic<$t>, arg: $t, order: c_int) -> $t {
+            (*obj).$method(arg, ordering(order))
+        }
+    };
+}
+
+ffi!(u8 "uint8_t" atomic_u8_load atomic_u8_store atomic_u8_exchange
+     atomic_u8_compare_exchange_strong atomic_u8_compare_exchange_weak
+     atomic_u8_fetch_add atomic_u8_fetch_sub atomic_u8_fetch_and atomic_u8_fetch_or
+     atomic_u8_fetch_xor);
+ffi!(u16 "uint16_t" atomic_u16_load atomic_u16_store atomic_u16_exchange
+     atomic_u16_compare_exchange_strong atomic_u16_compare_exchange_weak
+     atomic_u16_fetch_add atomic_u16_fetch_sub atomic_u16_fetch_and atomic_u16_fetch_or
+     atomic_u16_fetch_xor);
+ffi!(u32 "uint32_t" atomic_u32_load atomic_u32_store atomic_u32_exchange
+     atomic_u32_compare_exchange_strong atomic_u32_compare_exchange_weak
+     atomic_u32_fetch_add atomic_u32_fetch_sub atomic_u32_fetch_and atomic_u32_fetch_or
+     atomic_u32_fetch_xor);
+#[cfg(all(has_atomic_64, has_atomic_equal_alignment_64))]
+ffi!(u64 "uint64_t" atomic_u64_load atomic_u64_store atomic_u64_exchange
+     atomic_u64_compare_exchange_strong atomic_u64_compare_exchange_weak
+     atomic_u64_fetch_add atomic_u64_fetch_sub atomic_u64_fetch_and atomic_u64_fetch_or
+     atomic_u64_fetch_xor);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr;
+
+    #[test]
+    fn orders() {
+        assert_eq!(memory_order(MEMORY_ORDER_RELAXED), Some(Ordering::Relaxed));
+        assert_eq!(memory_order(MEMORY_ORDER_CONSUME), Some(Ordering::Acquire));
+        assert_eq!(memory_order(MEMORY_ORDER_SEQ_CST), Some(Ordering::SeqCst));
+        assert_eq!(memory_order(6), None);
+        assert_eq!(memory_order(-1), None);
+    }
+
+    #[test]
+    fn functions() {
+        let a = Atomic::new(5u32);
+        let mut expected = 4;
+        unsafe {
+            assert_eq!(atomic_u32_fetch_add(&a, 3, MEMORY_ORDER_ACQ_REL), 5);
+            assert!(!atomic_u32_compare_exchange_strong(
+                &a,
+                &mut expected,
+                1,
+                MEMORY_ORDER_SEQ_CST,
+                MEMORY_ORDER_RELAXED
+            ));
+            assert_eq!(expected, 8);
+            assert!(atomic_u32_compare_exchange_strong(
+                &a,
+                &mut expected,
+                1,
+                MEMORY_ORDER_SEQ_CST,
+                MEMORY_ORDER_CONSUME
+            ));
+            assert_eq!(atomic_u32_exchange(&a, 0xf0, MEMORY_ORDER_RELEASE), 1);
+            assert_eq!(atomic_u32_fetch_xor(&a, 0xff, MEMORY_ORDER_RELAXED), 0xf0);
+            atomic_u32_store(&a, 7, MEMORY_ORDER_SEQ_CST);
+            assert_eq!(atomic_u32_load(&a, MEMORY_ORDER_ACQUIRE), 7);
+
+            let b = Atomic::new(0u8);
+            assert_eq!(atomic_u8_fetch_sub(&b, 1, MEMORY_ORDER_RELAXED), 0);
+            assert_eq!(atomic_u8_load(ptr::addr_of!(b), MEMORY_ORDER_RELAXED), 255);
+        }
+    }
+
+    #[test]
+    fn header() {
+        assert!(C_HEADER.contains(
+            "uint32_t atomic_u32_fetch_add(volatile _Atomic uint32_t *obj, uint32_t arg, \
+             memory_order order);\n"
+        ));
+        assert!(C_HEADER.contains(
+            "bool atomic_u16_compare_exchange_weak(volatile _Atomic uint16_t *obj, \
+             uint16_t *expected, uint16_t desired, memory_order success, \
+             memory_order failure);\n"
+        ));
+        assert_eq!(
+            C_HEADER.contains("atomic_u64_load"),
+            cfg!(all(has_atomic_64, has_atomic_equal_alignment_64))
+        );
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#[cfg(not(any(loom, shuttle)))]
+use core::cell::UnsafeCell;
+use core::fmt;
+#[cfg(any(loom, shuttle))]
+use shadow;
+use ops;
+use Ordering;
+
+#[cfg(feature = "std")]
+use std::panic::RefUnwindSafe;
+
+/// A boolean flag which is always lock-free and exactly one byte in size.
+///
+/// This is the equivalent of C++'s `std::atomic_flag`. It only supports
+/// setting, clearing and testing the flag, which makes it a good fit for
+/// one-shot latches. The flag is stored as a `u8` which is either 0 or 1, and
+/// the type is `repr(transparent)` over that byte so it can be placed in
+/// memory shared with other processes.
+#[cfg_attr(not(any(loom, shuttle)), repr(transparent))]
+pub struct AtomicFlag {
+    #[cfg(not(any(loom, shuttle)))]
+    v: UnsafeCell<u8>,
+    #[cfg(any(loom, shuttle))]
+    v: shadow::ShadowCell<u8>,
+}
+
+/// An `AtomicFlag` which is initially clear.
+///
+/// This is mostly useful for array initializers; `AtomicFlag::new()` can be
+/// used directly in statics.
+#[allow(clippy::declare_interior_mutable_const)]
+#[cfg(not(any(loom, shuttle)))]
+pub const ATOMIC_FLAG_INIT: AtomicFlag = AtomicFlag::new();
+
+unsafe impl Sync for AtomicFlag {}
+
+#[cfg(feature = "std")]
+impl RefUnwindSafe for AtomicFlag {}
+
+impl Default for AtomicFlag {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for AtomicFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("AtomicFlag")
+            .field(&self.test(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+impl AtomicFlag {
+    /// Creates a new `AtomicFlag` which is clear.
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub const fn new() -> AtomicFlag {
+        AtomicFlag {
+            v: UnsafeCell::new(0),
+        }
+    }
+
+    /// Creates a new `AtomicFlag` which is clear.
+    ///
+    /// Under loom or shuttle this isn't a `const fn`, and `ATOMIC_FLAG_INIT`
+    /// doesn't exist, since the flag needs an atomic of the model checker to
+    /// model it.
+    #[inline]
+    #[cfg(any(loom, shuttle))]
+    pub fn new() -> AtomicFlag {
+        AtomicFlag {
+            v: shadow::ShadowCell::new(0),
+        }
+    }
+
+    /// Sets the flag and returns whether it was already set.
+    #[inline]
+    pub fn test_and_set(&self, order: Ordering) -> bool {
+        unsafe { ops::atomic_swap(self.v.get(), 1, order) != 0 }
+    }
+
+    /// Returns whether the flag is set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn test(&self, order: Ordering) -> bool {
+        unsafe { ops::atomic_load(self.v.get(), order) != 0 }
+    }
+
+    /// Clears the flag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn clear(&self, order: Ordering) {
+        unsafe { ops::atomic_store(self.v.get(), 0, order) }
+    }
+
+    /// Returns a mutable reference to the underlying flag.
+    ///
+    /// This is safe because the mutable reference guarantees that no other
+    /// threads are concurrently accessing the flag.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut bool {
+        unsafe { &mut *(self.v.get() as *mut bool) }
+    }
+
+    /// Consumes the `AtomicFlag` and returns whether it was set.
+    #[inline]
+    pub fn into_inner(self) -> bool {
+        self.v.into_inner() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AtomicFlag, ATOMIC_FLAG_INIT};
+    use core::mem;
+    use core::sync::atomic::AtomicUsize;
+    use std::thread;
+    use Ordering::*;
+
+    #[test]
+    fn atomic_flag() {
+        let f = AtomicFlag::new();
+        assert_eq!(mem::size_of::<AtomicFlag>(), 1);
+        assert!(!f.test(SeqCst));
+        assert!(!f.test_and_set(SeqCst));
+        assert!(f.test_and_set(SeqCst));
+        assert!(f.test(Relaxed));
+        assert_eq!(format!("{:?}", f), "AtomicFlag(true)");
+        f.clear(Release);
+        assert!(!f.test(Acquire));
+
+        let mut f = ATOMIC_FLAG_INIT;
+        *f.get_mut() = true;
+        assert!(f.into_inner());
+    }
+
+    #[test]
+    fn one_shot_init() {
+        static INITIALIZED: AtomicFlag = AtomicFlag::new();
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    if !INITIALIZED.test_and_set(AcqRel) {
+                        RUNS.fetch_add(1, Relaxed);
+                    }
+                    assert!(INITIALIZED.test(Acquire));
+                });
+            }
+        });
+        assert_eq!(RUNS.load(Relaxed), 1);
+    }
+}
@@ -0,0 +1,454 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use backoff::Backoff;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use ops;
+use Atomic;
+
+/// An atomic cell which carries its own lock, for values which aren't
+/// lock-free.
+///
+/// An `Atomic<T>` which isn't lock-free takes a lock from a global table,
+/// selected by its address. The table belongs to the process, so two
+/// processes which map the same memory at different addresses take different
+/// locks and don't exclude each other, and unrelated values which hash to the
+/// same lock contend on it. An `InlineAtomic<T>` embeds a lock word in front
+/// of the value instead, so it is self-contained: it works in memory shared
+/// between processes, and contention on one value never slows down another.
+///
+/// This makes it one word larger than `T`, plus the padding needed to align
+/// `T` after it. The lock word is there for every `T`, so that the layout
+/// doesn't depend on the target, but it is only used when `Atomic<T>` isn't
+/// lock-free (see `Atomic::IS_LOCK_FREE`): lock-free types use the same native
+/// instructions as `Atomic<T>`, which work across processes too. The lock
+/// only spins and yields, since a waiter in another process couldn't be
+/// woken up by a thread parked through this one.
+///
+/// The type is `#[repr(C)]` and a zero lock word is unlocked, so zeroed
+/// memory holding a valid `T`, such as a fresh shared memory mapping, is a
+/// valid `InlineAtomic<T>`. Every process must use the same version of this
+/// crate and the same `T`.
+///
+/// A value whose lock is held by a process which dies stays locked.
+///
+/// # Examples
+///
+/// ```
+/// use atomic::{InlineAtomic, Ordering};
+/// use std::mem;
+///
+/// #[derive(Clone, Copy, Debug, PartialEq)]
+/// struct Range {
+///     start: u64,
+///     end: u64,
+///     count: u64,
+/// }
+///
+/// let range = InlineAtomic::new(Range { start: 0, end: 0, count: 0 });
+/// assert_eq!(
+///     mem::size_of_val(&range),
+///     mem::size_of::<Range>() + mem::size_of::<usize>()
+/// );
+/// let f = |r: Range| Some(Range { end: r.end + 10, count: r.count + 1, ..r });
+/// range.fetch_update(Ordering::AcqRel, Ordering::Acquire, f).unwrap();
+/// assert_eq!(range.load(Ordering::Acquire), Range { start: 0, end: 10, count: 1 });
+/// ```
+#[repr(C)]
+pub struct InlineAtomic<T: Copy> {
+    lock: AtomicUsize,
+    v: UnsafeCell<T>,
+}
+
+// Same as `Atomic<T>`.
+unsafe impl<T: Copy + Send> Sync for InlineAtomic<T> {}
+
+impl<T: Copy + Default> Default for InlineAtomic<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for InlineAtomic<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("InlineAtomic")
+            .field(&self.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+// The native operations, for types which are lock-free.
+//
+// They are picked in a constant rather than by matching on `IS_LOCK_FREE` in
+// each operation, since with the `no-fallback` feature the functions of `ops`
+// fail to compile for types which aren't lock-free as soon as they are named,
+// even in an arm which is never taken.
+struct Native<T> {
+    load: unsafe fn(*mut T, Ordering) -> T,
+    store: unsafe fn(*mut T, T, Ordering),
+    swap: unsafe fn(*mut T, T, Ordering) -> T,
+    compare_exchange: unsafe fn(*mut T, T, T, Ordering, Ordering) -> Result<T, T>,
+    compare_exchange_weak: unsafe fn(*mut T, T, T, Ordering, Ordering) -> Result<T, T>,
+}
+
+// Unlocks the value when dropped.
+struct Guard<'a>(&'a AtomicUsize);
+
+impl<'a> Drop for Guard<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        self.0.store(0, Ordering::Release);
+    }
+}
+
+impl<T: Copy> InlineAtomic<T> {
+    const NATIVE: Option<Native<T>> = if Atomic::<T>::IS_LOCK_FREE {
+        Some(Native {
+            load: ops::atomic_load::<T>,
+            store: ops::atomic_store::<T>,
+            swap: ops::atomic_swap::<T>,
+            compare_exchange: ops::atomic_compare_exchange::<T>,
+            compare_exchange_weak: ops::atomic_compare_exchange_weak::<T>,
+        })
+    } else {
+        None
+    };
+
+    /// Creates a new `InlineAtomic`.
+    #[inline]
+    pub const fn new(v: T) -> InlineAtomic<T> {
+        InlineAtomic {
+            lock: AtomicUsize::new(0),
+            v: UnsafeCell::new(v),
+        }
+    }
+
+    /// Checks if operations on this type use native atomic instructions
+    /// rather than the embedded lock.
+    ///
+    /// This is `Atomic::<T>::IS_LOCK_FREE`. Unlike `Atomic::is_lock_free`,
+    /// it doesn't count 16-byte types for which the processor happens to
+    /// have `cmpxchg16b`, since those would take the global lock on
+    /// processors which don't.
+    #[inline]
+    pub const fn is_lock_free() -> bool {
+        Atomic::<T>::IS_LOCK_FREE
+    }
+
+    /// Returns a mutable reference to the underlying value.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.v.get() }
+    }
+
+    /// Consumes the atomic and returns the contained value.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+
+    /// Returns a raw pointer to the value, not including the lock word.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut T {
+        self.v.get()
+    }
+
+    // The orderings are only used by lock-free types. Otherwise taking the
+    // lock is an `Acquire` and releasing it a `Release`, like for the global
+    // table.
+    #[inline]
+    fn lock(&self) -> Guard<'_> {
+        let mut backoff = Backoff::new();
+        while self
+            .lock
+            .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.lock.load(Ordering::Relaxed) != 0 {
+                backoff.snooze();
+            }
+        }
+        Guard(&self.lock)
+    }
+
+    /// Loads a value from the `InlineAtomic`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> T {
+        match Self::NATIVE {
+            Some(native) => unsafe { (native.load)(self.v.get(), order) },
+            None => {
+                ops::check_load_ordering(order);
+                let _guard = self.lock();
+                unsafe { ptr::read(self.v.get()) }
+            }
+        }
+    }
+
+    /// Stores a value into the `InlineAtomic`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn store(&self, val: T, order: Ordering) {
+        match Self::NATIVE {
+            Some(native) => unsafe { (native.store)(self.v.get(), val, order) },
+            None => {
+                ops::check_store_ordering(order);
+                let _guard = self.lock();
+                unsafe { ptr::write(self.v.get(), val) }
+            }
+        }
+    }
+
+    /// Stores a value into the `InlineAtomic`, returning the old value.
+    #[inline]
+    pub fn swap(&self, val: T, order: Ordering) -> T {
+        match Self::NATIVE {
+            Some(native) => unsafe { (native.swap)(self.v.get(), val, order) },
+            None => {
+                let _guard = self.lock();
+                unsafe { ptr::replace(self.v.get(), val) }
+            }
+        }
+    }
+
+    /// Stores a value into the `InlineAtomic` if the current value is the
+    /// same as the `current` value.
+    ///
+    /// This works like `Atomic::compare_exchange`, including comparing the
+    /// values bytewise.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn compare_exchange(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        match Self::NATIVE {
+            Some(native) => unsafe {
+                (native.compare_exchange)(self.v.get(), current, new, success, failure)
+            },
+            None => {
+                ops::check_failure_ordering(failure);
+                let _guard = self.lock();
+                let val = unsafe { ptr::read(self.v.get()) };
+                if ops::same_bytes(&val, &current) {
+                    unsafe { ptr::write(self.v.get(), new) };
+                    Ok(val)
+                } else {
+                    Err(val)
+                }
+            }
+        }
+    }
+
+    /// Stores a value into the `InlineAtomic` if the current value is the
+    /// same as the `current` value.
+    ///
+    /// Unlike `compare_exchange`, this function is allowed to spuriously fail
+    /// when the type is lock-free.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn compare_exchange_weak(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        match Self::NATIVE {
+            Some(native) => unsafe {
+                (native.compare_exchange_weak)(self.v.get(), current, new, success, failure)
+            },
+            None => self.compare_exchange(current, new, success, failure),
+        }
+    }
+
+    /// Fetches the value, and applies a function to it that returns an
+    /// optional new value.
+    ///
+    /// This works like `Atomic::fetch_update`. When the type isn't lock-free
+    /// the function is called once, with the lock held, so it must not access
+    /// this `InlineAtomic`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fetch_order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn fetch_update<F>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<T, T>
+    where
+        F: FnMut(T) -> Option<T>,
+    {
+        match Self::NATIVE {
+            Some(_) => {
+                let mut prev = self.load(fetch_order);
+                while let Some(next) = f(prev) {
+                    match self.compare_exchange_weak(prev, next, set_order, fetch_order) {
+                        Ok(x) => return Ok(x),
+                        Err(next_prev) => prev = next_prev,
+                    }
+                }
+                Err(prev)
+            }
+            None => {
+                ops::check_failure_ordering(fetch_order);
+                let _guard = self.lock();
+                let prev = unsafe { ptr::read(self.v.get()) };
+                match f(prev) {
+                    Some(next) => {
+                        unsafe { ptr::write(self.v.get(), next) };
+                        Ok(prev)
+                    }
+                    None => Err(prev),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(any(loom, shuttle))))]
+mod tests {
+    use super::InlineAtomic;
+    use core::mem;
+    use std::thread;
+    use Ordering::*;
+
+    #[test]
+    fn layout() {
+        let word = mem::size_of::<usize>();
+        assert_eq!(mem::size_of::<InlineAtomic<[u8; 3]>>(), 2 * word);
+        assert_eq!(mem::size_of::<InlineAtomic<[u64; 4]>>(), 32 + word.max(8));
+        assert!(InlineAtomic::<u32>::is_lock_free());
+        assert!(!InlineAtomic::<[u64; 4]>::is_lock_free());
+
+        // Zeroed memory is unlocked.
+        let a: InlineAtomic<[u64; 4]> = unsafe { mem::zeroed() };
+        assert_eq!(format!("{:?}", a), "InlineAtomic([0, 0, 0, 0])");
+        assert_eq!(a.swap([1; 4], SeqCst), [0; 4]);
+        let r = a.compare_exchange([0; 4], [2; 4], SeqCst, SeqCst);
+        assert_eq!(r, Err([1; 4]));
+        let r = a.compare_exchange_weak([1; 4], [2; 4], SeqCst, SeqCst);
+        assert_eq!(r, Ok([1; 4]));
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, |_| None), Err([2; 4]));
+        assert_eq!(a.into_inner(), [2; 4]);
+    }
+
+    // Increments every element through both references, and checks that no
+    // update was lost and no value was torn.
+    fn increment_through(a: &InlineAtomic<[u64; 4]>, b: &InlineAtomic<[u64; 4]>) {
+        let rounds = if cfg!(miri) { 20 } else { 10_000 };
+        thread::scope(|s| {
+            for i in 0..4 {
+                let x = if i % 2 == 0 { a } else { b };
+                s.spawn(move || {
+                    for _ in 0..rounds {
+                        let f = |v: [u64; 4]| {
+                            assert!(v.iter().all(|&e| e == v[0]), "torn: {:?}", v);
+                            Some(v.map(|e| e + 1))
+                        };
+                        x.fetch_update(AcqRel, Acquire, f).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(a.load(SeqCst), [4 * rounds; 4]);
+        assert_eq!(b.load(SeqCst), [4 * rounds; 4]);
+    }
+
+    // Two mappings of the same memory, at different addresses, like in two
+    // processes sharing it. The global table would hand out different locks
+    // for the two addresses.
+    #[test]
+    #[cfg(all(target_os = "linux", not(miri)))]
+    fn shared_mapping() {
+        use std::os::raw::{c_char, c_int, c_long, c_uint, c_void};
+
+        extern "C" {
+            fn memfd_create(name: *const c_char, flags: c_uint) -> c_int;
+            fn ftruncate(fd: c_int, len: c_long) -> c_int;
+            fn mmap(
+                addr: *mut c_void,
+                len: usize,
+                prot: c_int,
+                flags: c_int,
+                fd: c_int,
+                offset: c_long,
+            ) -> *mut c_void;
+            fn munmap(addr: *mut c_void, len: usize) -> c_int;
+            fn close(fd: c_int) -> c_int;
+        }
+        const PROT_READ_WRITE: c_int = 3;
+        const MAP_SHARED: c_int = 1;
+        const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+        let len = 4096;
+        unsafe {
+            let fd = memfd_create(b"inline\0".as_ptr() as *const c_char, 0);
+            assert!(fd >= 0);
+            assert_eq!(ftruncate(fd, len as c_long), 0);
+            let map = || {
+                let p = mmap(
+                    core::ptr::null_mut(),
+                    len,
+                    PROT_READ_WRITE,
+                    MAP_SHARED,
+                    fd,
+                    0,
+                );
+                assert_ne!(p, MAP_FAILED);
+                p
+            };
+            let (p, q) = (map(), map());
+            assert_ne!(p, q);
+            {
+                // The file starts out zeroed, which is a valid value.
+                let a = &*(p as *const InlineAtomic<[u64; 4]>);
+                let b = &*(q as *const InlineAtomic<[u64; 4]>);
+                b.store([7; 4], Release);
+                assert_eq!(a.load(Acquire), [7; 4]);
+                a.store([0; 4], Release);
+                increment_through(a, b);
+            }
+            assert_eq!(munmap(p, len), 0);
+            assert_eq!(munmap(q, len), 0);
+            assert_eq!(close(fd), 0);
+        }
+    }
+
+    // The global lock for the value's address is held throughout, so any
+    // operation which took it would deadlock.
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn independent_of_global_table() {
+        use fallback::with_lock_held;
+
+        let a = InlineAtomic::new([0u64; 4]);
+        with_lock_held(a.as_ptr() as usize, || increment_through(&a, &a));
+    }
+}
@@ -21,41 +21,317 @@
 //!
 //! [1]: http://llvm.org/docs/LangRef.html#memory-model-for-concurrent-operations
 //!
+//! Methods panic when given an ordering which doesn't apply to them, like a
+//! `Release` load. With the `panic-free` feature, such an ordering loses the
+//! part which doesn't apply instead, so a `Release` load is `Relaxed` and an
+//! `AcqRel` store is `Release`.
+//!
 //! Atomic variables are safe to share between threads (they implement `Sync`)
 //! but they do not themselves provide the mechanism for sharing. The most
 //! common way to share an atomic variable is to put it into an `Arc` (an
 //! atomically-reference-counted shared pointer).
 //!
-//! Most atomic types may be stored in static variables, initialized using
-//! the `const fn` constructors (only available on nightly). Atomic statics
-//! are often used for lazy global initialization.
+//! Atomic types may be stored in static variables, initialized using the
+//! `const fn` constructor `Atomic::new`. Atomic statics are often used for
+//! lazy global initialization.
 
 #![warn(missing_docs)]
 #![no_std]
-#![cfg_attr(
-    feature = "nightly", feature(const_fn, cfg_target_has_atomic, atomic_min_max)
-)]
+#![cfg_attr(feature = "nightly", feature(core_intrinsics), allow(internal_features))]
 
-#[cfg(any(test, feature = "std"))]
+#[cfg(any(test, feature = "std", loom, shuttle))]
 #[macro_use]
 extern crate std;
 
+#[cfg(all(loom, shuttle))]
+compile_error!("`--cfg loom` and `--cfg shuttle` can't be used together");
+#[cfg(all(feature = "no-fallback", feature = "fallback-stats"))]
+compile_error!("the `no-fallback` and `fallback-stats` features can't be used together");
+#[cfg(all(
+    no_atomic_cas,
+    not(feature = "critical-section"),
+    not(feature = "no-fallback")
+))]
+compile_error!(
+    "this target has no compare-exchange, so atomics which need a lock require the \
+     `critical-section` feature (or `no-fallback` to only allow lock-free ones)"
+);
+#[cfg(all(no_atomic_cas, feature = "fallback-stats"))]
+compile_error!("the `fallback-stats` feature needs a target with compare-exchange");
+#[cfg(all(feature = "persist", not(target_arch = "x86_64")))]
+compile_error!("the `persist` feature is only supported on x86_64");
+#[cfg(all(single_threaded, feature = "fallback-stats"))]
+compile_error!("the `fallback-stats` feature needs a target with threads");
+#[cfg(all(feature = "nightly", feature = "portable-atomic"))]
+compile_error!("the `nightly` and `portable-atomic` features can't be used together");
+
+#[cfg(loom)]
+extern crate loom;
+#[cfg(shuttle)]
+extern crate shuttle;
+
+#[cfg(feature = "arbitrary")]
+extern crate arbitrary;
+#[cfg(feature = "atomic-traits")]
+extern crate atomic_traits;
+#[cfg(feature = "bitflags")]
+extern crate bitflags;
+#[cfg(feature = "bytemuck")]
+extern crate bytemuck;
+#[cfg(feature = "critical-section")]
+extern crate critical_section;
+#[cfg(feature = "portable-atomic")]
+extern crate portable_atomic;
+#[cfg(feature = "radium")]
+extern crate radium;
+#[cfg(feature = "zerocopy")]
+extern crate zerocopy;
+
 // Re-export some useful definitions from libcore
-pub use core::sync::atomic::{fence, Ordering};
+pub use core::sync::atomic::Ordering;
+#[cfg(not(any(loom, shuttle)))]
+pub use core::sync::atomic::fence;
+#[cfg(loom)]
+pub use loom::sync::atomic::fence;
+#[cfg(shuttle)]
+pub use shuttle::sync::atomic::fence;
 
+#[cfg(not(any(loom, shuttle)))]
 use core::cell::UnsafeCell;
 use core::fmt;
+use core::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU128,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize,
+};
+use core::ptr::NonNull;
 
 #[cfg(feature = "std")]
 use std::panic::RefUnwindSafe;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+use core::future::Future;
 
+mod any;
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl;
+mod array;
+#[cfg(feature = "atomic-traits")]
+mod atomic_traits_impl;
+mod backoff;
+mod bitfield;
+#[cfg(feature = "bitflags")]
+mod bitflags_impl;
+mod bitset;
+// Under loom and shuttle an `Atomic` refers to its model, so it can't be
+// zeroed.
+#[cfg(all(feature = "bytemuck", not(any(loom, shuttle))))]
+mod bytemuck_impl;
+#[cfg(all(debug_assertions, not(feature = "validate")))]
+mod char_check;
+#[cfg(detect_cmpxchg16b)]
+mod cmpxchg16b;
+// `AtomicDuration` needs 64-bit atomics, like `StampedU64`.
+#[cfg(any(
+    not(feature = "no-fallback"),
+    all(has_atomic_64, has_atomic_equal_alignment_64),
+    zacas_64
+))]
+mod duration;
+mod error;
+#[cfg(any(not(feature = "no-fallback"), single_threaded))]
 mod fallback;
-mod ops;
+#[cfg(all(
+    feature = "ffi",
+    has_atomic_8,
+    has_atomic_16,
+    has_atomic_32,
+    not(any(loom, shuttle, force_fallback))
+))]
+pub mod ffi;
+#[cfg(has_atomic_8)]
+mod flag;
+// The lock word needs a compare-exchange, and loom and shuttle can't model
+// memory which isn't allocated through them.
+#[cfg(all(has_atomic_ptr, not(any(loom, shuttle))))]
+mod inline;
+mod macros;
+mod markable;
+// The lock table under loom and shuttle only protects values in a shadow cell.
+#[cfg(all(not(feature = "no-fallback"), not(any(loom, shuttle))))]
+mod maybe_uninit;
+mod newtype;
+#[cfg(feature = "staleness-check")]
+mod misuse;
+#[cfg(all(feature = "nightly", not(any(loom, shuttle))))]
+mod nightly;
+#[cfg(all(feature = "no-fallback", not(single_threaded)))]
+mod no_fallback;
+pub mod ops;
+pub mod order;
+mod padded;
+mod pair;
+#[cfg(feature = "persist")]
+mod persist;
+#[cfg(feature = "radium")]
+mod radium_impl;
+#[cfg(has_atomic_32)]
+mod relptr;
+#[cfg(any(loom, shuttle))]
+mod shadow;
+pub mod slice;
+#[cfg(not(feature = "no-fallback"))]
+mod slot;
+// `StampedU64` needs 64-bit atomics, which aren't lock-free on every target.
+#[cfg(any(
+    not(feature = "no-fallback"),
+    all(has_atomic_64, has_atomic_equal_alignment_64),
+    zacas_64
+))]
+mod snapshot;
+mod stamped;
+#[cfg(feature = "staleness-check")]
+mod staleness;
+#[cfg(has_atomic_8)]
+mod std_atomic;
+mod tagged;
+pub mod types;
+#[cfg(feature = "validate")]
+mod validate;
+#[cfg(volatile_asm)]
+mod volatile;
+#[cfg(feature = "std")]
+mod wait;
+// The tests run on the host, with a compare-exchange of the standard library
+// in place of the riscv32 instruction.
+#[cfg(any(zacas_64, all(test, has_atomic_64)))]
+mod zacas;
+
+// The method examples live in `src/doc_examples` so that they can be shared
+// between the impls generated for each integer type. This module makes sure
+// the README example is compiled as a doctest along with them.
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+mod doc_examples {}
+
+#[cfg(feature = "fallback-stats")]
+pub use fallback::{fallback_stats, reset_fallback_stats, LockStats};
+pub use any::AnyAtomic;
+pub use array::AtomicArray;
+pub use backoff::Backoff;
+pub use bitfield::BitField;
+pub use bitset::AtomicBitSet;
+#[cfg(any(
+    not(feature = "no-fallback"),
+    all(has_atomic_64, has_atomic_equal_alignment_64),
+    zacas_64
+))]
+pub use duration::AtomicDuration;
+pub use error::{Error, ErrorKind};
+#[cfg(all(has_atomic_ptr, not(any(loom, shuttle))))]
+pub use inline::InlineAtomic;
+pub use markable::AtomicMarkablePtr;
+#[cfg(all(not(feature = "no-fallback"), not(any(loom, shuttle))))]
+pub use maybe_uninit::AtomicMaybeUninit;
+pub use newtype::{IntegerNewtype, PrimitiveInteger};
+#[cfg(not(feature = "no-fallback"))]
+pub use slot::AtomicSlot;
+#[cfg(has_atomic_8)]
+pub use flag::AtomicFlag;
+#[cfg(all(has_atomic_8, not(any(loom, shuttle))))]
+pub use flag::ATOMIC_FLAG_INIT;
+pub use padded::CachePadded;
+pub use pair::AtomicPair;
+#[cfg(has_atomic_32)]
+pub use relptr::RelPtr;
+#[cfg(any(
+    not(feature = "no-fallback"),
+    all(has_atomic_64, has_atomic_equal_alignment_64),
+    zacas_64
+))]
+pub use snapshot::{snapshot, try_snapshot, StampedU64};
+pub use stamped::{AtomicStamped, StampedValue};
+pub use tagged::AtomicTaggedPtr;
+pub use types::{AtomicF32, AtomicF64, AtomicI128, AtomicU128};
+#[cfg(feature = "std")]
+pub use wait::WaitResult;
+#[cfg(feature = "staleness-check")]
+pub use misuse::{set_misuse_handler, Misuse};
+#[cfg(feature = "staleness-check")]
+pub use staleness::set_staleness_thresholds;
+#[cfg(feature = "validate")]
+pub use validate::{register_validator, AtomicValidate};
+#[cfg(feature = "validate")]
+#[doc(hidden)]
+pub use validate::is_discriminant as __is_discriminant;
+
+// The operations which return values go through `checked`, which is `validate`
+// when the values are checked, and `char_check` in other debug builds.
+#[cfg(not(any(debug_assertions, feature = "validate")))]
+use ops as checked;
+#[cfg(all(debug_assertions, not(feature = "validate")))]
+use char_check as checked;
+#[cfg(feature = "validate")]
+use validate as checked;
+
+/// A store fence which orders all preceding streaming stores.
+///
+/// Streaming stores (`store_streaming`) are weakly ordered: they may become
+/// visible to other threads after later stores from the same thread, even
+/// `Release` ones. Calling `sfence` after a batch of streaming stores makes
+/// sure they are visible before any store which follows it, such as the one
+/// publishing the data.
+///
+/// This is the `sfence` instruction on x86 and a `Release` fence on other
+/// architectures.
+#[inline]
+pub fn sfence() {
+    ops::sfence()
+}
 
 /// A generic atomic wrapper type which allows an object to be safely shared
 /// between threads.
+///
+/// Zero-sized types such as `()` or `PhantomData<T>` are lock-free, and every
+/// operation on them is a no-op: loads return the only possible value and
+/// `compare_exchange` always succeeds. Since they don't access any memory,
+/// these operations don't synchronize with other threads.
+///
+/// The value must always be fully initialized, even in an
+/// `Atomic<MaybeUninit<T>>`, since the native operations access it as an
+/// integer. `AtomicMaybeUninit` holds values which may be uninitialized.
+///
+/// A shared reference to an atomic integer, float or `bool` supports the
+/// compound assignment operators which have a `fetch_*` method, so
+/// `counter += 1` with `mut counter: &Atomic<u64>` is `fetch_add(1, SeqCst)`.
+/// Since the ordering doesn't appear at the call site, these operators always
+/// use `SeqCst`; call the methods to choose a weaker one. Like the methods,
+/// integer arithmetic wraps around instead of panicking on overflow.
+///
+/// `Atomic<T>` is `#[repr(transparent)]` over `T`, so it has the same size,
+/// alignment and bit validity as `T` for every `T`. This is guaranteed and
+/// won't change in a semver-compatible release. In particular `Atomic<T>` is
+/// never more aligned than `T`: a type whose alignment is smaller than its
+/// size, such as `[u8; 4]`, or `u64` on 32-bit x86, keeps that alignment and
+/// isn't lock-free instead (see `is_lock_free`). The layout lets
+/// `get_mut_slice` and `from_mut_slice` convert between slices of values and
+/// of atomics in place, and `from_ptr` view memory owned by other code as an
+/// `Atomic`. It doesn't hold under `--cfg loom` or `--cfg shuttle`, where
+/// each `Atomic` also refers to its model, and those methods aren't
+/// available there.
+#[cfg_attr(not(any(loom, shuttle)), repr(transparent))]
+// `Immutable` is left out because of the interior mutability, so an `Atomic`
+// is overlaid on bytes with `FromBytes::mut_from_bytes` rather than
+// `ref_from_bytes` or `Ref::from_bytes`.
+#[cfg_attr(
+    all(feature = "zerocopy", not(any(loom, shuttle))),
+    derive(zerocopy::FromBytes, zerocopy::IntoBytes, zerocopy::KnownLayout)
+)]
 pub struct Atomic<T: Copy> {
+    #[cfg(not(any(loom, shuttle)))]
     v: UnsafeCell<T>,
+    #[cfg(any(loom, shuttle))]
+    v: shadow::ShadowCell<T>,
 }
 
 // Atomic<T> is only Sync if T is Send
@@ -88,7 +364,7 @@ impl<T: Copy + fmt::Debug> fmt::Debug for Atomic<T> {
 impl<T: Copy> Atomic<T> {
     /// Creates a new `Atomic`.
     #[inline]
-    #[cfg(feature = "nightly")]
+    #[cfg(not(any(loom, shuttle)))]
     pub const fn new(v: T) -> Atomic<T> {
         Atomic {
             v: UnsafeCell::new(v),
@@ -96,34 +372,155 @@ impl<T: Copy> Atomic<T> {
     }
 
     /// Creates a new `Atomic`.
+    ///
+    /// Under loom or shuttle this isn't a `const fn`, since it creates the
+    /// objects which model the `Atomic`.
     #[inline]
-    #[cfg(not(feature = "nightly"))]
+    #[cfg(any(loom, shuttle))]
     pub fn new(v: T) -> Atomic<T> {
         Atomic {
-            v: UnsafeCell::new(v),
+            v: shadow::ShadowCell::new(v),
         }
     }
 
+    /// Whether `Atomic` objects of this type are lock-free on every processor
+    /// of the target. This is the value of `is_lock_free()`, as a constant.
+    /// It is `false` for the 16-byte types which use `cmpxchg16b` where the
+    /// processor has it (see `is_lock_free_runtime`).
+    pub const IS_LOCK_FREE: bool = ops::atomic_is_lock_free::<T>();
+
+    /// Whether `load` and `store` on `Atomic` objects of this type use native
+    /// atomic instructions.
+    ///
+    /// This is true for every lock-free type. On targets which can load and
+    /// store atomically but have no compare-exchange, like the Cortex-M0
+    /// (`thumbv6m-none-eabi`), it is also true for types of up to the pointer
+    /// size which are aligned to their size: `load` and `store` are single
+    /// instructions, which can be used from an interrupt handler, while the
+    /// other operations run in a critical section. Targets without any
+    /// atomics, like MSP430, run every operation in the critical section.
+    #[doc = include_str!("doc_examples/load_store_lock_free.md")]
+    pub const LOAD_STORE_LOCK_FREE: bool = ops::atomic_is_load_store_lock_free::<T>();
+
     /// Checks if `Atomic` objects of this type are lock-free.
     ///
     /// If an `Atomic` is not lock-free then it may be implemented using locks
     /// internally, which makes it unsuitable for some situations (such as
     /// communicating with a signal handler).
+    ///
+    /// A type is lock-free if the target has native atomics of its size and
+    /// its alignment is at least its size. `Atomic<T>` has the same alignment
+    /// as `T`, since it can't pick an alignment based on the size of a generic
+    /// type, so types like `[u8; 4]` aren't lock-free. Wrapping them in a
+    /// `#[repr(align(4))]` newtype makes them lock-free.
+    ///
+    /// 16-byte types are lock-free with the `nightly` feature on targets with
+    /// native 128-bit atomics, and with the `portable-atomic` feature. On
+    /// x86_64 they are otherwise lock-free if the processor has the
+    /// `cmpxchg16b` instruction, which is detected at runtime, the first time
+    /// it is needed, which `is_lock_free_runtime` reports. This function only
+    /// counts them as lock-free if the target feature is enabled at compile
+    /// time, with `-C target-feature=+cmpxchg16b` or a `target-cpu` which has
+    /// it. The check costs a branch in each operation on a 16-byte value.
+    /// 16-byte types which are only 8-byte aligned, like `(u64, u64)`, always
+    /// take the lock. `AtomicPair` is 16-byte aligned, so it is the way to get
+    /// a lock-free pointer and counter pair against ABA.
+    ///
+    /// 8-byte types are lock-free on riscv32 when the Zacas extension is
+    /// enabled at compile time, with `-C target-feature=+zacas`, whose
+    /// `amocas.d` compare-exchange every other operation is built on.
+    ///
+    /// On wasm without the `atomics` target feature, like a plain
+    /// `wasm32-unknown-unknown` build, there is only ever one thread and every
+    /// type is lock-free: the types which would take the lock elsewhere are
+    /// read and written in place, without one.
+    ///
+    /// With the `portable-atomic` feature, this is what that crate's
+    /// `is_always_lock_free` says about the integer of the same size. Its
+    /// types which aren't always lock-free, like 16-byte ones on x86_64
+    /// without the `cmpxchg16b` target feature, aren't used, so they take the
+    /// lock of this crate instead.
+    ///
+    /// With the `no-fallback` feature, operations on types which aren't
+    /// lock-free fail to compile, so this is always `true` in code that
+    /// builds. The exception is `load` and `store` on types which are
+    /// `LOAD_STORE_LOCK_FREE`.
+    ///
+    /// On targets where 8-byte integers are only 4-byte aligned, like 32-bit
+    /// x86 on Linux, `Atomic<u64>` isn't lock-free by this definition. Values
+    /// which happen to be 8-byte aligned still use the native 64-bit atomics;
+    /// `ops::atomic_is_lock_free_at` tells whether that is the case.
+    ///
+    /// This is a `const fn`, so it can be used to check a type at compile
+    /// time; `assert_lock_free!` does that.
     #[inline]
-    #[cfg(feature = "nightly")]
     pub const fn is_lock_free() -> bool {
         ops::atomic_is_lock_free::<T>()
     }
 
-    /// Checks if `Atomic` objects of this type are lock-free.
+    /// Checks if `Atomic` objects of this type are lock-free on the processor
+    /// the program is running on.
     ///
-    /// If an `Atomic` is not lock-free then it may be implemented using locks
-    /// internally, which makes it unsuitable for some situations (such as
-    /// communicating with a signal handler).
+    /// This is the same as `is_lock_free`, except on x86_64, where 16-byte
+    /// types are also lock-free if the processor has the `cmpxchg16b`
+    /// instruction. Detecting it is a runtime check, so unlike `is_lock_free`
+    /// this isn't a `const fn`.
     #[inline]
-    #[cfg(not(feature = "nightly"))]
-    pub fn is_lock_free() -> bool {
-        ops::atomic_is_lock_free::<T>()
+    pub fn is_lock_free_runtime() -> bool {
+        ops::atomic_is_lock_free::<T>() || ops::has_cmpxchg16b::<T>()
+    }
+
+    /// Returns the index of the fallback lock slot used by this `Atomic`, or
+    /// `None` if it is lock-free.
+    ///
+    /// This can be matched against the `slot` field of the statistics returned
+    /// by `fallback_stats`.
+    #[inline]
+    #[cfg(feature = "fallback-stats")]
+    pub fn fallback_slot(&self) -> Option<usize> {
+        if ops::atomic_is_lock_free_at(self.v.get()) {
+            None
+        } else {
+            Some(fallback::fallback_slot(self.v.get() as usize))
+        }
+    }
+
+    /// Enables stale-read detection for this `Atomic`.
+    ///
+    /// Once enabled, a diagnostic is reported to the misuse handler if a
+    /// thread keeps observing the same value from `Relaxed` loads while other
+    /// threads modify the atomic, as configured by `set_staleness_thresholds`.
+    /// Only modifications made through this crate are counted.
+    ///
+    /// Instrumentation is keyed by address, so it must be disabled with
+    /// `disable_staleness_check` before the `Atomic` is moved or dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if too many atomics are already instrumented. Use
+    /// `try_enable_staleness_check` to handle this case.
+    #[cfg(feature = "staleness-check")]
+    pub fn enable_staleness_check(&self) {
+        if self.try_enable_staleness_check().is_err() {
+            panic!(
+                "too many atomics instrumented for stale-read detection (at most {})",
+                staleness::MAX_INSTRUMENTED
+            );
+        }
+    }
+
+    /// Enables stale-read detection for this `Atomic`, returning a
+    /// `CapacityExhausted` error if too many atomics are already
+    /// instrumented.
+    #[cfg(feature = "staleness-check")]
+    pub fn try_enable_staleness_check(&self) -> Result<(), Error> {
+        staleness::enable(self.v.get() as usize)
+    }
+
+    /// Disables stale-read detection for this `Atomic`.
+    #[cfg(feature = "staleness-check")]
+    pub fn disable_staleness_check(&self) {
+        staleness::disable(self.v.get() as usize);
     }
 
     /// Returns a mutable reference to the underlying type.
@@ -135,6 +532,106 @@ impl<T: Copy> Atomic<T> {
         unsafe { &mut *self.v.get() }
     }
 
+    /// Returns a mutable reference to the values of a slice of atomics.
+    ///
+    /// This is safe for the same reason as `get_mut`, and doesn't copy the
+    /// values since `Atomic<T>` has the same layout as `T`.
+    #[doc = include_str!("doc_examples/get_mut_slice.md")]
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub fn get_mut_slice(this: &mut [Atomic<T>]) -> &mut [T] {
+        unsafe { &mut *(this as *mut [Atomic<T>] as *mut [T]) }
+    }
+
+    /// Returns a mutable reference to the values of an array of atomics.
+    ///
+    /// This is the same as `get_mut_slice`, for arrays.
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub fn get_mut_array<const N: usize>(this: &mut [Atomic<T>; N]) -> &mut [T; N] {
+        unsafe { &mut *(this as *mut [Atomic<T>; N] as *mut [T; N]) }
+    }
+
+    /// Returns an atomic view of a mutable reference to a value.
+    ///
+    /// This is the inverse of `get_mut`. The mutable reference guarantees
+    /// that nothing else accesses the value while the `Atomic` is in use, and
+    /// `Atomic<T>` has the same layout as `T`.
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub fn from_mut(v: &mut T) -> &mut Atomic<T> {
+        unsafe { &mut *(v as *mut T as *mut Atomic<T>) }
+    }
+
+    /// Returns an atomic view of a mutable slice of values.
+    ///
+    /// This is the inverse of `get_mut_slice`. The atomics can then be shared
+    /// with other threads for as long as the slice is borrowed.
+    #[doc = include_str!("doc_examples/from_mut_slice.md")]
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub fn from_mut_slice(v: &mut [T]) -> &mut [Atomic<T>] {
+        unsafe { &mut *(v as *mut [T] as *mut [Atomic<T>]) }
+    }
+
+    /// Returns an atomic view of a value behind a raw pointer.
+    ///
+    /// This is for memory which is owned by other code, such as a buffer
+    /// shared with another process or handed out through FFI.
+    ///
+    /// # Safety
+    ///
+    /// - `ptr` must be aligned to `align_of::<T>()`, which is also the
+    ///   alignment of `Atomic<T>`.
+    /// - `ptr` must be valid for reads and writes for the whole lifetime
+    ///   `'a`, and point to a valid `T`.
+    /// - While the returned reference is in use, the value may only be
+    ///   accessed through `Atomic<T>`s. Non-atomic accesses, and atomic ones
+    ///   of a different size, must be synchronized with all of its uses.
+    ///   Values which aren't lock-free are protected by a lock which only
+    ///   `Atomic` takes.
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub unsafe fn from_ptr<'a>(ptr: *mut T) -> &'a Atomic<T> {
+        &*(ptr as *const Atomic<T>)
+    }
+
+    /// Loads the value with a plain, non-atomic read.
+    ///
+    /// This is for phases where the caller knows that nothing else accesses
+    /// the `Atomic`, such as after joining all the threads which used it, but
+    /// only has a shared reference. Values which are not lock-free are read
+    /// without taking their lock.
+    ///
+    /// # Safety
+    ///
+    /// No other access to this `Atomic`, atomic or not, may happen
+    /// concurrently with this one. Every earlier write must happen-before this
+    /// read, for example because the threads which made them were joined.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/load_unsynchronized.md"))]
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub unsafe fn load_unsynchronized(&self) -> T {
+        core::ptr::read(self.v.get())
+    }
+
+    /// Stores a value with a plain, non-atomic write.
+    ///
+    /// This is the counterpart of `load_unsynchronized`. Values which are not
+    /// lock-free are written without taking their lock.
+    ///
+    /// # Safety
+    ///
+    /// No other access to this `Atomic`, atomic or not, may happen
+    /// concurrently with this one. This write must happen-before every later
+    /// access from another thread, for example because that thread is spawned
+    /// afterwards.
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub unsafe fn store_unsynchronized(&self, val: T) {
+        core::ptr::write(self.v.get(), val)
+    }
+
     /// Consumes the atomic and returns the contained value.
     ///
     /// This is safe because passing `self` by value guarantees that no other threads are
@@ -144,6 +641,16 @@ impl<T: Copy> Atomic<T> {
         self.v.into_inner()
     }
 
+    /// Returns a mutable pointer to the underlying value.
+    ///
+    /// Accessing the value through this pointer while other threads use the
+    /// `Atomic` is only sound if those accesses are atomic themselves, and
+    /// only values which are valid for `T` may be written through it.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut T {
+        self.v.get()
+    }
+
     /// Loads a value from the `Atomic`.
     ///
     /// `load` takes an `Ordering` argument which describes the memory ordering
@@ -152,9 +659,38 @@ impl<T: Copy> Atomic<T> {
     /// # Panics
     ///
     /// Panics if `order` is `Release` or `AcqRel`.
+    ///
+    /// For `Atomic<char>`, debug builds also check that the stored bits are a
+    /// valid `char` and panic otherwise, here and in every other operation
+    /// which returns the stored value, like `swap`, `compare_exchange` and
+    /// `fetch_update`. Release builds trust the invariant that only valid
+    /// `char`s are ever stored, like the rest of the language does.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/load_store.md"))]
     #[inline]
+    #[cfg_attr(feature = "staleness-check", track_caller)]
     pub fn load(&self, order: Ordering) -> T {
-        unsafe { ops::atomic_load(self.v.get(), order) }
+        let val = unsafe { checked::atomic_load(self.v.get(), order) };
+        #[cfg(feature = "staleness-check")]
+        {
+            if let Ordering::Relaxed = order {
+                staleness::check_load(self.v.get() as usize, &val, core::panic::Location::caller());
+            }
+        }
+        val
+    }
+
+    /// Loads a value from the `Atomic` with consume ordering.
+    ///
+    /// Accesses which depend on the loaded value, such as dereferencing a
+    /// loaded pointer or indexing with a loaded index, are ordered after the
+    /// load like with `Acquire`, but unrelated accesses are not. On ARM and
+    /// PowerPC this is a relaxed load, which avoids the cost of a barrier.
+    /// Elsewhere, and for types which are not lock-free, it is an `Acquire`
+    /// load.
+    #[doc = include_str!("doc_examples/load_consume.md")]
+    #[inline]
+    pub fn load_consume(&self) -> T {
+        unsafe { checked::atomic_load_consume(self.v.get()) }
     }
 
     /// Stores a value into the `Atomic`.
@@ -165,6 +701,7 @@ impl<T: Copy> Atomic<T> {
     /// # Panics
     ///
     /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/load_store.md"))]
     #[inline]
     pub fn store(&self, val: T, order: Ordering) {
         unsafe {
@@ -176,9 +713,128 @@ impl<T: Copy> Atomic<T> {
     ///
     /// `swap` takes an `Ordering` argument which describes the memory ordering
     /// of this operation.
+    #[doc = include_str!("doc_examples/swap.md")]
     #[inline]
     pub fn swap(&self, val: T, order: Ordering) -> T {
-        unsafe { ops::atomic_swap(self.v.get(), val, order) }
+        unsafe { checked::atomic_swap(self.v.get(), val, order) }
+    }
+
+    /// Loads a value from the `Atomic` if it can be done without waiting.
+    ///
+    /// This always succeeds if `T` is lock-free. Otherwise it makes a single
+    /// attempt at taking the lock which protects the value, and returns `None`
+    /// if that lock is held, by another thread or by the code this one
+    /// interrupted.
+    ///
+    /// Together with `try_store` and `try_swap`, this is the only way to
+    /// access a value which is not lock-free from an async-signal context
+    /// such as a signal handler: every other operation may wait for the lock
+    /// forever if the interrupted code holds it. Note that the lock is shared
+    /// with other values whose address hashes to the same slot, so this can
+    /// also fail because of an operation on an unrelated `Atomic`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/try_load.md"))]
+    #[inline]
+    #[cfg_attr(feature = "staleness-check", track_caller)]
+    pub fn try_load(&self, order: Ordering) -> Option<T> {
+        if ops::atomic_is_lock_free_at(self.v.get()) {
+            Some(self.load(order))
+        } else {
+            unsafe { checked::atomic_try_load(self.v.get(), order) }
+        }
+    }
+
+    /// Stores a value into the `Atomic` if it can be done without waiting.
+    ///
+    /// This always succeeds if `T` is lock-free. Otherwise it makes a single
+    /// attempt at taking the lock which protects the value, and gives `val`
+    /// back as `Err(val)` if that lock is held. See `try_load` for when this
+    /// is useful.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/try_load.md"))]
+    #[inline]
+    pub fn try_store(&self, val: T, order: Ordering) -> Result<(), T> {
+        unsafe { ops::atomic_try_store(self.v.get(), val, order) }
+    }
+
+    /// Stores a value into the `Atomic` if it can be done without waiting,
+    /// returning the old value.
+    ///
+    /// This always succeeds if `T` is lock-free. Otherwise it makes a single
+    /// attempt at taking the lock which protects the value, and gives `val`
+    /// back as `Err(val)` if that lock is held. See `try_load` for when this
+    /// is useful.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/try_load.md"))]
+    #[inline]
+    pub fn try_swap(&self, val: T, order: Ordering) -> Result<T, T> {
+        unsafe { checked::atomic_try_swap(self.v.get(), val, order) }
+    }
+
+    /// Stores a value into the `Atomic`, returning the old value.
+    ///
+    /// This is the same as `swap`, named after `core::mem::replace`.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/replace.md"))]
+    #[inline]
+    pub fn replace(&self, val: T, order: Ordering) -> T {
+        self.swap(val, order)
+    }
+
+    /// Takes the value out of the `Atomic`, leaving `T::default()` in its
+    /// place.
+    ///
+    /// For `Option` payloads this leaves `None` behind, like `Option::take`.
+    #[doc = include_str!("doc_examples/take.md")]
+    #[inline]
+    pub fn take(&self, order: Ordering) -> T
+    where
+        T: Default,
+    {
+        self.swap(T::default(), order)
+    }
+
+    /// Stores `T::default()` into the `Atomic`, returning the old value.
+    ///
+    /// This is the same as `take`, named after `swap`.
+    #[inline]
+    pub fn swap_default(&self, order: Ordering) -> T
+    where
+        T: Default,
+    {
+        self.take(order)
+    }
+
+    /// Replaces the value with a function of it, returning the old value.
+    ///
+    /// This is `fetch_update` for a function which always produces a new
+    /// value. For lock-free types it is a compare-exchange loop, and `f` may
+    /// be called several times if the value is changed by other threads in
+    /// the meantime. For other types `f` is called exactly once, while the
+    /// lock which protects the value is held, so it should be short and must
+    /// not access other `Atomic`s which aren't lock-free.
+    ///
+    /// The orderings are the same as for `fetch_update`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fetch_order` is `Release` or `AcqRel`.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/replace_with.md"))]
+    #[inline]
+    pub fn replace_with<F: FnMut(T) -> T>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> T {
+        let f = |x| Some(f(x));
+        match unsafe { checked::atomic_fetch_update(self.v.get(), set_order, fetch_order, f) } {
+            Ok(x) | Err(x) => x,
+        }
     }
 
     /// Stores a value into the `Atomic` if the current value is the same as the
@@ -193,6 +849,7 @@ impl<T: Copy> Atomic<T> {
     /// the operation succeeds while the second describes the required ordering
     /// when the operation fails. The failure ordering can't be `Acquire` or
     /// `AcqRel` and must be equivalent or weaker than the success ordering.
+    #[doc = include_str!("doc_examples/compare_exchange.md")]
     #[inline]
     pub fn compare_exchange(
         &self,
@@ -201,7 +858,7 @@ impl<T: Copy> Atomic<T> {
         success: Ordering,
         failure: Ordering,
     ) -> Result<T, T> {
-        unsafe { ops::atomic_compare_exchange(self.v.get(), current, new, success, failure) }
+        unsafe { checked::atomic_compare_exchange(self.v.get(), current, new, success, failure) }
     }
 
     /// Stores a value into the `Atomic` if the current value is the same as the
@@ -218,6 +875,7 @@ impl<T: Copy> Atomic<T> {
     /// when the operation fails. The failure ordering can't be `Acquire` or
     /// `AcqRel` and must be equivalent or weaker than the success ordering.
     /// success ordering.
+    #[doc = include_str!("doc_examples/compare_exchange_weak.md")]
     #[inline]
     pub fn compare_exchange_weak(
         &self,
@@ -226,96 +884,781 @@ impl<T: Copy> Atomic<T> {
         success: Ordering,
         failure: Ordering,
     ) -> Result<T, T> {
-        unsafe { ops::atomic_compare_exchange_weak(self.v.get(), current, new, success, failure) }
+        unsafe {
+            checked::atomic_compare_exchange_weak(self.v.get(), current, new, success, failure)
+        }
     }
-}
 
-impl Atomic<bool> {
-    /// Logical "and" with a boolean value.
+    /// Stores `*new` into the `Atomic` if the current value is the same as
+    /// `*current`.
     ///
-    /// Performs a logical "and" operation on the current value and the argument
-    /// `val`, and sets the new value to the result.
+    /// This behaves exactly like `compare_exchange(*current, *new, success,
+    /// failure)`, but for values which aren't lock-free the comparison and the
+    /// copy of `*new` are done directly under the lock, which saves copying
+    /// large values into the call. Lock-free values are passed on to
+    /// `compare_exchange`.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/compare_exchange_ref.md"))]
+    #[inline]
+    pub fn compare_exchange_ref(
+        &self,
+        current: &T,
+        new: &T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        unsafe { checked::atomic_compare_exchange_ref(self.v.get(), current, new, success, failure) }
+    }
+
+    /// Stores `*new` into the `Atomic` if the current value is the same as
+    /// `*current`, and otherwise writes the current value into `*current`.
     ///
-    /// Returns the previous value.
+    /// Returns whether `*new` was stored. This is the same exchange as
+    /// `compare_exchange_ref`, except that the previous value isn't returned
+    /// by value: on success `*current` already holds it, and on failure it is
+    /// copied there under the lock. This matches C11's
+    /// `atomic_compare_exchange_strong` and suits a retry loop.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/compare_exchange_in_place.md"))]
     #[inline]
-    pub fn fetch_and(&self, val: bool, order: Ordering) -> bool {
-        unsafe { ops::atomic_and(self.v.get(), val, order) }
+    pub fn compare_exchange_in_place(
+        &self,
+        current: &mut T,
+        new: &T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> bool {
+        unsafe {
+            checked::atomic_compare_exchange_in_place(self.v.get(), current, new, success, failure)
+        }
     }
 
-    /// Logical "or" with a boolean value.
+    /// Stores a value into the `Atomic` if the current value is equal to
+    /// `current` according to `PartialEq`.
     ///
-    /// Performs a logical "or" operation on the current value and the argument
-    /// `val`, and sets the new value to the result.
+    /// `compare_exchange` compares the bytes of the values, so for example
+    /// `-0.0` doesn't match `0.0`. This method instead loads the value, checks
+    /// it with `==` and then does a bitwise `compare_exchange_weak` against
+    /// the exact value it loaded, retrying if that fails. Returns `Ok` with the
+    /// previous value if `new` was stored, or `Err` with a value which isn't
+    /// equal to `current`. As with `==`, a NaN never matches.
     ///
-    /// Returns the previous value.
+    /// This is not a single hardware compare-exchange. If another thread
+    /// replaces the value with a different one which is equal to it, for
+    /// example `0.0` with `-0.0`, in between the load and the exchange, the
+    /// exchange fails and is retried with the new value, so the change can't
+    /// be detected. For values which aren't lock-free the comparison is done
+    /// once under the lock.
+    ///
+    /// The orderings are the same as for `compare_exchange`.
+    #[doc = include_str!("doc_examples/compare_exchange_eq.md")]
     #[inline]
-    pub fn fetch_or(&self, val: bool, order: Ordering) -> bool {
-        unsafe { ops::atomic_or(self.v.get(), val, order) }
+    pub fn compare_exchange_eq(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T>
+    where
+        T: PartialEq,
+    {
+        let f = |x| if x == current { Some(new) } else { None };
+        unsafe { checked::atomic_fetch_update(self.v.get(), success, failure, f) }
     }
 
-    /// Logical "xor" with a boolean value.
+    /// Stores a value into the `Atomic` if the current value is the same as the
+    /// `current` value, returning whether the new value was written.
     ///
-    /// Performs a logical "xor" operation on the current value and the argument
-    /// `val`, and sets the new value to the result.
+    /// This is `compare_exchange(current, new, success, failure).is_ok()`.
+    #[doc = include_str!("doc_examples/compare_and_set.md")]
+    #[inline]
+    pub fn compare_and_set(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> bool {
+        self.compare_exchange(current, new, success, failure).is_ok()
+    }
+
+    /// Stores a value into the `Atomic` if the current value is the same as the
+    /// `current` value, returning the previous value.
     ///
-    /// Returns the previous value.
+    /// The failure ordering is derived from `order` the same way the
+    /// deprecated `compare_and_swap` in std did: `AcqRel` becomes `Acquire`,
+    /// `Release` becomes `Relaxed`, and all other orderings are unchanged.
+    #[doc = include_str!("doc_examples/compare_and_swap.md")]
+    #[deprecated(note = "Use `compare_exchange` or `compare_exchange_weak` instead")]
     #[inline]
-    pub fn fetch_xor(&self, val: bool, order: Ordering) -> bool {
-        unsafe { ops::atomic_xor(self.v.get(), val, order) }
+    pub fn compare_and_swap(&self, current: T, new: T, order: Ordering) -> T {
+        let failure = ops::strongest_failure_ordering(order);
+        match self.compare_exchange(current, new, order, failure) {
+            Ok(x) | Err(x) => x,
+        }
     }
-}
 
-macro_rules! atomic_ops_common {
-    ($($t:ty)*) => ($(
-        impl Atomic<$t> {
-            /// Add to the current value, returning the previous value.
-            #[inline]
-            pub fn fetch_add(&self, val: $t, order: Ordering) -> $t {
-                unsafe { ops::atomic_add(self.v.get(), val, order) }
+    /// Fetches the value, and applies a fallible function to it.
+    ///
+    /// If the function returns `Ok(new)`, the new value is stored and
+    /// `Ok(previous)` is returned. If it returns `Err(e)`, nothing is stored
+    /// and `Err(e)` is returned.
+    ///
+    /// The function may be called several times if the value is changed by
+    /// other threads in the meantime, but it only has an effect for the last
+    /// value it saw.
+    #[doc = include_str!("doc_examples/try_fetch_update.md")]
+    #[inline]
+    pub fn try_fetch_update<E, F: FnMut(T) -> Result<T, E>>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<T, E> {
+        let mut err = None;
+        let f = |x| match f(x) {
+            Ok(new) => Some(new),
+            Err(e) => {
+                err = Some(e);
+                None
             }
+        };
+        match unsafe { checked::atomic_fetch_update(self.v.get(), set_order, fetch_order, f) } {
+            Ok(prev) => Ok(prev),
+            Err(_) => Err(err.unwrap()),
+        }
+    }
 
-            /// Subtract from the current value, returning the previous value.
-            #[inline]
-            pub fn fetch_sub(&self, val: $t, order: Ordering) -> $t {
-                unsafe { ops::atomic_sub(self.v.get(), val, order) }
+    /// Spins until the value satisfies `pred`, returning that value.
+    ///
+    /// The value is loaded with `order` until `pred` returns `true` for it,
+    /// and the first such value is returned. Between attempts the thread
+    /// backs off with an increasing number of `spin_loop` hints, and with
+    /// the `std` feature it eventually yields to other threads.
+    ///
+    /// This is meant for values which are expected to change very soon. Use
+    /// `wait` to block the thread instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[doc = include_str!("doc_examples/spin_wait_until.md")]
+    #[inline]
+    pub fn spin_wait_until<F: Fn(T) -> bool>(&self, pred: F, order: Ordering) -> T {
+        let mut backoff = Backoff::new();
+        loop {
+            let val = self.load(order);
+            if pred(val) {
+                return val;
             }
+            backoff.snooze();
+        }
+    }
 
-            /// Bitwise and with the current value, returning the previous value.
-            #[inline]
-            pub fn fetch_and(&self, val: $t, order: Ordering) -> $t {
-                unsafe { ops::atomic_and(self.v.get(), val, order) }
-            }
+    /// Spins until the `Atomic` contains `expected`, returning it.
+    ///
+    /// Unlike `wait`, which blocks while the value is `expected`, this waits
+    /// for the value to become `expected`. Values are compared bytewise, like
+    /// `compare_exchange` does. See `spin_wait_until` for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/spin_wait_for.md"))]
+    #[inline]
+    pub fn spin_wait_for(&self, expected: T, order: Ordering) -> T {
+        self.spin_wait_until(|val| ops::same_bytes(&val, &expected), order)
+    }
 
-            /// Bitwise or with the current value, returning the previous value.
-            #[inline]
-            pub fn fetch_or(&self, val: $t, order: Ordering) -> $t {
-                unsafe { ops::atomic_or(self.v.get(), val, order) }
-            }
+    /// Blocks the current thread while the `Atomic` contains `expected`.
+    ///
+    /// The value is loaded with `order` and compared with `expected` bytewise,
+    /// like `compare_exchange` does. If they are equal, the thread sleeps
+    /// until another thread calls `notify_one` or `notify_all` on this
+    /// `Atomic`, and then compares again. It returns once a different value
+    /// was observed.
+    ///
+    /// The OS may wake the thread spuriously, and a notification may wake
+    /// more threads than requested; both are handled by comparing the value
+    /// again. A value which changes and then changes back before the thread
+    /// gets to look at it is not noticed, so the thread keeps waiting.
+    ///
+    /// Waiting uses `futex` on Linux, `__ulock_wait` on macOS and
+    /// `WaitOnAddress` on Windows when they support the type, and a global
+    /// table of condition variables otherwise.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[doc = include_str!("doc_examples/wait.md")]
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn wait(&self, expected: T, order: Ordering) {
+        let _ = unsafe { wait::atomic_wait(self.v.get(), expected, order, None) };
+    }
 
-            /// Bitwise xor with the current value, returning the previous value.
-            #[inline]
-            pub fn fetch_xor(&self, val: $t, order: Ordering) -> $t {
-                unsafe { ops::atomic_xor(self.v.get(), val, order) }
-            }
-        }
-    )*);
-}
-macro_rules! atomic_ops_signed {
-    ($($t:ty)*) => (
-        atomic_ops_common!{ $($t)* }
-        $(
-            impl Atomic<$t> {
+    /// Blocks the current thread while the `Atomic` contains `expected`, for
+    /// at most `timeout`.
+    ///
+    /// This works like `wait`, but gives up once `timeout` has elapsed.
+    /// Spurious wakeups are handled internally, so `WaitResult::TimedOut`
+    /// means the value was still `expected` when the timeout elapsed, and
+    /// `WaitResult::Changed` that a different value was observed. A zero
+    /// timeout only checks the value, and a timeout too large to represent
+    /// never elapses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[doc = include_str!("doc_examples/wait_timeout.md")]
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn wait_timeout(&self, expected: T, order: Ordering, timeout: Duration) -> WaitResult {
+        let deadline = Instant::now().checked_add(timeout);
+        unsafe { wait::atomic_wait(self.v.get(), expected, order, deadline) }
+    }
+
+    /// Blocks the current thread while the `Atomic` contains `expected`,
+    /// until `deadline` at the latest.
+    ///
+    /// This works like `wait_timeout`. If the deadline has already passed,
+    /// only the value is checked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[doc = include_str!("doc_examples/wait_deadline.md")]
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn wait_deadline(&self, expected: T, order: Ordering, deadline: Instant) -> WaitResult {
+        unsafe { wait::atomic_wait(self.v.get(), expected, order, Some(deadline)) }
+    }
+
+    /// Returns a future which resolves once the `Atomic` no longer contains
+    /// `expected`.
+    ///
+    /// This is the asynchronous version of `wait`: the value is loaded with
+    /// `order` and compared with `expected` bytewise, and while they are equal
+    /// the future stays pending until `notify_one` or `notify_all` is called
+    /// on this `Atomic`. It resolves to the different value which was
+    /// observed. It doesn't depend on any particular executor.
+    ///
+    /// A future which is dropped before it is woken up leaves nothing behind,
+    /// and one which is dropped after being woken by `notify_one` but before
+    /// it was polled passes the notification on to another waiting task.
+    ///
+    /// This is only available with the `async` feature, and not under loom
+    /// or shuttle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[doc = include_str!("doc_examples/wait_async.md")]
+    #[cfg(all(feature = "async", not(any(loom, shuttle))))]
+    #[inline]
+    pub fn wait_async(&self, expected: T, order: Ordering) -> impl Future<Output = T> + '_ {
+        wait::WaitFuture::new(self, expected, order)
+    }
+
+    /// Wakes up one thread blocked in `wait` on this `Atomic`, and one task
+    /// waiting in `wait_async`.
+    ///
+    /// This must be called after the value was changed. When the type can't
+    /// be waited on by the OS, all waiting threads are woken up.
+    #[doc = include_str!("doc_examples/notify_one.md")]
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn notify_one(&self) {
+        unsafe { wait::atomic_notify(self.v.get(), false) }
+    }
+
+    /// Wakes up all threads blocked in `wait` on this `Atomic`, and all tasks
+    /// waiting in `wait_async`.
+    ///
+    /// This must be called after the value was changed.
+    #[doc = include_str!("doc_examples/notify_all.md")]
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn notify_all(&self) {
+        unsafe { wait::atomic_notify(self.v.get(), true) }
+    }
+
+    /// Fetches the value, and applies a function to it that returns an
+    /// optional new value.
+    ///
+    /// The function is called repeatedly until the new value is stored
+    /// without interference from other threads. Returns `Ok(previous)` if the
+    /// function returned `Some`, and `Err(previous)` if it returned `None`.
+    ///
+    /// `set_order` is the ordering of the store and `fetch_order` the
+    /// ordering of the loads, with the same restrictions as the success and
+    /// failure orderings of `compare_exchange`.
+    ///
+    /// For lock-free types this is a loop of `compare_exchange_weak`, which
+    /// compares values by their bytes like `compare_exchange`, so a float
+    /// which is NaN doesn't make it retry forever. For other types the
+    /// function is called once, with the lock held, so it must not perform
+    /// atomic operations on values which aren't lock-free: the same lock may
+    /// protect them.
+    #[cfg_attr(not(feature = "no-fallback"), doc = include_str!("doc_examples/fetch_update.md"))]
+    #[inline]
+    pub fn fetch_update<F: FnMut(T) -> Option<T>>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        f: F,
+    ) -> Result<T, T> {
+        unsafe { checked::atomic_fetch_update(self.v.get(), set_order, fetch_order, f) }
+    }
+
+    /// Fetches the value, and applies a function to it that returns an
+    /// optional new value, returning the new value.
+    ///
+    /// This works like `fetch_update`, except that `Ok` contains the value
+    /// which was stored rather than the previous one. If the function
+    /// returns `None`, nothing is stored and `Err(previous)` is returned.
+    #[doc = include_str!("doc_examples/update_and_fetch.md")]
+    #[inline]
+    pub fn update_and_fetch<F: FnMut(T) -> Option<T>>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        f: F,
+    ) -> Result<T, T> {
+        self.fetch_update_both(set_order, fetch_order, f).map(|(_, new)| new)
+    }
+
+    /// Fetches the value, and applies a function to it that returns an
+    /// optional new value, returning both the previous and the new value.
+    ///
+    /// This works like `fetch_update`, except that `Ok` contains the pair
+    /// `(previous, new)` for the exchange which succeeded. If the function
+    /// returns `None`, nothing is stored and `Err(previous)` is returned.
+    #[doc = include_str!("doc_examples/fetch_update_both.md")]
+    #[inline]
+    pub fn fetch_update_both<F: FnMut(T) -> Option<T>>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<(T, T), T> {
+        // The closure is called once more after every failed exchange, so
+        // the last value it produced is the one which got stored.
+        let mut new = None;
+        let f = |x| {
+            new = f(x);
+            new
+        };
+        match unsafe { checked::atomic_fetch_update(self.v.get(), set_order, fetch_order, f) } {
+            Ok(prev) => Ok((prev, new.unwrap())),
+            Err(prev) => Err(prev),
+        }
+    }
+}
+
+impl Atomic<bool> {
+    /// Logical "and" with a boolean value.
+    ///
+    /// Performs a logical "and" operation on the current value and the argument
+    /// `val`, and sets the new value to the result.
+    ///
+    /// Returns the previous value.
+    #[doc = include_str!("doc_examples/bool_fetch_and.md")]
+    #[inline]
+    pub fn fetch_and(&self, val: bool, order: Ordering) -> bool {
+        unsafe { ops::atomic_and(self.v.get(), val, order) }
+    }
+
+    /// Logical "or" with a boolean value.
+    ///
+    /// Performs a logical "or" operation on the current value and the argument
+    /// `val`, and sets the new value to the result.
+    ///
+    /// Returns the previous value.
+    #[doc = include_str!("doc_examples/bool_fetch_or.md")]
+    #[inline]
+    pub fn fetch_or(&self, val: bool, order: Ordering) -> bool {
+        unsafe { ops::atomic_or(self.v.get(), val, order) }
+    }
+
+    /// Logical "xor" with a boolean value.
+    ///
+    /// Performs a logical "xor" operation on the current value and the argument
+    /// `val`, and sets the new value to the result.
+    ///
+    /// Returns the previous value.
+    #[doc = include_str!("doc_examples/bool_fetch_xor.md")]
+    #[inline]
+    pub fn fetch_xor(&self, val: bool, order: Ordering) -> bool {
+        unsafe { ops::atomic_xor(self.v.get(), val, order) }
+    }
+
+    /// Logical "nand" with a boolean value.
+    ///
+    /// Performs a logical "nand" operation on the current value and the
+    /// argument `val`, and sets the new value to the result.
+    ///
+    /// Returns the previous value.
+    #[doc = include_str!("doc_examples/bool_fetch_nand.md")]
+    #[inline]
+    pub fn fetch_nand(&self, val: bool, order: Ordering) -> bool {
+        // A bitwise nand on the underlying byte would turn `true` into an
+        // invalid bit pattern, so express it in terms of operations which
+        // only ever produce 0 or 1: !(x & true) == x ^ true and
+        // !(x & false) == true.
+        if val {
+            self.fetch_xor(true, order)
+        } else {
+            self.swap(true, order)
+        }
+    }
+
+    /// Logical "not" of the current value.
+    ///
+    /// Sets the new value to the logical negation of the current value, which
+    /// toggles the flag.
+    ///
+    /// Returns the previous value.
+    #[doc = include_str!("doc_examples/bool_fetch_not.md")]
+    #[inline]
+    pub fn fetch_not(&self, order: Ordering) -> bool {
+        self.fetch_xor(true, order)
+    }
+}
+
+macro_rules! atomic_ops_common {
+    ($($t:ty)*) => ($(
+        impl Atomic<$t> {
+            /// Stores a value using a non-temporal (streaming) store.
+            ///
+            /// On x86, 32-bit and 64-bit values (64-bit only on x86_64) are
+            /// stored with `movnti`, which writes around the cache instead of
+            /// evicting lines from it. This is useful when filling large
+            /// amounts of memory which won't be read soon. Other widths and
+            /// architectures use a `Relaxed` store.
+            ///
+            /// A streaming store provides at most `Relaxed` ordering, and on
+            /// x86 it isn't even ordered with respect to later stores from the
+            /// same thread. `sfence` must be called after the last streaming
+            /// store and before the data is published to other threads.
+            ///
+            /// # Safety
+            ///
+            /// No other thread may access this atomic between the streaming
+            /// store and the following `sfence`. `slice::fill_streaming`
+            /// provides a safe alternative for filling a slice.
+            #[doc = include_str!("doc_examples/store_streaming.md")]
+            #[inline]
+            pub unsafe fn store_streaming(&self, val: $t) {
+                ops::atomic_store_streaming(self.v.get(), val)
+            }
+
+            /// Add to the current value, returning the previous value.
+            #[doc = include_str!("doc_examples/fetch_add.md")]
+            #[inline]
+            pub fn fetch_add(&self, val: $t, order: Ordering) -> $t {
+                unsafe { ops::atomic_add(self.v.get(), val, order) }
+            }
+
+            /// Subtract from the current value, returning the previous value.
+            #[doc = include_str!("doc_examples/fetch_sub.md")]
+            #[inline]
+            pub fn fetch_sub(&self, val: $t, order: Ordering) -> $t {
+                unsafe { ops::atomic_sub(self.v.get(), val, order) }
+            }
+
+            /// Saturating add to the current value, returning the previous
+            /// value.
+            ///
+            /// The result is clamped to the range of the type instead of
+            /// wrapping around. This is implemented with a
+            /// `compare_exchange_weak` loop which returns without storing
+            /// anything if the addition wouldn't change the value, for
+            /// example because it is already saturated.
+            #[doc = include_str!("doc_examples/fetch_saturating_add.md")]
+            #[inline]
+            pub fn fetch_saturating_add(&self, val: $t, order: Ordering) -> $t {
+                let f = |x: $t| {
+                    let new = x.saturating_add(val);
+                    if new == x { None } else { Some(new) }
+                };
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+
+            /// Saturating subtract from the current value, returning the
+            /// previous value.
+            ///
+            /// This is implemented like `fetch_saturating_add`.
+            #[doc = include_str!("doc_examples/fetch_saturating_sub.md")]
+            #[inline]
+            pub fn fetch_saturating_sub(&self, val: $t, order: Ordering) -> $t {
+                let f = |x: $t| {
+                    let new = x.saturating_sub(val);
+                    if new == x { None } else { Some(new) }
+                };
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+
+            /// Add to the current value if the result doesn't overflow.
+            ///
+            /// Returns `Some` with the previous value if the addition was
+            /// performed, or `None` without modifying the value if it would
+            /// overflow. This is implemented with a `compare_exchange_weak`
+            /// loop.
+            #[doc = include_str!("doc_examples/fetch_add_checked.md")]
+            #[inline]
+            pub fn fetch_add_checked(&self, val: $t, order: Ordering) -> Option<$t> {
+                let f = |x: $t| x.checked_add(val);
+                let fetch_order = ops::failure_ordering(order);
+                unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f).ok() }
+            }
+
+            /// Subtract from the current value if the result doesn't
+            /// overflow.
+            ///
+            /// This is implemented like `fetch_add_checked`.
+            #[doc = include_str!("doc_examples/fetch_sub_checked.md")]
+            #[inline]
+            pub fn fetch_sub_checked(&self, val: $t, order: Ordering) -> Option<$t> {
+                let f = |x: $t| x.checked_sub(val);
+                let fetch_order = ops::failure_ordering(order);
+                unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f).ok() }
+            }
+
+            /// Add to the current value unless the result would be greater
+            /// than `limit`.
+            ///
+            /// Returns `Ok` with the previous value if the addition was
+            /// performed, or `Err` with the current value without modifying
+            /// it if the result would be greater than `limit` or overflow.
+            /// Unlike `fetch_saturating_add`, which adds as much as fits
+            /// below the bound, this either adds all of `val` or nothing. This
+            /// is implemented with a `compare_exchange_weak` loop.
+            #[doc = include_str!("doc_examples/fetch_add_unless.md")]
+            #[inline]
+            pub fn fetch_add_unless(&self, val: $t, limit: $t, order: Ordering) -> Result<$t, $t> {
+                let f = |x: $t| x.checked_add(val).filter(|&new| new <= limit);
+                let fetch_order = ops::failure_ordering(order);
+                unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) }
+            }
+
+            /// Clamp the current value to the range `[min, max]`, returning
+            /// the previous value.
+            ///
+            /// This is implemented with a `compare_exchange_weak` loop which
+            /// only stores a new value if the current one is out of range.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `min > max`, like `Ord::clamp`.
+            #[doc = include_str!("doc_examples/fetch_clamp.md")]
+            #[inline]
+            pub fn fetch_clamp(&self, min: $t, max: $t, order: Ordering) -> $t {
+                assert!(min <= max);
+                let f = |x: $t| {
+                    let new = x.clamp(min, max);
+                    if new == x { None } else { Some(new) }
+                };
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+
+            /// Raise the current value to at least `val`, returning whether
+            /// this call changed it.
+            ///
+            /// Unlike `fetch_max`, nothing is stored if the current value is
+            /// already greater than or equal to `val`.
+            #[doc = include_str!("doc_examples/store_max.md")]
+            #[inline]
+            pub fn store_max(&self, val: $t, order: Ordering) -> bool {
+                let f = |x: $t| if val > x { Some(val) } else { None };
+                let fetch_order = ops::failure_ordering(order);
+                unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) }.is_ok()
+            }
+
+            /// Lower the current value to at most `val`, returning whether
+            /// this call changed it.
+            ///
+            /// Unlike `fetch_min`, nothing is stored if the current value is
+            /// already less than or equal to `val`.
+            #[doc = include_str!("doc_examples/store_min.md")]
+            #[inline]
+            pub fn store_min(&self, val: $t, order: Ordering) -> bool {
+                let f = |x: $t| if val < x { Some(val) } else { None };
+                let fetch_order = ops::failure_ordering(order);
+                unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) }.is_ok()
+            }
+
+            /// Multiply the current value, returning the previous value.
+            ///
+            /// The multiplication wraps around on overflow. There is no native
+            /// instruction for this, so it is implemented with a
+            /// `compare_exchange_weak` loop, or under the lock for types which
+            /// are not lock-free.
+            #[doc = include_str!("doc_examples/fetch_mul.md")]
+            #[inline]
+            pub fn fetch_mul(&self, val: $t, order: Ordering) -> $t {
+                let f = |x: $t| Some(x.wrapping_mul(val));
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+
+            /// Divide the current value, returning the previous value.
+            ///
+            /// The division wraps around on overflow, which only happens when
+            /// dividing the minimum value of a signed type by -1. It is
+            /// implemented like `fetch_mul`.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `val` is 0, without modifying the value. Use
+            /// `try_fetch_div` to handle that case instead.
+            #[doc = include_str!("doc_examples/fetch_div.md")]
+            #[inline]
+            pub fn fetch_div(&self, val: $t, order: Ordering) -> $t {
+                match self.try_fetch_div(val, order) {
+                    Ok(x) => x,
+                    Err(_) => panic!("attempt to divide by zero"),
+                }
+            }
+
+            /// Divide the current value, returning the previous value, or
+            /// `Err` with the current value if `val` is 0.
+            ///
+            /// The value is left unmodified if `val` is 0.
+            #[doc = include_str!("doc_examples/try_fetch_div.md")]
+            #[inline]
+            pub fn try_fetch_div(&self, val: $t, order: Ordering) -> Result<$t, $t> {
+                let f = |x: $t| if val == 0 { None } else { Some(x.wrapping_div(val)) };
+                let fetch_order = ops::failure_ordering(order);
+                unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) }
+            }
+
+            /// Bitwise and with the current value, returning the previous value.
+            #[doc = include_str!("doc_examples/fetch_and.md")]
+            #[inline]
+            pub fn fetch_and(&self, val: $t, order: Ordering) -> $t {
+                unsafe { ops::atomic_and(self.v.get(), val, order) }
+            }
+
+            /// Bitwise or with the current value, returning the previous value.
+            #[doc = include_str!("doc_examples/fetch_or.md")]
+            #[inline]
+            pub fn fetch_or(&self, val: $t, order: Ordering) -> $t {
+                unsafe { ops::atomic_or(self.v.get(), val, order) }
+            }
+
+            /// Bitwise xor with the current value, returning the previous value.
+            #[doc = include_str!("doc_examples/fetch_xor.md")]
+            #[inline]
+            pub fn fetch_xor(&self, val: $t, order: Ordering) -> $t {
+                unsafe { ops::atomic_xor(self.v.get(), val, order) }
+            }
+
+            /// Rotate the bits of the current value to the left by `n`,
+            /// returning the previous value.
+            ///
+            /// This is implemented with a `compare_exchange_weak` loop.
+            /// Nothing is stored if the rotation doesn't change the value, in
+            /// particular when `n` is a multiple of the bit width.
+            #[doc = include_str!("doc_examples/fetch_rotate_left.md")]
+            #[inline]
+            pub fn fetch_rotate_left(&self, n: u32, order: Ordering) -> $t {
+                let f = |x: $t| {
+                    let new = x.rotate_left(n);
+                    if new == x { None } else { Some(new) }
+                };
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+
+            /// Rotate the bits of the current value to the right by `n`,
+            /// returning the previous value.
+            ///
+            /// This is implemented like `fetch_rotate_left`.
+            #[doc = include_str!("doc_examples/fetch_rotate_right.md")]
+            #[inline]
+            pub fn fetch_rotate_right(&self, n: u32, order: Ordering) -> $t {
+                let f = |x: $t| {
+                    let new = x.rotate_right(n);
+                    if new == x { None } else { Some(new) }
+                };
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+
+            /// Bitwise not of the current value, returning the previous value.
+            #[doc = include_str!("doc_examples/fetch_not.md")]
+            #[inline]
+            pub fn fetch_not(&self, order: Ordering) -> $t {
+                self.fetch_xor(!0, order)
+            }
+        }
+    )*);
+}
+macro_rules! atomic_ops_signed {
+    ($($t:ty)*) => (
+        atomic_ops_common!{ $($t)* }
+        $(
+            impl Atomic<$t> {
                 /// Minimum with the current value.
+                #[doc = include_str!("doc_examples/fetch_min.md")]
                 #[inline]
                 pub fn fetch_min(&self, val: $t, order: Ordering) -> $t {
                     unsafe { ops::atomic_min(self.v.get(), val, order) }
                 }
 
                 /// Maximum with the current value.
+                #[doc = include_str!("doc_examples/fetch_max.md")]
                 #[inline]
                 pub fn fetch_max(&self, val: $t, order: Ordering) -> $t {
                     unsafe { ops::atomic_max(self.v.get(), val, order) }
                 }
+
+                /// Negate the current value, returning the previous value.
+                ///
+                /// The negation wraps around, so negating the minimum value
+                /// leaves it unchanged. This is implemented with a
+                /// `compare_exchange_weak` loop.
+                #[doc = include_str!("doc_examples/fetch_neg.md")]
+                #[inline]
+                pub fn fetch_neg(&self, order: Ordering) -> $t {
+                    let f = |x: $t| Some(x.wrapping_neg());
+                    let fetch_order = ops::failure_ordering(order);
+                    match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                        Ok(x) | Err(x) => x,
+                    }
+                }
+
+                /// Replace the current value with its absolute value,
+                /// returning the previous value.
+                ///
+                /// Like `wrapping_abs`, the absolute value of the minimum value
+                /// is the minimum value itself, which is left unchanged.
+                /// Nothing is stored if the value doesn't change. This is
+                /// implemented with a `compare_exchange_weak` loop.
+                #[doc = include_str!("doc_examples/fetch_abs.md")]
+                #[inline]
+                pub fn fetch_abs(&self, order: Ordering) -> $t {
+                    let f = |x: $t| if x < 0 && x != <$t>::MIN { Some(-x) } else { None };
+                    let fetch_order = ops::failure_ordering(order);
+                    match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                        Ok(x) | Err(x) => x,
+                    }
+                }
             }
         )*
     );
@@ -326,16 +1669,74 @@ macro_rules! atomic_ops_unsigned {
         $(
             impl Atomic<$t> {
                 /// Minimum with the current value.
+                #[doc = include_str!("doc_examples/fetch_min.md")]
                 #[inline]
                 pub fn fetch_min(&self, val: $t, order: Ordering) -> $t {
                     unsafe { ops::atomic_umin(self.v.get(), val, order) }
                 }
 
                 /// Maximum with the current value.
+                #[doc = include_str!("doc_examples/fetch_max.md")]
                 #[inline]
                 pub fn fetch_max(&self, val: $t, order: Ordering) -> $t {
                     unsafe { ops::atomic_umax(self.v.get(), val, order) }
                 }
+
+                /// Sets a single bit, returning its previous state.
+                ///
+                /// This is a `fetch_or` with a single-bit mask, which compiles
+                /// to `lock bts` on x86.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `bit` is not less than the bit width of the type.
+                #[doc = include_str!("doc_examples/bit_set.md")]
+                #[inline]
+                pub fn bit_set(&self, bit: u32, order: Ordering) -> bool {
+                    let mask = Self::bit_mask(bit);
+                    self.fetch_or(mask, order) & mask != 0
+                }
+
+                /// Clears a single bit, returning its previous state.
+                ///
+                /// This is a `fetch_and` with a single-bit mask, which compiles
+                /// to `lock btr` on x86.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `bit` is not less than the bit width of the type.
+                #[doc = include_str!("doc_examples/bit_clear.md")]
+                #[inline]
+                pub fn bit_clear(&self, bit: u32, order: Ordering) -> bool {
+                    let mask = Self::bit_mask(bit);
+                    self.fetch_and(!mask, order) & mask != 0
+                }
+
+                /// Toggles a single bit, returning its previous state.
+                ///
+                /// This is a `fetch_xor` with a single-bit mask, which compiles
+                /// to `lock btc` on x86.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `bit` is not less than the bit width of the type.
+                #[doc = include_str!("doc_examples/bit_toggle.md")]
+                #[inline]
+                pub fn bit_toggle(&self, bit: u32, order: Ordering) -> bool {
+                    let mask = Self::bit_mask(bit);
+                    self.fetch_xor(mask, order) & mask != 0
+                }
+
+                #[inline]
+                fn bit_mask(bit: u32) -> $t {
+                    assert!(
+                        bit < <$t>::BITS,
+                        "bit index {} out of range for {}",
+                        bit,
+                        stringify!($t)
+                    );
+                    1 << bit
+                }
             }
         )*
     );
@@ -343,49 +1744,433 @@ macro_rules! atomic_ops_unsigned {
 atomic_ops_signed!{ i8 i16 i32 i64 isize i128 }
 atomic_ops_unsigned!{ u8 u16 u32 u64 usize u128 }
 
-#[cfg(test)]
-mod tests {
-    use core::mem;
-    use Atomic;
-    use Ordering::*;
+// `NonZero*` integers only get the operations which can't produce a zero.
+// Arithmetic and bitwise operations are left out since they could wrap around
+// to zero or clear every bit, which would create an invalid value.
+macro_rules! atomic_ops_nonzero {
+    ($($t:ident $min:ident $max:ident)*) => ($(
+        impl Atomic<$t> {
+            /// Minimum with the current value.
+            ///
+            /// The result is always one of the two non-zero operands, so it is
+            /// never zero.
+            #[doc = include_str!("doc_examples/nonzero_fetch_min.md")]
+            #[inline]
+            pub fn fetch_min(&self, val: $t, order: Ordering) -> $t {
+                unsafe { ops::$min(self.v.get(), val, order) }
+            }
 
-    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
-    struct Foo(u8, u8);
-    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
-    struct Bar(u64, u64);
-    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
-    struct Quux(u32);
+            /// Maximum with the current value.
+            ///
+            /// The result is always one of the two non-zero operands, so it is
+            /// never zero.
+            #[doc = include_str!("doc_examples/nonzero_fetch_max.md")]
+            #[inline]
+            pub fn fetch_max(&self, val: $t, order: Ordering) -> $t {
+                unsafe { ops::$max(self.v.get(), val, order) }
+            }
+        }
+    )*);
+}
+atomic_ops_nonzero!{
+    NonZeroI8 atomic_min atomic_max
+    NonZeroI16 atomic_min atomic_max
+    NonZeroI32 atomic_min atomic_max
+    NonZeroI64 atomic_min atomic_max
+    NonZeroIsize atomic_min atomic_max
+    NonZeroI128 atomic_min atomic_max
+    NonZeroU8 atomic_umin atomic_umax
+    NonZeroU16 atomic_umin atomic_umax
+    NonZeroU32 atomic_umin atomic_umax
+    NonZeroU64 atomic_umin atomic_umax
+    NonZeroUsize atomic_umin atomic_umax
+    NonZeroU128 atomic_umin atomic_umax
+}
 
-    #[test]
-    fn atomic_bool() {
-        let a = Atomic::new(false);
-        assert_eq!(Atomic::<bool>::is_lock_free(), cfg!(feature = "nightly"));
-        assert_eq!(format!("{:?}", a), "Atomic(false)");
-        assert_eq!(a.load(SeqCst), false);
-        a.store(true, SeqCst);
-        assert_eq!(a.swap(false, SeqCst), true);
-        assert_eq!(a.compare_exchange(true, false, SeqCst, SeqCst), Err(false));
-        assert_eq!(a.compare_exchange(false, true, SeqCst, SeqCst), Ok(false));
-        assert_eq!(a.fetch_and(false, SeqCst), true);
-        assert_eq!(a.fetch_or(true, SeqCst), false);
-        assert_eq!(a.fetch_xor(false, SeqCst), true);
-        assert_eq!(a.load(SeqCst), true);
-    }
+// There are no native atomic float instructions, so arithmetic on floats is a
+// compare-exchange loop on their bits.
+macro_rules! atomic_ops_float {
+    ($($t:ident)*) => ($(
+        impl Atomic<$t> {
+            /// Add to the current value, returning the previous value.
+            ///
+            /// This is implemented with a `compare_exchange_weak` loop.
+            #[doc = include_str!("doc_examples/float_fetch_add.md")]
+            #[inline]
+            pub fn fetch_add(&self, val: $t, order: Ordering) -> $t {
+                let f = |x: $t| Some(x + val);
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
 
-    #[test]
-    fn atomic_i8() {
-        let a = Atomic::new(0i8);
-        assert_eq!(
-            Atomic::<i8>::is_lock_free(),
-            cfg!(any(
-                target_pointer_width = "8",
-                all(feature = "nightly", target_has_atomic = "8")
-            ))
-        );
-        assert_eq!(format!("{:?}", a), "Atomic(0)");
-        assert_eq!(a.load(SeqCst), 0);
-        a.store(1, SeqCst);
-        assert_eq!(a.swap(2, SeqCst), 1);
+            /// Subtract from the current value, returning the previous value.
+            ///
+            /// This is implemented like `fetch_add`.
+            #[doc = include_str!("doc_examples/float_fetch_sub.md")]
+            #[inline]
+            pub fn fetch_sub(&self, val: $t, order: Ordering) -> $t {
+                let f = |x: $t| Some(x - val);
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+
+            /// Multiply the current value, returning the previous value.
+            ///
+            /// This is implemented like `fetch_add`.
+            #[doc = include_str!("doc_examples/float_fetch_mul.md")]
+            #[inline]
+            pub fn fetch_mul(&self, val: $t, order: Ordering) -> $t {
+                let f = |x: $t| Some(x * val);
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+
+            /// Divide the current value, returning the previous value.
+            ///
+            /// This is implemented like `fetch_add`. Unlike for the integer
+            /// types, dividing by zero doesn't panic, but gives an infinity or
+            /// NaN like `/` on floats.
+            #[doc = include_str!("doc_examples/float_fetch_div.md")]
+            #[inline]
+            pub fn fetch_div(&self, val: $t, order: Ordering) -> $t {
+                let f = |x: $t| Some(x / val);
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+
+            /// Minimum with the current value, returning the previous value.
+            ///
+            /// Like `min` on floats, a NaN is ignored in favor of the other
+            /// operand, so the value only stays NaN if `val` is NaN too. This
+            /// is implemented with a `compare_exchange_weak` loop which
+            /// returns without storing anything if the value doesn't change.
+            #[doc = include_str!("doc_examples/float_fetch_min.md")]
+            #[inline]
+            pub fn fetch_min(&self, val: $t, order: Ordering) -> $t {
+                let f = |x: $t| {
+                    let new = x.min(val);
+                    if new.to_bits() == x.to_bits() { None } else { Some(new) }
+                };
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+
+            /// Maximum with the current value, returning the previous value.
+            ///
+            /// NaNs are handled like in `fetch_min`.
+            #[doc = include_str!("doc_examples/float_fetch_max.md")]
+            #[inline]
+            pub fn fetch_max(&self, val: $t, order: Ordering) -> $t {
+                let f = |x: $t| {
+                    let new = x.max(val);
+                    if new.to_bits() == x.to_bits() { None } else { Some(new) }
+                };
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+        }
+    )*);
+}
+atomic_ops_float!{ f32 f64 }
+
+// Compound assignment through a shared reference, so that a counter reads like
+// `counter += 1` where `mut counter: &Atomic<u64>`. The ordering can't be seen
+// at the call site, so it is always the strongest one, `SeqCst`.
+macro_rules! atomic_assign_ops {
+    ($($trait:ident $method:ident $fetch:ident: $($t:ty)*;)*) => ($($(
+        #[doc = concat!("Performs `", stringify!($fetch), "` with `Ordering::SeqCst`.")]
+        impl<'a> core::ops::$trait<$t> for &'a Atomic<$t> {
+            #[inline]
+            fn $method(&mut self, val: $t) {
+                self.$fetch(val, Ordering::SeqCst);
+            }
+        }
+    )*)*);
+}
+atomic_assign_ops! {
+    AddAssign add_assign fetch_add: i8 i16 i32 i64 isize i128 u8 u16 u32 u64 usize u128 f32 f64;
+    SubAssign sub_assign fetch_sub: i8 i16 i32 i64 isize i128 u8 u16 u32 u64 usize u128 f32 f64;
+    BitAndAssign bitand_assign fetch_and: bool i8 i16 i32 i64 isize i128 u8 u16 u32 u64 usize u128;
+    BitOrAssign bitor_assign fetch_or: bool i8 i16 i32 i64 isize i128 u8 u16 u32 u64 usize u128;
+    BitXorAssign bitxor_assign fetch_xor: bool i8 i16 i32 i64 isize i128 u8 u16 u32 u64 usize u128;
+}
+
+macro_rules! atomic_ops_ptr {
+    ($($t:ty)*) => ($(
+        impl<T> Atomic<$t> {
+            /// Offsets the pointer by `val` elements of `T`, returning the
+            /// previous pointer.
+            ///
+            /// The offset wraps around like `wrapping_add` and never
+            /// dereferences the pointer.
+            #[inline]
+            pub fn fetch_ptr_add(&self, val: usize, order: Ordering) -> $t {
+                self.update_ptr(order, |p| p.wrapping_add(val))
+            }
+
+            /// Offsets the pointer backwards by `val` elements of `T`,
+            /// returning the previous pointer.
+            #[inline]
+            pub fn fetch_ptr_sub(&self, val: usize, order: Ordering) -> $t {
+                self.update_ptr(order, |p| p.wrapping_sub(val))
+            }
+
+            /// Offsets the pointer by `val` bytes, returning the previous
+            /// pointer.
+            #[inline]
+            pub fn fetch_byte_add(&self, val: usize, order: Ordering) -> $t {
+                self.update_ptr(order, |p| p.wrapping_byte_add(val))
+            }
+
+            /// Offsets the pointer backwards by `val` bytes, returning the
+            /// previous pointer.
+            #[inline]
+            pub fn fetch_byte_sub(&self, val: usize, order: Ordering) -> $t {
+                self.update_ptr(order, |p| p.wrapping_byte_sub(val))
+            }
+
+            /// Bitwise or of the pointer's address with `val`, returning the
+            /// previous pointer.
+            ///
+            /// This is typically used to set tag bits in the low bits of an
+            /// aligned pointer. The pointer keeps its provenance.
+            #[inline]
+            pub fn fetch_or(&self, val: usize, order: Ordering) -> $t {
+                self.fetch_map_addr(order, |addr| addr | val)
+            }
+
+            /// Bitwise and of the pointer's address with `val`, returning the
+            /// previous pointer.
+            ///
+            /// This is typically used to clear tag bits from the low bits of
+            /// an aligned pointer. The pointer keeps its provenance.
+            #[inline]
+            pub fn fetch_and(&self, val: usize, order: Ordering) -> $t {
+                self.fetch_map_addr(order, |addr| addr & val)
+            }
+
+            /// Replaces the address of the pointer with `f` of it, returning
+            /// the previous pointer.
+            ///
+            /// This is `map_addr` of the standard pointer types as an atomic
+            /// update: the new pointer is the old one offset to the new
+            /// address, so it keeps its provenance, and the address is never
+            /// turned back into a pointer. This makes it suitable for
+            /// manipulating tags in code which follows strict provenance.
+            /// Like in `fetch_update_both`, `f` may be called several times if
+            /// other threads change the pointer in the meantime.
+            #[doc = include_str!("doc_examples/fetch_map_addr.md")]
+            #[inline]
+            pub fn fetch_map_addr<F: Fn(usize) -> usize>(&self, order: Ordering, f: F) -> $t {
+                self.update_ptr(order, |p| {
+                    let addr = p as usize;
+                    p.wrapping_byte_add(f(addr).wrapping_sub(addr))
+                })
+            }
+
+            #[inline]
+            fn update_ptr<F: Fn($t) -> $t>(&self, order: Ordering, f: F) -> $t {
+                unsafe {
+                    ops::atomic_ptr_update(self.v.get() as *mut *mut T, order, |p| {
+                        f(p as $t) as *mut T
+                    }) as $t
+                }
+            }
+        }
+    )*);
+}
+atomic_ops_ptr!{ *mut T *const T }
+
+/// Helpers for using an `Atomic<Option<NonNull<T>>>` as the head of an
+/// intrusive list or stack.
+///
+/// `NonNull<T>` is never null, so `Option<NonNull<T>>` uses the null pointer
+/// to represent `None` and has exactly the same size, alignment and bit
+/// patterns as `*mut T`. Every operation on it therefore uses the same
+/// lock-free path as `Atomic<*mut T>`, and `is_lock_free` returns the same
+/// result for both. Reinterpreting the value as a pointer can't produce an
+/// invalid `Option<NonNull<T>>` since every pointer value is a valid one.
+///
+/// The generic `compare_exchange`, `compare_exchange_weak` and `take` methods
+/// accept `Option<NonNull<T>>` directly.
+impl<T> Atomic<Option<NonNull<T>>> {
+    /// Stores `ptr` if the `Atomic` currently contains `None`.
+    ///
+    /// On failure the current pointer is returned. The failure ordering is the
+    /// strongest one allowed for `order`.
+    #[inline]
+    pub fn store_if_none(&self, ptr: NonNull<T>, order: Ordering) -> Result<(), NonNull<T>> {
+        match self.compare_exchange(None, Some(ptr), order, ops::failure_ordering(order)) {
+            Ok(_) => Ok(()),
+            // The comparison with `None` can only fail if there is a pointer.
+            Err(current) => Err(current.unwrap()),
+        }
+    }
+}
+
+/// Helpers for using an `Atomic<Option<T>>` as a lazily initialized slot.
+///
+/// `None` is recognized with `Option::is_none` rather than by comparing bytes,
+/// so these work for any payload, including ones whose `None` leaves some
+/// bytes uninitialized.
+impl<T: Copy> Atomic<Option<T>> {
+    /// Returns the value in the `Atomic`, initializing it with `f` if it is
+    /// `None`.
+    ///
+    /// If several threads race to initialize the value, each of them may call
+    /// its `f`, but only one of the results is stored. The others are
+    /// discarded, and every caller returns the value which was stored, so `f`
+    /// shouldn't have side effects which need the value to be used.
+    ///
+    /// `order` is the ordering of the store if it succeeds. The loads use the
+    /// strongest failure ordering allowed for `order`, so with `AcqRel` a
+    /// caller sees everything written before the value was stored.
+    #[doc = include_str!("doc_examples/get_or_init.md")]
+    #[inline]
+    pub fn get_or_init<F: FnOnce() -> T>(&self, order: Ordering, f: F) -> T {
+        if let Some(val) = self.load(ops::failure_ordering(order)) {
+            return val;
+        }
+        let val = f();
+        match self.set_if_none(val, order) {
+            Ok(()) => val,
+            Err(current) => current,
+        }
+    }
+
+    /// Stores `Some(val)` if the `Atomic` currently contains `None`.
+    ///
+    /// On failure the value which is already present is returned. The failure
+    /// ordering is the strongest one allowed for `order`.
+    #[inline]
+    pub fn set_if_none(&self, val: T, order: Ordering) -> Result<(), T> {
+        let f = |x: Option<T>| if x.is_none() { Some(Some(val)) } else { None };
+        match unsafe {
+            ops::atomic_fetch_update(self.v.get(), order, ops::failure_ordering(order), f)
+        } {
+            Ok(_) => Ok(()),
+            // The update is only refused if there is a value.
+            Err(current) => Err(current.unwrap()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Some helpers are only used by the tests which need the fallback lock.
+    #![cfg_attr(feature = "no-fallback", allow(dead_code, unused_imports))]
+
+    use core::cell::UnsafeCell;
+    use core::fmt;
+    use core::mem;
+    use core::num::{NonZeroI16, NonZeroU128, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8};
+    use core::ptr::{self, NonNull};
+    use std::boxed::Box;
+    use ops;
+    use std::thread;
+    use Atomic;
+    use AtomicPair;
+    use Ordering::*;
+
+    // 16-byte integers are lock-free with the `nightly` feature on targets
+    // which have 128-bit atomics, with `portable-atomic` where that crate
+    // says so, and on x86_64 with the `cmpxchg16b` target feature.
+    fn lock_free_128() -> bool {
+        #[cfg(feature = "portable-atomic")]
+        {
+            cfg!(native_128) && ::portable_atomic::AtomicU128::is_always_lock_free()
+        }
+        #[cfg(not(feature = "portable-atomic"))]
+        {
+            cfg!(native_128) || cfg!(all(detect_cmpxchg16b, target_feature = "cmpxchg16b"))
+        }
+    }
+
+    // At runtime, they are also lock-free on x86_64 processors which have
+    // `cmpxchg16b`.
+    fn lock_free_128_runtime() -> bool {
+        lock_free_128() || ops::has_cmpxchg16b::<u128>()
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    struct Foo(u8, u8);
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    struct Bar(u64, u64);
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    struct Quux(u32);
+
+    #[test]
+    fn atomic_bool() {
+        let a = Atomic::new(false);
+        assert_eq!(Atomic::<bool>::is_lock_free(), cfg!(has_atomic_8));
+        assert_eq!(format!("{:?}", a), "Atomic(false)");
+        assert!(!a.load(SeqCst));
+        a.store(true, SeqCst);
+        assert!(a.swap(false, SeqCst));
+        assert_eq!(a.compare_exchange(true, false, SeqCst, SeqCst), Err(false));
+        assert_eq!(a.compare_exchange(false, true, SeqCst, SeqCst), Ok(false));
+        assert!(a.fetch_and(false, SeqCst));
+        assert!(!a.fetch_or(true, SeqCst));
+        assert!(a.fetch_xor(false, SeqCst));
+        assert!(a.load(SeqCst));
+    }
+
+    #[test]
+    fn atomic_bool_logic() {
+        use core::sync::atomic::AtomicBool;
+
+        for &current in &[false, true] {
+            for &val in &[false, true] {
+                let a = Atomic::new(current);
+                let b = AtomicBool::new(current);
+                assert_eq!(a.fetch_and(val, SeqCst), b.fetch_and(val, SeqCst));
+                assert_eq!(a.load(SeqCst), b.load(SeqCst));
+
+                let a = Atomic::new(current);
+                let b = AtomicBool::new(current);
+                assert_eq!(a.fetch_or(val, SeqCst), b.fetch_or(val, SeqCst));
+                assert_eq!(a.load(SeqCst), b.load(SeqCst));
+
+                let a = Atomic::new(current);
+                let b = AtomicBool::new(current);
+                assert_eq!(a.fetch_xor(val, SeqCst), b.fetch_xor(val, SeqCst));
+                assert_eq!(a.load(SeqCst), b.load(SeqCst));
+
+                let a = Atomic::new(current);
+                let b = AtomicBool::new(current);
+                assert_eq!(a.fetch_nand(val, SeqCst), b.fetch_nand(val, SeqCst));
+                assert_eq!(a.load(SeqCst), b.load(SeqCst));
+                // The stored byte must still be a valid bool.
+                assert_eq!(unsafe { *(a.v.get() as *const u8) }, !(current && val) as u8);
+            }
+
+            let a = Atomic::new(current);
+            assert_eq!(a.fetch_not(SeqCst), current);
+            assert_eq!(a.load(SeqCst), !current);
+            assert_eq!(unsafe { *(a.v.get() as *const u8) }, !current as u8);
+        }
+    }
+
+    #[test]
+    fn atomic_i8() {
+        let a = Atomic::new(0i8);
+        assert_eq!(Atomic::<i8>::is_lock_free(), cfg!(has_atomic_8));
+        assert_eq!(format!("{:?}", a), "Atomic(0)");
+        assert_eq!(a.load(SeqCst), 0);
+        a.store(1, SeqCst);
+        assert_eq!(a.swap(2, SeqCst), 1);
         assert_eq!(a.compare_exchange(5, 45, SeqCst, SeqCst), Err(2));
         assert_eq!(a.compare_exchange(2, 3, SeqCst, SeqCst), Ok(2));
         assert_eq!(a.fetch_add(123, SeqCst), 3);
@@ -402,13 +2187,7 @@ mod tests {
     #[test]
     fn atomic_i16() {
         let a = Atomic::new(0i16);
-        assert_eq!(
-            Atomic::<i16>::is_lock_free(),
-            cfg!(any(
-                target_pointer_width = "16",
-                all(feature = "nightly", target_has_atomic = "16")
-            ))
-        );
+        assert_eq!(Atomic::<i16>::is_lock_free(), cfg!(has_atomic_16));
         assert_eq!(format!("{:?}", a), "Atomic(0)");
         assert_eq!(a.load(SeqCst), 0);
         a.store(1, SeqCst);
@@ -428,13 +2207,7 @@ mod tests {
     #[test]
     fn atomic_i32() {
         let a = Atomic::new(0i32);
-        assert_eq!(
-            Atomic::<i32>::is_lock_free(),
-            cfg!(any(
-                target_pointer_width = "32",
-                all(feature = "nightly", target_has_atomic = "32")
-            ))
-        );
+        assert_eq!(Atomic::<i32>::is_lock_free(), cfg!(has_atomic_32));
         assert_eq!(format!("{:?}", a), "Atomic(0)");
         assert_eq!(a.load(SeqCst), 0);
         a.store(1, SeqCst);
@@ -456,10 +2229,7 @@ mod tests {
         let a = Atomic::new(0i64);
         assert_eq!(
             Atomic::<i64>::is_lock_free(),
-            cfg!(any(
-                target_pointer_width = "64",
-                all(feature = "nightly", target_has_atomic = "64")
-            )) && mem::align_of::<i64>() == 8
+            cfg!(has_atomic_64) && mem::align_of::<i64>() == 8
         );
         assert_eq!(format!("{:?}", a), "Atomic(0)");
         assert_eq!(a.load(SeqCst), 0);
@@ -478,15 +2248,11 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "no-fallback"))]
     fn atomic_i128() {
         let a = Atomic::new(0i128);
-        assert_eq!(
-            Atomic::<i128>::is_lock_free(),
-            cfg!(any(
-                target_pointer_width = "128",
-                all(feature = "nightly", target_has_atomic = "128")
-            ))
-        );
+        assert_eq!(Atomic::<i128>::is_lock_free(), lock_free_128());
+        assert_eq!(Atomic::<i128>::is_lock_free_runtime(), lock_free_128_runtime());
         assert_eq!(format!("{:?}", a), "Atomic(0)");
         assert_eq!(a.load(SeqCst), 0);
         a.store(1, SeqCst);
@@ -526,13 +2292,7 @@ mod tests {
     #[test]
     fn atomic_u8() {
         let a = Atomic::new(0u8);
-        assert_eq!(
-            Atomic::<u8>::is_lock_free(),
-            cfg!(any(
-                target_pointer_width = "8",
-                all(feature = "nightly", target_has_atomic = "8")
-            ))
-        );
+        assert_eq!(Atomic::<u8>::is_lock_free(), cfg!(has_atomic_8));
         assert_eq!(format!("{:?}", a), "Atomic(0)");
         assert_eq!(a.load(SeqCst), 0);
         a.store(1, SeqCst);
@@ -552,13 +2312,7 @@ mod tests {
     #[test]
     fn atomic_u16() {
         let a = Atomic::new(0u16);
-        assert_eq!(
-            Atomic::<u16>::is_lock_free(),
-            cfg!(any(
-                target_pointer_width = "16",
-                all(feature = "nightly", target_has_atomic = "16")
-            ))
-        );
+        assert_eq!(Atomic::<u16>::is_lock_free(), cfg!(has_atomic_16));
         assert_eq!(format!("{:?}", a), "Atomic(0)");
         assert_eq!(a.load(SeqCst), 0);
         a.store(1, SeqCst);
@@ -578,13 +2332,7 @@ mod tests {
     #[test]
     fn atomic_u32() {
         let a = Atomic::new(0u32);
-        assert_eq!(
-            Atomic::<u32>::is_lock_free(),
-            cfg!(any(
-                target_pointer_width = "32",
-                all(feature = "nightly", target_has_atomic = "32")
-            ))
-        );
+        assert_eq!(Atomic::<u32>::is_lock_free(), cfg!(has_atomic_32));
         assert_eq!(format!("{:?}", a), "Atomic(0)");
         assert_eq!(a.load(SeqCst), 0);
         a.store(1, SeqCst);
@@ -606,10 +2354,7 @@ mod tests {
         let a = Atomic::new(0u64);
         assert_eq!(
             Atomic::<u64>::is_lock_free(),
-            cfg!(any(
-                target_pointer_width = "64",
-                all(feature = "nightly", target_has_atomic = "64")
-            )) && mem::align_of::<u64>() == 8
+            cfg!(has_atomic_64) && mem::align_of::<u64>() == 8
         );
         assert_eq!(format!("{:?}", a), "Atomic(0)");
         assert_eq!(a.load(SeqCst), 0);
@@ -627,16 +2372,221 @@ mod tests {
         assert_eq!(a.load(SeqCst), 30);
     }
 
+    // The operators take the atomic by shared reference, so they can be used
+    // from several threads at once.
+    #[test]
+    fn compound_assignment() {
+        fn bump(mut counter: &Atomic<u64>) {
+            counter += 2;
+            counter -= 1;
+        }
+
+        let counter = Atomic::new(0u64);
+        let bits = Atomic::new(0u32);
+        let any = Atomic::new(false);
+        thread::scope(|s| {
+            for t in 0..4 {
+                let (counter, mut bits, mut any) = (&counter, &bits, &any);
+                s.spawn(move || {
+                    for _ in 0..1000 {
+                        bump(counter);
+                    }
+                    bits |= 0b11 << (2 * t);
+                    bits &= !(0b10 << (2 * t));
+                    bits ^= 1 << (16 + t);
+                    any |= t == 2;
+                });
+            }
+        });
+        assert_eq!(counter.load(SeqCst), 4000);
+        assert_eq!(bits.load(SeqCst), 0b1111 << 16 | 0b0101_0101);
+        assert!(any.load(SeqCst));
+
+        let mut x = &Atomic::new(1.5f64);
+        x += 2.0;
+        x -= 0.5;
+        assert_eq!(x.load(SeqCst), 3.0);
+        let mut y = &Atomic::new(0u8);
+        y -= 1;
+        assert_eq!(y.load(SeqCst), 255);
+        let mut z = &Atomic::new(true);
+        z ^= true;
+        z &= true;
+        assert!(!z.load(SeqCst));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn get_mut_slice() {
+        const THREADS: u32 = 4;
+        let mut buf: Box<[Atomic<u32>]> = (0..64).map(|_| Atomic::new(0)).collect();
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let buf = &buf;
+                s.spawn(move || {
+                    let mine = buf.iter().enumerate().skip(t as usize);
+                    for (i, a) in mine.step_by(THREADS as usize) {
+                        a.store(1000 - i as u32, Relaxed);
+                    }
+                });
+            }
+        });
+        let values = Atomic::get_mut_slice(&mut buf);
+        values.sort_unstable();
+        assert_eq!(values.len(), 64);
+        assert!(values.iter().zip(937..).all(|(&x, y)| x == y));
+        assert_eq!(buf[0].load(Relaxed), 937);
+
+        let mut a = [Atomic::new(Foo(1, 2)), Atomic::new(Foo(3, 4))];
+        Atomic::get_mut_array(&mut a)[1].0 = 5;
+        assert_eq!(a[1].load(Relaxed), Foo(5, 4));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn unsynchronized() {
+        let buf: Box<[Atomic<[u8; 32]>]> = (0..16).map(|_| Atomic::new([0; 32])).collect();
+        thread::scope(|s| {
+            for (i, a) in buf.iter().enumerate() {
+                s.spawn(move || a.store([i as u8; 32], Relaxed));
+            }
+        });
+        // Every store happened-before the end of the scope.
+        let sum: u32 = buf
+            .iter()
+            .map(|a| unsafe { a.load_unsynchronized() }[31] as u32)
+            .sum();
+        assert_eq!(sum, (0..16).sum());
+
+        let a = Atomic::new(Bar(1, 2));
+        unsafe { a.store_unsynchronized(Bar(3, 4)) };
+        thread::scope(|s| {
+            s.spawn(|| assert_eq!(a.swap(Bar(5, 6), SeqCst), Bar(3, 4)));
+        });
+        assert_eq!(unsafe { a.load_unsynchronized() }, Bar(5, 6));
+        let n = Atomic::new(7u16);
+        unsafe { n.store_unsynchronized(8) };
+        assert_eq!(n.load(Relaxed), 8);
+    }
+
     #[test]
+    fn has_atomic_cfgs_match_target() {
+        assert_eq!(cfg!(has_atomic_8), cfg!(target_has_atomic = "8"));
+        assert_eq!(cfg!(has_atomic_16), cfg!(target_has_atomic = "16"));
+        assert_eq!(cfg!(has_atomic_32), cfg!(target_has_atomic = "32"));
+        assert_eq!(cfg!(has_atomic_64), cfg!(target_has_atomic = "64"));
+        assert_eq!(cfg!(has_atomic_ptr), cfg!(target_has_atomic = "ptr"));
+    }
+
+    // On 32-bit x86 `u64` is only 4-byte aligned, so whether an `Atomic<u64>`
+    // uses native instructions depends on its address. Concurrent updates
+    // through the native instructions and through compare-exchange loops must
+    // still agree on which way to go.
+    #[test]
+    fn atomic_u64_by_address() {
+        #[repr(C, align(8))]
+        struct Split {
+            aligned: Atomic<u64>,
+            pad: u32,
+            maybe_misaligned: Atomic<u64>,
+        }
+        let s = Split {
+            aligned: Atomic::new(0),
+            pad: 0,
+            maybe_misaligned: Atomic::new(0),
+        };
+        assert_eq!(s.pad, 0);
+        for a in [&s.aligned, &s.maybe_misaligned] {
+            let p = a.as_ptr();
+            assert_eq!(
+                ops::atomic_is_lock_free_at(p),
+                cfg!(has_atomic_64) && p as usize & 7 == 0
+            );
+            thread::scope(|scope| {
+                for t in 0..4 {
+                    scope.spawn(move || {
+                        for _ in 0..1000 {
+                            if t % 2 == 0 {
+                                a.fetch_add(1 << 32 | 1, Relaxed);
+                            } else {
+                                let add = |x: u64| Some(x + (1 << 32 | 1));
+                                a.fetch_update(Relaxed, Relaxed, add).unwrap();
+                            }
+                        }
+                    });
+                }
+            });
+            assert_eq!(a.load(SeqCst), 4000 << 32 | 4000);
+        }
+    }
+
+    #[test]
+    fn try_ops_lock_free() {
+        let a = Atomic::new(1u32);
+        assert_eq!(a.try_load(SeqCst), Some(1));
+        assert_eq!(a.try_store(2, SeqCst), Ok(()));
+        assert_eq!(a.try_swap(3, SeqCst), Ok(2));
+        assert_eq!(a.load(SeqCst), 3);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn try_ops_fail_while_locked() {
+        use std::sync::Barrier;
+        let a = Atomic::new(Bar(1, 2));
+        assert!(!Atomic::<Bar>::is_lock_free());
+        let (locked, release) = (Barrier::new(2), Barrier::new(2));
+        thread::scope(|s| {
+            s.spawn(|| {
+                ::fallback::with_lock_held(a.as_ptr() as usize, || {
+                    locked.wait();
+                    release.wait();
+                })
+            });
+            locked.wait();
+            assert_eq!(a.try_load(SeqCst), None);
+            assert_eq!(a.try_store(Bar(3, 4), SeqCst), Err(Bar(3, 4)));
+            assert_eq!(a.try_swap(Bar(5, 6), SeqCst), Err(Bar(5, 6)));
+            release.wait();
+        });
+        assert_eq!(a.try_load(SeqCst), Some(Bar(1, 2)));
+        assert_eq!(a.try_swap(Bar(3, 4), SeqCst), Ok(Bar(1, 2)));
+        assert_eq!(a.try_store(Bar(5, 6), SeqCst), Ok(()));
+        assert_eq!(a.load(SeqCst), Bar(5, 6));
+    }
+
+    // An `AtomicPair` which uses `cmpxchg16b` never waits for the lock of its
+    // slot, unlike the `Bar` above with the same contents.
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn lock_free_pair_ignores_lock() {
+        use std::sync::Barrier;
+        if !AtomicPair::<u64, u64>::is_lock_free_runtime() {
+            return;
+        }
+        let a = AtomicPair::new(1u64, 2u64);
+        let (locked, release) = (Barrier::new(2), Barrier::new(2));
+        thread::scope(|s| {
+            s.spawn(|| {
+                ::fallback::with_lock_held(&a as *const _ as usize, || {
+                    locked.wait();
+                    release.wait();
+                })
+            });
+            locked.wait();
+            assert_eq!(a.swap((3, 4), SeqCst), (1, 2));
+            assert_eq!(a.compare_exchange((3, 4), (5, 6), SeqCst, SeqCst), Ok((3, 4)));
+            assert_eq!(a.load(SeqCst), (5, 6));
+            release.wait();
+        });
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
     fn atomic_u128() {
         let a = Atomic::new(0u128);
-        assert_eq!(
-            Atomic::<u128>::is_lock_free(),
-            cfg!(any(
-                target_pointer_width = "128",
-                all(feature = "nightly", target_has_atomic = "128")
-            ))
-        );
+        assert_eq!(Atomic::<u128>::is_lock_free(), lock_free_128());
+        assert_eq!(Atomic::<u128>::is_lock_free_runtime(), lock_free_128_runtime());
         assert_eq!(format!("{:?}", a), "Atomic(0)");
         assert_eq!(a.load(SeqCst), 0);
         a.store(1, SeqCst);
@@ -674,9 +2624,10 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "no-fallback"))]
     fn atomic_foo() {
         let a = Atomic::default();
-        assert_eq!(Atomic::<Foo>::is_lock_free(), false);
+        assert!(!Atomic::<Foo>::is_lock_free());
         assert_eq!(format!("{:?}", a), "Atomic(Foo(0, 0))");
         assert_eq!(a.load(SeqCst), Foo(0, 0));
         a.store(Foo(1, 1), SeqCst);
@@ -693,9 +2644,10 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "no-fallback"))]
     fn atomic_bar() {
         let a = Atomic::default();
-        assert_eq!(Atomic::<Bar>::is_lock_free(), false);
+        assert!(!Atomic::<Bar>::is_lock_free());
         assert_eq!(format!("{:?}", a), "Atomic(Bar(0, 0))");
         assert_eq!(a.load(SeqCst), Bar(0, 0));
         a.store(Bar(1, 1), SeqCst);
@@ -714,10 +2666,7 @@ mod tests {
     #[test]
     fn atomic_quxx() {
         let a = Atomic::default();
-        assert_eq!(
-            Atomic::<Quux>::is_lock_free(),
-            cfg!(any(feature = "nightly", target_pointer_width = "32"))
-        );
+        assert_eq!(Atomic::<Quux>::is_lock_free(), cfg!(has_atomic_32));
         assert_eq!(format!("{:?}", a), "Atomic(Quux(0))");
         assert_eq!(a.load(SeqCst), Quux(0));
         a.store(Quux(1), SeqCst);
@@ -732,4 +2681,1548 @@ mod tests {
         );
         assert_eq!(a.load(SeqCst), Quux(3));
     }
+
+    #[test]
+    fn atomic_ptr_arithmetic() {
+        let mut buf = [0u64; 4];
+        let base = buf.as_mut_ptr();
+        let a = Atomic::new(base);
+        assert_eq!(a.fetch_ptr_add(2, SeqCst), base);
+        assert_eq!(a.fetch_byte_add(8, SeqCst), base.wrapping_add(2));
+        assert_eq!(a.fetch_byte_sub(16, SeqCst), base.wrapping_add(3));
+        assert_eq!(a.fetch_ptr_sub(1, SeqCst), base.wrapping_add(1));
+
+        // Tag the low bit, then strip it and write through the result.
+        assert_eq!(a.fetch_or(1, SeqCst), base);
+        assert_eq!(a.fetch_and(!1, SeqCst) as usize, base as usize | 1);
+        let p = a.fetch_and(!0, SeqCst);
+        assert_eq!(p, base);
+        unsafe { *p.add(3) = 7 };
+        assert_eq!(buf, [0, 0, 0, 7]);
+
+        let c = Atomic::new(buf.as_ptr());
+        assert_eq!(c.fetch_ptr_add(1, SeqCst), buf.as_ptr());
+        assert_eq!(unsafe { *c.fetch_byte_add(16, SeqCst) }, 0);
+        assert_eq!(unsafe { *c.fetch_ptr_sub(0, SeqCst) }, 7);
+    }
+
+    // Run by CI under Miri with `-Zmiri-strict-provenance`, where a pointer
+    // which went through an integer couldn't be dereferenced.
+    #[test]
+    fn atomic_ptr_strict_provenance() {
+        let mut x = 1u64;
+        let mut y = 2u64;
+        let px: *mut u64 = &mut x;
+        let py: *mut u64 = &mut y;
+
+        let a = Atomic::new(ptr::null_mut());
+        a.store(px, SeqCst);
+        unsafe { *a.load(SeqCst) += 10 };
+        assert_eq!(a.compare_exchange(px, py, SeqCst, SeqCst), Ok(px));
+        assert_eq!(a.compare_exchange(px, py, SeqCst, SeqCst), Err(py));
+        while a.compare_exchange_weak(py, px, SeqCst, SeqCst).is_err() {}
+        unsafe { *a.swap(py, SeqCst) += 10 };
+        unsafe { *a.load(SeqCst) += 20 };
+
+        // A tag in the low bits, which are zero in an aligned pointer.
+        assert_eq!(a.fetch_map_addr(SeqCst, |addr| addr | 3), py);
+        assert_eq!(a.load(SeqCst) as usize & 3, 3);
+        let untagged = a.fetch_map_addr(SeqCst, |addr| addr & !3);
+        assert_eq!(untagged.wrapping_byte_sub(3), py);
+        unsafe { *a.load(SeqCst) += 20 };
+
+        let c = Atomic::new(px as *const u64);
+        assert_eq!(unsafe { *c.swap(py, SeqCst) }, 21);
+        assert_eq!(unsafe { *c.load(Acquire) }, 42);
+
+        let n = Atomic::new(NonNull::new(px).unwrap());
+        n.store(NonNull::new(py).unwrap(), Release);
+        assert_eq!(unsafe { *n.load(Acquire).as_ptr() }, 42);
+
+        let o: Atomic<Option<NonNull<u64>>> = Atomic::new(None);
+        assert_eq!(o.store_if_none(NonNull::new(px).unwrap(), AcqRel), Ok(()));
+        let prev = o.compare_exchange(NonNull::new(px), NonNull::new(py), SeqCst, SeqCst);
+        assert_eq!(unsafe { *prev.unwrap().unwrap().as_ptr() }, 21);
+        assert_eq!(unsafe { *o.take(SeqCst).unwrap().as_ptr() }, 42);
+    }
+
+    #[test]
+    fn bump_allocator() {
+        struct Arena(UnsafeCell<[u64; 64]>);
+        unsafe impl Sync for Arena {}
+        static ARENA: Arena = Arena(UnsafeCell::new([0; 64]));
+
+        // Raw pointers aren't `Send`, so `Atomic<*mut T>` has to be wrapped to
+        // be shared between threads.
+        struct Bump(Atomic<*mut u64>, usize);
+        unsafe impl Sync for Bump {}
+        impl Bump {
+            fn alloc(&self) -> Option<&'static mut u64> {
+                let p = self.0.fetch_ptr_add(1, Relaxed);
+                if p as usize >= self.1 {
+                    None
+                } else {
+                    Some(unsafe { &mut *p })
+                }
+            }
+        }
+
+        let start = ARENA.0.get() as *mut u64;
+        let bump = Bump(Atomic::new(start), start.wrapping_add(64) as usize);
+        thread::scope(|s| {
+            for t in 1..=4 {
+                let bump = &bump;
+                s.spawn(move || {
+                    while let Some(slot) = bump.alloc() {
+                        assert_eq!(*slot, 0);
+                        *slot = t;
+                    }
+                });
+            }
+        });
+
+        let slots = unsafe { &*ARENA.0.get() };
+        assert!(slots.iter().all(|&t| (1..=4).contains(&t)));
+    }
+
+    #[test]
+    fn atomic_option_non_null() {
+        assert_eq!(
+            Atomic::<Option<NonNull<u64>>>::is_lock_free(),
+            Atomic::<*mut u64>::is_lock_free()
+        );
+        assert_eq!(
+            Atomic::<Option<NonNull<u64>>>::is_lock_free(),
+            cfg!(has_atomic_ptr)
+        );
+
+        let mut x = 1u64;
+        let mut y = 2u64;
+        let px = NonNull::from(&mut x);
+        let py = NonNull::from(&mut y);
+        let a = Atomic::new(None);
+        assert_eq!(a.take(SeqCst), None);
+        assert_eq!(a.store_if_none(px, SeqCst), Ok(()));
+        assert_eq!(a.store_if_none(py, SeqCst), Err(px));
+        assert_eq!(a.compare_exchange(Some(px), Some(py), SeqCst, SeqCst), Ok(Some(px)));
+        assert_eq!(a.compare_exchange(None, Some(px), SeqCst, SeqCst), Err(Some(py)));
+        let p = a.take(SeqCst).unwrap();
+        assert_eq!(a.load(SeqCst), None);
+        unsafe { *p.as_ptr() += 40 };
+        assert_eq!(y, 42);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn replace_with() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+        struct Triple(u64, u64, u64);
+        const THREADS: u64 = 4;
+        const ITERS: u64 = 1000;
+        let a = Atomic::new(Triple(0, 0, 0));
+        assert_eq!(mem::size_of::<Triple>(), 24);
+        assert!(!Atomic::<Triple>::is_lock_free());
+        let calls = Atomic::new(0u64);
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let (a, calls) = (&a, &calls);
+                s.spawn(move || {
+                    for _ in 0..ITERS {
+                        let old = a.replace_with(AcqRel, Acquire, |x| {
+                            calls.fetch_add(1, Relaxed);
+                            Triple(x.0 + 1, x.1 + t, x.2 ^ x.0)
+                        });
+                        assert!(old.1 <= old.0 * (THREADS - 1));
+                    }
+                });
+            }
+        });
+        let end = a.load(SeqCst);
+        assert_eq!(end.0, THREADS * ITERS);
+        assert_eq!(end.1, ITERS * (0..THREADS).sum::<u64>());
+        // The closure runs exactly once per call under the lock.
+        assert_eq!(calls.load(Relaxed), THREADS * ITERS);
+        assert_eq!(a.swap_default(SeqCst), end);
+        assert_eq!(a.load(SeqCst), Triple::default());
+
+        let n = Atomic::new(1u32);
+        assert_eq!(n.replace_with(Relaxed, Relaxed, |x| x * 3), 1);
+        assert_eq!(n.swap_default(Relaxed), 3);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn option_set_if_none() {
+        let a = Atomic::new(None);
+        assert_eq!(a.set_if_none(Bar(1, 2), AcqRel), Ok(()));
+        assert_eq!(a.set_if_none(Bar(3, 4), AcqRel), Err(Bar(1, 2)));
+        assert_eq!(a.get_or_init(AcqRel, || unreachable!()), Bar(1, 2));
+        let b = Atomic::new(None::<u16>);
+        assert_eq!(b.get_or_init(Release, || 5), 5);
+        assert_eq!(b.set_if_none(6, Release), Err(5));
+    }
+
+    fn get_or_init_race<T: Copy + Eq + fmt::Debug + Send, F: Fn(u32) -> T + Sync>(init: F) {
+        use std::sync::Barrier;
+        use std::vec::Vec;
+        const THREADS: u32 = 8;
+        let slot = Atomic::new(None);
+        let calls = Atomic::new(0u32);
+        let start = Barrier::new(THREADS as usize);
+        let seen: Vec<T> = thread::scope(|s| {
+            let handles: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    let (slot, calls, start, init) = (&slot, &calls, &start, &init);
+                    s.spawn(move || {
+                        start.wait();
+                        slot.get_or_init(AcqRel, || {
+                            calls.fetch_add(1, Relaxed);
+                            init(t)
+                        })
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        // Several initializers may have run, but only one value won.
+        assert!(calls.load(Relaxed) >= 1);
+        assert!(seen.iter().all(|&x| x == seen[0]));
+        assert!((0..THREADS).any(|t| init(t) == seen[0]));
+        assert_eq!(slot.load(Acquire), Some(seen[0]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn option_get_or_init_race() {
+        get_or_init_race(|t| NonZeroU64::new(t as u64 + 1).unwrap());
+        get_or_init_race(|t| Bar(t as u64, !t as u64));
+    }
+
+    #[test]
+    fn option_non_null_list() {
+        struct Node {
+            value: usize,
+            next: Option<NonNull<Node>>,
+        }
+
+        // `NonNull` isn't `Send`, so the list head has to be wrapped to be
+        // shared between threads.
+        struct Head(Atomic<Option<NonNull<Node>>>);
+        unsafe impl Sync for Head {}
+
+        const THREADS: usize = 4;
+        let per_thread = if cfg!(miri) { 20 } else { 1000 };
+        let head = Head(Atomic::new(None));
+        let mut drained = std::vec::Vec::new();
+
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let head = &head;
+                s.spawn(move || {
+                    for i in 0..per_thread {
+                        let node = Box::new(Node {
+                            value: t * per_thread + i,
+                            next: None,
+                        });
+                        let node = NonNull::from(Box::leak(node));
+                        let mut current = head.0.load(Relaxed);
+                        loop {
+                            unsafe { (*node.as_ptr()).next = current };
+                            match head.0.compare_exchange_weak(
+                                current,
+                                Some(node),
+                                Release,
+                                Relaxed,
+                            ) {
+                                Ok(_) => break,
+                                Err(x) => current = x,
+                            }
+                        }
+                    }
+                });
+            }
+
+            // Drain the list while it is being built.
+            while drained.len() < THREADS * per_thread {
+                let mut node = head.0.take(Acquire);
+                while let Some(p) = node {
+                    let b = unsafe { Box::from_raw(p.as_ptr()) };
+                    drained.push(b.value);
+                    node = b.next;
+                }
+            }
+        });
+
+        drained.sort();
+        assert!(drained.iter().cloned().eq(0..THREADS * per_thread));
+        assert_eq!(head.0.load(SeqCst), None);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_nonzero() {
+        assert_eq!(mem::size_of::<Atomic<NonZeroU32>>(), 4);
+        assert_eq!(mem::size_of::<Atomic<Option<NonZeroU32>>>(), 4);
+        assert_eq!(mem::size_of::<Atomic<Option<NonZeroU64>>>(), 8);
+        assert_eq!(Atomic::<NonZeroU8>::is_lock_free(), cfg!(has_atomic_8));
+        assert_eq!(Atomic::<Option<NonZeroU16>>::is_lock_free(), cfg!(has_atomic_16));
+        assert_eq!(Atomic::<Option<NonZeroU32>>::is_lock_free(), cfg!(has_atomic_32));
+        assert_eq!(Atomic::<NonZeroU64>::is_lock_free(), cfg!(has_atomic_64));
+
+        let n = |x| NonZeroU32::new(x).unwrap();
+        let a = Atomic::new(n(5));
+        assert_eq!(format!("{:?}", a), "Atomic(5)");
+        a.store(n(7), SeqCst);
+        assert_eq!(a.swap(n(9), SeqCst), n(7));
+        assert_eq!(a.compare_exchange(n(1), n(2), SeqCst, SeqCst), Err(n(9)));
+        assert_eq!(a.compare_exchange(n(9), n(u32::MAX), SeqCst, SeqCst), Ok(n(9)));
+        assert_eq!(a.fetch_min(n(3), SeqCst), n(u32::MAX));
+        assert_eq!(a.fetch_max(n(1), SeqCst), n(3));
+        assert_eq!(a.load(SeqCst), n(3));
+
+        let b = Atomic::new(NonZeroI16::new(-5).unwrap());
+        assert_eq!(b.fetch_max(NonZeroI16::new(4).unwrap(), SeqCst).get(), -5);
+        assert_eq!(b.fetch_min(NonZeroI16::MIN, SeqCst).get(), 4);
+        assert_eq!(b.load(SeqCst), NonZeroI16::MIN);
+
+        let c = Atomic::new(NonZeroU128::new(1 << 100));
+        assert_eq!(c.swap(None, SeqCst), NonZeroU128::new(1 << 100));
+        assert_eq!(c.compare_exchange(None, NonZeroU128::new(3), SeqCst, SeqCst), Ok(None));
+        assert_eq!(c.load(SeqCst), NonZeroU128::new(3));
+    }
+
+    #[test]
+    fn atomic_char() {
+        let a = Atomic::new('a');
+        assert_eq!(Atomic::<char>::is_lock_free(), cfg!(has_atomic_32));
+        assert_eq!(format!("{:?}", a), "Atomic('a')");
+        a.store('\u{10FFFF}', SeqCst);
+        assert_eq!(a.swap('\u{D7FF}', SeqCst), '\u{10FFFF}');
+        assert_eq!(a.compare_exchange('x', 'y', SeqCst, SeqCst), Err('\u{D7FF}'));
+        assert_eq!(a.compare_exchange('\u{D7FF}', '\u{E000}', SeqCst, SeqCst), Ok('\u{D7FF}'));
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, |_| None), Err('\u{E000}'));
+        assert_eq!(
+            a.fetch_update(SeqCst, SeqCst, |c| char::from_u32(c as u32 + 1)),
+            Ok('\u{E000}')
+        );
+        assert_eq!(a.load(SeqCst), '\u{E001}');
+        assert_eq!(a.into_inner(), '\u{E001}');
+    }
+
+    #[test]
+    fn atomic_f32() {
+        let a = Atomic::new(1.5f32);
+        assert_eq!(Atomic::<f32>::is_lock_free(), cfg!(has_atomic_32));
+        assert_eq!(format!("{:?}", a), "Atomic(1.5)");
+        assert_eq!(a.fetch_add(2.0, SeqCst), 1.5);
+        assert_eq!(a.fetch_sub(4.5, SeqCst), 3.5);
+        assert_eq!(a.fetch_min(-2.0, SeqCst), -1.0);
+        assert_eq!(a.fetch_max(0.25, SeqCst), -2.0);
+        assert_eq!(a.fetch_max(f32::NAN, SeqCst), 0.25);
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, |_| None), Err(0.25));
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, |x| Some(-x)), Ok(0.25));
+        assert_eq!(a.into_inner(), -0.25);
+    }
+
+    #[test]
+    fn atomic_f64_nan() {
+        let a = Atomic::new(f64::NAN);
+        // A NaN operand is ignored, so the stored NaN is replaced.
+        assert!(a.fetch_min(3.0, SeqCst).is_nan());
+        assert_eq!(a.load(SeqCst), 3.0);
+        a.store(f64::NAN, SeqCst);
+        assert!(a.fetch_max(f64::NAN, SeqCst).is_nan());
+        assert!(a.load(SeqCst).is_nan());
+        // The update loop compares bits, so a stored NaN doesn't make it spin.
+        assert!(a.fetch_update(SeqCst, SeqCst, |_| Some(1.0)).unwrap().is_nan());
+        assert_eq!(a.fetch_add(f64::NAN, SeqCst), 1.0);
+        assert!(a.into_inner().is_nan());
+    }
+
+    #[test]
+    fn atomic_float_mul_div() {
+        let a = Atomic::new(1.5f32);
+        assert_eq!(a.fetch_mul(-4.0, SeqCst), 1.5);
+        assert_eq!(a.fetch_div(8.0, SeqCst), -6.0);
+        assert_eq!(a.load(SeqCst), -0.75);
+
+        let b = Atomic::new(3.0f64);
+        assert_eq!(b.fetch_div(0.0, SeqCst), 3.0);
+        assert_eq!(b.load(SeqCst), f64::INFINITY);
+        assert_eq!(b.fetch_mul(0.0, SeqCst), f64::INFINITY);
+        assert!(b.load(SeqCst).is_nan());
+    }
+
+    #[test]
+    fn atomic_f64_concurrent_add() {
+        let a = Atomic::new(0.0f64);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        a.fetch_add(0.25, Relaxed);
+                    }
+                });
+            }
+        });
+        assert_eq!(a.into_inner(), 1000.0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_128_fetch_update() {
+        let a = Atomic::new(u128::MAX - 1);
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, |x| x.checked_add(1)), Ok(u128::MAX - 1));
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, |x| x.checked_add(1)), Err(u128::MAX));
+        let b = Atomic::new(i128::MIN);
+        assert_eq!(b.fetch_update(SeqCst, SeqCst, |x| x.checked_neg()), Err(i128::MIN));
+        assert_eq!(b.fetch_update(SeqCst, SeqCst, |x| Some(x / -2)), Ok(i128::MIN));
+        assert_eq!(b.into_inner(), 1 << 126);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_generic_fetch_update() {
+        let a = Atomic::new(Foo(1, 2));
+        let swap = |Foo(x, y)| if x < y { Some(Foo(y, x)) } else { None };
+        assert_eq!(a.fetch_update(AcqRel, Acquire, swap), Ok(Foo(1, 2)));
+        assert_eq!(a.fetch_update(AcqRel, Acquire, swap), Err(Foo(2, 1)));
+
+        // Not lock-free, so the function runs once under the lock.
+        let b = Atomic::new(Bar(1, 2));
+        let mut calls = 0;
+        let res = b.fetch_update(SeqCst, SeqCst, |Bar(x, y)| {
+            calls += 1;
+            Some(Bar(x + y, y))
+        });
+        assert_eq!(res, Ok(Bar(1, 2)));
+        assert_eq!(b.load(SeqCst), Bar(3, 2));
+        if !Atomic::<Bar>::is_lock_free() {
+            assert_eq!(calls, 1);
+        }
+
+        let c = Atomic::new('a');
+        assert_eq!(c.fetch_update(SeqCst, SeqCst, |c| char::from_u32(c as u32 + 1)), Ok('a'));
+        assert_eq!(c.load(SeqCst), 'b');
+    }
+
+    #[test]
+    #[cfg(all(debug_assertions, not(feature = "validate")))]
+    #[should_panic(expected = "Atomic<char> contains an invalid char: 0xd800")]
+    fn atomic_char_surrogate() {
+        let a = Atomic::new('a');
+        unsafe { (a.as_ptr() as *mut u32).write(0xD800) };
+        a.load(SeqCst);
+    }
+
+    #[test]
+    #[cfg(all(debug_assertions, not(feature = "validate")))]
+    #[should_panic(expected = "Atomic<char> contains an invalid char: 0x110000")]
+    fn atomic_char_out_of_range() {
+        let a = Atomic::new('a');
+        unsafe { (a.as_ptr() as *mut u32).write(0x110000) };
+        a.load(Relaxed);
+    }
+
+    #[test]
+    #[cfg(all(debug_assertions, not(feature = "validate")))]
+    #[should_panic(expected = "Atomic<char> contains an invalid char: 0xdfff")]
+    fn atomic_char_swap_invalid() {
+        let a = Atomic::new('a');
+        unsafe { (a.as_ptr() as *mut u32).write(0xDFFF) };
+        a.swap('b', SeqCst);
+    }
+
+    #[test]
+    #[cfg(all(debug_assertions, not(feature = "validate")))]
+    #[should_panic(expected = "Atomic<char> contains an invalid char: 0xdfff")]
+    fn atomic_char_compare_exchange_invalid() {
+        let a = Atomic::new('a');
+        unsafe { (a.as_ptr() as *mut u32).write(0xDFFF) };
+        let _ = a.compare_exchange_weak('a', 'b', SeqCst, Relaxed);
+    }
+
+    #[test]
+    #[cfg(all(debug_assertions, not(feature = "validate")))]
+    #[should_panic(expected = "Atomic<char> contains an invalid char: 0xffffffff")]
+    fn atomic_char_fetch_update_invalid() {
+        let a = Atomic::new('a');
+        unsafe { (a.as_ptr() as *mut u32).write(u32::MAX) };
+        let _ = a.fetch_update(SeqCst, SeqCst, |_| Some('b'));
+    }
+
+    // `u32` has the size of `char`, but every bit pattern is valid.
+    #[test]
+    fn atomic_u32_not_checked_as_char() {
+        let a = Atomic::new(0xD800u32);
+        assert_eq!(a.swap(u32::MAX, SeqCst), 0xD800);
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, |x| x.checked_add(1)), Err(u32::MAX));
+        assert_eq!(a.load(SeqCst), u32::MAX);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_mul_div() {
+        let a = Atomic::new(7i32);
+        assert_eq!(a.fetch_mul(-3, SeqCst), 7);
+        assert_eq!(a.fetch_div(2, SeqCst), -21);
+        assert_eq!(a.try_fetch_div(0, SeqCst), Err(-10));
+        assert_eq!(a.load(SeqCst), -10);
+        a.store(i32::MIN, SeqCst);
+        assert_eq!(a.fetch_div(-1, SeqCst), i32::MIN);
+        assert_eq!(a.fetch_mul(-1, SeqCst), i32::MIN);
+        assert_eq!(a.load(SeqCst), i32::MIN);
+
+        let b = Atomic::new(200u8);
+        assert_eq!(b.fetch_mul(2, SeqCst), 200);
+        assert_eq!(b.load(SeqCst), 144);
+
+        // u128 takes the fallback path unless there are 128-bit atomics.
+        let c = Atomic::new(3u128 << 100);
+        assert_eq!(c.fetch_div(3, SeqCst), 3 << 100);
+        assert_eq!(c.try_fetch_div(0, SeqCst), Err(1 << 100));
+        assert_eq!(c.fetch_mul(1 << 28, SeqCst), 1 << 100);
+        assert_eq!(c.load(SeqCst), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "attempt to divide by zero")]
+    fn atomic_div_by_zero() {
+        Atomic::new(1u32).fetch_div(0, SeqCst);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_mul_concurrent() {
+        // Multiplication commutes, so any interleaving must produce the same
+        // product as a sequential loop.
+        fn check<T: Copy + PartialEq + core::fmt::Debug + Send + Sync>(
+            a: &Atomic<T>,
+            factors: &[T],
+            mul: fn(&Atomic<T>, T),
+            expected: T,
+        ) {
+            thread::scope(|s| {
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        for &f in factors {
+                            mul(a, f);
+                        }
+                    });
+                }
+            });
+            assert_eq!(a.load(SeqCst), expected);
+        }
+
+        let factors = [3, 5, 7, 11, 13, 0x1234_5679];
+        let mut expected = 1u64;
+        for _ in 0..4 {
+            for &f in &factors {
+                expected = expected.wrapping_mul(f);
+            }
+        }
+        check(&Atomic::new(1u64), &factors, |a, f| { a.fetch_mul(f, Relaxed); }, expected);
+        let factors: std::vec::Vec<u128> = factors.iter().map(|&f| f as u128).collect();
+        let mut expected = 1u128;
+        for _ in 0..4 {
+            for &f in &factors {
+                expected = expected.wrapping_mul(f);
+            }
+        }
+        check(&Atomic::new(1u128), &factors, |a, f| { a.fetch_mul(f, Relaxed); }, expected);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_saturating() {
+        let a = Atomic::new(250u8);
+        assert_eq!(a.fetch_saturating_add(3, SeqCst), 250);
+        assert_eq!(a.fetch_saturating_add(3, SeqCst), 253);
+        assert_eq!(a.fetch_saturating_add(3, SeqCst), 255);
+        assert_eq!(a.fetch_saturating_sub(200, SeqCst), 255);
+        assert_eq!(a.fetch_saturating_sub(200, SeqCst), 55);
+        assert_eq!(a.load(SeqCst), 0);
+
+        let b = Atomic::new(-100i8);
+        assert_eq!(b.fetch_saturating_add(-100, SeqCst), -100);
+        assert_eq!(b.fetch_saturating_sub(1, SeqCst), i8::MIN);
+        assert_eq!(b.fetch_saturating_sub(-100, SeqCst), i8::MIN);
+        assert_eq!(b.fetch_saturating_add(i8::MAX, SeqCst), -28);
+        assert_eq!(b.fetch_saturating_add(i8::MAX, SeqCst), 99);
+        assert_eq!(b.load(SeqCst), i8::MAX);
+
+        let c = Atomic::new(u128::MAX - 1);
+        assert_eq!(c.fetch_saturating_add(5, SeqCst), u128::MAX - 1);
+        assert_eq!(c.fetch_saturating_add(5, SeqCst), u128::MAX);
+        assert_eq!(c.load(SeqCst), u128::MAX);
+    }
+
+    #[test]
+    fn atomic_saturating_contention() {
+        // Only the calls which observed an unsaturated value changed it, so
+        // exactly 20 of them must have seen something other than MAX.
+        let a = Atomic::new(u16::MAX - 20);
+        let changed = Atomic::new(0usize);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        if a.fetch_saturating_add(1, Relaxed) != u16::MAX {
+                            changed.fetch_add(1, Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(a.load(SeqCst), u16::MAX);
+        assert_eq!(changed.load(SeqCst), 20);
+
+        let b = Atomic::new(i64::MIN + 20);
+        let changed = Atomic::new(0usize);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        if b.fetch_saturating_sub(1, Relaxed) != i64::MIN {
+                            changed.fetch_add(1, Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(b.load(SeqCst), i64::MIN);
+        assert_eq!(changed.load(SeqCst), 20);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_checked() {
+        let a = Atomic::new(u32::MAX - 1);
+        assert_eq!(a.fetch_add_checked(1, SeqCst), Some(u32::MAX - 1));
+        assert_eq!(a.fetch_add_checked(1, SeqCst), None);
+        assert_eq!(a.fetch_add_checked(0, SeqCst), Some(u32::MAX));
+        assert_eq!(a.load(SeqCst), u32::MAX);
+        a.store(1, SeqCst);
+        assert_eq!(a.fetch_sub_checked(2, SeqCst), None);
+        assert_eq!(a.fetch_sub_checked(1, SeqCst), Some(1));
+        assert_eq!(a.load(SeqCst), 0);
+
+        let b = Atomic::new(i16::MAX);
+        assert_eq!(b.fetch_add_checked(1, SeqCst), None);
+        assert_eq!(b.fetch_sub_checked(-1, SeqCst), None);
+        assert_eq!(b.fetch_add_checked(i16::MIN, SeqCst), Some(i16::MAX));
+        assert_eq!(b.fetch_sub_checked(1, SeqCst), Some(-1));
+        assert_eq!(b.fetch_sub_checked(i16::MAX, SeqCst), None);
+        assert_eq!(b.fetch_sub_checked(i16::MAX - 1, SeqCst), Some(-2));
+        assert_eq!(b.load(SeqCst), i16::MIN);
+        assert_eq!(b.fetch_sub_checked(1, SeqCst), None);
+
+        let c = Atomic::new(i128::MAX);
+        assert_eq!(c.fetch_add_checked(1, SeqCst), None);
+        assert_eq!(c.fetch_sub_checked(1, SeqCst), Some(i128::MAX));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_add_unless() {
+        let a = Atomic::new(5u8);
+        assert_eq!(a.fetch_add_unless(3, 8, SeqCst), Ok(5));
+        assert_eq!(a.fetch_add_unless(1, 8, SeqCst), Err(8));
+        assert_eq!(a.fetch_add_unless(0, 8, SeqCst), Ok(8));
+        assert_eq!(a.fetch_add_unless(1, 7, SeqCst), Err(8));
+        assert_eq!(a.load(SeqCst), 8);
+
+        // With the maximum as the limit, only overflow fails.
+        a.store(u8::MAX - 1, SeqCst);
+        assert_eq!(a.fetch_add_unless(2, u8::MAX, SeqCst), Err(u8::MAX - 1));
+        assert_eq!(a.fetch_add_unless(1, u8::MAX, SeqCst), Ok(u8::MAX - 1));
+        assert_eq!(a.fetch_add_unless(1, u8::MAX, SeqCst), Err(u8::MAX));
+        assert_eq!(a.load(SeqCst), u8::MAX);
+
+        let b = Atomic::new(i64::MAX - 1);
+        assert_eq!(b.fetch_add_unless(2, i64::MAX, SeqCst), Err(i64::MAX - 1));
+        assert_eq!(b.fetch_add_unless(-10, -5, SeqCst), Err(i64::MAX - 1));
+        b.store(0, SeqCst);
+        assert_eq!(b.fetch_add_unless(-10, -5, SeqCst), Ok(0));
+        assert_eq!(b.load(SeqCst), -10);
+
+        let c = Atomic::new(u128::MAX);
+        assert_eq!(c.fetch_add_unless(1, u128::MAX, SeqCst), Err(u128::MAX));
+    }
+
+    #[test]
+    fn atomic_add_unless_contention() {
+        // Eight threads each try to take one permit from a budget of four, so
+        // exactly four of them get one and the count never goes past it.
+        const THREADS: u32 = 8;
+        let taken = Atomic::new(0u32);
+        let granted = Atomic::new(0u32);
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    if taken.fetch_add_unless(1, THREADS / 2, AcqRel).is_ok() {
+                        granted.fetch_add(1, Relaxed);
+                    }
+                });
+            }
+        });
+        assert_eq!(granted.load(SeqCst), THREADS / 2);
+        assert_eq!(taken.load(SeqCst), THREADS / 2);
+    }
+
+    #[test]
+    fn atomic_checked_racers() {
+        let permits = Atomic::new(100u64);
+        let acquired = Atomic::new(0usize);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        if permits.fetch_sub_checked(1, Acquire).is_some() {
+                            acquired.fetch_add(1, Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(acquired.load(SeqCst), 100);
+        assert_eq!(permits.load(SeqCst), 0);
+
+        let count = Atomic::new(i8::MAX - 30);
+        let added = Atomic::new(0usize);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..20 {
+                        if count.fetch_add_checked(1, Relaxed).is_some() {
+                            added.fetch_add(1, Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(added.load(SeqCst), 30);
+        assert_eq!(count.load(SeqCst), i8::MAX);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_neg_abs_not() {
+        let a = Atomic::new(5i32);
+        assert_eq!(a.fetch_neg(SeqCst), 5);
+        assert_eq!(a.fetch_not(SeqCst), -5);
+        assert_eq!(a.fetch_abs(SeqCst), 4);
+        assert_eq!(a.fetch_neg(SeqCst), 4);
+        assert_eq!(a.fetch_abs(SeqCst), -4);
+        assert_eq!(a.load(SeqCst), 4);
+
+        a.store(i32::MIN, SeqCst);
+        assert_eq!(a.fetch_abs(SeqCst), i32::MIN);
+        assert_eq!(a.fetch_neg(SeqCst), i32::MIN);
+        assert_eq!(a.load(SeqCst), i32::MIN);
+        assert_eq!(a.fetch_not(SeqCst), i32::MIN);
+        assert_eq!(a.load(SeqCst), i32::MAX);
+
+        let b = Atomic::new(0b0101u8);
+        assert_eq!(b.fetch_not(SeqCst), 0b0101);
+        assert_eq!(b.load(SeqCst), 0b1111_1010);
+
+        let c = Atomic::new(-7i128);
+        assert_eq!(c.fetch_abs(SeqCst), -7);
+        assert_eq!(c.fetch_neg(SeqCst), 7);
+        assert_eq!(c.load(SeqCst), -7);
+    }
+
+    #[test]
+    fn atomic_neg_abs_not_concurrent() {
+        // Every operation turns the value it observed into a new one. If no
+        // update was lost, the states observed by the operations plus the
+        // final state are exactly the initial state plus the states they
+        // produced.
+        let a = Atomic::new(1i64);
+        let ops: [fn(i64) -> i64; 4] = [|x| x.wrapping_add(3), |x| -x, |x| x.abs(), |x| !x];
+        let mut transitions = std::vec::Vec::new();
+        thread::scope(|s| {
+            let handles: std::vec::Vec<_> = (0..4)
+                .map(|i| {
+                    let a = &a;
+                    s.spawn(move || {
+                        (0..1000)
+                            .map(|_| {
+                                let prev = match i {
+                                    0 => a.fetch_add(3, Relaxed),
+                                    1 => a.fetch_neg(Relaxed),
+                                    2 => a.fetch_abs(Relaxed),
+                                    _ => a.fetch_not(Relaxed),
+                                };
+                                (prev, ops[i](prev))
+                            })
+                            .collect::<std::vec::Vec<_>>()
+                    })
+                })
+                .collect();
+            for h in handles {
+                transitions.extend(h.join().unwrap());
+            }
+        });
+
+        let mut before: std::vec::Vec<i64> = transitions.iter().map(|t| t.0).collect();
+        let mut after: std::vec::Vec<i64> = transitions.iter().map(|t| t.1).collect();
+        before.push(a.load(SeqCst));
+        after.push(1);
+        before.sort();
+        after.sort();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_rotate() {
+        macro_rules! check {
+            ($($t:ty)*) => ($({
+                let bits = <$t>::BITS;
+                let x: $t = 0b1011 << (bits - 4) | 0b0110;
+                let a = Atomic::new(x);
+                assert_eq!(a.fetch_rotate_left(3, SeqCst), x);
+                assert_eq!(a.load(SeqCst), x.rotate_left(3));
+                assert_eq!(a.fetch_rotate_right(5, SeqCst), x.rotate_left(3));
+                assert_eq!(a.load(SeqCst), x.rotate_right(2));
+                for n in [0, bits, 2 * bits] {
+                    assert_eq!(a.fetch_rotate_left(n, SeqCst), x.rotate_right(2));
+                    assert_eq!(a.fetch_rotate_right(n, SeqCst), x.rotate_right(2));
+                }
+                assert_eq!(a.fetch_rotate_left(bits + 2, SeqCst), x.rotate_right(2));
+                assert_eq!(a.load(SeqCst), x);
+            })*);
+        }
+        check!(i8 i16 i32 i64 isize i128 u8 u16 u32 u64 usize u128);
+    }
+
+    #[test]
+    fn atomic_rotate_concurrent() {
+        // See atomic_neg_abs_not_concurrent.
+        let a = Atomic::new(1u32);
+        let ops: [fn(u32) -> u32; 3] = [|x| x.rotate_left(3), |x| x.rotate_right(1), |x| x | 1];
+        let mut transitions = std::vec::Vec::new();
+        thread::scope(|s| {
+            let handles: std::vec::Vec<_> = (0..3)
+                .map(|i| {
+                    let a = &a;
+                    s.spawn(move || {
+                        (0..1000)
+                            .map(|_| {
+                                let prev = match i {
+                                    0 => a.fetch_rotate_left(3, Relaxed),
+                                    1 => a.fetch_rotate_right(1, Relaxed),
+                                    _ => a.fetch_or(1, Relaxed),
+                                };
+                                (prev, ops[i](prev))
+                            })
+                            .collect::<std::vec::Vec<_>>()
+                    })
+                })
+                .collect();
+            for h in handles {
+                transitions.extend(h.join().unwrap());
+            }
+        });
+
+        let mut before: std::vec::Vec<u32> = transitions.iter().map(|t| t.0).collect();
+        let mut after: std::vec::Vec<u32> = transitions.iter().map(|t| t.1).collect();
+        before.push(a.load(SeqCst));
+        after.push(1);
+        before.sort();
+        after.sort();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_bit_ops() {
+        let a = Atomic::new(0b0100u8);
+        assert!(!a.bit_set(0, SeqCst));
+        assert!(a.bit_set(0, SeqCst));
+        assert!(a.bit_clear(2, SeqCst));
+        assert!(!a.bit_clear(2, SeqCst));
+        assert!(!a.bit_toggle(7, SeqCst));
+        assert!(a.bit_toggle(0, SeqCst));
+        assert_eq!(a.load(SeqCst), 0x80);
+
+        let b = Atomic::new(0u128);
+        assert!(!b.bit_set(127, SeqCst));
+        assert_eq!(b.load(SeqCst), 1 << 127);
+    }
+
+    #[test]
+    #[should_panic(expected = "bit index 64 out of range for u64")]
+    fn atomic_bit_out_of_range() {
+        Atomic::new(0u64).bit_set(64, SeqCst);
+    }
+
+    #[test]
+    fn atomic_bit_claims() {
+        // Every bit must be claimed by exactly one thread.
+        let word = Atomic::new(0usize);
+        let claims: std::vec::Vec<Atomic<usize>> =
+            (0..usize::BITS).map(|_| Atomic::new(0)).collect();
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for bit in 0..usize::BITS {
+                        if !word.bit_set(bit, AcqRel) {
+                            claims[bit as usize].fetch_add(1, Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(word.load(SeqCst), usize::MAX);
+        assert!(claims.iter().all(|c| c.load(SeqCst) == 1));
+
+        thread::scope(|s| {
+            for t in 0..8 {
+                let word = &word;
+                s.spawn(move || {
+                    for bit in (t..usize::BITS).step_by(8) {
+                        assert!(word.bit_clear(bit, AcqRel));
+                        assert!(!word.bit_toggle(bit, AcqRel));
+                    }
+                });
+            }
+        });
+        assert_eq!(word.load(SeqCst), usize::MAX);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_clamp() {
+        let a = Atomic::new(10i32);
+        assert_eq!(a.fetch_clamp(-5, 5, SeqCst), 10);
+        assert_eq!(a.fetch_clamp(-5, 5, SeqCst), 5);
+        assert_eq!(a.fetch_clamp(5, 5, SeqCst), 5);
+        assert_eq!(a.fetch_clamp(6, 9, SeqCst), 5);
+        assert_eq!(a.fetch_clamp(i32::MIN, i32::MAX, SeqCst), 6);
+        assert_eq!(a.load(SeqCst), 6);
+
+        let b = Atomic::new(u128::MAX);
+        assert_eq!(b.fetch_clamp(0, 1 << 100, SeqCst), u128::MAX);
+        assert_eq!(b.load(SeqCst), 1 << 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "min <= max")]
+    fn atomic_clamp_inverted() {
+        Atomic::new(0u8).fetch_clamp(2, 1, SeqCst);
+    }
+
+    #[test]
+    fn atomic_clamp_concurrent() {
+        // See atomic_neg_abs_not_concurrent.
+        let a = Atomic::new(0i32);
+        let ops: [fn(i32) -> i32; 3] = [|x| x + 7, |x| x - 3, |x| x.clamp(-10, 10)];
+        let mut transitions = std::vec::Vec::new();
+        thread::scope(|s| {
+            let handles: std::vec::Vec<_> = (0..3)
+                .map(|i| {
+                    let a = &a;
+                    s.spawn(move || {
+                        (0..1000)
+                            .map(|_| {
+                                let prev = match i {
+                                    0 => a.fetch_add(7, Relaxed),
+                                    1 => a.fetch_sub(3, Relaxed),
+                                    _ => a.fetch_clamp(-10, 10, Relaxed),
+                                };
+                                (prev, ops[i](prev))
+                            })
+                            .collect::<std::vec::Vec<_>>()
+                    })
+                })
+                .collect();
+            for h in handles {
+                transitions.extend(h.join().unwrap());
+            }
+        });
+
+        let mut before: std::vec::Vec<i32> = transitions.iter().map(|t| t.0).collect();
+        let mut after: std::vec::Vec<i32> = transitions.iter().map(|t| t.1).collect();
+        before.push(a.load(SeqCst));
+        after.push(0);
+        before.sort();
+        after.sort();
+        assert_eq!(before, after);
+
+        // Once the other threads are done, a final clamp must bring the value
+        // back in range.
+        a.fetch_clamp(-10, 10, SeqCst);
+        assert!((-10..=10).contains(&a.load(SeqCst)));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_store_max_min() {
+        let a = Atomic::new(-3i16);
+        assert!(a.store_max(4, SeqCst));
+        assert!(!a.store_max(4, SeqCst));
+        assert!(!a.store_max(-100, SeqCst));
+        assert_eq!(a.load(SeqCst), 4);
+        assert!(a.store_min(i16::MIN, SeqCst));
+        assert!(!a.store_min(i16::MIN, SeqCst));
+        assert!(!a.store_min(0, SeqCst));
+        assert_eq!(a.load(SeqCst), i16::MIN);
+
+        let b = Atomic::new(1u128 << 70);
+        assert!(!b.store_max(5, SeqCst));
+        assert!(b.store_max(u128::MAX, SeqCst));
+        assert!(b.store_min(0, SeqCst));
+        assert_eq!(b.load(SeqCst), 0);
+    }
+
+    #[test]
+    fn atomic_store_max_single_winner() {
+        for _ in 0..if cfg!(miri) { 2 } else { 100 } {
+            let a = Atomic::new(0u64);
+            let wins = Atomic::new(0usize);
+            thread::scope(|s| {
+                for _ in 0..8 {
+                    s.spawn(|| {
+                        if a.store_max(42, Relaxed) {
+                            wins.fetch_add(1, Relaxed);
+                        }
+                    });
+                }
+            });
+            assert_eq!(wins.load(SeqCst), 1);
+            assert_eq!(a.load(SeqCst), 42);
+
+            let b = Atomic::new(0i8);
+            let wins = Atomic::new(0usize);
+            thread::scope(|s| {
+                for _ in 0..8 {
+                    s.spawn(|| {
+                        if b.store_min(-42, Relaxed) {
+                            wins.fetch_add(1, Relaxed);
+                        }
+                    });
+                }
+            });
+            assert_eq!(wins.load(SeqCst), 1);
+            assert_eq!(b.load(SeqCst), -42);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_update_and_fetch() {
+        let a = Atomic::new(5u8);
+        assert_eq!(a.update_and_fetch(SeqCst, SeqCst, |x| x.checked_mul(10)), Ok(50));
+        assert_eq!(a.update_and_fetch(SeqCst, SeqCst, |x| x.checked_mul(10)), Err(50));
+        assert_eq!(a.fetch_update_both(SeqCst, SeqCst, |x| Some(x + 1)), Ok((50, 51)));
+        assert_eq!(a.fetch_update_both(SeqCst, SeqCst, |_| None), Err(51));
+        assert_eq!(a.load(SeqCst), 51);
+
+        let b = Atomic::new([1u64; 5]);
+        let res = b.fetch_update_both(SeqCst, SeqCst, |mut x| {
+            x[4] = 9;
+            Some(x)
+        });
+        assert_eq!(res, Ok(([1; 5], [1, 1, 1, 1, 9])));
+        assert_eq!(b.update_and_fetch(SeqCst, SeqCst, |_| None), Err([1, 1, 1, 1, 9]));
+    }
+
+    #[test]
+    fn atomic_update_and_fetch_concurrent() {
+        // Every new value handed out must be distinct and match what a later
+        // update saw as its previous value.
+        let a = Atomic::new(0u32);
+        let mut pairs = std::vec::Vec::new();
+        thread::scope(|s| {
+            let handles: std::vec::Vec<_> = (0..4)
+                .map(|i| {
+                    let a = &a;
+                    s.spawn(move || {
+                        (0..1000)
+                            .map(|_| {
+                                let f = |x: u32| Some(x.wrapping_mul(3).wrapping_add(i + 1));
+                                let (old, new) = a.fetch_update_both(Relaxed, Relaxed, f).unwrap();
+                                assert_eq!(new, f(old).unwrap());
+                                (old, new)
+                            })
+                            .collect::<std::vec::Vec<_>>()
+                    })
+                })
+                .collect();
+            for h in handles {
+                pairs.extend(h.join().unwrap());
+            }
+        });
+
+        let mut olds: std::vec::Vec<u32> = pairs.iter().map(|p| p.0).collect();
+        let mut news: std::vec::Vec<u32> = pairs.iter().map(|p| p.1).collect();
+        olds.push(a.load(SeqCst));
+        news.push(0);
+        olds.sort();
+        news.sort();
+        assert_eq!(olds, news);
+    }
+
+    #[test]
+    fn atomic_compare_exchange_eq_floats() {
+        let a = Atomic::new(-0.0f64);
+        assert_eq!(a.compare_exchange(0.0, 1.0, SeqCst, SeqCst), Err(-0.0));
+        let prev = a.compare_exchange_eq(0.0, 1.0, SeqCst, SeqCst).unwrap();
+        assert!(prev == 0.0 && prev.is_sign_negative());
+        assert_eq!(a.load(SeqCst), 1.0);
+
+        let a = Atomic::new(f32::NAN);
+        assert!(a.compare_exchange_eq(f32::NAN, 1.0, SeqCst, SeqCst).unwrap_err().is_nan());
+        assert!(a.load(SeqCst).is_nan());
+    }
+
+    // Two angles are equal if they point the same way.
+    #[derive(Clone, Copy, Debug)]
+    struct Degrees(u16);
+
+    impl PartialEq for Degrees {
+        fn eq(&self, other: &Degrees) -> bool {
+            self.0 % 360 == other.0 % 360
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_compare_exchange_eq_custom() {
+        let a = Atomic::new(Degrees(450));
+        assert!(Atomic::<Degrees>::is_lock_free());
+        assert!(a.compare_exchange(Degrees(90), Degrees(0), SeqCst, SeqCst).is_err());
+        let res = a.compare_exchange_eq(Degrees(90), Degrees(0), SeqCst, SeqCst);
+        assert_eq!(res.unwrap().0, 450);
+        let res = a.compare_exchange_eq(Degrees(90), Degrees(1), SeqCst, SeqCst);
+        assert_eq!(res.unwrap_err().0, 0);
+
+        // Too large to be lock-free, so the comparison happens under the lock.
+        let b = Atomic::new([Degrees(720); 3]);
+        assert!(!Atomic::<[Degrees; 3]>::is_lock_free());
+        assert!(b.compare_exchange_eq([Degrees(0); 3], [Degrees(10); 3], SeqCst, SeqCst).is_ok());
+        assert_eq!(b.load(SeqCst)[2].0, 10);
+        assert!(b.compare_exchange_eq([Degrees(0); 3], [Degrees(20); 3], SeqCst, SeqCst).is_err());
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct LimitReached(u32);
+
+    fn acquire_slot(x: u32, limit: u32) -> Result<u32, LimitReached> {
+        if x < limit {
+            Ok(x + 1)
+        } else {
+            Err(LimitReached(x))
+        }
+    }
+
+    #[test]
+    fn atomic_try_fetch_update() {
+        let a = Atomic::new(8u32);
+        assert_eq!(a.try_fetch_update(SeqCst, SeqCst, |x| acquire_slot(x, 9)), Ok(8));
+        let res = a.try_fetch_update(SeqCst, SeqCst, |x| acquire_slot(x, 9));
+        assert_eq!(res, Err(LimitReached(9)));
+        assert_eq!(a.load(SeqCst), 9);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_try_fetch_update_limit() {
+        // A counter of handed-out slots, stored alongside a checksum so that
+        // the payload is too large to be lock-free.
+        let a = Atomic::new([0u32; 9]);
+        assert!(!Atomic::<[u32; 9]>::is_lock_free());
+        let b = Atomic::new(0u32);
+        let (ok, err) = thread::scope(|s| {
+            let handles: std::vec::Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        let (mut ok, mut err) = (0, 0);
+                        for _ in 0..100 {
+                            let res = a.try_fetch_update(Relaxed, Relaxed, |mut x| {
+                                x[0] = acquire_slot(x[0], 500)?;
+                                x[8] = x[0].wrapping_mul(31);
+                                Ok(x)
+                            });
+                            match res {
+                                Ok(_) => ok += 1,
+                                Err(LimitReached(500)) => err += 1,
+                                Err(e) => panic!("{:?}", e),
+                            }
+                            let _ = b.try_fetch_update(Relaxed, Relaxed, |x| acquire_slot(x, 300));
+                        }
+                        (ok, err)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .fold((0, 0), |acc, x| (acc.0 + x.0, acc.1 + x.1))
+        });
+        assert_eq!((ok, err), (500, 300));
+        let x = a.load(SeqCst);
+        assert_eq!((x[0], x[8]), (500, 500 * 31));
+        assert_eq!(b.load(SeqCst), 300);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_take_replace() {
+        let a = Atomic::new(Some(3u8));
+        assert_eq!(a.replace(Some(4), SeqCst), Some(3));
+        assert_eq!(a.take(SeqCst), Some(4));
+        assert_eq!(a.take(SeqCst), None);
+
+        let b = Atomic::new(Bar(1, 2));
+        assert_eq!(b.take(SeqCst), Bar(1, 2));
+        assert_eq!(b.load(SeqCst), Bar(0, 0));
+
+        let c = Atomic::new([7u64; 6]);
+        assert!(!Atomic::<[u64; 6]>::is_lock_free());
+        assert_eq!(c.replace([8; 6], SeqCst), [7; 6]);
+        assert_eq!(c.take(SeqCst), [8; 6]);
+        assert_eq!(c.load(SeqCst), [0; 6]);
+    }
+
+    #[test]
+    fn atomic_take_concurrent() {
+        // Producers set distinct bits, so every nonzero value that is taken
+        // must be disjoint from all the others.
+        let a = Atomic::new(0u64);
+        let mut taken = std::vec::Vec::new();
+        thread::scope(|s| {
+            for i in 0..4 {
+                let a = &a;
+                s.spawn(move || {
+                    for bit in (i..64).step_by(4) {
+                        a.fetch_or(1 << bit, Relaxed);
+                    }
+                });
+            }
+            let takers: std::vec::Vec<_> = (0..2)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut taken = std::vec::Vec::new();
+                        for _ in 0..1000 {
+                            match a.take(Relaxed) {
+                                0 => thread::yield_now(),
+                                x => taken.push(x),
+                            }
+                        }
+                        taken
+                    })
+                })
+                .collect();
+            for h in takers {
+                taken.extend(h.join().unwrap());
+            }
+        });
+        taken.push(a.take(SeqCst));
+
+        let mut seen = 0u64;
+        for x in taken {
+            assert_eq!(seen & x, 0);
+            seen |= x;
+        }
+        assert_eq!(seen, !0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_compare_and_set() {
+        let a = Atomic::new(Foo(1, 2));
+        assert!(a.compare_and_set(Foo(1, 2), Foo(3, 4), SeqCst, SeqCst));
+        assert!(!a.compare_and_set(Foo(1, 2), Foo(5, 6), SeqCst, SeqCst));
+        assert_eq!(a.load(SeqCst), Foo(3, 4));
+
+        let b = Atomic::new([1u16; 20]);
+        assert!(!b.compare_and_set([0; 20], [2; 20], AcqRel, Acquire));
+        assert!(b.compare_and_set([1; 20], [2; 20], AcqRel, Acquire));
+        assert_eq!(b.load(SeqCst), [2; 20]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    #[allow(deprecated)]
+    fn atomic_compare_and_swap() {
+        // Every ordering, including the ones which are invalid as a failure
+        // ordering, must be accepted.
+        let a = Atomic::new(0i32);
+        for (i, &order) in [Relaxed, Release, Acquire, AcqRel, SeqCst].iter().enumerate() {
+            let i = i as i32;
+            assert_eq!(a.compare_and_swap(i, i + 1, order), i);
+            assert_eq!(a.compare_and_swap(i, i + 10, order), i + 1);
+        }
+        assert_eq!(a.load(SeqCst), 5);
+
+        let b = Atomic::new(Bar(1, 1));
+        assert_eq!(b.compare_and_swap(Bar(1, 1), Bar(2, 2), AcqRel), Bar(1, 1));
+        assert_eq!(b.compare_and_swap(Bar(1, 1), Bar(3, 3), Release), Bar(2, 2));
+    }
+
+    #[test]
+    fn strongest_failure_ordering() {
+        use ops::strongest_failure_ordering;
+        assert_eq!(strongest_failure_ordering(Relaxed), Relaxed);
+        assert_eq!(strongest_failure_ordering(Release), Relaxed);
+        assert_eq!(strongest_failure_ordering(Acquire), Acquire);
+        assert_eq!(strongest_failure_ordering(AcqRel), Acquire);
+        assert_eq!(strongest_failure_ordering(SeqCst), SeqCst);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_load_consume() {
+        assert_eq!(Atomic::new(Foo(1, 2)).load_consume(), Foo(1, 2));
+        assert_eq!(Atomic::new([3u8; 33]).load_consume(), [3; 33]);
+        assert_eq!(Atomic::new('x').load_consume(), 'x');
+
+        // The reader follows the published index into data written before it.
+        let data: std::vec::Vec<_> = (0..64).map(|_| Atomic::new(0u32)).collect();
+        let idx = Atomic::new(0usize);
+        thread::scope(|s| {
+            s.spawn(|| {
+                for (i, x) in data.iter().enumerate().skip(1) {
+                    x.store(i as u32, Relaxed);
+                    idx.store(i, Release);
+                }
+            });
+            s.spawn(|| {
+                let mut last = 0;
+                while last != 63 {
+                    let i = idx.load_consume();
+                    assert!(i >= last);
+                    assert_eq!(data[i].load(Relaxed), i as u32);
+                    last = i;
+                }
+            });
+        });
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn atomic_spin_wait() {
+        let a = Atomic::new(3u8);
+        assert_eq!(a.spin_wait_until(|x| x > 2, SeqCst), 3);
+        assert_eq!(a.spin_wait_for(3, SeqCst), 3);
+        let b = Atomic::new(Bar(1, 2));
+        assert_eq!(b.spin_wait_for(Bar(1, 2), SeqCst), Bar(1, 2));
+
+        // The writer publishes an increasing sequence, so the first value the
+        // waiter sees which satisfies the predicate is the one it was told to
+        // wait for, unless the writer already moved past it.
+        let c = Atomic::new(0u32);
+        let d = Atomic::new([0u16; 5]);
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=1000 {
+                    c.store(i, Release);
+                }
+                for i in 1..=100 {
+                    d.store([i; 5], Release);
+                }
+            });
+            let x = c.spin_wait_until(|x| x % 100 == 0 && x > 0, Acquire);
+            assert_eq!(x % 100, 0);
+            assert!(x > 0);
+            assert_eq!(c.spin_wait_for(1000, Acquire), 1000);
+            assert_eq!(d.spin_wait_for([100; 5], Acquire), [100; 5]);
+        });
+    }
+
+    #[test]
+    fn atomic_under_aligned() {
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        #[repr(align(8))]
+        struct Aligned8([u8; 8]);
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        #[repr(align(4))]
+        struct Aligned4((u16, u16));
+
+        assert!(!Atomic::<[u8; 8]>::is_lock_free());
+        assert!(!Atomic::<(u16, u16)>::is_lock_free());
+        assert_eq!(Atomic::<Aligned8>::is_lock_free(), cfg!(has_atomic_64));
+        assert_eq!(Atomic::<Aligned4>::is_lock_free(), cfg!(has_atomic_32));
+
+        let (x, y) = (Aligned8(*b"abcdefgh"), Aligned8(*b"hgfedcba"));
+        let a = Atomic::new(x);
+        assert_eq!(a.compare_exchange(x, y, SeqCst, SeqCst), Ok(x));
+        assert_eq!(a.compare_exchange(x, y, SeqCst, SeqCst), Err(y));
+        assert_eq!(a.load(SeqCst), y);
+
+        let (x, y) = (Aligned4((1, 2)), Aligned4((3, 4)));
+        let b = Atomic::new(x);
+        assert_eq!(b.compare_exchange(y, x, SeqCst, SeqCst), Err(x));
+        assert_eq!(b.compare_exchange(x, y, SeqCst, SeqCst), Ok(x));
+        assert_eq!(b.load(SeqCst), y);
+    }
+
+    fn zero_sized_ops<T: Copy + PartialEq + fmt::Debug + Default>() {
+        let val = T::default();
+        let a = Atomic::new(val);
+        assert!(Atomic::<T>::is_lock_free());
+        for &order in &[Relaxed, Acquire, SeqCst] {
+            assert_eq!(a.load(order), val);
+        }
+        assert_eq!(a.load_consume(), val);
+        for &order in &[Relaxed, Release, SeqCst] {
+            a.store(val, order);
+        }
+        for &order in &[Relaxed, Release, Acquire, AcqRel, SeqCst] {
+            assert_eq!(a.swap(val, order), val);
+            assert_eq!(a.replace(val, order), val);
+            let failure = ops::failure_ordering(order);
+            assert_eq!(a.compare_exchange(val, val, order, failure), Ok(val));
+            assert_eq!(a.compare_exchange_weak(val, val, order, failure), Ok(val));
+            assert!(a.compare_and_set(val, val, order, failure));
+            assert_eq!(a.fetch_update_both(order, failure, Some), Ok((val, val)));
+            assert_eq!(a.update_and_fetch(order, failure, |_| None), Err(val));
+            assert_eq!(a.try_fetch_update(order, failure, Ok::<T, ()>), Ok(val));
+        }
+        assert_eq!(a.take(SeqCst), val);
+        assert_eq!(a.spin_wait_for(val, SeqCst), val);
+        assert_eq!(a.spin_wait_until(|_| true, SeqCst), val);
+        assert_eq!(format!("{:?}", a), format!("Atomic({:?})", val));
+        assert_eq!(a.into_inner(), val);
+    }
+
+    #[test]
+    fn atomic_zero_sized() {
+        #[derive(Copy, Clone, Debug, Default, PartialEq)]
+        struct Unit;
+
+        zero_sized_ops::<()>();
+        zero_sized_ops::<Unit>();
+        zero_sized_ops::<core::marker::PhantomData<Box<u32>>>();
+        zero_sized_ops::<[u64; 0]>();
+    }
+
+    // The reference-taking compare-exchanges give the same results as
+    // `compare_exchange` for each pair of `current` and `new`, whether or not
+    // the value matches.
+    fn compare_exchange_by_reference<T: Copy + PartialEq + fmt::Debug>(values: &[T]) {
+        for &initial in values {
+            for &current in values {
+                for &new in values {
+                    let a = Atomic::new(initial);
+                    let b = Atomic::new(initial);
+                    let c = Atomic::new(initial);
+                    let expected = a.compare_exchange(current, new, AcqRel, Acquire);
+                    assert_eq!(b.compare_exchange_ref(&current, &new, AcqRel, Acquire), expected);
+                    let mut observed = current;
+                    let stored = c.compare_exchange_in_place(&mut observed, &new, AcqRel, Acquire);
+                    assert_eq!(stored, expected.is_ok());
+                    match expected {
+                        Ok(_) => assert_eq!(observed, current),
+                        Err(x) => assert_eq!(observed, x),
+                    }
+                    assert_eq!(b.load(Relaxed), a.load(Relaxed));
+                    assert_eq!(c.load(Relaxed), a.load(Relaxed));
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn compare_exchange_ref() {
+        compare_exchange_by_reference(&[0u8, 1, 255]);
+        compare_exchange_by_reference(&[Quux(0), Quux(1), Quux(!0)]);
+        compare_exchange_by_reference(&[Foo(0, 1), Foo(1, 0), Foo(1, 1)]);
+        compare_exchange_by_reference(&[Bar(0, 1), Bar(1, 0), Bar(1, 1)]);
+        compare_exchange_by_reference(&[[0u64; 8], [1; 8], [0, 0, 0, 0, 0, 0, 0, 1]]);
+        compare_exchange_by_reference(&[(), ()]);
+    }
+
+    // Invalid orderings panic with the messages of the standard types, for
+    // lock-free values and for values which take the fallback lock.
+    macro_rules! invalid_ordering {
+        ($($name:ident $val:expr, $message:expr, |$a:ident, $x:ident| $op:expr;)*) => ($(
+            #[test]
+            #[cfg(not(feature = "panic-free"))]
+            #[should_panic(expected = $message)]
+            fn $name() {
+                let $a = Atomic::new($val);
+                let $x = $val;
+                let _ = $op;
+            }
+        )*);
+    }
+
+    invalid_ordering! {
+        release_load Quux(1), "there is no such thing as a release load",
+            |a, _x| a.load(Release);
+        acq_rel_load Quux(1), "there is no such thing as an acquire-release load",
+            |a, _x| a.load(AcqRel);
+        acquire_store Quux(1), "there is no such thing as an acquire store",
+            |a, x| a.store(x, Acquire);
+        acq_rel_store Quux(1), "there is no such thing as an acquire-release store",
+            |a, x| a.store(x, AcqRel);
+        release_failure Quux(1), "there is no such thing as a release failure ordering",
+            |a, x| a.compare_exchange(x, x, SeqCst, Release);
+        acq_rel_failure Quux(1), "there is no such thing as an acquire-release failure ordering",
+            |a, x| a.compare_exchange_weak(x, x, SeqCst, AcqRel);
+        release_fetch_update Quux(1), "there is no such thing as a release load",
+            |a, _x| a.fetch_update_both(SeqCst, Release, Some);
+        zero_sized_acquire_store (), "there is no such thing as an acquire store",
+            |a, x| a.store(x, Acquire);
+        zero_sized_release_failure (), "there is no such thing as a release failure ordering",
+            |a, x| a.compare_exchange(x, x, SeqCst, Release);
+    }
+
+    // The fallback lock is compiled out by `no-fallback`, and the tests by
+    // `panic-free` like the ones above.
+    #[cfg(not(any(feature = "no-fallback", feature = "panic-free")))]
+    mod fallback_orderings {
+        use super::*;
+
+        invalid_ordering! {
+            fallback_release_load Bar(1, 2), "there is no such thing as a release load",
+                |a, _x| a.load(Release);
+            fallback_acq_rel_load Bar(1, 2), "there is no such thing as an acquire-release load",
+                |a, _x| a.load(AcqRel);
+            fallback_acquire_store Bar(1, 2), "there is no such thing as an acquire store",
+                |a, x| a.store(x, Acquire);
+            fallback_acq_rel_store Bar(1, 2), "there is no such thing as an acquire-release store",
+                |a, x| a.store(x, AcqRel);
+            fallback_release_failure Bar(1, 2),
+                "there is no such thing as a release failure ordering",
+                |a, x| a.compare_exchange(x, x, SeqCst, Release);
+            fallback_acq_rel_failure Bar(1, 2),
+                "there is no such thing as an acquire-release failure ordering",
+                |a, x| a.compare_exchange_weak(x, x, SeqCst, AcqRel);
+            fallback_release_fetch_update Bar(1, 2), "there is no such thing as a release load",
+                |a, _x| a.fetch_update_both(SeqCst, Release, Some);
+            fallback_release_failure_ref Bar(1, 2),
+                "there is no such thing as a release failure ordering",
+                |a, x| a.compare_exchange_ref(&x, &x, SeqCst, Release);
+            fallback_acq_rel_failure_in_place Bar(1, 2),
+                "there is no such thing as an acquire-release failure ordering",
+                |a, x| a.compare_exchange_in_place(&mut { x }, &x, SeqCst, AcqRel);
+        }
+    }
+
+    // With `panic-free`, the same orderings lose the part which doesn't apply.
+    #[test]
+    #[cfg(feature = "panic-free")]
+    fn invalid_orderings_replaced() {
+        let a = Atomic::new(Quux(1));
+        assert_eq!(a.load(Release), Quux(1));
+        a.store(Quux(2), AcqRel);
+        assert_eq!(a.load(AcqRel), Quux(2));
+        assert_eq!(a.compare_exchange(Quux(2), Quux(3), SeqCst, Release), Ok(Quux(2)));
+        assert_eq!(a.compare_exchange_weak(Quux(2), Quux(4), SeqCst, AcqRel), Err(Quux(3)));
+        assert_eq!(a.fetch_update_both(SeqCst, Release, |_| Some(Quux(5))), Ok((Quux(3), Quux(5))));
+
+        let b = Atomic::new(Bar(1, 2));
+        assert_eq!(b.load(Release), Bar(1, 2));
+        b.store(Bar(3, 4), Acquire);
+        assert_eq!(b.compare_exchange(Bar(3, 4), Bar(5, 6), SeqCst, AcqRel), Ok(Bar(3, 4)));
+        assert_eq!(b.compare_exchange_ref(&Bar(3, 4), &Bar(7, 8), SeqCst, Release), Err(Bar(5, 6)));
+        assert_eq!(b.load(AcqRel), Bar(5, 6));
+    }
 }
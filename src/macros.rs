@@ -0,0 +1,68 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/// Fails to compile unless `Atomic<T>` is lock-free for the given type.
+///
+/// This expands to a constant which evaluates `Atomic::<T>::IS_LOCK_FREE`,
+/// so it can be placed next to the `static` or type which relies on the
+/// payload being lock-free, for example because it is accessed from a signal
+/// handler or from memory shared with another process. A custom message can
+/// be given as a second argument.
+///
+/// The check is made for the target being compiled for, using the atomic
+/// widths the build script detected for it, so a crate which builds for the
+/// host may fail to build for another target. For example `u64` is
+/// lock-free on x86_64, but not on 32-bit ARM microcontrollers, which have no
+/// 64-bit atomics, or on 32-bit x86 Linux, where `u64` is only 4-byte aligned.
+/// On wasm without the `atomics` target feature every type passes.
+/// When cross-compiling, the assertion therefore needs to hold for every
+/// target the crate is built for, and can be wrapped in `#[cfg(...)]` for
+/// types which are only lock-free on some of them.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate atomic;
+///
+/// use atomic::Atomic;
+///
+/// #[derive(Copy, Clone)]
+/// #[repr(C, align(4))]
+/// struct Rgba(u8, u8, u8, u8);
+///
+/// assert_lock_free!(Rgba);
+/// assert_lock_free!(u32, "the state word is read from a signal handler");
+/// static COLOR: Atomic<Rgba> = Atomic::new(Rgba(0, 0, 0, 255));
+/// # fn main() {}
+/// ```
+///
+/// A payload which isn't lock-free is rejected:
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate atomic;
+///
+/// assert_lock_free!([u64; 4]);
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! assert_lock_free {
+    ($t:ty $(,)?) => {
+        const _: () = if !$crate::ops::atomic_is_lock_free::<$t>() {
+            panic!(
+                "{}",
+                concat!("`Atomic<", stringify!($t), ">` is not lock-free on this target")
+            )
+        };
+    };
+    ($t:ty, $msg:expr $(,)?) => {
+        const _: () = if !$crate::ops::atomic_is_lock_free::<$t>() {
+            panic!("{}", $msg)
+        };
+    };
+}
@@ -0,0 +1,371 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use core::mem;
+use core::ptr;
+use ops;
+use Atomic;
+use Ordering;
+
+const MARK: usize = 1;
+
+/// An atomic pointer with a mark bit, which are read and updated together.
+///
+/// The mark is stored in the lowest bit of the pointer, which is always zero
+/// because of the alignment of `T`, so `T` must be aligned to at least 2
+/// bytes. A null pointer can carry either mark. This is typically used to mark
+/// a node of a lock-free linked list as deleted before it is unlinked, by
+/// setting the mark on its `next` pointer.
+///
+/// The pointer keeps its provenance while it is marked.
+pub struct AtomicMarkablePtr<T> {
+    p: Atomic<*mut T>,
+}
+
+// Like `AtomicPtr`, an `AtomicMarkablePtr` can be shared freely since
+// dereferencing the pointer it returns is unsafe.
+unsafe impl<T> Send for AtomicMarkablePtr<T> {}
+unsafe impl<T> Sync for AtomicMarkablePtr<T> {}
+
+impl<T> Default for AtomicMarkablePtr<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(ptr::null_mut(), false)
+    }
+}
+
+impl<T> fmt::Debug for AtomicMarkablePtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (ptr, mark) = self.load(Ordering::SeqCst);
+        f.debug_struct("AtomicMarkablePtr")
+            .field("ptr", &ptr)
+            .field("mark", &mark)
+            .finish()
+    }
+}
+
+impl<T> AtomicMarkablePtr<T> {
+    const CHECK: () = assert!(
+        mem::align_of::<T>() >= 2,
+        "AtomicMarkablePtr needs a type aligned to at least 2 bytes"
+    );
+
+    /// Creates a new `AtomicMarkablePtr`.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if the lowest bit of `ptr` is set.
+    #[inline]
+    pub fn new(ptr: *mut T, mark: bool) -> AtomicMarkablePtr<T> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::CHECK;
+        AtomicMarkablePtr {
+            p: Atomic::new(Self::compose(ptr, mark)),
+        }
+    }
+
+    #[inline]
+    fn compose(ptr: *mut T, mark: bool) -> *mut T {
+        debug_assert!(
+            ptr as usize & MARK == 0,
+            "pointer already has its mark bit set"
+        );
+        ptr.wrapping_byte_add(mark as usize)
+    }
+
+    #[inline]
+    fn decompose(p: *mut T) -> (*mut T, bool) {
+        let mark = p as usize & MARK;
+        (p.wrapping_byte_sub(mark), mark != 0)
+    }
+
+    /// Loads the pointer and the mark.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> (*mut T, bool) {
+        Self::decompose(self.p.load(order))
+    }
+
+    /// Stores a pointer and a mark.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`. In debug builds, also
+    /// panics if the lowest bit of the pointer is set.
+    #[inline]
+    pub fn set(&self, new: (*mut T, bool), order: Ordering) {
+        self.p.store(Self::compose(new.0, new.1), order)
+    }
+
+    /// Stores a pointer and a mark, returning the previous ones.
+    #[inline]
+    pub fn swap(&self, new: (*mut T, bool), order: Ordering) -> (*mut T, bool) {
+        Self::decompose(self.p.swap(Self::compose(new.0, new.1), order))
+    }
+
+    /// Stores `new` if the current pointer and mark are both equal to
+    /// `current`.
+    ///
+    /// The return value is a result indicating whether the new value was
+    /// written and containing the previous pointer and mark.
+    #[inline]
+    pub fn compare_exchange(
+        &self,
+        current: (*mut T, bool),
+        new: (*mut T, bool),
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(*mut T, bool), (*mut T, bool)> {
+        match self.p.compare_exchange(
+            Self::compose(current.0, current.1),
+            Self::compose(new.0, new.1),
+            success,
+            failure,
+        ) {
+            Ok(p) => Ok(Self::decompose(p)),
+            Err(p) => Err(Self::decompose(p)),
+        }
+    }
+
+    /// Stores `new` if the current pointer and mark are both equal to
+    /// `current`.
+    ///
+    /// Unlike `compare_exchange`, this function is allowed to spuriously fail
+    /// even when the comparison succeeds, which can result in more efficient
+    /// code on some platforms.
+    #[inline]
+    pub fn compare_exchange_weak(
+        &self,
+        current: (*mut T, bool),
+        new: (*mut T, bool),
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(*mut T, bool), (*mut T, bool)> {
+        match self.p.compare_exchange_weak(
+            Self::compose(current.0, current.1),
+            Self::compose(new.0, new.1),
+            success,
+            failure,
+        ) {
+            Ok(p) => Ok(Self::decompose(p)),
+            Err(p) => Err(Self::decompose(p)),
+        }
+    }
+
+    /// Sets the mark to `mark` if the current pointer is equal to `expected`.
+    ///
+    /// Returns `true` if the pointer was equal to `expected`, in which case
+    /// the mark is now `mark`, whether or not it already was. The loads use
+    /// the strongest ordering allowed for the failure of a compare-exchange
+    /// with the ordering `order`.
+    #[inline]
+    pub fn attempt_mark(&self, expected: *mut T, mark: bool, order: Ordering) -> bool {
+        let failure = ops::failure_ordering(order);
+        let mut current = self.load(failure);
+        loop {
+            if current.0 != expected {
+                return false;
+            }
+            if current.1 == mark {
+                return true;
+            }
+            match self.compare_exchange_weak(current, (expected, mark), order, failure) {
+                Ok(_) => return true,
+                Err(x) => current = x,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AtomicMarkablePtr;
+    use core::ptr;
+    use std::thread;
+    use std::vec::Vec;
+    use Ordering::*;
+
+    #[test]
+    fn markable_ptr() {
+        let mut x = 0u64;
+        let p = &mut x as *mut u64;
+        let a = AtomicMarkablePtr::new(p, false);
+        assert_eq!(a.load(SeqCst), (p, false));
+        assert!(a.attempt_mark(p, true, SeqCst));
+        assert_eq!(a.load(SeqCst), (p, true));
+        assert!(a.attempt_mark(p, true, SeqCst));
+        assert!(!a.attempt_mark(ptr::null_mut(), false, SeqCst));
+        assert_eq!(
+            a.compare_exchange((p, false), (ptr::null_mut(), false), SeqCst, SeqCst),
+            Err((p, true))
+        );
+        assert_eq!(
+            a.compare_exchange((p, true), (p, false), SeqCst, SeqCst),
+            Ok((p, true))
+        );
+        assert_eq!(a.swap((p, true), SeqCst), (p, false));
+        assert_eq!(
+            format!("{:?}", a),
+            format!("AtomicMarkablePtr {{ ptr: {:?}, mark: true }}", p)
+        );
+
+        // Writing through a loaded pointer is fine: the mark doesn't cost the
+        // pointer its provenance.
+        unsafe { *a.load(SeqCst).0 = 42 };
+        assert_eq!(x, 42);
+    }
+
+    #[test]
+    fn null_with_either_mark() {
+        let a = AtomicMarkablePtr::<u32>::default();
+        assert_eq!(a.load(SeqCst), (ptr::null_mut(), false));
+        a.set((ptr::null_mut(), true), SeqCst);
+        assert_eq!(a.load(SeqCst), (ptr::null_mut(), true));
+        assert!(a.attempt_mark(ptr::null_mut(), false, SeqCst));
+        assert_eq!(a.load(SeqCst), (ptr::null_mut(), false));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "pointer already has its mark bit set")]
+    fn marked_pointer() {
+        let x = 0u32;
+        let p = (&x as *const u32 as *mut u32).wrapping_byte_add(1);
+        AtomicMarkablePtr::new(p, false);
+    }
+
+    struct Node {
+        key: usize,
+        next: AtomicMarkablePtr<Node>,
+    }
+
+    // A sorted linked list where a node is deleted by first marking its `next`
+    // pointer, which makes inserting after it fail, and then unlinking it. Any
+    // thread which finds a marked node on its way unlinks it. The nodes are
+    // owned by the test, so they are never freed while the list is in use.
+    struct List {
+        head: Node,
+    }
+
+    impl List {
+        // Returns the node with `key` and its predecessor, unlinking every
+        // marked node on the way.
+        fn find(&self, key: usize) -> Option<(&Node, &Node)> {
+            'retry: loop {
+                let mut prev = &self.head;
+                let mut curr = prev.next.load(Acquire).0;
+                while let Some(node) = unsafe { curr.as_ref() } {
+                    let (succ, marked) = node.next.load(Acquire);
+                    if marked {
+                        match prev.next.compare_exchange(
+                            (curr, false),
+                            (succ, false),
+                            AcqRel,
+                            Acquire,
+                        ) {
+                            Ok(_) => curr = succ,
+                            Err(_) => continue 'retry,
+                        }
+                        continue;
+                    }
+                    if node.key == key {
+                        return Some((prev, node));
+                    }
+                    prev = node;
+                    curr = succ;
+                }
+                return None;
+            }
+        }
+
+        // Returns whether this call deleted the node with `key`.
+        fn delete(&self, key: usize) -> bool {
+            loop {
+                let (prev, node) = match self.find(key) {
+                    Some(x) => x,
+                    None => return false,
+                };
+                let (succ, marked) = node.next.load(Acquire);
+                if marked {
+                    continue;
+                }
+                if node
+                    .next
+                    .compare_exchange((succ, false), (succ, true), AcqRel, Acquire)
+                    .is_ok()
+                {
+                    let curr = node as *const Node as *mut Node;
+                    let _ =
+                        prev.next
+                            .compare_exchange((curr, false), (succ, false), AcqRel, Acquire);
+                    return true;
+                }
+            }
+        }
+
+        fn keys(&self) -> Vec<usize> {
+            let mut keys = Vec::new();
+            let mut curr = self.head.next.load(Acquire).0;
+            while let Some(node) = unsafe { curr.as_ref() } {
+                let (succ, marked) = node.next.load(Acquire);
+                assert!(!marked, "a marked node is still linked");
+                keys.push(node.key);
+                curr = succ;
+            }
+            keys
+        }
+    }
+
+    #[test]
+    fn logical_deletion() {
+        const NODES: usize = 8;
+        let mut nodes: Vec<Node> = (1..=NODES)
+            .map(|key| Node {
+                key,
+                next: AtomicMarkablePtr::default(),
+            })
+            .collect();
+        let base = nodes.as_mut_ptr();
+        let list = List {
+            head: Node {
+                key: 0,
+                next: AtomicMarkablePtr::new(base, false),
+            },
+        };
+        for (i, node) in nodes[..NODES - 1].iter().enumerate() {
+            node.next.set((base.wrapping_add(i + 1), false), Relaxed);
+        }
+
+        // Every thread deletes every even key, so each of them is deleted by
+        // exactly one thread, while the odd keys stay.
+        let deleted: Vec<usize> = thread::scope(|s| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        (2..=NODES)
+                            .step_by(2)
+                            .filter(|&key| list.delete(key))
+                            .count()
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        assert_eq!(deleted.iter().sum::<usize>(), NODES / 2);
+
+        // A failed unlink is left to the next search, which finishes it.
+        assert!(list.find(usize::MAX).is_none());
+        assert_eq!(list.keys(), [1, 3, 5, 7]);
+        for node in &nodes {
+            assert_eq!(node.next.load(Relaxed).1, node.key % 2 == 0);
+        }
+    }
+}
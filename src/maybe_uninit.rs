@@ -0,0 +1,426 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::{self, MaybeUninit};
+use core::sync::atomic::Ordering;
+use fallback;
+use ops;
+
+/// An atomic cell for a value which may be uninitialized.
+///
+/// An `Atomic<MaybeUninit<T>>` can't hold uninitialized bytes. The integer
+/// atomics it is built on don't freeze the memory they access: loading
+/// uninitialized bytes into an `AtomicU64`, or turning them into a `u64` to
+/// store them, is undefined behavior, and so is comparing them in
+/// `compare_exchange`. An `AtomicMaybeUninit` only has the operations which
+/// move the bytes without looking at them, `load`, `store` and `swap`, and
+/// has no `compare_exchange` at all.
+///
+/// On x86 and x86_64, values of 1, 2, 4 and, on x86_64, 8 bytes which are
+/// aligned to their size are accessed with inline assembly, whose operands
+/// may be uninitialized, so they are lock-free. Everywhere else, including
+/// under Miri, the value is protected by the lock table used by `Atomic<T>`
+/// for types which aren't lock-free. Either way a load of uninitialized bytes
+/// returns them uninitialized rather than frozen to some arbitrary value, so
+/// the result may only be assumed to be initialized once the value is known
+/// to have been stored, for example by an `Acquire` load of a flag which was
+/// set with `Release` after the store.
+///
+/// This type isn't available with the `no-fallback` feature, which removes
+/// the lock table.
+///
+/// # Examples
+///
+/// ```
+/// use atomic::{Atomic, AtomicMaybeUninit, Ordering};
+/// use std::mem::MaybeUninit;
+///
+/// let payload = AtomicMaybeUninit::<[u8; 8]>::uninit();
+/// let ready = Atomic::new(false);
+///
+/// // Reading the slot before it is published is fine, as long as the result
+/// // isn't assumed to be initialized.
+/// let _early = payload.load(Ordering::Relaxed);
+///
+/// payload.store(MaybeUninit::new(*b"payload!"), Ordering::Relaxed);
+/// ready.store(true, Ordering::Release);
+///
+/// if ready.load(Ordering::Acquire) {
+///     let bytes = unsafe { payload.load(Ordering::Relaxed).assume_init() };
+///     assert_eq!(&bytes, b"payload!");
+/// }
+/// ```
+///
+/// The bytes can't be compared:
+///
+/// ```compile_fail
+/// use atomic::{AtomicMaybeUninit, Ordering};
+/// use std::mem::MaybeUninit;
+///
+/// let a = AtomicMaybeUninit::<u32>::uninit();
+/// let (current, new) = (MaybeUninit::new(0), MaybeUninit::new(1));
+/// let _ = a.compare_exchange(current, new, Ordering::AcqRel, Ordering::Acquire);
+/// ```
+pub struct AtomicMaybeUninit<T: Copy> {
+    v: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Like `Atomic<T>`, the cell only hands out copies of the value.
+unsafe impl<T: Copy + Send> Sync for AtomicMaybeUninit<T> {}
+
+impl<T: Copy> Default for AtomicMaybeUninit<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::uninit()
+    }
+}
+
+impl<T: Copy> fmt::Debug for AtomicMaybeUninit<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("AtomicMaybeUninit { .. }")
+    }
+}
+
+impl<T: Copy> AtomicMaybeUninit<T> {
+    // Whether the value is accessed with the instructions in `native`. This is
+    // a constant so that only one of the paths is compiled for each `T`.
+    const NATIVE: bool = native::AVAILABLE
+        && (mem::size_of::<T>() == 1
+            || mem::size_of::<T>() == 2
+            || mem::size_of::<T>() == 4
+            || (mem::size_of::<T>() == 8 && native::HAS_64))
+        && mem::align_of::<T>() >= mem::size_of::<T>();
+
+    /// Creates a new `AtomicMaybeUninit`.
+    #[inline]
+    pub const fn new(v: MaybeUninit<T>) -> AtomicMaybeUninit<T> {
+        AtomicMaybeUninit {
+            v: UnsafeCell::new(v),
+        }
+    }
+
+    /// Creates a new `AtomicMaybeUninit` whose value is uninitialized.
+    #[inline]
+    pub const fn uninit() -> AtomicMaybeUninit<T> {
+        Self::new(MaybeUninit::uninit())
+    }
+
+    /// Returns whether operations on values of type `T` are lock-free.
+    #[inline]
+    pub const fn is_lock_free() -> bool {
+        Self::NATIVE
+    }
+
+    /// Returns a mutable reference to the value.
+    ///
+    /// This is safe because the mutable reference guarantees that no other
+    /// threads are concurrently accessing the value.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut MaybeUninit<T> {
+        self.v.get_mut()
+    }
+
+    /// Consumes the cell and returns the contained value.
+    #[inline]
+    pub fn into_inner(self) -> MaybeUninit<T> {
+        self.v.into_inner()
+    }
+
+    /// Returns a raw pointer to the value.
+    ///
+    /// Accesses through the pointer while other threads may access the cell
+    /// must be made in the same way as the cell makes them, see the type
+    /// documentation.
+    #[inline]
+    pub const fn as_ptr(&self) -> *mut MaybeUninit<T> {
+        self.v.get()
+    }
+
+    /// Loads the value, which may be uninitialized.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> MaybeUninit<T> {
+        ops::check_load_ordering(order);
+        if Self::NATIVE {
+            unsafe { native::load(self.v.get()) }
+        } else {
+            unsafe { fallback::atomic_load(self.v.get()) }
+        }
+    }
+
+    /// Stores a value, which may be uninitialized.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn store(&self, val: MaybeUninit<T>, order: Ordering) {
+        let order = ops::check_store_ordering(order);
+        if Self::NATIVE {
+            unsafe { native::store(self.v.get(), val, order) }
+        } else {
+            unsafe { fallback::atomic_store(self.v.get(), val) }
+        }
+    }
+
+    /// Stores a value, which may be uninitialized, and returns the previous
+    /// value.
+    #[inline]
+    pub fn swap(&self, val: MaybeUninit<T>, _order: Ordering) -> MaybeUninit<T> {
+        if Self::NATIVE {
+            unsafe { native::swap(self.v.get(), val) }
+        } else {
+            unsafe { fallback::atomic_swap(self.v.get(), val) }
+        }
+    }
+}
+
+// Loads and stores are plain `mov`s on x86, which are atomic when aligned and
+// already have acquire and release semantics. Sequentially consistent stores
+// and swaps use `xchg`, which has an implicit `lock` prefix, like the
+// standard atomic types. None of the blocks are marked `nomem`, so they are
+// also compiler barriers.
+//
+// Miri can't run inline assembly, and ThreadSanitizer can't see the accesses,
+// so they take the lock instead.
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(miri),
+    not(sanitize_thread)
+))]
+mod native {
+    use core::arch::asm;
+    use core::mem::{self, MaybeUninit};
+    use core::sync::atomic::Ordering;
+    use ops::transmute_bits;
+
+    pub const AVAILABLE: bool = true;
+    pub const HAS_64: bool = cfg!(target_arch = "x86_64");
+
+    macro_rules! width {
+        ($load:ident, $store:ident, $swap:ident, $int:ty, $class:ident, $size:literal, $reg:literal) => {
+            #[inline]
+            unsafe fn $load(src: *const MaybeUninit<$int>) -> MaybeUninit<$int> {
+                let out: MaybeUninit<$int>;
+                asm!(
+                    concat!("mov {out", $reg, "}, ", $size, " ptr [{src}]"),
+                    src = in(reg) src,
+                    out = lateout($class) out,
+                    options(nostack, preserves_flags),
+                );
+                out
+            }
+
+            #[inline]
+            unsafe fn $store(dst: *mut MaybeUninit<$int>, val: MaybeUninit<$int>, order: Ordering) {
+                if order == Ordering::SeqCst {
+                    asm!(
+                        concat!("xchg ", $size, " ptr [{dst}], {val", $reg, "}"),
+                        dst = in(reg) dst,
+                        val = inout($class) val => _,
+                        options(nostack, preserves_flags),
+                    );
+                } else {
+                    asm!(
+                        concat!("mov ", $size, " ptr [{dst}], {val", $reg, "}"),
+                        dst = in(reg) dst,
+                        val = in($class) val,
+                        options(nostack, preserves_flags),
+                    );
+                }
+            }
+
+            #[inline]
+            unsafe fn $swap(dst: *mut MaybeUninit<$int>, val: MaybeUninit<$int>) -> MaybeUninit<$int> {
+                let out: MaybeUninit<$int>;
+                asm!(
+                    concat!("xchg ", $size, " ptr [{dst}], {val", $reg, "}"),
+                    dst = in(reg) dst,
+                    val = inout($class) val => out,
+                    options(nostack, preserves_flags),
+                );
+                out
+            }
+        };
+    }
+
+    width!(load8, store8, swap8, u8, reg_byte, "byte", "");
+    width!(load16, store16, swap16, u16, reg, "word", ":x");
+    width!(load32, store32, swap32, u32, reg, "dword", ":e");
+    #[cfg(target_arch = "x86_64")]
+    width!(load64, store64, swap64, u64, reg, "qword", ":r");
+
+    // The callers only use these for the sizes `NATIVE` allows.
+    macro_rules! dispatch {
+        ($size:expr, $f8:expr, $f16:expr, $f32:expr, $f64:expr) => {
+            match $size {
+                1 => $f8,
+                2 => $f16,
+                4 => $f32,
+                #[cfg(target_arch = "x86_64")]
+                8 => $f64,
+                _ => unreachable!(),
+            }
+        };
+    }
+
+    #[inline]
+    pub unsafe fn load<T>(src: *const MaybeUninit<T>) -> MaybeUninit<T> {
+        dispatch!(
+            mem::size_of::<T>(),
+            transmute_bits(load8(src as *const _)),
+            transmute_bits(load16(src as *const _)),
+            transmute_bits(load32(src as *const _)),
+            transmute_bits(load64(src as *const _))
+        )
+    }
+
+    #[inline]
+    pub unsafe fn store<T>(dst: *mut MaybeUninit<T>, val: MaybeUninit<T>, order: Ordering) {
+        dispatch!(
+            mem::size_of::<T>(),
+            store8(dst as *mut _, transmute_bits(val), order),
+            store16(dst as *mut _, transmute_bits(val), order),
+            store32(dst as *mut _, transmute_bits(val), order),
+            store64(dst as *mut _, transmute_bits(val), order)
+        )
+    }
+
+    #[inline]
+    pub unsafe fn swap<T>(dst: *mut MaybeUninit<T>, val: MaybeUninit<T>) -> MaybeUninit<T> {
+        dispatch!(
+            mem::size_of::<T>(),
+            transmute_bits(swap8(dst as *mut _, transmute_bits(val))),
+            transmute_bits(swap16(dst as *mut _, transmute_bits(val))),
+            transmute_bits(swap32(dst as *mut _, transmute_bits(val))),
+            transmute_bits(swap64(dst as *mut _, transmute_bits(val)))
+        )
+    }
+}
+
+#[cfg(not(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(miri),
+    not(sanitize_thread)
+)))]
+mod native {
+    use core::mem::MaybeUninit;
+    use core::sync::atomic::Ordering;
+
+    pub const AVAILABLE: bool = false;
+    pub const HAS_64: bool = false;
+
+    pub unsafe fn load<T>(_src: *const MaybeUninit<T>) -> MaybeUninit<T> {
+        unreachable!()
+    }
+
+    pub unsafe fn store<T>(_dst: *mut MaybeUninit<T>, _val: MaybeUninit<T>, _order: Ordering) {
+        unreachable!()
+    }
+
+    pub unsafe fn swap<T>(_dst: *mut MaybeUninit<T>, _val: MaybeUninit<T>) -> MaybeUninit<T> {
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AtomicMaybeUninit;
+    use core::mem::MaybeUninit;
+    use core::sync::atomic::Ordering::*;
+    use std::thread;
+    use Atomic;
+
+    #[test]
+    fn lock_free() {
+        let native = cfg!(all(
+            any(target_arch = "x86", target_arch = "x86_64"),
+            not(miri),
+            not(sanitize_thread)
+        ));
+        assert_eq!(AtomicMaybeUninit::<u8>::is_lock_free(), native);
+        assert_eq!(AtomicMaybeUninit::<u32>::is_lock_free(), native);
+        assert_eq!(
+            AtomicMaybeUninit::<u64>::is_lock_free(),
+            native && cfg!(target_arch = "x86_64")
+        );
+        // These aren't aligned to their size.
+        assert!(!AtomicMaybeUninit::<[u8; 2]>::is_lock_free());
+        assert!(!AtomicMaybeUninit::<[u16; 3]>::is_lock_free());
+    }
+
+    // Moves uninitialized bytes in and out without ever looking at them, which
+    // Miri would report.
+    fn uninit_values<T: Copy + PartialEq + ::core::fmt::Debug>(a: T, b: T) {
+        let c = AtomicMaybeUninit::<T>::uninit();
+        let _ = c.load(SeqCst);
+        c.store(MaybeUninit::uninit(), Release);
+        c.store(MaybeUninit::uninit(), SeqCst);
+        let _ = c.swap(MaybeUninit::new(a), AcqRel);
+        assert_eq!(unsafe { c.load(Acquire).assume_init() }, a);
+        let old = c.swap(MaybeUninit::uninit(), SeqCst);
+        assert_eq!(unsafe { old.assume_init() }, a);
+        c.store(MaybeUninit::new(b), SeqCst);
+        assert_eq!(unsafe { c.load(Relaxed).assume_init() }, b);
+        c.store(MaybeUninit::new(a), Relaxed);
+        assert_eq!(unsafe { c.into_inner().assume_init() }, a);
+    }
+
+    #[test]
+    fn uninit() {
+        uninit_values(1u8, 2);
+        uninit_values(1u16, 2);
+        uninit_values(1u32, 2);
+        uninit_values(1u64, !0);
+        uninit_values([1u8; 2], [2; 2]);
+        uninit_values([1u16; 3], [2; 3]);
+        uninit_values([1u64; 4], [2; 4]);
+        uninit_values((), ());
+    }
+
+    // A slab slot which is loaded before it is initialized, and only assumed
+    // to be initialized once the flag publishing it has been seen.
+    #[test]
+    fn publish() {
+        let slot = AtomicMaybeUninit::<[u8; 8]>::default();
+        let ready = Atomic::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                slot.store(MaybeUninit::new(*b"payload!"), Relaxed);
+                ready.store(true, Release);
+            });
+            s.spawn(|| loop {
+                let _early = slot.load(Relaxed);
+                if ready.load(Acquire) {
+                    let payload = unsafe { slot.load(Relaxed).assume_init() };
+                    assert_eq!(&payload, b"payload!");
+                    break;
+                }
+                thread::yield_now();
+            });
+        });
+    }
+
+    #[test]
+    #[cfg(not(feature = "panic-free"))]
+    #[should_panic(expected = "there is no such thing as a release load")]
+    fn release_load() {
+        AtomicMaybeUninit::<u32>::uninit().load(Release);
+    }
+
+    #[test]
+    #[cfg(not(feature = "panic-free"))]
+    #[should_panic(expected = "there is no such thing as an acquire store")]
+    fn acquire_store() {
+        AtomicMaybeUninit::<[u8; 3]>::uninit().store(MaybeUninit::uninit(), Acquire);
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use core::mem;
+use core::panic::Location;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A misuse of an `Atomic` detected by one of the debugging features.
+///
+/// Diagnostics are reported to the handler installed with
+/// `set_misuse_handler`, which panics by default.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Misuse {
+    /// A thread kept observing the same value from `Relaxed` loads while
+    /// other threads were modifying the atomic.
+    StaleRead {
+        /// Location of the load which triggered the diagnostic.
+        location: &'static Location<'static>,
+        /// Number of consecutive loads which observed the same value.
+        loads: usize,
+        /// Number of modifications made to the atomic since the value was
+        /// first observed.
+        modifications: usize,
+    },
+}
+
+impl fmt::Display for Misuse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Misuse::StaleRead {
+                location,
+                loads,
+                modifications,
+            } => write!(
+                f,
+                "stale relaxed load at {}: the same value was observed by {} consecutive \
+                 loads while the atomic was modified {} times",
+                location, loads, modifications
+            ),
+        }
+    }
+}
+
+// The handler is stored as a function pointer cast to usize, with 0 meaning
+// the default handler.
+static HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Installs a handler which is called whenever a misuse is detected.
+///
+/// The default handler panics with a description of the misuse.
+pub fn set_misuse_handler(handler: fn(&Misuse)) {
+    HANDLER.store(handler as usize, Ordering::Release);
+}
+
+fn default_handler(misuse: &Misuse) {
+    panic!("{}", misuse);
+}
+
+pub fn report(misuse: Misuse) {
+    let handler = match HANDLER.load(Ordering::Acquire) {
+        0 => default_handler as fn(&Misuse),
+        f => unsafe { mem::transmute::<usize, fn(&Misuse)>(f) },
+    };
+    handler(&misuse);
+}
@@ -0,0 +1,462 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Integer operations for newtypes around primitive integers, including
+// `Wrapping` and `Saturating` from the standard library.
+//
+// The operations are performed on the memory of the `Atomic` as the primitive
+// integer, which has the same size and alignment as the newtype, so they take
+// exactly the same path through `ops` as they would for an `Atomic` of the
+// integer itself, including the native instructions where there are some.
+
+use core::num::{Saturating, Wrapping};
+use core::sync::atomic::Ordering;
+use ops;
+use Atomic;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A primitive integer type, which can be the representation of an
+/// [`IntegerNewtype`].
+///
+/// This trait is sealed and implemented for all the primitive integer types.
+/// Whether the type is signed decides how `fetch_min` and `fetch_max` compare
+/// values.
+pub trait PrimitiveInteger: Copy + Eq + sealed::Sealed {
+    #[doc(hidden)]
+    unsafe fn atomic_add(dst: *mut Self, val: Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    unsafe fn atomic_sub(dst: *mut Self, val: Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    unsafe fn atomic_min(dst: *mut Self, val: Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    unsafe fn atomic_max(dst: *mut Self, val: Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    fn saturating_add(self, val: Self) -> Self;
+    #[doc(hidden)]
+    fn saturating_sub(self, val: Self) -> Self;
+}
+
+macro_rules! primitive_integer {
+    ($min:ident $max:ident: $($t:ty)*) => ($(
+        impl sealed::Sealed for $t {}
+
+        impl PrimitiveInteger for $t {
+            #[inline]
+            unsafe fn atomic_add(dst: *mut $t, val: $t, order: Ordering) -> $t {
+                ops::atomic_add(dst, val, order)
+            }
+
+            #[inline]
+            unsafe fn atomic_sub(dst: *mut $t, val: $t, order: Ordering) -> $t {
+                ops::atomic_sub(dst, val, order)
+            }
+
+            #[inline]
+            unsafe fn atomic_min(dst: *mut $t, val: $t, order: Ordering) -> $t {
+                ops::$min(dst, val, order)
+            }
+
+            #[inline]
+            unsafe fn atomic_max(dst: *mut $t, val: $t, order: Ordering) -> $t {
+                ops::$max(dst, val, order)
+            }
+
+            #[inline]
+            fn saturating_add(self, val: $t) -> $t {
+                <$t>::saturating_add(self, val)
+            }
+
+            #[inline]
+            fn saturating_sub(self, val: $t) -> $t {
+                <$t>::saturating_sub(self, val)
+            }
+        }
+    )*);
+}
+primitive_integer!(atomic_min atomic_max: i8 i16 i32 i64 isize i128);
+primitive_integer!(atomic_umin atomic_umax: u8 u16 u32 u64 usize u128);
+
+/// A newtype around a primitive integer, which gets the integer operations of
+/// `Atomic`.
+///
+/// `Atomic<T>` has `fetch_add`, `fetch_sub`, `fetch_min` and `fetch_max` for
+/// every `T` which implements this trait. They operate on the value as its
+/// `Repr`: arithmetic wraps around like for the integer, and `fetch_min` and
+/// `fetch_max` compare values as `Repr`, signed or unsigned, whatever the
+/// `Ord` implementation of `T` is. Other updates can be made with the generic
+/// `update_and_fetch` and `fetch_update_both`.
+///
+/// It is implemented for `Wrapping` of every primitive integer, whose
+/// arithmetic wraps around anyway. `Atomic<Saturating<T>>` has the same
+/// methods, except that the arithmetic saturates.
+///
+/// # Safety
+///
+/// `Self` must have the same size, alignment and bit validity as `Repr`, so
+/// that every value of `Repr` is a valid `Self`. This is the case for a
+/// `#[repr(transparent)]` struct whose only non-zero-sized field is a `Repr`.
+///
+/// # Examples
+///
+/// ```
+/// use atomic::{Atomic, IntegerNewtype, Ordering};
+///
+/// #[derive(Copy, Clone, Debug, PartialEq)]
+/// #[repr(transparent)]
+/// struct Offset(u64);
+///
+/// unsafe impl IntegerNewtype for Offset {
+///     type Repr = u64;
+/// }
+///
+/// let end = Atomic::new(Offset(0));
+/// assert_eq!(end.fetch_add(Offset(16), Ordering::Relaxed), Offset(0));
+/// assert_eq!(end.fetch_max(Offset(8), Ordering::Relaxed), Offset(16));
+/// assert_eq!(end.load(Ordering::Relaxed), Offset(16));
+/// ```
+pub unsafe trait IntegerNewtype: Copy {
+    /// The primitive integer which `Self` wraps.
+    type Repr: PrimitiveInteger;
+}
+
+#[inline]
+fn to_repr<T: IntegerNewtype>(val: T) -> T::Repr {
+    unsafe { ops::transmute_bits(val) }
+}
+
+#[inline]
+fn from_repr<T: IntegerNewtype>(val: T::Repr) -> T {
+    unsafe { ops::transmute_bits(val) }
+}
+
+impl<T: IntegerNewtype> Atomic<T> {
+    #[inline]
+    fn repr_ptr(&self) -> *mut T::Repr {
+        self.v.get() as *mut T::Repr
+    }
+
+    /// Add to the current value, returning the previous value.
+    ///
+    /// The addition wraps around like for `T::Repr`.
+    #[inline]
+    pub fn fetch_add(&self, val: T, order: Ordering) -> T {
+        from_repr(unsafe { T::Repr::atomic_add(self.repr_ptr(), to_repr(val), order) })
+    }
+
+    /// Subtract from the current value, returning the previous value.
+    ///
+    /// The subtraction wraps around like for `T::Repr`.
+    #[inline]
+    pub fn fetch_sub(&self, val: T, order: Ordering) -> T {
+        from_repr(unsafe { T::Repr::atomic_sub(self.repr_ptr(), to_repr(val), order) })
+    }
+
+    /// Minimum with the current value, compared as `T::Repr`.
+    #[inline]
+    pub fn fetch_min(&self, val: T, order: Ordering) -> T {
+        from_repr(unsafe { T::Repr::atomic_min(self.repr_ptr(), to_repr(val), order) })
+    }
+
+    /// Maximum with the current value, compared as `T::Repr`.
+    #[inline]
+    pub fn fetch_max(&self, val: T, order: Ordering) -> T {
+        from_repr(unsafe { T::Repr::atomic_max(self.repr_ptr(), to_repr(val), order) })
+    }
+}
+
+// `Wrapping` is `#[repr(transparent)]`.
+unsafe impl<T: PrimitiveInteger> IntegerNewtype for Wrapping<T> {
+    type Repr = T;
+}
+
+// `Saturating` is `#[repr(transparent)]` too, but its arithmetic has no
+// native instruction, so it is a compare-exchange loop like
+// `fetch_saturating_add` on the integers.
+impl<T: PrimitiveInteger> Atomic<Saturating<T>> {
+    #[inline]
+    fn repr_ptr(&self) -> *mut T {
+        self.v.get() as *mut T
+    }
+
+    #[inline]
+    fn fetch_saturating<F: Fn(T) -> T>(&self, f: F, order: Ordering) -> Saturating<T> {
+        let f = |x: T| {
+            let new = f(x);
+            if new == x {
+                None
+            } else {
+                Some(new)
+            }
+        };
+        let fetch_order = ops::failure_ordering(order);
+        match unsafe { ops::atomic_fetch_update(self.repr_ptr(), order, fetch_order, f) } {
+            Ok(x) | Err(x) => Saturating(x),
+        }
+    }
+
+    /// Saturating add to the current value, returning the previous value.
+    ///
+    /// This returns without storing anything if the addition wouldn't change
+    /// the value, for example because it is already saturated.
+    #[inline]
+    pub fn fetch_add(&self, val: Saturating<T>, order: Ordering) -> Saturating<T> {
+        self.fetch_saturating(|x| x.saturating_add(val.0), order)
+    }
+
+    /// Saturating subtract from the current value, returning the previous
+    /// value.
+    ///
+    /// This is implemented like `fetch_add`.
+    #[inline]
+    pub fn fetch_sub(&self, val: Saturating<T>, order: Ordering) -> Saturating<T> {
+        self.fetch_saturating(|x| x.saturating_sub(val.0), order)
+    }
+
+    /// Minimum with the current value.
+    #[inline]
+    pub fn fetch_min(&self, val: Saturating<T>, order: Ordering) -> Saturating<T> {
+        Saturating(unsafe { T::atomic_min(self.repr_ptr(), val.0, order) })
+    }
+
+    /// Maximum with the current value.
+    #[inline]
+    pub fn fetch_max(&self, val: Saturating<T>, order: Ordering) -> Saturating<T> {
+        Saturating(unsafe { T::atomic_max(self.repr_ptr(), val.0, order) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![cfg_attr(feature = "no-fallback", allow(dead_code, unused_imports))]
+
+    use super::IntegerNewtype;
+    use core::num::{Saturating, Wrapping};
+    use core::sync::atomic::Ordering::*;
+    use std::thread;
+    use std::vec::Vec;
+    use Atomic;
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    #[repr(transparent)]
+    struct Ticks(u32);
+
+    unsafe impl IntegerNewtype for Ticks {
+        type Repr = u32;
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    #[repr(transparent)]
+    struct Delta(i64);
+
+    unsafe impl IntegerNewtype for Delta {
+        type Repr = i64;
+    }
+
+    // Too big to be lock-free, so the operations take the fallback lock.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    #[repr(transparent)]
+    struct Wide(i128);
+
+    unsafe impl IntegerNewtype for Wide {
+        type Repr = i128;
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn signedness() {
+        let t = Atomic::new(Ticks(5));
+        // Unsigned: `u32::MAX` is the largest value.
+        assert_eq!(t.fetch_max(Ticks(!0), Relaxed), Ticks(5));
+        assert_eq!(t.fetch_add(Ticks(2), Relaxed), Ticks(!0));
+        assert_eq!(t.fetch_sub(Ticks(3), Relaxed), Ticks(1));
+        assert_eq!(t.load(Relaxed), Ticks(!0 - 1));
+
+        let d = Atomic::new(Delta(5));
+        // Signed: -1 is smaller than 5.
+        assert_eq!(d.fetch_min(Delta(-1), Relaxed), Delta(5));
+        assert_eq!(d.fetch_max(Delta(-7), Relaxed), Delta(-1));
+        assert_eq!(d.fetch_sub(Delta(i64::MAX), Relaxed), Delta(-1));
+        assert_eq!(d.load(Relaxed), Delta(i64::MIN));
+
+        let w = Atomic::new(Wide(-3));
+        assert_eq!(w.fetch_max(Wide(2), Relaxed), Wide(-3));
+        assert_eq!(w.fetch_add(Wide(1 << 100), Relaxed), Wide(2));
+        assert_eq!(w.load(Relaxed), Wide((1 << 100) + 2));
+    }
+
+    #[test]
+    fn concurrent_accumulation() {
+        const THREADS: u32 = 4;
+        const ITERS: u32 = 1000;
+        let ticks = Atomic::new(Ticks(0));
+        let balance = Atomic::new(Delta(0));
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let (ticks, balance) = (&ticks, &balance);
+                s.spawn(move || {
+                    for i in 0..ITERS {
+                        ticks.fetch_add(Ticks(1), Relaxed);
+                        // Even threads deposit and odd threads withdraw.
+                        let amount = Delta(i as i64);
+                        if t % 2 == 0 {
+                            balance.fetch_add(amount, Relaxed);
+                        } else {
+                            balance.fetch_sub(amount, Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(ticks.load(Relaxed), Ticks(THREADS * ITERS));
+        assert_eq!(balance.load(Relaxed), Delta(0));
+    }
+
+    #[test]
+    fn concurrent_watermarks() {
+        const THREADS: i64 = 4;
+        const ITERS: i64 = 1000;
+        let high = Atomic::new(Delta(i64::MIN));
+        let low = Atomic::new(Delta(i64::MAX));
+        let high_ticks = Atomic::new(Ticks(0));
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let (high, low, high_ticks) = (&high, &low, &high_ticks);
+                s.spawn(move || {
+                    for i in 0..ITERS {
+                        // Samples range over -ITERS * THREADS..ITERS * THREADS.
+                        let sample = (i * THREADS + t) * if i % 2 == 0 { 1 } else { -1 };
+                        high.fetch_max(Delta(sample), Relaxed);
+                        low.fetch_min(Delta(sample), Relaxed);
+                        high_ticks.fetch_max(Ticks(sample as u32), Relaxed);
+                    }
+                });
+            }
+        });
+        let top = (ITERS - 2) * THREADS + THREADS - 1;
+        let bottom = -((ITERS - 1) * THREADS + THREADS - 1);
+        assert_eq!(high.load(Relaxed), Delta(top));
+        assert_eq!(low.load(Relaxed), Delta(bottom));
+        // As unsigned, the negative samples are the largest, and the one
+        // closest to zero is the largest of them.
+        assert_eq!(high_ticks.load(Relaxed), Ticks((-THREADS) as u32));
+    }
+
+    #[test]
+    fn wrapping() {
+        let a = Atomic::new(Wrapping(u8::MAX - 1));
+        assert_eq!(a.fetch_add(Wrapping(3), Relaxed), Wrapping(u8::MAX - 1));
+        assert_eq!(a.fetch_sub(Wrapping(2), Relaxed), Wrapping(1));
+        assert_eq!(a.load(Relaxed), Wrapping(u8::MAX));
+        assert_eq!(a.fetch_min(Wrapping(7), Relaxed), Wrapping(u8::MAX));
+        assert_eq!(a.fetch_max(Wrapping(3), Relaxed), Wrapping(7));
+
+        let b = Atomic::new(Wrapping(i32::MAX));
+        assert_eq!(b.fetch_add(Wrapping(1), Relaxed), Wrapping(i32::MAX));
+        assert_eq!(b.load(Relaxed), Wrapping(i32::MIN));
+        assert_eq!(b.fetch_max(Wrapping(-1), Relaxed), Wrapping(i32::MIN));
+        assert_eq!(b.load(Relaxed), Wrapping(-1));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn saturating() {
+        let a = Atomic::new(Saturating(u16::MAX - 1));
+        assert_eq!(
+            a.fetch_add(Saturating(3), Relaxed),
+            Saturating(u16::MAX - 1)
+        );
+        assert_eq!(a.fetch_add(Saturating(1), Relaxed), Saturating(u16::MAX));
+        assert_eq!(a.load(Relaxed), Saturating(u16::MAX));
+        assert_eq!(a.fetch_min(Saturating(2), Relaxed), Saturating(u16::MAX));
+        assert_eq!(a.fetch_sub(Saturating(5), Relaxed), Saturating(2));
+        assert_eq!(a.load(Relaxed), Saturating(0));
+        assert_eq!(a.fetch_max(Saturating(9), Relaxed), Saturating(0));
+
+        let b = Atomic::new(Saturating(i64::MIN + 1));
+        assert_eq!(
+            b.fetch_sub(Saturating(2), Relaxed),
+            Saturating(i64::MIN + 1)
+        );
+        assert_eq!(b.load(Relaxed), Saturating(i64::MIN));
+        assert_eq!(b.fetch_add(Saturating(-1), Relaxed), Saturating(i64::MIN));
+        assert_eq!(b.fetch_sub(Saturating(-5), Relaxed), Saturating(i64::MIN));
+        assert_eq!(b.load(Relaxed), Saturating(i64::MIN + 5));
+
+        // Too big to be lock-free.
+        let c = Atomic::new(Saturating(i128::MAX - 1));
+        assert_eq!(
+            c.fetch_add(Saturating(7), Relaxed),
+            Saturating(i128::MAX - 1)
+        );
+        assert_eq!(c.load(Relaxed), Saturating(i128::MAX));
+    }
+
+    // Every thread adds the same amount, so whatever the interleaving, the
+    // previous values returned are those which adding it the same number of
+    // times in sequence returns.
+    #[test]
+    fn concurrent_wrapping_and_saturating() {
+        const THREADS: usize = 4;
+        const ITERS: usize = 100;
+        let wrapping = Atomic::new(Wrapping(0u8));
+        let saturating = Atomic::new(Saturating(0u8));
+        let results: Vec<_> = thread::scope(|s| {
+            let threads: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut results = Vec::new();
+                        for _ in 0..ITERS {
+                            results.push((
+                                wrapping.fetch_add(Wrapping(3), AcqRel),
+                                saturating.fetch_add(Saturating(3), AcqRel),
+                            ));
+                        }
+                        results
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .flat_map(|t| t.join().unwrap())
+                .collect()
+        });
+        let (mut w, mut s): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+        w.sort();
+        s.sort();
+
+        let (mut model_w, mut model_s) = (Wrapping(0u8), Saturating(0u8));
+        let (mut expected_w, mut expected_s) = (Vec::new(), Vec::new());
+        for _ in 0..THREADS * ITERS {
+            expected_w.push(model_w);
+            expected_s.push(model_s);
+            model_w += Wrapping(3);
+            model_s += Saturating(3);
+        }
+        expected_w.sort();
+        expected_s.sort();
+        assert_eq!(w, expected_w);
+        assert_eq!(s, expected_s);
+        assert_eq!(wrapping.load(Relaxed), model_w);
+        assert_eq!(saturating.load(Relaxed), Saturating(u8::MAX));
+        assert_eq!(model_s, Saturating(u8::MAX));
+
+        // Subtracting sticks at the lower bound in the same way.
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..ITERS {
+                        saturating.fetch_sub(Saturating(1), Relaxed);
+                    }
+                });
+            }
+        });
+        assert_eq!(saturating.load(Relaxed), Saturating(0));
+    }
+}
@@ -0,0 +1,265 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The native atomic types used by the ops module with the `nightly` feature.
+//
+// They have the same interface as the standard atomic types which the ops
+// module uses otherwise, but a single generic implementation which calls the
+// atomic intrinsics directly. This also covers 128-bit integers, which don't
+// have a stable atomic type.
+//
+// The intrinsics take the ordering as a const generic parameter, so each
+// method matches on the `Ordering` and calls the right instantiation, like the
+// standard atomic types do. The intrinsics are only defined for integers and
+// raw pointers; codegen rejects anything else, so values of other types are
+// still reinterpreted as the integer of their size by the ops module.
+//
+// Without a compare-exchange the ops module only loads and stores.
+#![cfg_attr(no_atomic_cas, allow(dead_code))]
+
+use core::cell::UnsafeCell;
+use core::intrinsics::{self, AtomicOrdering};
+use core::sync::atomic::Ordering;
+
+// Evaluates `$e` with the const `$ord` set to the intrinsic ordering which
+// corresponds to `$order`.
+macro_rules! with_ordering {
+    ($order:expr, $ord:ident => $e:expr) => {
+        match $order {
+            Ordering::Relaxed => {
+                const $ord: AtomicOrdering = AtomicOrdering::Relaxed;
+                $e
+            }
+            Ordering::Acquire => {
+                const $ord: AtomicOrdering = AtomicOrdering::Acquire;
+                $e
+            }
+            Ordering::Release => {
+                const $ord: AtomicOrdering = AtomicOrdering::Release;
+                $e
+            }
+            Ordering::AcqRel => {
+                const $ord: AtomicOrdering = AtomicOrdering::AcqRel;
+                $e
+            }
+            _ => {
+                const $ord: AtomicOrdering = AtomicOrdering::SeqCst;
+                $e
+            }
+        }
+    };
+}
+
+// Like `with_ordering!`, for the orderings which are valid for a load and
+// the failure case of a compare-exchange.
+macro_rules! with_load_ordering {
+    ($order:expr, $what:expr, $ord:ident => $e:expr) => {
+        match $order {
+            Ordering::Release => panic!("there is no such thing as a release {}", $what),
+            Ordering::AcqRel => panic!("there is no such thing as an acquire-release {}", $what),
+            order => with_ordering!(order, $ord => $e),
+        }
+    };
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+// The integer types which the intrinsics support. `SIGNED` picks between the
+// signed and unsigned minimum and maximum.
+pub trait Int: Copy + sealed::Sealed {
+    const SIGNED: bool;
+}
+
+macro_rules! int {
+    ($($t:ident $signed:expr)*) => ($(
+        impl sealed::Sealed for $t {}
+
+        impl Int for $t {
+            const SIGNED: bool = $signed;
+        }
+    )*);
+}
+int! {
+    u8 false u16 false u32 false u64 false u128 false usize false
+    i8 true i16 true i32 true i64 true i128 true isize true
+}
+
+#[repr(transparent)]
+pub struct Native<T>(UnsafeCell<T>);
+
+unsafe impl<T> Sync for Native<T> {}
+
+#[cfg(has_atomic_load_store_ptr)]
+pub type AtomicPtr<T> = Native<*mut T>;
+#[cfg(has_atomic_load_store_8)]
+pub type AtomicU8 = Native<u8>;
+#[cfg(has_atomic_load_store_16)]
+pub type AtomicU16 = Native<u16>;
+#[cfg(has_atomic_load_store_32)]
+pub type AtomicU32 = Native<u32>;
+#[cfg(all(has_atomic_load_store_64, not(zacas_64)))]
+pub type AtomicU64 = Native<u64>;
+#[cfg(native_128)]
+pub type AtomicU128 = Native<u128>;
+#[cfg(has_atomic_8)]
+pub type AtomicI8 = Native<i8>;
+#[cfg(has_atomic_16)]
+pub type AtomicI16 = Native<i16>;
+#[cfg(has_atomic_32)]
+pub type AtomicI32 = Native<i32>;
+#[cfg(has_atomic_64)]
+pub type AtomicI64 = Native<i64>;
+#[cfg(native_128)]
+pub type AtomicI128 = Native<i128>;
+
+impl<T: Copy> Native<T> {
+    #[inline]
+    pub unsafe fn from_ptr<'a>(ptr: *mut T) -> &'a Native<T> {
+        &*(ptr as *const Native<T>)
+    }
+
+    #[inline]
+    pub fn load(&self, order: Ordering) -> T {
+        let p = self.0.get();
+        unsafe { with_load_ordering!(order, "load", O => intrinsics::atomic_load::<T, O>(p)) }
+    }
+
+    #[inline]
+    pub fn store(&self, val: T, order: Ordering) {
+        let p = self.0.get();
+        unsafe {
+            match order {
+                Ordering::Acquire => panic!("there is no such thing as an acquire store"),
+                Ordering::AcqRel => panic!("there is no such thing as an acquire-release store"),
+                order => with_ordering!(order, O => intrinsics::atomic_store::<T, O>(p, val)),
+            }
+        }
+    }
+
+    #[inline]
+    pub fn swap(&self, val: T, order: Ordering) -> T {
+        let p = self.0.get();
+        unsafe { with_ordering!(order, O => intrinsics::atomic_xchg::<T, O>(p, val)) }
+    }
+
+    #[inline]
+    pub fn compare_exchange(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        let p = self.0.get();
+        let (val, ok) = unsafe {
+            with_ordering!(success, S => with_load_ordering!(failure, "failure ordering", F => {
+                intrinsics::atomic_cxchg::<T, S, F>(p, current, new)
+            }))
+        };
+        if ok {
+            Ok(val)
+        } else {
+            Err(val)
+        }
+    }
+
+    #[inline]
+    pub fn compare_exchange_weak(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        let p = self.0.get();
+        let (val, ok) = unsafe {
+            with_ordering!(success, S => with_load_ordering!(failure, "failure ordering", F => {
+                intrinsics::atomic_cxchgweak::<T, S, F>(p, current, new)
+            }))
+        };
+        if ok {
+            Ok(val)
+        } else {
+            Err(val)
+        }
+    }
+
+    #[inline]
+    pub fn fetch_update<F: FnMut(T) -> Option<T>>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<T, T> {
+        let mut prev = self.load(fetch_order);
+        while let Some(next) = f(prev) {
+            match self.compare_exchange_weak(prev, next, set_order, fetch_order) {
+                Ok(x) => return Ok(x),
+                Err(x) => prev = x,
+            }
+        }
+        Err(prev)
+    }
+}
+
+impl<T: Int> Native<T> {
+    #[inline]
+    pub fn fetch_add(&self, val: T, order: Ordering) -> T {
+        let p = self.0.get();
+        unsafe { with_ordering!(order, O => intrinsics::atomic_xadd::<T, T, O>(p, val)) }
+    }
+
+    #[inline]
+    pub fn fetch_sub(&self, val: T, order: Ordering) -> T {
+        let p = self.0.get();
+        unsafe { with_ordering!(order, O => intrinsics::atomic_xsub::<T, T, O>(p, val)) }
+    }
+
+    #[inline]
+    pub fn fetch_and(&self, val: T, order: Ordering) -> T {
+        let p = self.0.get();
+        unsafe { with_ordering!(order, O => intrinsics::atomic_and::<T, T, O>(p, val)) }
+    }
+
+    #[inline]
+    pub fn fetch_or(&self, val: T, order: Ordering) -> T {
+        let p = self.0.get();
+        unsafe { with_ordering!(order, O => intrinsics::atomic_or::<T, T, O>(p, val)) }
+    }
+
+    #[inline]
+    pub fn fetch_xor(&self, val: T, order: Ordering) -> T {
+        let p = self.0.get();
+        unsafe { with_ordering!(order, O => intrinsics::atomic_xor::<T, T, O>(p, val)) }
+    }
+
+    #[inline]
+    pub fn fetch_min(&self, val: T, order: Ordering) -> T {
+        let p = self.0.get();
+        unsafe {
+            if T::SIGNED {
+                with_ordering!(order, O => intrinsics::atomic_min::<T, O>(p, val))
+            } else {
+                with_ordering!(order, O => intrinsics::atomic_umin::<T, O>(p, val))
+            }
+        }
+    }
+
+    #[inline]
+    pub fn fetch_max(&self, val: T, order: Ordering) -> T {
+        let p = self.0.get();
+        unsafe {
+            if T::SIGNED {
+                with_ordering!(order, O => intrinsics::atomic_max::<T, O>(p, val))
+            } else {
+                with_ordering!(order, O => intrinsics::atomic_umax::<T, O>(p, val))
+            }
+        }
+    }
+}
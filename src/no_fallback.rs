@@ -0,0 +1,95 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Stands in for the fallback module when it is compiled out by the
+// `no-fallback` feature.
+//
+// The operations in `ops` still name these functions in the arms of their
+// size dispatch, but those arms are never taken: every operation first checks
+// at compile time that `T` is lock-free.
+
+use core::cmp;
+use core::ops;
+
+pub unsafe fn atomic_load<T>(_dst: *mut T) -> T {
+    unreachable!()
+}
+
+pub unsafe fn atomic_store<T>(_dst: *mut T, _val: T) {
+    unreachable!()
+}
+
+pub unsafe fn atomic_swap<T>(_dst: *mut T, _val: T) -> T {
+    unreachable!()
+}
+
+pub unsafe fn atomic_try_load<T>(_dst: *mut T) -> Option<T> {
+    unreachable!()
+}
+
+pub unsafe fn atomic_try_store<T>(_dst: *mut T, _val: T) -> Result<(), T> {
+    unreachable!()
+}
+
+pub unsafe fn atomic_try_swap<T>(_dst: *mut T, _val: T) -> Result<T, T> {
+    unreachable!()
+}
+
+pub unsafe fn atomic_compare_exchange<T>(_dst: *mut T, _current: T, _new: T) -> Result<T, T> {
+    unreachable!()
+}
+
+pub unsafe fn atomic_compare_exchange_ref<T: Copy>(
+    _dst: *mut T,
+    _current: &T,
+    _new: &T,
+) -> Result<T, T> {
+    unreachable!()
+}
+
+pub unsafe fn atomic_compare_exchange_in_place<T: Copy>(
+    _dst: *mut T,
+    _current: &mut T,
+    _new: &T,
+) -> bool {
+    unreachable!()
+}
+
+pub unsafe fn atomic_fetch_update<T: Copy, F: FnOnce(T) -> Option<T>>(
+    _dst: *mut T,
+    _f: F,
+) -> Result<T, T> {
+    unreachable!()
+}
+
+pub unsafe fn atomic_add<T: Copy>(_dst: *mut T, _val: T) -> T {
+    unreachable!()
+}
+
+pub unsafe fn atomic_sub<T: Copy>(_dst: *mut T, _val: T) -> T {
+    unreachable!()
+}
+
+pub unsafe fn atomic_and<T: Copy + ops::BitAnd<Output = T>>(_dst: *mut T, _val: T) -> T {
+    unreachable!()
+}
+
+pub unsafe fn atomic_or<T: Copy + ops::BitOr<Output = T>>(_dst: *mut T, _val: T) -> T {
+    unreachable!()
+}
+
+pub unsafe fn atomic_xor<T: Copy + ops::BitXor<Output = T>>(_dst: *mut T, _val: T) -> T {
+    unreachable!()
+}
+
+pub unsafe fn atomic_min<T: Copy + cmp::Ord>(_dst: *mut T, _val: T) -> T {
+    unreachable!()
+}
+
+pub unsafe fn atomic_max<T: Copy + cmp::Ord>(_dst: *mut T, _val: T) -> T {
+    unreachable!()
+}
@@ -5,152 +5,855 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+//! Atomic operations on raw pointers.
+//!
+//! These are the functions which `Atomic<T>` is built on. They are useful for
+//! atomic fields which live in memory that can't be typed as an `Atomic<T>`,
+//! for example inside a structure shared with C code. Each of them picks a
+//! native atomic instruction of the right width if `T` is lock-free (see
+//! [`atomic_is_lock_free`]), and otherwise takes a lock from a global table of
+//! spinlocks, which is selected by the address of the value. The choice is
+//! made for each `T` at compile time, so the other one isn't compiled at all.
+//!
+//! The orderings behave like those of the standard atomic types, including
+//! panicking on orderings which aren't valid for an operation.
+//!
+//! # Safety
+//!
+//! All the functions taking a `dst: *mut T` require that:
+//!
+//! - `dst` is valid for reads and writes of `T` and aligned to
+//!   `align_of::<T>()`. The native instructions need no more than that, since
+//!   they are only used when the value is aligned to its size (see
+//!   [`atomic_is_lock_free_at`]).
+//! - While any of these functions may run concurrently on `*dst`, it is only
+//!   accessed through them or through an `Atomic<T>` at the same address, and
+//!   always as the same type `T`. Non-atomic accesses are data races, and so
+//!   are accesses with a different type: it may be dispatched to a native
+//!   instruction while `T` takes the lock, or the other way around.
+//! - Values which aren't lock-free are always accessed through the same
+//!   address. The lock is selected by the address passed in, so overlapping
+//!   values at different addresses, such as a structure and its first field,
+//!   don't exclude each other.
+//!
+//! The arithmetic and bitwise operations treat the value as an integer of the
+//! same size when a native instruction is used, so `T` must be a primitive
+//! integer (or `bool` for the bitwise ones), as required by each function.
+//!
+//! Under `--cfg loom` or `--cfg shuttle` these functions only work on memory
+//! which belongs to an `Atomic<T>`, since the model checker needs to know
+//! about every atomic in advance.
+//!
+//! # Examples
+//!
+//! Using an atomic field of a heap allocation which is only known by its raw
+//! pointer:
+//!
+//! ```
+//! use atomic::ops;
+//! use atomic::Ordering;
+//!
+//! let p = Box::into_raw(Box::new(5u64));
+//! unsafe {
+//!     assert_eq!(ops::atomic_add(p, 2, Ordering::AcqRel), 5);
+//!     assert_eq!(ops::atomic_swap(p, 10, Ordering::AcqRel), 7);
+//!     let r = ops::atomic_compare_exchange(p, 10, 11, Ordering::AcqRel, Ordering::Acquire);
+//!     assert_eq!(r, Ok(10));
+//!     assert_eq!(ops::atomic_load(p, Ordering::Acquire), 11);
+//!     drop(Box::from_raw(p));
+//! }
+//! ```
+//!
+//! A value which is too big to be lock-free goes through the lock instead:
+//!
+//! ```
+//! # #[cfg(not(feature = "no-fallback"))] {
+//! use atomic::ops;
+//! use atomic::Ordering;
+//! use std::thread;
+//!
+//! #[derive(Clone, Copy, Debug, PartialEq)]
+//! struct Range {
+//!     start: u64,
+//!     end: u64,
+//!     count: u64,
+//! }
+//!
+//! assert!(!ops::atomic_is_lock_free::<Range>());
+//!
+//! struct Shared(*mut Range);
+//! unsafe impl Sync for Shared {}
+//!
+//! let mut range = Range { start: 0, end: 0, count: 0 };
+//! let shared = Shared(&mut range);
+//! let shared = &shared;
+//! thread::scope(|s| {
+//!     for _ in 0..4 {
+//!         s.spawn(move || {
+//!             for _ in 0..100 {
+//!                 let grow = |r: Range| Some(Range { end: r.end + 2, count: r.count + 1, ..r });
+//!                 let result = unsafe {
+//!                     ops::atomic_fetch_update(shared.0, Ordering::AcqRel, Ordering::Acquire, grow)
+//!                 };
+//!                 assert!(result.is_ok());
+//!             }
+//!         });
+//!     }
+//! });
+//! let last = unsafe { ops::atomic_load(shared.0, Ordering::Acquire) };
+//! assert_eq!(last, Range { start: 0, end: 800, count: 400 });
+//! # }
+//! ```
+
+// Without a compare-exchange only the load and store arms are compiled, so
+// the orderings of the other operations and some of the helpers go unused,
+// and on targets without any atomics, like MSP430, so do the native types.
+// With `--cfg force_fallback` none of the native arms are.
+#![cfg_attr(no_atomic_cas, allow(unused_imports, unused_variables, dead_code))]
+#![cfg_attr(force_fallback, allow(unused_imports, unused_variables, dead_code))]
+
 use core::cmp;
+use core::marker::PhantomData;
 use core::mem;
 use core::num::Wrapping;
 use core::ops;
-use core::sync::atomic::Ordering;
+use core::ptr;
+use core::slice;
+use core::sync::atomic::{compiler_fence, Ordering};
+#[cfg(any(not(feature = "no-fallback"), single_threaded))]
 use fallback;
+#[cfg(all(feature = "no-fallback", not(single_threaded)))]
+use no_fallback as fallback;
+#[cfg(feature = "staleness-check")]
+use staleness;
+#[cfg(detect_cmpxchg16b)]
+use cmpxchg16b;
 
-#[cfg(feature = "nightly")]
-use core::sync::atomic::{
-    AtomicI16, AtomicI32, AtomicI64, AtomicI8, AtomicU16, AtomicU32, AtomicU64, AtomicU8,
-};
+#[cfg(all(target_arch = "x86", not(any(loom, shuttle))))]
+use core::arch::x86 as arch;
+#[cfg(all(target_arch = "x86_64", not(any(loom, shuttle))))]
+use core::arch::x86_64 as arch;
 
-#[cfg(not(feature = "nightly"))]
-use core::sync::atomic::AtomicUsize;
-#[cfg(not(feature = "nightly"))]
-const SIZEOF_USIZE: usize = mem::size_of::<usize>();
-#[cfg(not(feature = "nightly"))]
-const ALIGNOF_USIZE: usize = mem::align_of::<usize>();
+// Under loom or shuttle the native atomic types are replaced by ones which
+// the model checker can see. With the `nightly` feature they are replaced by
+// ones which call the atomic intrinsics, which also support 128-bit integers,
+// and with the `portable-atomic` feature by those of the `portable-atomic`
+// crate.
+#[cfg(not(any(loom, shuttle, feature = "nightly", feature = "portable-atomic")))]
+use core::sync::atomic as native;
+#[cfg(all(feature = "nightly", not(any(loom, shuttle))))]
+use nightly as native;
+#[cfg(all(feature = "portable-atomic", not(any(loom, shuttle))))]
+use portable_atomic as native;
+#[cfg(any(loom, shuttle))]
+use shadow as native;
 
-#[cfg(feature = "nightly")]
+#[cfg(has_atomic_load_store_ptr)]
+use self::native::AtomicPtr;
+#[cfg(has_atomic_load_store_8)]
+use self::native::AtomicU8;
+#[cfg(has_atomic_load_store_16)]
+use self::native::AtomicU16;
+#[cfg(has_atomic_load_store_32)]
+use self::native::AtomicU32;
+#[cfg(all(has_atomic_load_store_64, not(zacas_64)))]
+use self::native::AtomicU64;
+#[cfg(zacas_64)]
+use zacas::AtomicU64;
+#[cfg(all(native_min_max, native_8))]
+use self::native::AtomicI8;
+#[cfg(all(native_min_max, native_16))]
+use self::native::AtomicI16;
+#[cfg(all(native_min_max, native_32))]
+use self::native::AtomicI32;
+#[cfg(all(native_min_max, native_64, not(zacas_64)))]
+use self::native::AtomicI64;
+#[cfg(all(native_min_max, zacas_64))]
+use zacas::AtomicI64;
+#[cfg(native_128)]
+use self::native::AtomicI128;
+#[cfg(native_128)]
+use self::native::AtomicU128;
+
+// The `native_*` cfgs are emitted by the build script for each width which
+// the native types support every operation for. Without the `portable-atomic`
+// feature these are the widths which the target supports natively, plus 128
+// bits when the `nightly` feature provides a type for it. The
+// `has_atomic_load_store_*` cfgs are a superset of them: on targets without
+// any compare-exchange (`no_atomic_cas`) they list the widths which can still
+// be loaded and stored natively.
+// `native_min_max` says that the native types have `fetch_min` and
+// `fetch_max` for both signed and unsigned integers.
+//
+// Zero-sized types have a single value and no memory to access, so every
+// operation on them is a no-op which is trivially atomic.
+//
+// Loads, stores, swaps and compare-exchanges of pointer-sized values go
+// through `AtomicPtr` rather than an integer type, so that an `Atomic` holding
+// a pointer preserves its provenance. Like the integer types it needs values
+// aligned to their size, which pointers themselves aren't on AVR.
+/// Returns whether the operations in this module use native atomic
+/// instructions for `T` rather than a lock.
+///
+/// This is the same as `Atomic::<T>::IS_LOCK_FREE`. Like it, this is
+/// conservative for 16-byte types on x86_64, whose support is only known at
+/// runtime; [`atomic_is_lock_free_at`] has the runtime answer.
 #[inline]
 pub const fn atomic_is_lock_free<T>() -> bool {
-    let size = mem::size_of::<T>();
-    // FIXME: switch to … && … && … once that operator is supported in const functions
-    (1 == size.count_ones()) & (8 >= size) & (mem::align_of::<T>() >= size)
+    kind_is_lock_free::<T>(Select::<T>::ARITHMETIC)
+}
+
+// Whether the native type of each width is lock-free, for the widths which the
+// `native_*` cfgs say it has. The standard atomic types always are. The types
+// of `portable-atomic` exist for every width on every target and fall back to
+// a lock of their own where they have to, so they are only used where that
+// crate says they are always lock-free, and everything else takes our own
+// lock. This keeps `atomic_is_lock_free` exact for the code which relies on it
+// to know whether a value goes through the lock. The constants fold away in
+// the dispatch.
+#[cfg(not(all(feature = "portable-atomic", not(any(loom, shuttle)))))]
+mod lock_free {
+    pub const U8: bool = true;
+    pub const U16: bool = true;
+    pub const U32: bool = true;
+    pub const U64: bool = true;
+    pub const U128: bool = true;
+    pub const PTR: bool = true;
 }
 
-#[cfg(not(feature = "nightly"))]
+#[cfg(all(feature = "portable-atomic", not(any(loom, shuttle))))]
+mod lock_free {
+    use portable_atomic::{AtomicPtr, AtomicU128, AtomicU16, AtomicU32, AtomicU64, AtomicU8};
+
+    pub const U8: bool = AtomicU8::is_always_lock_free();
+    pub const U16: bool = AtomicU16::is_always_lock_free();
+    pub const U32: bool = AtomicU32::is_always_lock_free();
+    pub const U64: bool = AtomicU64::is_always_lock_free();
+    pub const U128: bool = AtomicU128::is_always_lock_free();
+    pub const PTR: bool = AtomicPtr::<()>::is_always_lock_free();
+}
+
+/// Returns whether loads and stores in this module use native atomic
+/// instructions for `T` rather than a lock.
+///
+/// This is the same as [`atomic_is_lock_free`], except on targets which can
+/// load and store atomically but have no compare-exchange, like Cortex-M0. On
+/// those the read-modify-write operations take the fallback lock, which is a
+/// critical section, while loads and stores of the natively supported sizes
+/// are still plain instructions.
+///
+/// This is the same as `Atomic::<T>::LOAD_STORE_LOCK_FREE`.
 #[inline]
-pub fn atomic_is_lock_free<T>() -> bool {
+pub const fn atomic_is_load_store_lock_free<T>() -> bool {
+    atomic_is_lock_free::<T>() || kind_is_lock_free::<T>(Select::<T>::LOAD_STORE)
+}
+
+// The operations dispatch on the native type they use for a `T`, which
+// `select` picks from the size and alignment of `T` and the widths the target
+// has native types for. The dispatch matches on these constants rather than
+// on `mem::size_of::<T>()`, so that the compiler only generates the arm which
+// is taken even without optimizations: an operation on a lock-free type is
+// just the native operation, and the fallback isn't instantiated for it. The
+// only checks left at runtime are the address of 8-byte values which aren't
+// 8-byte aligned, which use `AtomicU64` if the address is, and whether the
+// processor has `cmpxchg16b`.
+mod kind {
+    pub const ZERO: u8 = 0;
+    pub const PTR: u8 = 1;
+    pub const U8: u8 = 2;
+    pub const U16: u8 = 3;
+    pub const U32: u8 = 4;
+    pub const U64: u8 = 5;
+    pub const U128: u8 = 6;
+    pub const CMPXCHG16B: u8 = 7;
+    pub const FALLBACK: u8 = 8;
+}
+
+struct Select<T>(PhantomData<T>);
+
+impl<T> Select<T> {
+    const LOAD_STORE: u8 = select::<T>(
+        cfg!(has_atomic_load_store_ptr),
+        cfg!(has_atomic_load_store_8),
+        cfg!(has_atomic_load_store_16),
+        cfg!(has_atomic_load_store_32),
+        cfg!(has_atomic_load_store_64),
+    );
+    // Swaps and compare-exchanges, which also have `AtomicPtr`.
+    const EXCHANGE: u8 = select::<T>(
+        cfg!(native_ptr),
+        cfg!(native_8),
+        cfg!(native_16),
+        cfg!(native_32),
+        cfg!(native_64),
+    );
+    const ARITHMETIC: u8 = select::<T>(
+        false,
+        cfg!(native_8),
+        cfg!(native_16),
+        cfg!(native_32),
+        cfg!(native_64),
+    );
+    const MIN_MAX: u8 = select::<T>(
+        false,
+        cfg!(all(native_min_max, native_8)),
+        cfg!(all(native_min_max, native_16)),
+        cfg!(all(native_min_max, native_32)),
+        cfg!(all(native_min_max, native_64)),
+    );
+    const LOCK_FREE: bool = atomic_is_lock_free::<T>();
+    const LOAD_STORE_LOCK_FREE: bool = atomic_is_load_store_lock_free::<T>();
+    const ALIGNED_8: bool = mem::align_of::<T>() >= 8;
+}
+
+const fn select<T>(ptr: bool, w8: bool, w16: bool, w32: bool, w64: bool) -> u8 {
     let size = mem::size_of::<T>();
-    1 == size.count_ones() && SIZEOF_USIZE >= size && mem::align_of::<T>() >= ALIGNOF_USIZE
+    let align = mem::align_of::<T>();
+    if size == 0 {
+        kind::ZERO
+    } else if ptr && lock_free::PTR && size == mem::size_of::<usize>() && align >= size {
+        kind::PTR
+    } else if w8 && lock_free::U8 && size == 1 {
+        kind::U8
+    } else if w16 && lock_free::U16 && size == 2 && align >= 2 {
+        kind::U16
+    } else if w32 && lock_free::U32 && size == 4 && align >= 4 {
+        kind::U32
+    } else if w64 && lock_free::U64 && size == 8 && (align >= 8 || cfg!(not(any(loom, shuttle))))
+    {
+        // The model checkers decide how to model a value from its type alone,
+        // so they don't look at the address.
+        kind::U64
+    } else if cfg!(native_128) && lock_free::U128 && size == 16 && align >= 16 {
+        kind::U128
+    } else if cfg!(detect_cmpxchg16b) && size == 16 && align >= 16 {
+        kind::CMPXCHG16B
+    } else {
+        kind::FALLBACK
+    }
+}
+
+// Whether every value of type `T` which is dispatched to `kind` is lock-free.
+const fn kind_is_lock_free<T>(kind: u8) -> bool {
+    match kind {
+        // A single thread doesn't need a lock, and the fallback doesn't take
+        // one.
+        _ if cfg!(single_threaded) => true,
+        kind::FALLBACK => false,
+        kind::U64 => mem::align_of::<T>() >= 8,
+        kind::CMPXCHG16B => cfg!(target_feature = "cmpxchg16b"),
+        _ => true,
+    }
+}
+
+// Whether the value at `dst` is lock-free if it is dispatched to `kind`.
+#[inline]
+fn kind_is_lock_free_at<T>(kind: u8, dst: *const T) -> bool {
+    match kind {
+        _ if cfg!(single_threaded) => true,
+        kind::FALLBACK => false,
+        kind::U64 => Select::<T>::ALIGNED_8 || is_aligned_8(dst as *mut T),
+        kind::CMPXCHG16B => has_cmpxchg16b::<T>(),
+        _ => true,
+    }
+}
+
+// With the `no-fallback` feature there is no lock to fall back to, so using a
+// type which isn't lock-free is a compile error. This is checked before the
+// size dispatch since its fallback arms are instantiated for every type, even
+// though they are never taken for lock-free ones.
+#[cfg(feature = "no-fallback")]
+struct LockFree<T>(PhantomData<T>);
+
+#[cfg(feature = "no-fallback")]
+impl<T> LockFree<T> {
+    const CHECK: () = assert!(
+        atomic_is_lock_free::<T>(),
+        "Atomic<T> is not lock-free on this target"
+    );
+    const CHECK_LOAD_STORE: () = assert!(
+        atomic_is_load_store_lock_free::<T>(),
+        "Atomic<T> is not lock-free on this target"
+    );
+}
+
+#[inline(always)]
+fn check_lock_free<T>() {
+    #[cfg(feature = "no-fallback")]
+    #[allow(clippy::let_unit_value)]
+    let () = LockFree::<T>::CHECK;
+}
+
+// Loads and stores only need native loads and stores, which some targets have
+// for more types than the other operations.
+#[inline(always)]
+fn check_load_store_lock_free<T>() {
+    #[cfg(feature = "no-fallback")]
+    #[allow(clippy::let_unit_value)]
+    let () = LockFree::<T>::CHECK_LOAD_STORE;
+}
+
+/// Returns whether the operations in this module use native atomic
+/// instructions for the value at `dst`.
+///
+/// This is true for every value of a type which is lock-free. In addition, on
+/// targets where 8-byte integers are only 4-byte aligned, like 32-bit x86 on
+/// Linux, 8-byte values use the native 64-bit atomics whenever `dst` happens
+/// to be 8-byte aligned. On x86_64, 16-byte values which are 16-byte aligned
+/// use `cmpxchg16b` if the processor has it, which is detected at runtime.
+#[inline]
+pub fn atomic_is_lock_free_at<T>(dst: *const T) -> bool {
+    kind_is_lock_free_at(Select::<T>::ARITHMETIC, dst)
+}
+
+// Like `atomic_is_lock_free_at`, for loads and stores.
+#[inline]
+fn is_load_store_lock_free_at<T>(dst: *const T) -> bool {
+    atomic_is_lock_free_at(dst) || kind_is_lock_free_at(Select::<T>::LOAD_STORE, dst)
+}
+
+// Whether `T` is dispatched to `cmpxchg16b`, which depends on the processor.
+#[inline]
+pub(crate) fn has_cmpxchg16b<T>() -> bool {
+    mem::size_of::<T>() == 16 && mem::align_of::<T>() >= 16 && {
+        #[cfg(detect_cmpxchg16b)]
+        {
+            cmpxchg16b::detected()
+        }
+        #[cfg(not(detect_cmpxchg16b))]
+        {
+            false
+        }
+    }
+}
+
+// Whether an 8-byte value at `dst` whose type isn't 8-byte aligned can use
+// `AtomicU64`. This depends only on the address, so all accesses to a value
+// are dispatched the same way.
+#[inline]
+fn is_aligned_8<T>(dst: *mut T) -> bool {
+    dst as usize & 7 == 0
+}
+
+#[repr(C)]
+union Bits<T, U> {
+    val: mem::ManuallyDrop<T>,
+    bits: mem::ManuallyDrop<U>,
+}
+
+// Reinterprets a value as another type of the same size, such as a `T` as the
+// integer it is stored as. Unlike `transmute` this works for generic types.
+// Unlike `transmute_copy` it takes the value itself rather than a reference to
+// it, so there is no borrow of a temporary to reason about, and debug builds
+// check the sizes instead of reading past the end of a smaller value.
+#[inline]
+pub(crate) unsafe fn transmute_bits<T, U>(val: T) -> U {
+    debug_assert_eq!(mem::size_of::<T>(), mem::size_of::<U>());
+    let u = Bits {
+        val: mem::ManuallyDrop::new(val),
+    };
+    mem::ManuallyDrop::into_inner(u.bits)
+}
+
+// The strongest failure ordering allowed for a compare-exchange with the given
+// success ordering.
+#[inline]
+pub(crate) fn failure_ordering(order: Ordering) -> Ordering {
+    match order {
+        Ordering::Release | Ordering::Relaxed => Ordering::Relaxed,
+        _ => Ordering::Acquire,
+    }
+}
+
+// The standard atomic types panic on orderings which aren't valid for an
+// operation, but the fallback lock and zero-sized values would just ignore
+// them. They are checked before the dispatch instead, so that an invalid
+// ordering panics on every path with the same message as the standard types.
+// The checks fold away when the ordering is a constant.
+//
+// With the `panic-free` feature they don't panic, and return a valid ordering
+// instead, which the operation uses from then on: the part of the ordering
+// which doesn't apply to the operation is dropped. The standard types then
+// never see an invalid ordering, so the optimizer removes their panics even
+// when the ordering isn't a constant.
+#[inline]
+pub(crate) fn check_load_ordering(order: Ordering) -> Ordering {
+    match order {
+        #[cfg(not(feature = "panic-free"))]
+        Ordering::Release => panic!("there is no such thing as a release load"),
+        #[cfg(not(feature = "panic-free"))]
+        Ordering::AcqRel => panic!("there is no such thing as an acquire-release load"),
+        #[cfg(feature = "panic-free")]
+        Ordering::Release => Ordering::Relaxed,
+        #[cfg(feature = "panic-free")]
+        Ordering::AcqRel => Ordering::Acquire,
+        order => order,
+    }
 }
 
+#[inline]
+pub(crate) fn check_store_ordering(order: Ordering) -> Ordering {
+    match order {
+        #[cfg(not(feature = "panic-free"))]
+        Ordering::Acquire => panic!("there is no such thing as an acquire store"),
+        #[cfg(not(feature = "panic-free"))]
+        Ordering::AcqRel => panic!("there is no such thing as an acquire-release store"),
+        #[cfg(feature = "panic-free")]
+        Ordering::Acquire => Ordering::Relaxed,
+        #[cfg(feature = "panic-free")]
+        Ordering::AcqRel => Ordering::Release,
+        order => order,
+    }
+}
+
+#[inline]
+pub(crate) fn check_failure_ordering(order: Ordering) -> Ordering {
+    match order {
+        #[cfg(not(feature = "panic-free"))]
+        Ordering::Release => panic!("there is no such thing as a release failure ordering"),
+        #[cfg(not(feature = "panic-free"))]
+        Ordering::AcqRel => {
+            panic!("there is no such thing as an acquire-release failure ordering")
+        }
+        #[cfg(feature = "panic-free")]
+        Ordering::Release => Ordering::Relaxed,
+        #[cfg(feature = "panic-free")]
+        Ordering::AcqRel => Ordering::Acquire,
+        order => order,
+    }
+}
+
+// The standard `compare_exchange` matches on both orderings at once, which
+// the optimizer can't take apart when they aren't constants, so even a valid
+// failure ordering leaves its panics in. With the `panic-free` feature the
+// operation is instead compiled once for each valid failure ordering.
+#[inline(always)]
+fn with_constant_failure<R, F: FnOnce(Ordering) -> R>(failure: Ordering, f: F) -> R {
+    #[cfg(feature = "panic-free")]
+    match failure {
+        Ordering::Relaxed => f(Ordering::Relaxed),
+        Ordering::Acquire => f(Ordering::Acquire),
+        _ => f(Ordering::SeqCst),
+    }
+    #[cfg(not(feature = "panic-free"))]
+    f(failure)
+}
+
+// Like `compare_exchange`, comparisons of whole values are done on their bytes
+// rather than with `PartialEq`.
+#[inline]
+pub(crate) fn same_bytes<T>(a: &T, b: &T) -> bool {
+    unsafe {
+        let a = slice::from_raw_parts(a as *const T as *const u8, mem::size_of::<T>());
+        let b = slice::from_raw_parts(b as *const T as *const u8, mem::size_of::<T>());
+        a == b
+    }
+}
+
+// The mapping used by the deprecated `compare_and_swap` in std, which keeps
+// `SeqCst` as the failure ordering.
+#[inline]
+pub(crate) fn strongest_failure_ordering(order: Ordering) -> Ordering {
+    match order {
+        Ordering::Release | Ordering::Relaxed => Ordering::Relaxed,
+        Ordering::AcqRel | Ordering::Acquire => Ordering::Acquire,
+        _ => order,
+    }
+}
+
+/// Loads a value from `dst`.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
+///
+/// # Panics
+///
+/// Panics if `order` is `Release` or `AcqRel`.
 #[inline]
 pub unsafe fn atomic_load<T>(dst: *mut T, order: Ordering) -> T {
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_load_store_lock_free::<T>();
+    let order = check_load_ordering(order);
+    match Select::<T>::LOAD_STORE {
+        kind::ZERO => ptr::read(dst),
+        #[cfg(has_atomic_load_store_ptr)]
+        kind::PTR =>
+        {
+            transmute_bits(AtomicPtr::from_ptr(dst as *mut *mut ()).load(order))
+        }
+        #[cfg(has_atomic_load_store_8)]
+        kind::U8 =>
+        {
+            transmute_bits(AtomicU8::from_ptr(dst as *mut u8).load(order))
+        }
+        #[cfg(has_atomic_load_store_16)]
+        kind::U16 =>
         {
-            mem::transmute_copy(&(*(dst as *const AtomicU8)).load(order))
+            transmute_bits(AtomicU16::from_ptr(dst as *mut u16).load(order))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(has_atomic_load_store_32)]
+        kind::U32 =>
         {
-            mem::transmute_copy(&(*(dst as *const AtomicU16)).load(order))
+            transmute_bits(AtomicU32::from_ptr(dst as *mut u32).load(order))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(has_atomic_load_store_64)]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            mem::transmute_copy(&(*(dst as *const AtomicU32)).load(order))
+            transmute_bits(AtomicU64::from_ptr(dst as *mut u64).load(order))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            mem::transmute_copy(&(*(dst as *const AtomicU64)).load(order))
+            transmute_bits(AtomicU128::from_ptr(dst as *mut u128).load(order))
         }
-        #[cfg(not(feature = "nightly"))]
-        SIZEOF_USIZE if mem::align_of::<T>() >= ALIGNOF_USIZE =>
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
         {
-            mem::transmute_copy(&(*(dst as *const AtomicUsize)).load(order))
+            transmute_bits(cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).load(order))
         }
         _ => fallback::atomic_load(dst),
     }
 }
 
+// Architectures which order loads after the loads they depend on, so that a
+// consume load is a relaxed load which the compiler may not reorder with later
+// accesses. Neither Miri, loom nor shuttle model dependency ordering.
+#[inline]
+pub(crate) unsafe fn atomic_load_consume<T>(dst: *mut T) -> T {
+    if cfg!(all(
+        any(
+            target_arch = "arm",
+            target_arch = "aarch64",
+            target_arch = "powerpc",
+            target_arch = "powerpc64"
+        ),
+        not(miri),
+        not(any(loom, shuttle))
+    )) {
+        let val = atomic_load(dst, Ordering::Relaxed);
+        compiler_fence(Ordering::Acquire);
+        val
+    } else {
+        atomic_load(dst, Ordering::Acquire)
+    }
+}
+
+/// Stores a value into `dst`.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
+///
+/// # Panics
+///
+/// Panics if `order` is `Acquire` or `AcqRel`.
 #[inline]
 pub unsafe fn atomic_store<T>(dst: *mut T, val: T, order: Ordering) {
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_load_store_lock_free::<T>();
+    let order = check_store_ordering(order);
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::LOAD_STORE {
+        kind::ZERO => {}
+        #[cfg(has_atomic_load_store_ptr)]
+        kind::PTR =>
+        {
+            AtomicPtr::from_ptr(dst as *mut *mut ()).store(transmute_bits(val), order)
+        }
+        #[cfg(has_atomic_load_store_8)]
+        kind::U8 =>
+        {
+            AtomicU8::from_ptr(dst as *mut u8).store(transmute_bits(val), order)
+        }
+        #[cfg(has_atomic_load_store_16)]
+        kind::U16 =>
         {
-            (*(dst as *const AtomicU8)).store(mem::transmute_copy(&val), order)
+            AtomicU16::from_ptr(dst as *mut u16).store(transmute_bits(val), order)
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(has_atomic_load_store_32)]
+        kind::U32 =>
         {
-            (*(dst as *const AtomicU16)).store(mem::transmute_copy(&val), order)
+            AtomicU32::from_ptr(dst as *mut u32).store(transmute_bits(val), order)
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(has_atomic_load_store_64)]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            (*(dst as *const AtomicU32)).store(mem::transmute_copy(&val), order)
+            AtomicU64::from_ptr(dst as *mut u64).store(transmute_bits(val), order)
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            (*(dst as *const AtomicU64)).store(mem::transmute_copy(&val), order)
+            AtomicU128::from_ptr(dst as *mut u128).store(transmute_bits(val), order)
         }
-        #[cfg(not(feature = "nightly"))]
-        SIZEOF_USIZE if mem::align_of::<T>() >= ALIGNOF_USIZE =>
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
         {
-            (*(dst as *const AtomicUsize)).store(mem::transmute_copy(&val), order)
+            cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).store(transmute_bits(val), order)
         }
         _ => fallback::atomic_store(dst, val),
     }
 }
 
+/// Stores a value into `dst`, returning the previous value.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
 #[inline]
 pub unsafe fn atomic_swap<T>(dst: *mut T, val: T, order: Ordering) -> T {
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_lock_free::<T>();
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::EXCHANGE {
+        kind::ZERO => val,
+        #[cfg(native_ptr)]
+        kind::PTR =>
+        {
+            transmute_bits(
+                AtomicPtr::from_ptr(dst as *mut *mut ()).swap(transmute_bits(val), order),
+            )
+        }
+        #[cfg(native_8)]
+        kind::U8 =>
         {
-            mem::transmute_copy(&(*(dst as *const AtomicU8)).swap(mem::transmute_copy(&val), order))
+            transmute_bits(AtomicU8::from_ptr(dst as *mut u8).swap(transmute_bits(val), order))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(native_16)]
+        kind::U16 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU16)).swap(mem::transmute_copy(&val), order),
-            )
+            transmute_bits(AtomicU16::from_ptr(dst as *mut u16).swap(transmute_bits(val), order))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(native_32)]
+        kind::U32 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU32)).swap(mem::transmute_copy(&val), order),
-            )
+            transmute_bits(AtomicU32::from_ptr(dst as *mut u32).swap(transmute_bits(val), order))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_64)]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU64)).swap(mem::transmute_copy(&val), order),
-            )
+            transmute_bits(AtomicU64::from_ptr(dst as *mut u64).swap(transmute_bits(val), order))
         }
-        #[cfg(not(feature = "nightly"))]
-        SIZEOF_USIZE if mem::align_of::<T>() >= ALIGNOF_USIZE =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicUsize)).swap(mem::transmute_copy(&val), order),
-            )
+            transmute_bits(AtomicU128::from_ptr(dst as *mut u128).swap(transmute_bits(val), order))
+        }
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
+        {
+            transmute_bits(cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).swap(transmute_bits(val), order))
         }
         _ => fallback::atomic_swap(dst, val),
     }
 }
 
+// The non-blocking variants of `atomic_load`, `atomic_store` and
+// `atomic_swap`. Lock-free values never wait anyway, while values which take
+// the fallback path make a single attempt at taking the lock.
+
+/// Loads a value from `dst` if it can be done without waiting.
+///
+/// Returns `None` if `dst` isn't lock-free (see [`atomic_is_lock_free_at`])
+/// and its lock is currently held. Lock-free values always succeed.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
+///
+/// # Panics
+///
+/// Panics if `order` is `Release` or `AcqRel`.
+#[inline]
+pub unsafe fn atomic_try_load<T>(dst: *mut T, order: Ordering) -> Option<T> {
+    check_load_store_lock_free::<T>();
+    let order = check_load_ordering(order);
+    if Select::<T>::LOAD_STORE_LOCK_FREE || is_load_store_lock_free_at(dst) {
+        Some(atomic_load(dst, order))
+    } else {
+        fallback::atomic_try_load(dst)
+    }
+}
+
+/// Stores a value into `dst` if it can be done without waiting.
+///
+/// Returns `Err(val)` if `dst` isn't lock-free (see
+/// [`atomic_is_lock_free_at`]) and its lock is currently held. Lock-free
+/// values always succeed.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
+///
+/// # Panics
+///
+/// Panics if `order` is `Acquire` or `AcqRel`.
+#[inline]
+pub unsafe fn atomic_try_store<T>(dst: *mut T, val: T, order: Ordering) -> Result<(), T> {
+    check_load_store_lock_free::<T>();
+    let order = check_store_ordering(order);
+    if Select::<T>::LOAD_STORE_LOCK_FREE || is_load_store_lock_free_at(dst) {
+        atomic_store(dst, val, order);
+        Ok(())
+    } else {
+        let result = fallback::atomic_try_store(dst, val);
+        #[cfg(feature = "staleness-check")]
+        {
+            if result.is_ok() {
+                staleness::record_modification(dst as usize);
+            }
+        }
+        result
+    }
+}
+
+/// Stores a value into `dst` if it can be done without waiting, returning the
+/// previous value.
+///
+/// Returns `Err(val)` if `dst` isn't lock-free (see
+/// [`atomic_is_lock_free_at`]) and its lock is currently held. Lock-free
+/// values always succeed.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
+#[inline]
+pub unsafe fn atomic_try_swap<T>(dst: *mut T, val: T, order: Ordering) -> Result<T, T> {
+    check_lock_free::<T>();
+    if Select::<T>::LOCK_FREE || atomic_is_lock_free_at(dst) {
+        Ok(atomic_swap(dst, val, order))
+    } else {
+        let result = fallback::atomic_try_swap(dst, val);
+        #[cfg(feature = "staleness-check")]
+        {
+            if result.is_ok() {
+                staleness::record_modification(dst as usize);
+            }
+        }
+        result
+    }
+}
+
 #[inline]
 unsafe fn map_result<T, U>(r: Result<T, T>) -> Result<U, U> {
     match r {
-        Ok(x) => Ok(mem::transmute_copy(&x)),
-        Err(x) => Err(mem::transmute_copy(&x)),
+        Ok(x) => Ok(transmute_bits(x)),
+        Err(x) => Err(transmute_bits(x)),
     }
 }
 
+/// Stores `new` into `dst` if it currently holds `current`.
+///
+/// Returns `Ok` with the previous value if it was replaced, or `Err` with the
+/// current value otherwise. Like `Atomic::compare_exchange`, the values are
+/// compared by their bytes rather than with `PartialEq`.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
+///
+/// # Panics
+///
+/// Panics if `failure` is `Release` or `AcqRel`.
 #[inline]
 pub unsafe fn atomic_compare_exchange<T>(
     dst: *mut T,
@@ -159,61 +862,118 @@ pub unsafe fn atomic_compare_exchange<T>(
     success: Ordering,
     failure: Ordering,
 ) -> Result<T, T> {
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_lock_free::<T>();
+    let failure = check_failure_ordering(failure);
+    with_constant_failure(failure, |failure| {
+        compare_exchange(dst, current, new, success, failure)
+    })
+}
+
+#[inline(always)]
+unsafe fn compare_exchange<T>(
+    dst: *mut T,
+    current: T,
+    new: T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    let result = match Select::<T>::EXCHANGE {
+        kind::ZERO => Ok(current),
+        #[cfg(native_ptr)]
+        kind::PTR =>
+        {
+            map_result(AtomicPtr::from_ptr(dst as *mut *mut ()).compare_exchange(
+                transmute_bits(current),
+                transmute_bits(new),
+                success,
+                failure,
+            ))
+        }
+        #[cfg(native_8)]
+        kind::U8 =>
         {
-            map_result((*(dst as *const AtomicU8)).compare_exchange(
-                mem::transmute_copy(&current),
-                mem::transmute_copy(&new),
+            map_result(AtomicU8::from_ptr(dst as *mut u8).compare_exchange(
+                transmute_bits(current),
+                transmute_bits(new),
                 success,
                 failure,
             ))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(native_16)]
+        kind::U16 =>
         {
-            map_result((*(dst as *const AtomicU16)).compare_exchange(
-                mem::transmute_copy(&current),
-                mem::transmute_copy(&new),
+            map_result(AtomicU16::from_ptr(dst as *mut u16).compare_exchange(
+                transmute_bits(current),
+                transmute_bits(new),
                 success,
                 failure,
             ))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(native_32)]
+        kind::U32 =>
         {
-            map_result((*(dst as *const AtomicU32)).compare_exchange(
-                mem::transmute_copy(&current),
-                mem::transmute_copy(&new),
+            map_result(AtomicU32::from_ptr(dst as *mut u32).compare_exchange(
+                transmute_bits(current),
+                transmute_bits(new),
                 success,
                 failure,
             ))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_64)]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            map_result((*(dst as *const AtomicU64)).compare_exchange(
-                mem::transmute_copy(&current),
-                mem::transmute_copy(&new),
+            map_result(AtomicU64::from_ptr(dst as *mut u64).compare_exchange(
+                transmute_bits(current),
+                transmute_bits(new),
                 success,
                 failure,
             ))
         }
-        #[cfg(not(feature = "nightly"))]
-        SIZEOF_USIZE if mem::align_of::<T>() >= ALIGNOF_USIZE =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            map_result((*(dst as *const AtomicUsize)).compare_exchange(
-                mem::transmute_copy(&current),
-                mem::transmute_copy(&new),
+            map_result(AtomicU128::from_ptr(dst as *mut u128).compare_exchange(
+                transmute_bits(current),
+                transmute_bits(new),
+                success,
+                failure,
+            ))
+        }
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
+        {
+            map_result(cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).compare_exchange(
+                transmute_bits(current),
+                transmute_bits(new),
                 success,
                 failure,
             ))
         }
         _ => fallback::atomic_compare_exchange(dst, current, new),
+    };
+    #[cfg(feature = "staleness-check")]
+    {
+        if result.is_ok() {
+            staleness::record_modification(dst as usize);
+        }
     }
+    result
 }
 
+/// Stores `new` into `dst` if it currently holds `current`, but may fail
+/// spuriously.
+///
+/// This is like [`atomic_compare_exchange`], except that it may return `Err`
+/// even if the value is `current`, which makes it cheaper in a loop on some
+/// architectures.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
+///
+/// # Panics
+///
+/// Panics if `failure` is `Release` or `AcqRel`.
 #[inline]
 pub unsafe fn atomic_compare_exchange_weak<T>(
     dst: *mut T,
@@ -222,441 +982,853 @@ pub unsafe fn atomic_compare_exchange_weak<T>(
     success: Ordering,
     failure: Ordering,
 ) -> Result<T, T> {
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_lock_free::<T>();
+    let failure = check_failure_ordering(failure);
+    with_constant_failure(failure, |failure| {
+        compare_exchange_weak(dst, current, new, success, failure)
+    })
+}
+
+#[inline(always)]
+unsafe fn compare_exchange_weak<T>(
+    dst: *mut T,
+    current: T,
+    new: T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    let result = match Select::<T>::EXCHANGE {
+        kind::ZERO => Ok(current),
+        #[cfg(native_ptr)]
+        kind::PTR =>
+        {
+            map_result(AtomicPtr::from_ptr(dst as *mut *mut ()).compare_exchange_weak(
+                transmute_bits(current),
+                transmute_bits(new),
+                success,
+                failure,
+            ))
+        }
+        #[cfg(native_8)]
+        kind::U8 =>
+        {
+            map_result(AtomicU8::from_ptr(dst as *mut u8).compare_exchange_weak(
+                transmute_bits(current),
+                transmute_bits(new),
+                success,
+                failure,
+            ))
+        }
+        #[cfg(native_16)]
+        kind::U16 =>
         {
-            map_result((*(dst as *const AtomicU8)).compare_exchange_weak(
-                mem::transmute_copy(&current),
-                mem::transmute_copy(&new),
+            map_result(AtomicU16::from_ptr(dst as *mut u16).compare_exchange_weak(
+                transmute_bits(current),
+                transmute_bits(new),
                 success,
                 failure,
             ))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(native_32)]
+        kind::U32 =>
         {
-            map_result((*(dst as *const AtomicU16)).compare_exchange_weak(
-                mem::transmute_copy(&current),
-                mem::transmute_copy(&new),
+            map_result(AtomicU32::from_ptr(dst as *mut u32).compare_exchange_weak(
+                transmute_bits(current),
+                transmute_bits(new),
                 success,
                 failure,
             ))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(native_64)]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            map_result((*(dst as *const AtomicU32)).compare_exchange_weak(
-                mem::transmute_copy(&current),
-                mem::transmute_copy(&new),
+            map_result(AtomicU64::from_ptr(dst as *mut u64).compare_exchange_weak(
+                transmute_bits(current),
+                transmute_bits(new),
                 success,
                 failure,
             ))
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            map_result((*(dst as *const AtomicU64)).compare_exchange_weak(
-                mem::transmute_copy(&current),
-                mem::transmute_copy(&new),
+            map_result(AtomicU128::from_ptr(dst as *mut u128).compare_exchange_weak(
+                transmute_bits(current),
+                transmute_bits(new),
                 success,
                 failure,
             ))
         }
-        #[cfg(not(feature = "nightly"))]
-        SIZEOF_USIZE if mem::align_of::<T>() >= ALIGNOF_USIZE =>
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
         {
-            map_result((*(dst as *const AtomicUsize)).compare_exchange_weak(
-                mem::transmute_copy(&current),
-                mem::transmute_copy(&new),
+            map_result(cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).compare_exchange_weak(
+                transmute_bits(current),
+                transmute_bits(new),
                 success,
                 failure,
             ))
         }
         _ => fallback::atomic_compare_exchange(dst, current, new),
+    };
+    #[cfg(feature = "staleness-check")]
+    {
+        if result.is_ok() {
+            staleness::record_modification(dst as usize);
+        }
+    }
+    result
+}
+
+/// Stores `*new` into `dst` if it currently holds `*current`.
+///
+/// This is [`atomic_compare_exchange`] with the values passed by reference.
+/// For values which aren't lock-free they are compared and copied under the
+/// lock, rather than first being copied into the call, which saves copies for
+/// large values. Lock-free values are passed on to `atomic_compare_exchange`.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
+///
+/// # Panics
+///
+/// Panics if `failure` is `Release` or `AcqRel`.
+#[inline]
+pub unsafe fn atomic_compare_exchange_ref<T: Copy>(
+    dst: *mut T,
+    current: &T,
+    new: &T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    match Select::<T>::EXCHANGE {
+        kind::FALLBACK => {
+            check_lock_free::<T>();
+            check_failure_ordering(failure);
+            let result = fallback::atomic_compare_exchange_ref(dst, current, new);
+            #[cfg(feature = "staleness-check")]
+            {
+                if result.is_ok() {
+                    staleness::record_modification(dst as usize);
+                }
+            }
+            result
+        }
+        _ => atomic_compare_exchange(dst, *current, *new, success, failure),
     }
 }
 
+/// Stores `*new` into `dst` if it currently holds `*current`, and otherwise
+/// writes the value it holds into `*current`.
+///
+/// Returns whether `*new` was stored, like C11's
+/// `atomic_compare_exchange_strong`. On success `*current` is left alone,
+/// since it already has the previous value, so unlike
+/// [`atomic_compare_exchange_ref`] nothing is returned by value.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
+///
+/// # Panics
+///
+/// Panics if `failure` is `Release` or `AcqRel`.
+#[inline]
+pub unsafe fn atomic_compare_exchange_in_place<T: Copy>(
+    dst: *mut T,
+    current: &mut T,
+    new: &T,
+    success: Ordering,
+    failure: Ordering,
+) -> bool {
+    match Select::<T>::EXCHANGE {
+        kind::FALLBACK => {
+            check_lock_free::<T>();
+            check_failure_ordering(failure);
+            let stored = fallback::atomic_compare_exchange_in_place(dst, current, new);
+            #[cfg(feature = "staleness-check")]
+            {
+                if stored {
+                    staleness::record_modification(dst as usize);
+                }
+            }
+            stored
+        }
+        _ => match atomic_compare_exchange(dst, *current, *new, success, failure) {
+            Ok(_) => true,
+            Err(observed) => {
+                *current = observed;
+                false
+            }
+        },
+    }
+}
+
+/// Adds `val` to the value in `dst`, wrapping around on overflow,
+/// and returns the previous value.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
 #[inline]
 pub unsafe fn atomic_add<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T
 where
     Wrapping<T>: ops::Add<Output = Wrapping<T>>,
 {
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_lock_free::<T>();
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::ARITHMETIC {
+        #[cfg(native_8)]
+        kind::U8 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU8)).fetch_add(mem::transmute_copy(&val), order),
+            transmute_bits(AtomicU8::from_ptr(dst as *mut u8).fetch_add(transmute_bits(val), order))
+        }
+        #[cfg(native_16)]
+        kind::U16 =>
+        {
+            transmute_bits(
+                AtomicU16::from_ptr(dst as *mut u16).fetch_add(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(native_32)]
+        kind::U32 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU16)).fetch_add(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU32::from_ptr(dst as *mut u32).fetch_add(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(native_64)]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU32)).fetch_add(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU64::from_ptr(dst as *mut u64).fetch_add(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU64)).fetch_add(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU128::from_ptr(dst as *mut u128).fetch_add(transmute_bits(val), order),
             )
         }
-        #[cfg(not(feature = "nightly"))]
-        SIZEOF_USIZE if mem::align_of::<T>() >= ALIGNOF_USIZE =>
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicUsize)).fetch_add(mem::transmute_copy(&val), order),
+            transmute_bits(
+                cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).fetch_add(transmute_bits(val), order),
             )
         }
         _ => fallback::atomic_add(dst, val),
     }
 }
 
+/// Subtracts `val` from the value in `dst`, wrapping around on overflow,
+/// and returns the previous value.
+///
+/// # Safety
+///
+/// See the [module documentation](index.html#safety).
 #[inline]
 pub unsafe fn atomic_sub<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T
 where
     Wrapping<T>: ops::Sub<Output = Wrapping<T>>,
 {
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_lock_free::<T>();
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::ARITHMETIC {
+        #[cfg(native_8)]
+        kind::U8 =>
+        {
+            transmute_bits(AtomicU8::from_ptr(dst as *mut u8).fetch_sub(transmute_bits(val), order))
+        }
+        #[cfg(native_16)]
+        kind::U16 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU8)).fetch_sub(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU16::from_ptr(dst as *mut u16).fetch_sub(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(native_32)]
+        kind::U32 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU16)).fetch_sub(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU32::from_ptr(dst as *mut u32).fetch_sub(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(native_64)]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU32)).fetch_sub(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU64::from_ptr(dst as *mut u64).fetch_sub(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU64)).fetch_sub(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU128::from_ptr(dst as *mut u128).fetch_sub(transmute_bits(val), order),
             )
         }
-        #[cfg(not(feature = "nightly"))]
-        SIZEOF_USIZE if mem::align_of::<T>() >= ALIGNOF_USIZE =>
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicUsize)).fetch_sub(mem::transmute_copy(&val), order),
+            transmute_bits(
+                cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).fetch_sub(transmute_bits(val), order),
             )
         }
         _ => fallback::atomic_sub(dst, val),
     }
 }
 
+/// Performs a bitwise and of the value in `dst` with `val`, returning the
+/// previous value.
+///
+/// # Safety
+///
+/// `T` must be a primitive integer or `bool`. See also the
+/// [module documentation](index.html#safety).
 #[inline]
 pub unsafe fn atomic_and<T: Copy + ops::BitAnd<Output = T>>(
     dst: *mut T,
     val: T,
     order: Ordering,
 ) -> T {
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_lock_free::<T>();
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::ARITHMETIC {
+        #[cfg(native_8)]
+        kind::U8 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU8)).fetch_and(mem::transmute_copy(&val), order),
+            transmute_bits(AtomicU8::from_ptr(dst as *mut u8).fetch_and(transmute_bits(val), order))
+        }
+        #[cfg(native_16)]
+        kind::U16 =>
+        {
+            transmute_bits(
+                AtomicU16::from_ptr(dst as *mut u16).fetch_and(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(native_32)]
+        kind::U32 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU16)).fetch_and(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU32::from_ptr(dst as *mut u32).fetch_and(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(native_64)]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU32)).fetch_and(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU64::from_ptr(dst as *mut u64).fetch_and(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU64)).fetch_and(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU128::from_ptr(dst as *mut u128).fetch_and(transmute_bits(val), order),
             )
         }
-        #[cfg(not(feature = "nightly"))]
-        SIZEOF_USIZE if mem::align_of::<T>() >= ALIGNOF_USIZE =>
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicUsize)).fetch_and(mem::transmute_copy(&val), order),
+            transmute_bits(
+                cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).fetch_and(transmute_bits(val), order),
             )
         }
         _ => fallback::atomic_and(dst, val),
     }
 }
 
+/// Performs a bitwise or of the value in `dst` with `val`, returning the
+/// previous value.
+///
+/// # Safety
+///
+/// `T` must be a primitive integer or `bool`. See also the
+/// [module documentation](index.html#safety).
 #[inline]
 pub unsafe fn atomic_or<T: Copy + ops::BitOr<Output = T>>(
     dst: *mut T,
     val: T,
     order: Ordering,
 ) -> T {
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_lock_free::<T>();
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::ARITHMETIC {
+        #[cfg(native_8)]
+        kind::U8 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU8)).fetch_or(mem::transmute_copy(&val), order),
+            transmute_bits(AtomicU8::from_ptr(dst as *mut u8).fetch_or(transmute_bits(val), order))
+        }
+        #[cfg(native_16)]
+        kind::U16 =>
+        {
+            transmute_bits(
+                AtomicU16::from_ptr(dst as *mut u16).fetch_or(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(native_32)]
+        kind::U32 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU16)).fetch_or(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU32::from_ptr(dst as *mut u32).fetch_or(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(native_64)]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU32)).fetch_or(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU64::from_ptr(dst as *mut u64).fetch_or(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU64)).fetch_or(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU128::from_ptr(dst as *mut u128).fetch_or(transmute_bits(val), order),
             )
         }
-        #[cfg(not(feature = "nightly"))]
-        SIZEOF_USIZE if mem::align_of::<T>() >= ALIGNOF_USIZE =>
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicUsize)).fetch_or(mem::transmute_copy(&val), order),
+            transmute_bits(
+                cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).fetch_or(transmute_bits(val), order),
             )
         }
         _ => fallback::atomic_or(dst, val),
     }
 }
 
+/// Performs a bitwise xor of the value in `dst` with `val`, returning the
+/// previous value.
+///
+/// # Safety
+///
+/// `T` must be a primitive integer or `bool`. See also the
+/// [module documentation](index.html#safety).
 #[inline]
 pub unsafe fn atomic_xor<T: Copy + ops::BitXor<Output = T>>(
     dst: *mut T,
     val: T,
     order: Ordering,
 ) -> T {
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_lock_free::<T>();
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::ARITHMETIC {
+        #[cfg(native_8)]
+        kind::U8 =>
+        {
+            transmute_bits(AtomicU8::from_ptr(dst as *mut u8).fetch_xor(transmute_bits(val), order))
+        }
+        #[cfg(native_16)]
+        kind::U16 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU8)).fetch_xor(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU16::from_ptr(dst as *mut u16).fetch_xor(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(native_32)]
+        kind::U32 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU16)).fetch_xor(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU32::from_ptr(dst as *mut u32).fetch_xor(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(native_64)]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU32)).fetch_xor(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU64::from_ptr(dst as *mut u64).fetch_xor(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU64)).fetch_xor(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU128::from_ptr(dst as *mut u128).fetch_xor(transmute_bits(val), order),
             )
         }
-        #[cfg(not(feature = "nightly"))]
-        SIZEOF_USIZE if mem::align_of::<T>() >= ALIGNOF_USIZE =>
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicUsize)).fetch_xor(mem::transmute_copy(&val), order),
+            transmute_bits(
+                cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).fetch_xor(transmute_bits(val), order),
             )
         }
         _ => fallback::atomic_xor(dst, val),
     }
 }
 
+// Without a native instruction for min and max, lock-free types still have to
+// use a compare-exchange loop rather than the lock, which wouldn't exclude the
+// native operations on the same value.
 #[inline]
-pub unsafe fn atomic_min<T: Copy + cmp::Ord>(dst: *mut T, val: T, order: Ordering) -> T {
-    // Silence warning, fetch_min is not stable yet
-    #[cfg(not(feature = "nightly"))]
-    let _ = order;
+unsafe fn update<T: Copy, F: Fn(T) -> T>(dst: *mut T, order: Ordering, f: F) -> T {
+    match atomic_fetch_update(dst, order, failure_ordering(order), |x| Some(f(x))) {
+        Ok(x) | Err(x) => x,
+    }
+}
 
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+/// Replaces the value in `dst` with the minimum of it and `val`, returning the
+/// previous value.
+///
+/// # Safety
+///
+/// `T` must be a signed primitive integer. See also the
+/// [module documentation](index.html#safety).
+#[inline]
+pub unsafe fn atomic_min<T: Copy + cmp::Ord>(dst: *mut T, val: T, order: Ordering) -> T {
+    check_lock_free::<T>();
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::MIN_MAX {
+        #[cfg(all(native_min_max, native_8))]
+        kind::U8 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicI8)).fetch_min(mem::transmute_copy(&val), order),
+            transmute_bits(AtomicI8::from_ptr(dst as *mut i8).fetch_min(transmute_bits(val), order))
+        }
+        #[cfg(all(native_min_max, native_16))]
+        kind::U16 =>
+        {
+            transmute_bits(
+                AtomicI16::from_ptr(dst as *mut i16).fetch_min(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(all(native_min_max, native_32))]
+        kind::U32 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicI16)).fetch_min(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicI32::from_ptr(dst as *mut i32).fetch_min(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(all(native_min_max, native_64))]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicI32)).fetch_min(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicI64::from_ptr(dst as *mut i64).fetch_min(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicI64)).fetch_min(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicI128::from_ptr(dst as *mut i128).fetch_min(transmute_bits(val), order),
             )
         }
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
+        {
+            transmute_bits(
+                cmpxchg16b::AtomicI128::from_ptr(dst as *mut i128).fetch_min(transmute_bits(val), order),
+            )
+        }
+        _ if Select::<T>::LOCK_FREE || atomic_is_lock_free_at(dst) => update(dst, order, |x| cmp::min(x, val)),
         _ => fallback::atomic_min(dst, val),
     }
 }
 
+/// Replaces the value in `dst` with the maximum of it and `val`, returning the
+/// previous value.
+///
+/// # Safety
+///
+/// `T` must be a signed primitive integer. See also the
+/// [module documentation](index.html#safety).
 #[inline]
 pub unsafe fn atomic_max<T: Copy + cmp::Ord>(dst: *mut T, val: T, order: Ordering) -> T {
-    // Silence warning, fetch_min is not stable yet
-    #[cfg(not(feature = "nightly"))]
-    let _ = order;
-
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_lock_free::<T>();
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::MIN_MAX {
+        #[cfg(all(native_min_max, native_8))]
+        kind::U8 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicI8)).fetch_max(mem::transmute_copy(&val), order),
+            transmute_bits(AtomicI8::from_ptr(dst as *mut i8).fetch_max(transmute_bits(val), order))
+        }
+        #[cfg(all(native_min_max, native_16))]
+        kind::U16 =>
+        {
+            transmute_bits(
+                AtomicI16::from_ptr(dst as *mut i16).fetch_max(transmute_bits(val), order),
+            )
+        }
+        #[cfg(all(native_min_max, native_32))]
+        kind::U32 =>
+        {
+            transmute_bits(
+                AtomicI32::from_ptr(dst as *mut i32).fetch_max(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(all(native_min_max, native_64))]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicI16)).fetch_max(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicI64::from_ptr(dst as *mut i64).fetch_max(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicI32)).fetch_max(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicI128::from_ptr(dst as *mut i128).fetch_max(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicI64)).fetch_max(mem::transmute_copy(&val), order),
+            transmute_bits(
+                cmpxchg16b::AtomicI128::from_ptr(dst as *mut i128).fetch_max(transmute_bits(val), order),
             )
         }
+        _ if Select::<T>::LOCK_FREE || atomic_is_lock_free_at(dst) => update(dst, order, |x| cmp::max(x, val)),
         _ => fallback::atomic_max(dst, val),
     }
 }
 
+/// Replaces the value in `dst` with the minimum of it and `val`, returning the
+/// previous value.
+///
+/// # Safety
+///
+/// `T` must be an unsigned primitive integer. See also the
+/// [module documentation](index.html#safety).
 #[inline]
 pub unsafe fn atomic_umin<T: Copy + cmp::Ord>(dst: *mut T, val: T, order: Ordering) -> T {
-    // Silence warning, fetch_min is not stable yet
-    #[cfg(not(feature = "nightly"))]
-    let _ = order;
-
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_lock_free::<T>();
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::MIN_MAX {
+        #[cfg(all(native_min_max, native_8))]
+        kind::U8 =>
+        {
+            transmute_bits(AtomicU8::from_ptr(dst as *mut u8).fetch_min(transmute_bits(val), order))
+        }
+        #[cfg(all(native_min_max, native_16))]
+        kind::U16 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU8)).fetch_min(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU16::from_ptr(dst as *mut u16).fetch_min(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(all(native_min_max, native_32))]
+        kind::U32 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU16)).fetch_min(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU32::from_ptr(dst as *mut u32).fetch_min(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(all(native_min_max, native_64))]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU32)).fetch_min(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU64::from_ptr(dst as *mut u64).fetch_min(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU64)).fetch_min(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU128::from_ptr(dst as *mut u128).fetch_min(transmute_bits(val), order),
             )
         }
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
+        {
+            transmute_bits(
+                cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).fetch_min(transmute_bits(val), order),
+            )
+        }
+        _ if Select::<T>::LOCK_FREE || atomic_is_lock_free_at(dst) => update(dst, order, |x| cmp::min(x, val)),
         _ => fallback::atomic_min(dst, val),
     }
 }
 
+/// Replaces the value in `dst` with the maximum of it and `val`, returning the
+/// previous value.
+///
+/// # Safety
+///
+/// `T` must be an unsigned primitive integer. See also the
+/// [module documentation](index.html#safety).
 #[inline]
 pub unsafe fn atomic_umax<T: Copy + cmp::Ord>(dst: *mut T, val: T, order: Ordering) -> T {
-    // Silence warning, fetch_min is not stable yet
-    #[cfg(not(feature = "nightly"))]
-    let _ = order;
-
-    match mem::size_of::<T>() {
-        #[cfg(all(feature = "nightly", target_has_atomic = "8"))]
-        1 if mem::align_of::<T>() >= 1 =>
+    check_lock_free::<T>();
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::MIN_MAX {
+        #[cfg(all(native_min_max, native_8))]
+        kind::U8 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU8)).fetch_max(mem::transmute_copy(&val), order),
+            transmute_bits(AtomicU8::from_ptr(dst as *mut u8).fetch_max(transmute_bits(val), order))
+        }
+        #[cfg(all(native_min_max, native_16))]
+        kind::U16 =>
+        {
+            transmute_bits(
+                AtomicU16::from_ptr(dst as *mut u16).fetch_max(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "16"))]
-        2 if mem::align_of::<T>() >= 2 =>
+        #[cfg(all(native_min_max, native_32))]
+        kind::U32 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU16)).fetch_max(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU32::from_ptr(dst as *mut u32).fetch_max(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "32"))]
-        4 if mem::align_of::<T>() >= 4 =>
+        #[cfg(all(native_min_max, native_64))]
+        kind::U64 if Select::<T>::ALIGNED_8 || is_aligned_8(dst) =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU32)).fetch_max(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU64::from_ptr(dst as *mut u64).fetch_max(transmute_bits(val), order),
             )
         }
-        #[cfg(all(feature = "nightly", target_has_atomic = "64"))]
-        8 if mem::align_of::<T>() >= 8 =>
+        #[cfg(native_128)]
+        kind::U128 =>
         {
-            mem::transmute_copy(
-                &(*(dst as *const AtomicU64)).fetch_max(mem::transmute_copy(&val), order),
+            transmute_bits(
+                AtomicU128::from_ptr(dst as *mut u128).fetch_max(transmute_bits(val), order),
             )
         }
+        #[cfg(detect_cmpxchg16b)]
+        kind::CMPXCHG16B if cmpxchg16b::detected() =>
+        {
+            transmute_bits(
+                cmpxchg16b::AtomicU128::from_ptr(dst as *mut u128).fetch_max(transmute_bits(val), order),
+            )
+        }
+        _ if Select::<T>::LOCK_FREE || atomic_is_lock_free_at(dst) => update(dst, order, |x| cmp::max(x, val)),
         _ => fallback::atomic_max(dst, val),
     }
 }
+
+// Pointer arithmetic goes through `AtomicPtr` instead of an integer of the same
+// size so that the pointer keeps its provenance.
+#[inline]
+pub(crate) unsafe fn atomic_ptr_update<T, F: Fn(*mut T) -> *mut T>(
+    dst: *mut *mut T,
+    order: Ordering,
+    f: F,
+) -> *mut T {
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    #[cfg(native_ptr)]
+    {
+        if lock_free::PTR {
+            let fetch_order = failure_ordering(order);
+            let a = AtomicPtr::from_ptr(dst);
+            return match a.fetch_update(order, fetch_order, |p| Some(f(p))) {
+                Ok(x) | Err(x) => x,
+            };
+        }
+    }
+    check_lock_free::<*mut T>();
+    match fallback::atomic_fetch_update(dst, |p| Some(f(p))) {
+        Ok(x) | Err(x) => x,
+    }
+}
+
+// Read-modify-write operations which have no native instruction. Natively
+// atomic types use a compare-exchange loop, while types which take the
+// fallback path compute the new value once under the lock.
+/// Applies `f` to the value in `dst` and stores the value it returns, if any.
+///
+/// Returns `Ok` with the previous value if `f` returned `Some`, or `Err` with
+/// the value passed to `f` if it returned `None`. Like in the update methods of
+/// `Atomic`, `f` may be called several times if the value is changed by other
+/// threads in the meantime.
+///
+/// # Safety
+///
+/// `f` must not perform atomic operations on values which aren't lock-free,
+/// since it may run while the lock of `dst` is held, and the same lock may
+/// protect other values. See also the
+/// [module documentation](index.html#safety).
+///
+/// # Panics
+///
+/// Panics if `fetch_order` is `Release` or `AcqRel`.
+#[inline]
+pub unsafe fn atomic_fetch_update<T: Copy, F: FnMut(T) -> Option<T>>(
+    dst: *mut T,
+    set_order: Ordering,
+    fetch_order: Ordering,
+    mut f: F,
+) -> Result<T, T> {
+    check_lock_free::<T>();
+    // The standard types start with a load with `fetch_order`.
+    let fetch_order = check_load_ordering(fetch_order);
+    if Select::<T>::LOCK_FREE || atomic_is_lock_free_at(dst) {
+        let mut prev = atomic_load(dst, fetch_order);
+        while let Some(next) = f(prev) {
+            match atomic_compare_exchange_weak(dst, prev, next, set_order, fetch_order) {
+                Ok(x) => return Ok(x),
+                Err(x) => prev = x,
+            }
+        }
+        Err(prev)
+    } else {
+        let result = fallback::atomic_fetch_update(dst, f);
+        #[cfg(feature = "staleness-check")]
+        {
+            if result.is_ok() {
+                staleness::record_modification(dst as usize);
+            }
+        }
+        result
+    }
+}
+
+// Non-temporal stores bypass the cache on x86. They are weakly ordered, even
+// with respect to other stores from the same thread, so they must be followed
+// by an `sfence` before the data is published.
+//
+// They write to memory behind the model checker's back, so under loom or
+// shuttle they are relaxed stores and `sfence` is a release fence. Miri can't
+// run them or `sfence` either, so it gets the same substitutes.
+#[inline]
+pub(crate) unsafe fn atomic_store_streaming<T>(dst: *mut T, val: T) {
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    match Select::<T>::LOAD_STORE {
+        #[cfg(all(
+            any(target_arch = "x86", target_arch = "x86_64"),
+            target_feature = "sse2",
+            not(any(loom, shuttle, miri))
+        ))]
+        kind::U32 =>
+        {
+            arch::_mm_stream_si32(dst as *mut i32, transmute_bits(val))
+        }
+        #[cfg(all(target_arch = "x86_64", not(any(loom, shuttle, miri))))]
+        kind::U64 if Select::<T>::ALIGNED_8 =>
+        {
+            arch::_mm_stream_si64(dst as *mut i64, transmute_bits(val))
+        }
+        _ => atomic_store(dst, val, Ordering::Relaxed),
+    }
+}
+
+#[inline]
+pub(crate) fn sfence() {
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse",
+        not(any(loom, shuttle, miri))
+    ))]
+    unsafe {
+        arch::_mm_sfence()
+    }
+    #[cfg(not(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse",
+        not(any(loom, shuttle, miri))
+    )))]
+    ::fence(Ordering::Release)
+}
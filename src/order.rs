@@ -0,0 +1,243 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Memory orderings checked at compile time.
+//!
+//! Each ordering has a marker type here, and the `*_with` methods of `Atomic`
+//! take one instead of an `Ordering`. The traits implemented by a marker say
+//! which operations it is valid for, so an ordering which would make `load`
+//! or `store` panic is a type error instead:
+//!
+//! | Marker    | `LoadOrdering` | `StoreOrdering` | `RmwOrdering` |
+//! |-----------|:--------------:|:---------------:|:-------------:|
+//! | `Relaxed` | yes            | yes             | yes           |
+//! | `Acquire` | yes            |                 | yes           |
+//! | `Release` |                | yes             | yes           |
+//! | `AcqRel`  |                |                 | yes           |
+//! | `SeqCst`  | yes            | yes             | yes           |
+//!
+//! This is most useful in generic code which forwards orderings from its
+//! callers. The methods are inlined and pass `ORDERING` as a constant to the
+//! method which takes an `Ordering`, so they compile to the same code.
+//!
+//! # Examples
+//!
+//! ```
+//! use atomic::order::{Acquire, LoadOrdering, Release, StoreOrdering};
+//! use atomic::Atomic;
+//!
+//! fn publish<O: StoreOrdering>(slot: &Atomic<u32>, val: u32, order: O) {
+//!     slot.store_with(val, order);
+//! }
+//!
+//! fn read<O: LoadOrdering>(slot: &Atomic<u32>, order: O) -> u32 {
+//!     slot.load_with(order)
+//! }
+//!
+//! let slot = Atomic::new(0);
+//! publish(&slot, 5, Release);
+//! assert_eq!(read(&slot, Acquire), 5);
+//! ```
+//!
+//! Orderings which are invalid for an operation don't compile:
+//!
+//! ```compile_fail
+//! use atomic::order::Release;
+//! use atomic::Atomic;
+//!
+//! Atomic::new(0u32).load_with(Release);
+//! ```
+
+use core::fmt;
+use core::sync::atomic::Ordering;
+use Atomic;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A marker type for a memory ordering.
+///
+/// This trait is sealed and implemented by the marker types of this module.
+pub trait StaticOrdering: Copy + fmt::Debug + sealed::Sealed {
+    /// The `Ordering` this marker stands for.
+    const ORDERING: Ordering;
+}
+
+/// An ordering which is valid for loads, and for the failure case of a
+/// compare-exchange: `Relaxed`, `Acquire` or `SeqCst`.
+pub trait LoadOrdering: StaticOrdering {}
+
+/// An ordering which is valid for stores: `Relaxed`, `Release` or `SeqCst`.
+pub trait StoreOrdering: StaticOrdering {}
+
+/// An ordering which is valid for read-modify-write operations, such as
+/// `swap` and the success case of a compare-exchange. All of the orderings
+/// are.
+pub trait RmwOrdering: StaticOrdering {}
+
+macro_rules! marker {
+    ($(#[$attr:meta])* $name:ident: $($tr:ident)*) => {
+        $(#[$attr])*
+        #[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+        pub struct $name;
+
+        impl sealed::Sealed for $name {}
+
+        impl StaticOrdering for $name {
+            const ORDERING: Ordering = Ordering::$name;
+        }
+
+        $(impl $tr for $name {})*
+
+        impl From<$name> for Ordering {
+            #[inline]
+            fn from(_: $name) -> Ordering {
+                Ordering::$name
+            }
+        }
+    };
+}
+
+marker! {
+    /// `Ordering::Relaxed` as a type.
+    Relaxed: LoadOrdering StoreOrdering RmwOrdering
+}
+marker! {
+    /// `Ordering::Acquire` as a type.
+    Acquire: LoadOrdering RmwOrdering
+}
+marker! {
+    /// `Ordering::Release` as a type.
+    Release: StoreOrdering RmwOrdering
+}
+marker! {
+    /// `Ordering::AcqRel` as a type.
+    AcqRel: RmwOrdering
+}
+marker! {
+    /// `Ordering::SeqCst` as a type.
+    SeqCst: LoadOrdering StoreOrdering RmwOrdering
+}
+
+impl<T: Copy> Atomic<T> {
+    /// Loads a value from the `Atomic`, with an ordering checked at compile
+    /// time.
+    ///
+    /// This is `load` with `O::ORDERING`.
+    #[inline]
+    #[cfg_attr(feature = "staleness-check", track_caller)]
+    pub fn load_with<O: LoadOrdering>(&self, _order: O) -> T {
+        self.load(O::ORDERING)
+    }
+
+    /// Stores a value into the `Atomic`, with an ordering checked at compile
+    /// time.
+    ///
+    /// This is `store` with `O::ORDERING`.
+    #[inline]
+    pub fn store_with<O: StoreOrdering>(&self, val: T, _order: O) {
+        self.store(val, O::ORDERING)
+    }
+
+    /// Stores a value into the `Atomic`, returning the old value, with an
+    /// ordering checked at compile time.
+    ///
+    /// This is `swap` with `O::ORDERING`.
+    #[inline]
+    pub fn swap_with<O: RmwOrdering>(&self, val: T, _order: O) -> T {
+        self.swap(val, O::ORDERING)
+    }
+
+    /// Stores a value into the `Atomic` if the current value is the same as
+    /// the `current` value, with orderings checked at compile time.
+    ///
+    /// This is `compare_exchange` with `S::ORDERING` and `F::ORDERING`.
+    #[inline]
+    pub fn compare_exchange_with<S: RmwOrdering, F: LoadOrdering>(
+        &self,
+        current: T,
+        new: T,
+        _success: S,
+        _failure: F,
+    ) -> Result<T, T> {
+        self.compare_exchange(current, new, S::ORDERING, F::ORDERING)
+    }
+
+    /// Stores a value into the `Atomic` if the current value is the same as
+    /// the `current` value, with orderings checked at compile time. Like
+    /// `compare_exchange_weak`, this may fail spuriously.
+    ///
+    /// This is `compare_exchange_weak` with `S::ORDERING` and `F::ORDERING`.
+    #[inline]
+    pub fn compare_exchange_weak_with<S: RmwOrdering, F: LoadOrdering>(
+        &self,
+        current: T,
+        new: T,
+        _success: S,
+        _failure: F,
+    ) -> Result<T, T> {
+        self.compare_exchange_weak(current, new, S::ORDERING, F::ORDERING)
+    }
+}
+
+#[cfg(all(test, not(any(loom, shuttle))))]
+mod tests {
+    #![cfg_attr(feature = "no-fallback", allow(dead_code, unused_imports))]
+
+    use super::*;
+    use core::sync::atomic::Ordering;
+    use std::vec::Vec;
+
+    // Runs the same operations through both APIs and checks that they agree.
+    fn same_results<L: LoadOrdering, S: StoreOrdering, R: RmwOrdering>(l: L, s: S, r: R) {
+        let a = Atomic::new(1u64);
+        let b = Atomic::new(1u64);
+        a.store_with(2, s);
+        b.store(2, S::ORDERING);
+        assert_eq!(a.swap_with(3, r), b.swap(3, R::ORDERING));
+        assert_eq!(
+            a.compare_exchange_with(9, 4, r, l),
+            b.compare_exchange(9, 4, R::ORDERING, L::ORDERING)
+        );
+        assert_eq!(
+            a.compare_exchange_with(3, 4, r, l),
+            b.compare_exchange(3, 4, R::ORDERING, L::ORDERING)
+        );
+        let weak = loop {
+            if let Ok(x) = a.compare_exchange_weak_with(4, 5, r, l) {
+                break x;
+            }
+        };
+        assert_eq!(weak, 4);
+        assert_eq!(a.load_with(l), 5);
+        assert_eq!(a.compare_exchange_weak_with(9, 6, r, l), Err(5));
+
+        // A payload which isn't lock-free takes the fallback path.
+        let a = Atomic::new([1u64; 3]);
+        a.store_with([2; 3], s);
+        assert_eq!(a.swap_with([3; 3], r), [2; 3]);
+        assert_eq!(a.compare_exchange_with([3; 3], [4; 3], r, l), Ok([3; 3]));
+        assert_eq!(a.load_with(l), [4; 3]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn matches_dynamic_api() {
+        let loads: Vec<Ordering> = vec![Relaxed.into(), Acquire.into(), SeqCst.into()];
+        assert_eq!(
+            loads,
+            [Ordering::Relaxed, Ordering::Acquire, Ordering::SeqCst]
+        );
+        same_results(Relaxed, Relaxed, Relaxed);
+        same_results(Acquire, Release, AcqRel);
+        same_results(SeqCst, SeqCst, SeqCst);
+        same_results(Relaxed, Release, Acquire);
+        same_results(SeqCst, Relaxed, Release);
+        assert_eq!(<AcqRel as StaticOrdering>::ORDERING, Ordering::AcqRel);
+    }
+}
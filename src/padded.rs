@@ -0,0 +1,187 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+/// Pads and aligns a value to the size of a cache line.
+///
+/// Values which are written by different threads should live in different
+/// cache lines, otherwise every write invalidates the line in the other
+/// cores' caches even though they never touch the same value ("false
+/// sharing"). Wrapping each value in `CachePadded` guarantees this, so for
+/// example an array of per-core counters can be declared as
+/// `[CachePadded<Atomic<u64>>; N]`.
+///
+/// The alignment depends on the target architecture:
+///
+/// - 128 bytes on aarch64 and powerpc64, whose cache lines (or the pairs of
+///   lines fetched together by the prefetcher) are 128 bytes.
+/// - 256 bytes on s390x.
+/// - 32 bytes on arm, mips, mips64, riscv32 and riscv64.
+/// - 64 bytes everywhere else, including x86 and x86_64.
+///
+/// `CachePadded<T>` dereferences to `T`, so the methods of the wrapped value
+/// can be called on it directly.
+///
+/// # Examples
+///
+/// ```
+/// use atomic::{Atomic, CachePadded, Ordering};
+/// use std::mem;
+///
+/// static COUNTERS: [CachePadded<Atomic<u64>>; 4] = [
+///     CachePadded::new(Atomic::new(0)),
+///     CachePadded::new(Atomic::new(0)),
+///     CachePadded::new(Atomic::new(0)),
+///     CachePadded::new(Atomic::new(0)),
+/// ];
+///
+/// COUNTERS[1].fetch_add(1, Ordering::Relaxed);
+/// assert_eq!(COUNTERS[1].load(Ordering::Relaxed), 1);
+/// assert!(mem::size_of_val(&COUNTERS[0]) >= 32);
+/// ```
+#[cfg_attr(
+    any(target_arch = "aarch64", target_arch = "powerpc64"),
+    repr(align(128))
+)]
+#[cfg_attr(target_arch = "s390x", repr(align(256)))]
+#[cfg_attr(
+    any(
+        target_arch = "arm",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "riscv32",
+        target_arch = "riscv64",
+    ),
+    repr(align(32))
+)]
+#[cfg_attr(
+    not(any(
+        target_arch = "aarch64",
+        target_arch = "powerpc64",
+        target_arch = "s390x",
+        target_arch = "arm",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "riscv32",
+        target_arch = "riscv64",
+    )),
+    repr(align(64))
+)]
+#[derive(Clone, Copy, Default, Hash, PartialEq, Eq)]
+// Not `IntoBytes`, since the padding after the value is uninitialized.
+#[cfg_attr(
+    feature = "zerocopy",
+    derive(zerocopy::FromBytes, zerocopy::KnownLayout)
+)]
+pub struct CachePadded<T> {
+    value: T,
+}
+
+impl<T> CachePadded<T> {
+    /// Pads and aligns a value to the size of a cache line.
+    #[inline]
+    pub const fn new(value: T) -> CachePadded<T> {
+        CachePadded { value }
+    }
+
+    /// Returns the inner value.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for CachePadded<T> {
+    #[inline]
+    fn from(value: T) -> CachePadded<T> {
+        CachePadded::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CachePadded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CachePadded")
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CachePadded;
+    use core::mem;
+    use Atomic;
+    use Ordering::*;
+
+    fn expected_align() -> usize {
+        if cfg!(any(target_arch = "aarch64", target_arch = "powerpc64")) {
+            128
+        } else if cfg!(target_arch = "s390x") {
+            256
+        } else if cfg!(any(
+            target_arch = "arm",
+            target_arch = "mips",
+            target_arch = "mips64",
+            target_arch = "riscv32",
+            target_arch = "riscv64",
+        )) {
+            32
+        } else {
+            64
+        }
+    }
+
+    #[test]
+    fn cache_padded_alignment() {
+        let align = expected_align();
+        assert_eq!(mem::align_of::<CachePadded<u8>>(), align);
+        assert_eq!(mem::size_of::<CachePadded<u8>>(), align);
+        assert_eq!(mem::align_of::<CachePadded<Atomic<u64>>>(), align);
+        assert_eq!(mem::size_of::<CachePadded<[u8; 3]>>() % align, 0);
+
+        let a = [CachePadded::new(0u8), CachePadded::new(0u8)];
+        let distance = &*a[1] as *const u8 as usize - &*a[0] as *const u8 as usize;
+        assert!(distance >= align);
+    }
+
+    #[test]
+    fn cache_padded_large_value() {
+        let align = expected_align();
+        let size = mem::size_of::<CachePadded<[u8; 300]>>();
+        assert!(size >= 300);
+        assert_eq!(size % align, 0);
+    }
+
+    #[test]
+    fn cache_padded_deref() {
+        static COUNTER: CachePadded<Atomic<u64>> = CachePadded::new(Atomic::new(5));
+        COUNTER.fetch_add(2, SeqCst);
+        assert_eq!(COUNTER.load(SeqCst), 7);
+
+        let mut p = CachePadded::from(Atomic::new(1u32));
+        *p.get_mut() = 3;
+        assert_eq!(p.into_inner().into_inner(), 3);
+    }
+}
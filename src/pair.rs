@@ -0,0 +1,289 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use core::mem;
+use Atomic;
+use Ordering;
+
+// The value of an `AtomicPair`, aligned to its size so that it can use the
+// 16-byte atomics wherever the target or the processor has them.
+#[derive(Copy, Clone)]
+#[repr(C, align(16))]
+struct Pair<A, B>(A, B);
+
+/// A pair of values which are read and updated together, such as a pointer
+/// and a counter which is bumped by every update against the ABA problem.
+///
+/// `Atomic<(A, B)>` has the alignment of the tuple, which is only 8 bytes for
+/// two 8-byte values, so it always takes the fallback lock. An `AtomicPair` is
+/// always 16-byte aligned, which makes it lock-free wherever 16-byte atomics
+/// are: on x86_64 processors which have the `cmpxchg16b` instruction, and with
+/// the `nightly` or `portable-atomic` features on targets with 128-bit
+/// atomics. Elsewhere it takes the fallback lock.
+///
+/// The two values must fill the 16 bytes without padding, like two 8-byte
+/// values do, since they are compared by their bits. This is checked at
+/// compile time.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(not(feature = "no-fallback"))] {
+/// use atomic::{AtomicPair, Ordering};
+///
+/// let head = AtomicPair::new(0u64, 0u64);
+/// let push = |(_, tag): (u64, u64)| Some((0x1000, tag + 1));
+/// assert_eq!(head.fetch_update(Ordering::AcqRel, Ordering::Acquire, push), Ok((0, 0)));
+/// assert_eq!(
+///     head.compare_exchange((0x1000, 0), (0, 2), Ordering::AcqRel, Ordering::Acquire),
+///     Err((0x1000, 1))
+/// );
+/// assert_eq!(head.swap((0x2000, 2), Ordering::AcqRel), (0x1000, 1));
+/// assert_eq!(head.load(Ordering::Acquire), (0x2000, 2));
+/// # }
+/// ```
+pub struct AtomicPair<A: Copy, B: Copy> {
+    v: Atomic<Pair<A, B>>,
+}
+
+impl<A: Copy + Default, B: Copy + Default> Default for AtomicPair<A, B> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(Default::default(), Default::default())
+    }
+}
+
+impl<A: Copy + fmt::Debug, B: Copy + fmt::Debug> fmt::Debug for AtomicPair<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("AtomicPair")
+            .field(&self.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+impl<A: Copy, B: Copy> AtomicPair<A, B> {
+    const CHECK: () = assert!(
+        mem::size_of::<A>() + mem::size_of::<B>() == 16 && mem::size_of::<Pair<A, B>>() == 16,
+        "the values of an AtomicPair must fill 16 bytes without padding"
+    );
+
+    /// Creates a new `AtomicPair`.
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub const fn new(a: A, b: B) -> AtomicPair<A, B> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::CHECK;
+        AtomicPair {
+            v: Atomic::new(Pair(a, b)),
+        }
+    }
+
+    /// Creates a new `AtomicPair`.
+    ///
+    /// Under loom or shuttle this isn't a `const fn`, like `Atomic::new`.
+    #[inline]
+    #[cfg(any(loom, shuttle))]
+    pub fn new(a: A, b: B) -> AtomicPair<A, B> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::CHECK;
+        AtomicPair {
+            v: Atomic::new(Pair(a, b)),
+        }
+    }
+
+    /// Checks if the operations on this type are lock-free on every processor
+    /// of the target, like `Atomic::is_lock_free`.
+    #[inline]
+    pub const fn is_lock_free() -> bool {
+        Atomic::<Pair<A, B>>::is_lock_free()
+    }
+
+    /// Checks if the operations on this type are lock-free on the processor
+    /// the program is running on, like `Atomic::is_lock_free_runtime`.
+    #[inline]
+    pub fn is_lock_free_runtime() -> bool {
+        Atomic::<Pair<A, B>>::is_lock_free_runtime()
+    }
+
+    /// Consumes the `AtomicPair` and returns the contained values.
+    #[inline]
+    pub fn into_inner(self) -> (A, B) {
+        let Pair(a, b) = self.v.into_inner();
+        (a, b)
+    }
+
+    /// Loads both values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> (A, B) {
+        let Pair(a, b) = self.v.load(order);
+        (a, b)
+    }
+
+    /// Stores both values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn store(&self, new: (A, B), order: Ordering) {
+        self.v.store(Pair(new.0, new.1), order)
+    }
+
+    /// Stores both values, returning the previous ones.
+    #[inline]
+    pub fn swap(&self, new: (A, B), order: Ordering) -> (A, B) {
+        let Pair(a, b) = self.v.swap(Pair(new.0, new.1), order);
+        (a, b)
+    }
+
+    /// Stores `new` if both current values are equal to `current`.
+    ///
+    /// The return value is a result indicating whether the new values were
+    /// written and containing the previous ones.
+    #[inline]
+    pub fn compare_exchange(
+        &self,
+        current: (A, B),
+        new: (A, B),
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(A, B), (A, B)> {
+        match self.v.compare_exchange(
+            Pair(current.0, current.1),
+            Pair(new.0, new.1),
+            success,
+            failure,
+        ) {
+            Ok(Pair(a, b)) => Ok((a, b)),
+            Err(Pair(a, b)) => Err((a, b)),
+        }
+    }
+
+    /// Stores `new` if both current values are equal to `current`.
+    ///
+    /// Unlike `compare_exchange`, this function is allowed to spuriously fail
+    /// even when the comparison succeeds, which can result in more efficient
+    /// code on some platforms.
+    #[inline]
+    pub fn compare_exchange_weak(
+        &self,
+        current: (A, B),
+        new: (A, B),
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(A, B), (A, B)> {
+        match self.v.compare_exchange_weak(
+            Pair(current.0, current.1),
+            Pair(new.0, new.1),
+            success,
+            failure,
+        ) {
+            Ok(Pair(a, b)) => Ok((a, b)),
+            Err(Pair(a, b)) => Err((a, b)),
+        }
+    }
+
+    /// Fetches both values, and applies a function to them that returns
+    /// optional new values, like `Atomic::fetch_update`.
+    ///
+    /// Returns a `Result` of `Ok(previous_values)` if the function returned
+    /// `Some`, else `Err(previous_values)`.
+    #[inline]
+    pub fn fetch_update<F: FnMut((A, B)) -> Option<(A, B)>>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<(A, B), (A, B)> {
+        let f = |Pair(a, b)| f((a, b)).map(|(a, b)| Pair(a, b));
+        match self.v.fetch_update(set_order, fetch_order, f) {
+            Ok(Pair(a, b)) => Ok((a, b)),
+            Err(Pair(a, b)) => Err((a, b)),
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "no-fallback")))]
+mod tests {
+    use super::AtomicPair;
+    use core::mem;
+    use ops;
+    use std::thread;
+    use Atomic;
+    use Ordering::*;
+
+    #[test]
+    fn pair_layout() {
+        assert_eq!(mem::size_of::<AtomicPair<u64, u64>>(), 16);
+        assert_eq!(mem::align_of::<AtomicPair<u64, u64>>(), 16);
+        assert_eq!(mem::align_of::<AtomicPair<[u32; 3], u32>>(), 16);
+    }
+
+    // The pair is lock-free wherever 16-byte integers are, unlike the tuple.
+    #[test]
+    fn pair_lock_free() {
+        assert_eq!(
+            AtomicPair::<u64, u64>::is_lock_free(),
+            Atomic::<u128>::is_lock_free()
+        );
+        assert_eq!(
+            AtomicPair::<i64, f64>::is_lock_free_runtime(),
+            Atomic::<u128>::is_lock_free_runtime()
+        );
+        assert!(!Atomic::<(u64, u64)>::is_lock_free_runtime());
+        let pair = AtomicPair::new(1u64, 2u64);
+        assert_eq!(
+            ops::atomic_is_lock_free_at(pair.v.as_ptr()),
+            Atomic::<u128>::is_lock_free_runtime()
+        );
+    }
+
+    #[test]
+    fn pair_ops() {
+        let a = AtomicPair::new(1u64, -1i64);
+        assert_eq!(format!("{:?}", a), "AtomicPair((1, -1))");
+        a.store((2, -2), Release);
+        assert_eq!(a.swap((3, -3), AcqRel), (2, -2));
+        assert_eq!(a.compare_exchange((3, 0), (4, -4), SeqCst, SeqCst), Err((3, -3)));
+        assert_eq!(a.compare_exchange((3, -3), (4, -4), SeqCst, SeqCst), Ok((3, -3)));
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, |_| None), Err((4, -4)));
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, |(x, y)| Some((x + 1, y - 1))), Ok((4, -4)));
+        assert_eq!(a.load(Acquire), (5, -5));
+        assert_eq!(a.into_inner(), (5, -5));
+        assert_eq!(AtomicPair::<u64, u64>::default().load(Relaxed), (0, 0));
+    }
+
+    // A pointer and a counter which is bumped by every update, as used against
+    // ABA, are never torn or lost.
+    #[test]
+    fn tagged_pair() {
+        let a = &AtomicPair::new(0u64, 0u64);
+        thread::scope(|s| {
+            for t in 0..4u64 {
+                s.spawn(move || {
+                    for _ in 0..1000 {
+                        let mut old = a.load(Relaxed);
+                        loop {
+                            assert_eq!(old.0 % 8, 0);
+                            let new = ((t + 1) * 8, old.1 + 1);
+                            match a.compare_exchange_weak(old, new, AcqRel, Relaxed) {
+                                Ok(_) => break,
+                                Err(x) => old = x,
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(a.load(SeqCst).1, 4000);
+    }
+}
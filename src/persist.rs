@@ -0,0 +1,238 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Writing the cache lines of an `Atomic` back to memory, for values in
+// persistent memory.
+//
+// x86_64 has three instructions which write a cache line back: `clwb` keeps
+// the line in the cache, `clflushopt` evicts it, and `clflush` evicts it and is
+// ordered with every other store and write-back, which makes it slow for
+// values spanning several lines. Only `clflush` is part of the baseline
+// x86_64, so the best one the processor has is detected with `cpuid` the
+// first time a value is written back, like `cmpxchg16b`. `clwb` and
+// `clflushopt` are only ordered with later stores by `sfence`.
+//
+// The model checkers and Miri can't see the cache, so there the write-back
+// does nothing, and `sfence` is a release fence as usual.
+#![cfg_attr(
+    any(loom, shuttle, miri),
+    allow(dead_code, unused_imports, unused_variables)
+)]
+
+use core::arch::asm;
+use core::arch::x86_64 as arch;
+use core::mem;
+use core::sync::atomic::{AtomicU8, Ordering};
+use Atomic;
+
+const UNKNOWN: u8 = 0;
+const CLFLUSH: u8 = 1;
+const CLFLUSHOPT: u8 = 2;
+const CLWB: u8 = 3;
+
+static INSTRUCTION: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+// Every x86_64 processor has 64-byte cache lines. Writing back every 64 bytes
+// of a value would still cover each line it touches if they were bigger.
+const LINE: usize = 64;
+
+// Whether the processor has `instruction`.
+#[cold]
+fn has(instruction: u8) -> bool {
+    // Leaf 7 reports `clflushopt` in bit 23 of `ebx` and `clwb` in bit 24.
+    // Asking for a leaf above the highest one returns garbage.
+    #[allow(unused_unsafe)]
+    let ebx = unsafe {
+        if arch::__cpuid(0).eax < 7 {
+            return instruction == CLFLUSH;
+        }
+        arch::__cpuid_count(7, 0).ebx
+    };
+    match instruction {
+        CLWB => ebx & (1 << 24) != 0,
+        CLFLUSHOPT => ebx & (1 << 23) != 0,
+        _ => true,
+    }
+}
+
+#[inline]
+fn instruction() -> u8 {
+    // The answer never changes, so a relaxed load of it is enough.
+    match INSTRUCTION.load(Ordering::Relaxed) {
+        UNKNOWN => detect(),
+        instruction => instruction,
+    }
+}
+
+#[cold]
+fn detect() -> u8 {
+    let instruction = [CLWB, CLFLUSHOPT]
+        .iter()
+        .cloned()
+        .find(|&i| has(i))
+        .unwrap_or(CLFLUSH);
+    INSTRUCTION.store(instruction, Ordering::Relaxed);
+    instruction
+}
+
+// The assembly isn't marked as not touching memory, so the compiler keeps the
+// stores before it in program order.
+#[inline]
+unsafe fn write_back_line(line: usize, instruction: u8) {
+    match instruction {
+        CLWB => asm!("clwb byte ptr [{}]", in(reg) line, options(nostack, preserves_flags)),
+        CLFLUSHOPT => asm!(
+            "clflushopt byte ptr [{}]",
+            in(reg) line,
+            options(nostack, preserves_flags)
+        ),
+        _ => asm!("clflush byte ptr [{}]", in(reg) line, options(nostack, preserves_flags)),
+    }
+}
+
+// Writes back every cache line holding part of the `len` bytes at `addr`.
+#[inline]
+fn write_back_with(addr: usize, len: usize, instruction: u8) {
+    if len == 0 {
+        return;
+    }
+    let mut line = addr & !(LINE - 1);
+    while line < addr + len {
+        unsafe { write_back_line(line, instruction) };
+        line += LINE;
+    }
+}
+
+#[inline]
+fn write_back(addr: usize, len: usize) {
+    #[cfg(not(any(loom, shuttle, miri)))]
+    write_back_with(addr, len, instruction());
+}
+
+impl<T: Copy> Atomic<T> {
+    /// Stores a value into the `Atomic`, then writes it back to memory and
+    /// waits for that with `sfence`.
+    ///
+    /// This is for values in persistent memory, such as a file on a DAX
+    /// filesystem which is mapped into memory. Once this returns, the value
+    /// stored, or a later one, survives a power failure. Since each call
+    /// waits for its write-back, the values one thread stores with
+    /// `store_persist` become persistent in the order they were stored.
+    ///
+    /// The cache lines holding the value are written back with `clwb` where
+    /// the processor has it, and otherwise with `clflushopt` or `clflush`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[doc = include_str!("doc_examples/store_persist.md")]
+    #[inline]
+    pub fn store_persist(&self, val: T, order: Ordering) {
+        self.store(val, order);
+        self.flush();
+        ::sfence();
+    }
+
+    /// Stores a value into the `Atomic`, returning the old value, then writes
+    /// the new one back to memory and waits for that with `sfence`.
+    ///
+    /// This is `swap` followed by the write-back of `store_persist`.
+    #[inline]
+    pub fn swap_persist(&self, val: T, order: Ordering) -> T {
+        let prev = self.swap(val, order);
+        self.flush();
+        ::sfence();
+        prev
+    }
+
+    /// Writes the cache lines holding the value back to memory, without
+    /// waiting for it.
+    ///
+    /// A write-back is only known to be complete, and ordered with the stores
+    /// which follow it, after `sfence`. Several values can be written back
+    /// with a single `sfence` after the last of them.
+    #[inline]
+    pub fn flush(&self) {
+        write_back(self.v.get() as usize, mem::size_of::<T>());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has, write_back_with, CLFLUSH, CLFLUSHOPT, CLWB};
+    use std::boxed::Box;
+    use std::thread;
+    use std::vec::Vec;
+    use Atomic;
+    use Ordering::*;
+
+    // Every instruction the processor has can write back ordinary memory, on
+    // the stack and the heap, including values straddling two lines.
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn instructions() {
+        let stack = [0u8; 200];
+        let heap = Box::new([0u64; 40]);
+        for &instruction in &[CLFLUSH, CLFLUSHOPT, CLWB] {
+            if !has(instruction) {
+                continue;
+            }
+            write_back_with(stack.as_ptr() as usize, stack.len(), instruction);
+            write_back_with(stack.as_ptr() as usize + 60, 8, instruction);
+            write_back_with(heap.as_ptr() as usize, 320, instruction);
+            write_back_with(heap.as_ptr() as usize, 0, instruction);
+        }
+    }
+
+    #[test]
+    fn operations() {
+        let a = Atomic::new(1u64);
+        a.store_persist(2, Release);
+        assert_eq!(a.swap_persist(3, AcqRel), 2);
+        a.flush();
+        ::sfence();
+        assert_eq!(a.load(SeqCst), 3);
+
+        // Values which take the fallback lock, and ones with no memory.
+        let b = Atomic::new([7u8; 100]);
+        b.store_persist([8; 100], SeqCst);
+        assert_eq!(b.swap_persist([9; 100], SeqCst), [8; 100]);
+        b.flush();
+        let c = Atomic::new(());
+        c.store_persist((), SeqCst);
+        c.swap_persist((), SeqCst);
+        c.flush();
+    }
+
+    // Every value swapped in is swapped out again exactly once, by another
+    // swap or at the end.
+    #[test]
+    fn contention() {
+        let threads = 4;
+        let rounds = if cfg!(miri) { 20 } else { 1000 };
+        let a = Atomic::new(0u64);
+        let mut seen: Vec<u64> = thread::scope(|s| {
+            let handles: Vec<_> = (0..threads)
+                .map(|t| {
+                    let a = &a;
+                    s.spawn(move || {
+                        (1..=rounds)
+                            .map(|i| a.swap_persist(t * rounds + i, AcqRel))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        seen.push(a.load(SeqCst));
+        seen.sort_unstable();
+        assert_eq!(seen, (0..=threads * rounds).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// `radium::Radium` implementations, so that `Atomic<T>` can be used by code
+// which is generic over atomic and non-atomic storage.
+//
+// The methods forward to the inherent ones. Radium only allows calling the
+// bitwise and arithmetic methods when `Item` implements its `BitOps` and
+// `NumericOps` markers, so the ones which can't be called for a payload are
+// stubbed out like in radium itself. Integers don't have an inherent
+// `fetch_nand`, so it is implemented with the usual update loop.
+
+use core::sync::atomic::{self, Ordering};
+use ops;
+use radium::Radium;
+use Atomic;
+
+macro_rules! radium_common {
+    ($t:ty) => {
+        type Item = $t;
+
+        #[inline]
+        fn new(value: $t) -> Self {
+            Atomic::new(value)
+        }
+
+        #[inline]
+        fn fence(order: Ordering) {
+            atomic::fence(order);
+        }
+
+        #[inline]
+        fn get_mut(&mut self) -> &mut $t {
+            Atomic::<$t>::get_mut(self)
+        }
+
+        #[inline]
+        fn into_inner(self) -> $t {
+            Atomic::<$t>::into_inner(self)
+        }
+
+        #[inline]
+        fn load(&self, order: Ordering) -> $t {
+            Atomic::<$t>::load(self, order)
+        }
+
+        #[inline]
+        fn store(&self, value: $t, order: Ordering) {
+            Atomic::<$t>::store(self, value, order)
+        }
+
+        #[inline]
+        fn swap(&self, value: $t, order: Ordering) -> $t {
+            Atomic::<$t>::swap(self, value, order)
+        }
+
+        #[inline]
+        #[allow(deprecated)]
+        fn compare_and_swap(&self, current: $t, new: $t, order: Ordering) -> $t {
+            Atomic::<$t>::compare_and_swap(self, current, new, order)
+        }
+
+        #[inline]
+        fn compare_exchange(
+            &self,
+            current: $t,
+            new: $t,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<$t, $t> {
+            Atomic::<$t>::compare_exchange(self, current, new, success, failure)
+        }
+
+        #[inline]
+        fn compare_exchange_weak(
+            &self,
+            current: $t,
+            new: $t,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<$t, $t> {
+            Atomic::<$t>::compare_exchange_weak(self, current, new, success, failure)
+        }
+
+        #[inline]
+        fn fetch_update<F>(
+            &self,
+            set_order: Ordering,
+            fetch_order: Ordering,
+            f: F,
+        ) -> Result<$t, $t>
+        where
+            F: FnMut($t) -> Option<$t>,
+        {
+            unsafe { ops::atomic_fetch_update(self.v.get(), set_order, fetch_order, f) }
+        }
+    };
+}
+
+macro_rules! radium_unreachable {
+    ($($name:ident)*) => {
+        $(
+            fn $name(&self, _: Self::Item, _: Ordering) -> Self::Item {
+                unreachable!("this method can't be called for this payload type")
+            }
+        )*
+    };
+}
+
+macro_rules! radium_int {
+    ($($t:ty)*) => ($(
+        unsafe impl Radium for Atomic<$t> {
+            radium_common!($t);
+
+            #[inline]
+            fn fetch_and(&self, value: $t, order: Ordering) -> $t {
+                Atomic::<$t>::fetch_and(self, value, order)
+            }
+
+            #[inline]
+            fn fetch_nand(&self, value: $t, order: Ordering) -> $t {
+                let f = |x: $t| Some(!(x & value));
+                let fetch_order = ops::failure_ordering(order);
+                match unsafe { ops::atomic_fetch_update(self.v.get(), order, fetch_order, f) } {
+                    Ok(x) | Err(x) => x,
+                }
+            }
+
+            #[inline]
+            fn fetch_or(&self, value: $t, order: Ordering) -> $t {
+                Atomic::<$t>::fetch_or(self, value, order)
+            }
+
+            #[inline]
+            fn fetch_xor(&self, value: $t, order: Ordering) -> $t {
+                Atomic::<$t>::fetch_xor(self, value, order)
+            }
+
+            #[inline]
+            fn fetch_add(&self, value: $t, order: Ordering) -> $t {
+                Atomic::<$t>::fetch_add(self, value, order)
+            }
+
+            #[inline]
+            fn fetch_sub(&self, value: $t, order: Ordering) -> $t {
+                Atomic::<$t>::fetch_sub(self, value, order)
+            }
+
+            #[inline]
+            fn fetch_max(&self, value: $t, order: Ordering) -> $t {
+                Atomic::<$t>::fetch_max(self, value, order)
+            }
+
+            #[inline]
+            fn fetch_min(&self, value: $t, order: Ordering) -> $t {
+                Atomic::<$t>::fetch_min(self, value, order)
+            }
+        }
+    )*);
+}
+radium_int! { i8 i16 i32 i64 i128 isize u8 u16 u32 u64 u128 usize }
+
+unsafe impl Radium for Atomic<bool> {
+    radium_common!(bool);
+
+    #[inline]
+    fn fetch_and(&self, value: bool, order: Ordering) -> bool {
+        Atomic::<bool>::fetch_and(self, value, order)
+    }
+
+    #[inline]
+    fn fetch_nand(&self, value: bool, order: Ordering) -> bool {
+        Atomic::<bool>::fetch_nand(self, value, order)
+    }
+
+    #[inline]
+    fn fetch_or(&self, value: bool, order: Ordering) -> bool {
+        Atomic::<bool>::fetch_or(self, value, order)
+    }
+
+    #[inline]
+    fn fetch_xor(&self, value: bool, order: Ordering) -> bool {
+        Atomic::<bool>::fetch_xor(self, value, order)
+    }
+
+    radium_unreachable!(fetch_add fetch_sub fetch_max fetch_min);
+}
+
+unsafe impl<T> Radium for Atomic<*mut T> {
+    radium_common!(*mut T);
+    radium_unreachable!(fetch_and fetch_nand fetch_or fetch_xor);
+    radium_unreachable!(fetch_add fetch_sub fetch_max fetch_min);
+}
+
+unsafe impl<T> Radium for Atomic<*const T> {
+    radium_common!(*const T);
+    radium_unreachable!(fetch_and fetch_nand fetch_or fetch_xor);
+    radium_unreachable!(fetch_add fetch_sub fetch_max fetch_min);
+}
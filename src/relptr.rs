@@ -0,0 +1,292 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+use error::{Error, ErrorKind};
+use Atomic;
+use Ordering;
+
+/// An atomic self-relative pointer.
+///
+/// Instead of an address, a `RelPtr` stores the signed byte offset from its
+/// own address to the target, which stays valid when the memory containing
+/// both the `RelPtr` and its target is mapped at a different address or
+/// moved with `memmove`. This makes it suitable for building data structures
+/// in memory shared between processes.
+///
+/// An offset of 0 encodes a null pointer, so zeroed memory contains null
+/// `RelPtr`s. As a consequence a `RelPtr` can't point to its own address, and
+/// since the offset is 32 bits wide the target must be within 2GiB of the
+/// `RelPtr`. Storing a pointer which can't be represented returns an error.
+///
+/// Pointers returned by `load` are reconstructed from an address, so they use
+/// the provenance which was exposed for that address. Memory obtained from the
+/// operating system, such as a shared memory mapping, is always exposed; for
+/// memory allocated by Rust, cast a pointer to the whole region to `usize`
+/// before dereferencing pointers loaded from it.
+#[repr(transparent)]
+pub struct RelPtr<T> {
+    offset: Atomic<i32>,
+    marker: PhantomData<*mut T>,
+}
+
+// Like `AtomicPtr`, a `RelPtr` can be shared freely since dereferencing the
+// pointer it returns is unsafe.
+unsafe impl<T> Send for RelPtr<T> {}
+unsafe impl<T> Sync for RelPtr<T> {}
+
+impl<T> Default for RelPtr<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl<T> fmt::Debug for RelPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RelPtr")
+            .field(&self.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+impl<T> RelPtr<T> {
+    /// Creates a new null `RelPtr`.
+    #[inline]
+    pub fn null() -> RelPtr<T> {
+        RelPtr {
+            offset: Atomic::new(0),
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns whether `RelPtr` operations are lock-free on this target.
+    ///
+    /// The fallback lock table is private to each process, so a `RelPtr` which
+    /// is not lock-free must not be shared between processes.
+    #[inline]
+    pub fn is_lock_free() -> bool {
+        Atomic::<i32>::is_lock_free()
+    }
+
+    #[inline]
+    fn encode(&self, ptr: Option<NonNull<T>>) -> Result<i32, Error<Option<NonNull<T>>>> {
+        let target = match ptr {
+            Some(p) => p.as_ptr() as usize,
+            None => return Ok(0),
+        };
+        let diff = target.wrapping_sub(self as *const Self as usize) as isize;
+        if diff == 0 {
+            return Err(Error::with_payload(ErrorKind::Unrepresentable, ptr));
+        }
+        if diff < i32::MIN as isize || diff > i32::MAX as isize {
+            return Err(Error::with_payload(ErrorKind::ValueTooLarge, ptr));
+        }
+        Ok(diff as i32)
+    }
+
+    #[inline]
+    fn decode(&self, offset: i32) -> Option<NonNull<T>> {
+        if offset == 0 {
+            return None;
+        }
+        let addr = (self as *const Self as usize).wrapping_add(offset as isize as usize);
+        NonNull::new(addr as *mut T)
+    }
+
+    /// Loads the pointer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> Option<NonNull<T>> {
+        self.decode(self.offset.load(order))
+    }
+
+    /// Stores a pointer.
+    ///
+    /// Returns an error carrying `ptr` if it is `ValueTooLarge` (more than
+    /// 2GiB away from the `RelPtr`) or `Unrepresentable` (equal to the address
+    /// of the `RelPtr`). Nothing is stored in that case.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn store(
+        &self,
+        ptr: Option<NonNull<T>>,
+        order: Ordering,
+    ) -> Result<(), Error<Option<NonNull<T>>>> {
+        let offset = self.encode(ptr)?;
+        self.offset.store(offset, order);
+        Ok(())
+    }
+
+    /// Stores a pointer, returning the previous one.
+    ///
+    /// Returns the same errors as `store`.
+    #[inline]
+    pub fn swap(
+        &self,
+        ptr: Option<NonNull<T>>,
+        order: Ordering,
+    ) -> Result<Option<NonNull<T>>, Error<Option<NonNull<T>>>> {
+        let offset = self.encode(ptr)?;
+        Ok(self.decode(self.offset.swap(offset, order)))
+    }
+
+    /// Stores `new` if the current pointer is `current`.
+    ///
+    /// Pointers are compared by their offsets. On success the previous pointer
+    /// is returned. If the current pointer is different, a `Stale` error
+    /// carrying the current pointer is returned. If `new` can't be stored, the
+    /// error from `store` is returned and nothing is compared.
+    #[inline]
+    pub fn compare_exchange(
+        &self,
+        current: Option<NonNull<T>>,
+        new: Option<NonNull<T>>,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Option<NonNull<T>>, Error<Option<NonNull<T>>>> {
+        let new = self.encode(new)?;
+        let current = match self.encode(current) {
+            Ok(offset) => offset,
+            // No stored offset can match, just report the current pointer.
+            Err(_) => {
+                let actual = self.load(failure);
+                return Err(Error::with_payload(ErrorKind::Stale, actual));
+            }
+        };
+        match self.offset.compare_exchange(current, new, success, failure) {
+            Ok(offset) => Ok(self.decode(offset)),
+            Err(offset) => Err(Error::with_payload(ErrorKind::Stale, self.decode(offset))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RelPtr;
+    use core::ptr::{self, NonNull};
+    use error::ErrorKind;
+    use std::boxed::Box;
+    use std::vec::Vec;
+    use Ordering::*;
+
+    #[repr(C)]
+    struct Node {
+        next: RelPtr<Node>,
+        value: u32,
+    }
+
+    fn list() -> [Node; 4] {
+        let nodes = [10, 20, 30, 40].map(|value| Node {
+            next: RelPtr::null(),
+            value,
+        });
+        for i in 0..3 {
+            nodes[i]
+                .next
+                .store(Some(NonNull::from(&nodes[i + 1])), Release)
+                .unwrap();
+        }
+        nodes
+    }
+
+    fn traverse(head: &Node) -> Vec<(u32, usize)> {
+        let mut out = Vec::new();
+        let mut node = Some(NonNull::from(head));
+        while let Some(p) = node {
+            let n = unsafe { p.as_ref() };
+            out.push((n.value, p.as_ptr() as usize));
+            node = n.next.load(Acquire);
+        }
+        out
+    }
+
+    #[test]
+    fn survives_memmove() {
+        let mut buf = Box::new(list());
+        // `list` moved the nodes after linking them, which is exactly what
+        // the offsets are supposed to survive.
+        let base = buf.as_mut_ptr() as usize;
+        let seen = traverse(&buf[0]);
+        assert_eq!(
+            seen.iter().map(|x| x.0).collect::<Vec<_>>(),
+            [10, 20, 30, 40]
+        );
+        for (i, &(_, addr)) in seen.iter().enumerate() {
+            assert_eq!(addr, base + i * core::mem::size_of::<Node>());
+        }
+
+        let mut moved: Box<[Node; 4]> = Box::new(list());
+        unsafe { ptr::copy_nonoverlapping(&*buf, &mut *moved, 1) };
+        buf[0].value = 0;
+        let moved_base = moved.as_mut_ptr() as usize;
+        let seen = traverse(&moved[0]);
+        assert_eq!(
+            seen.iter().map(|x| x.0).collect::<Vec<_>>(),
+            [10, 20, 30, 40]
+        );
+        for (i, &(_, addr)) in seen.iter().enumerate() {
+            assert_eq!(addr, moved_base + i * core::mem::size_of::<Node>());
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        let nodes = list();
+        let a = &nodes[0].next;
+        assert!(RelPtr::<u8>::default().load(SeqCst).is_none());
+        assert_eq!(format!("{:?}", nodes[3].next), "RelPtr(None)");
+
+        // Offset 0 means null, so a pointer to the `RelPtr` itself can't be
+        // stored.
+        let own = NonNull::from(&nodes[0]);
+        let err = a.store(Some(own), SeqCst).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unrepresentable);
+        assert_eq!(err.into_payload(), Some(own));
+        assert_eq!(a.load(SeqCst), Some(NonNull::from(&nodes[1])));
+
+        // Backwards offsets work as well.
+        let last = &nodes[3].next;
+        last.store(Some(own), SeqCst).unwrap();
+        assert_eq!(last.swap(None, SeqCst).unwrap(), Some(own));
+        assert_eq!(last.load(SeqCst), None);
+
+        #[cfg(target_pointer_width = "64")]
+        {
+            let far = (a as *const _ as usize + (1 << 31)) as *mut Node;
+            let err = a.store(NonNull::new(far), SeqCst).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ValueTooLarge);
+        }
+    }
+
+    #[test]
+    fn compare_exchange() {
+        let nodes = list();
+        let a = &nodes[0].next;
+        let n1 = Some(NonNull::from(&nodes[1]));
+        let n2 = Some(NonNull::from(&nodes[2]));
+        assert_eq!(a.compare_exchange(n1, n2, SeqCst, SeqCst), Ok(n1));
+        let err = a.compare_exchange(n1, None, SeqCst, SeqCst).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Stale);
+        assert_eq!(err.into_payload(), n2);
+        let own = Some(NonNull::from(&nodes[0]));
+        let err = a.compare_exchange(own, None, SeqCst, SeqCst).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Stale);
+        assert_eq!(err.into_payload(), n2);
+        let err = a.compare_exchange(n2, own, SeqCst, SeqCst).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unrepresentable);
+        assert_eq!(a.load(SeqCst), n2);
+    }
+}
@@ -0,0 +1,446 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Model checking support for `--cfg loom` and `--cfg shuttle`.
+//
+// Loom and shuttle only see operations on their own types, which keep their
+// state in the model, while this crate performs atomic operations on raw
+// memory. Under either of them every `Atomic` therefore carries a model of its
+// value in a `ShadowCell`: an atomic of the model checker with the same width
+// if the type is lock-free, or a mutex of the model checker which replaces the
+// fallback lock otherwise. The model is created by `Atomic::new`, so that loom
+// sees its creation in the thread which created the `Atomic`.
+//
+// The native atomic types used by `ops` are replaced by the types below, which
+// have the same layout and API. Each of them looks up the model bound to its
+// address and performs the operation on it with the ordering it was given.
+// Every modification is also written through to memory, so that `get_mut` and
+// `into_inner` see the last value in the modification order. The model checker
+// can't see writes made through `get_mut` or `as_ptr`, so an operation which
+// finds a different value in memory panics rather than silently ignoring them.
+
+use core::cell::{RefCell, UnsafeCell};
+#[cfg(feature = "nightly")]
+use core::cmp;
+use core::mem;
+use core::ptr;
+#[cfg(loom)]
+use loom::sync::atomic as model;
+#[cfg(loom)]
+use loom::sync::{Mutex, MutexGuard};
+#[cfg(feature = "nightly")]
+use ops;
+#[cfg(shuttle)]
+use shuttle::sync::atomic as model;
+#[cfg(shuttle)]
+use shuttle::sync::{Mutex, MutexGuard};
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64 as LastBits;
+use std::sync::{Arc, Weak};
+use Ordering;
+
+enum Shadow {
+    U8(model::AtomicU8),
+    U16(model::AtomicU16),
+    U32(model::AtomicU32),
+    U64(model::AtomicU64),
+}
+
+enum Model {
+    Atomic(Shadow, LastBits),
+    Lock(Mutex<()>),
+}
+
+thread_local! {
+    // Loom and shuttle run all the threads of an execution as coroutines on
+    // the thread which started the test, so a thread local is enough to find
+    // the model of an address. The cells own their models; entries of dropped cells are
+    // simply replaced when their address is bound again.
+    static MODELS: RefCell<HashMap<usize, Weak<Model>>> = RefCell::new(HashMap::new());
+}
+
+pub struct ShadowCell<T> {
+    value: UnsafeCell<T>,
+    model: Option<Arc<Model>>,
+}
+
+impl<T> ShadowCell<T> {
+    pub fn new(value: T) -> ShadowCell<T> {
+        let model = if mem::size_of::<T>() == 0 {
+            // Operations on zero-sized types don't access memory at all.
+            None
+        } else if ::ops::atomic_is_lock_free::<T>() {
+            let (shadow, bits) = unsafe {
+                match mem::size_of::<T>() {
+                    1 => {
+                        let bits: u8 = mem::transmute_copy(&value);
+                        (Shadow::U8(model::AtomicU8::new(bits)), bits as u64)
+                    }
+                    2 => {
+                        let bits: u16 = mem::transmute_copy(&value);
+                        (Shadow::U16(model::AtomicU16::new(bits)), bits as u64)
+                    }
+                    4 => {
+                        let bits: u32 = mem::transmute_copy(&value);
+                        (Shadow::U32(model::AtomicU32::new(bits)), bits as u64)
+                    }
+                    8 => {
+                        let bits: u64 = mem::transmute_copy(&value);
+                        (Shadow::U64(model::AtomicU64::new(bits)), bits)
+                    }
+                    _ => unreachable!(),
+                }
+            };
+            Some(Arc::new(Model::Atomic(shadow, LastBits::new(bits))))
+        } else {
+            Some(Arc::new(Model::Lock(Mutex::new(()))))
+        };
+        ShadowCell {
+            value: UnsafeCell::new(value),
+            model,
+        }
+    }
+
+    // Always models the value with a mutex, for `AtomicSlot`, whose
+    // operations take the fallback lock whatever the type.
+    pub fn new_locked(value: T) -> ShadowCell<T> {
+        ShadowCell {
+            value: UnsafeCell::new(value),
+            model: Some(Arc::new(Model::Lock(Mutex::new(())))),
+        }
+    }
+
+    // Every operation gets its pointer from here, so binding the address to
+    // the model here keeps the binding current even if the cell was moved.
+    #[inline]
+    pub fn get(&self) -> *mut T {
+        let addr = self.value.get();
+        if let Some(ref model) = self.model {
+            MODELS.with(|models| {
+                models
+                    .borrow_mut()
+                    .insert(addr as usize, Arc::downgrade(model))
+            });
+        }
+        addr
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+fn model(addr: usize) -> Arc<Model> {
+    MODELS
+        .with(|models| models.borrow().get(&addr).and_then(Weak::upgrade))
+        .expect("atomic operation on memory which doesn't belong to an `Atomic`")
+}
+
+// Takes the mutex which replaces the fallback lock for `addr`.
+pub fn lock(addr: usize) -> LockGuard {
+    lock_with(addr, |mutex| Some(mutex.lock().unwrap())).unwrap()
+}
+
+// Takes the mutex which replaces the fallback lock for `addr` if it is free.
+pub fn try_lock(addr: usize) -> Option<LockGuard> {
+    lock_with(addr, |mutex| mutex.try_lock().ok())
+}
+
+fn lock_with<F>(addr: usize, f: F) -> Option<LockGuard>
+where
+    F: for<'a> FnOnce(&'a Mutex<()>) -> Option<MutexGuard<'a, ()>>,
+{
+    let model = model(addr);
+    let guard = match *model {
+        Model::Lock(ref mutex) => f(mutex)?,
+        Model::Atomic(..) => unreachable!(),
+    };
+    // The guard borrows from the model, which is kept alive next to it and
+    // dropped after it.
+    let guard = unsafe { mem::transmute::<MutexGuard<()>, MutexGuard<'static, ()>>(guard) };
+    Some(LockGuard {
+        _guard: guard,
+        _model: model,
+    })
+}
+
+pub struct LockGuard {
+    _guard: MutexGuard<'static, ()>,
+    _model: Arc<Model>,
+}
+
+#[inline]
+pub fn yield_now() {
+    #[cfg(loom)]
+    ::loom::thread::yield_now();
+    #[cfg(shuttle)]
+    ::shuttle::thread::yield_now();
+}
+
+macro_rules! shadow_atomic {
+    ($($name:ident $int:ident $variant:ident)*) => ($(
+        #[repr(transparent)]
+        pub struct $name(UnsafeCell<$int>);
+
+        unsafe impl Sync for $name {}
+
+        impl $name {
+            // Like `from_ptr` of the standard atomic types.
+            pub unsafe fn from_ptr<'a>(ptr: *mut $int) -> &'a $name {
+                &*(ptr as *const $name)
+            }
+
+            // Runs `f` on the model atomic for this address and writes the new
+            // value it returns, if any, to memory.
+            fn with<R, F: FnOnce(&model::$name) -> (R, Option<$int>)>(&self, f: F) -> R {
+                let addr = self.0.get();
+                let model = model(addr as usize);
+                let (atomic, last) = match *model {
+                    Model::Atomic(Shadow::$variant(ref atomic), ref last) => (atomic, last),
+                    _ => unreachable!(),
+                };
+                let current = unsafe { ptr::read(addr) };
+                assert!(
+                    current as u64 == last.load(Ordering::Relaxed),
+                    "`Atomic` was modified through `get_mut` or `as_ptr`, which the model \
+                     checker can't see; use `store` instead"
+                );
+                let (result, new) = f(atomic);
+                if let Some(new) = new {
+                    unsafe { ptr::write(addr, new) };
+                    last.store(new as u64, Ordering::Relaxed);
+                }
+                result
+            }
+
+            pub fn load(&self, order: Ordering) -> $int {
+                self.with(|a| (a.load(order), None))
+            }
+
+            pub fn store(&self, val: $int, order: Ordering) {
+                self.with(|a| (a.store(val, order), Some(val)))
+            }
+
+            pub fn swap(&self, val: $int, order: Ordering) -> $int {
+                self.with(|a| (a.swap(val, order), Some(val)))
+            }
+
+            pub fn compare_exchange(
+                &self,
+                current: $int,
+                new: $int,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$int, $int> {
+                self.with(|a| {
+                    let result = a.compare_exchange(current, new, success, failure);
+                    (result, result.ok().map(|_| new))
+                })
+            }
+
+            pub fn compare_exchange_weak(
+                &self,
+                current: $int,
+                new: $int,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$int, $int> {
+                self.with(|a| {
+                    let result = a.compare_exchange_weak(current, new, success, failure);
+                    (result, result.ok().map(|_| new))
+                })
+            }
+
+            pub fn fetch_add(&self, val: $int, order: Ordering) -> $int {
+                self.with(|a| {
+                    let prev = a.fetch_add(val, order);
+                    (prev, Some(prev.wrapping_add(val)))
+                })
+            }
+
+            pub fn fetch_sub(&self, val: $int, order: Ordering) -> $int {
+                self.with(|a| {
+                    let prev = a.fetch_sub(val, order);
+                    (prev, Some(prev.wrapping_sub(val)))
+                })
+            }
+
+            pub fn fetch_and(&self, val: $int, order: Ordering) -> $int {
+                self.with(|a| {
+                    let prev = a.fetch_and(val, order);
+                    (prev, Some(prev & val))
+                })
+            }
+
+            pub fn fetch_or(&self, val: $int, order: Ordering) -> $int {
+                self.with(|a| {
+                    let prev = a.fetch_or(val, order);
+                    (prev, Some(prev | val))
+                })
+            }
+
+            pub fn fetch_xor(&self, val: $int, order: Ordering) -> $int {
+                self.with(|a| {
+                    let prev = a.fetch_xor(val, order);
+                    (prev, Some(prev ^ val))
+                })
+            }
+
+            #[allow(dead_code)]
+            pub fn fetch_min(&self, val: $int, order: Ordering) -> $int {
+                self.with(|a| {
+                    let prev = a.fetch_min(val, order);
+                    (prev, Some(prev.min(val)))
+                })
+            }
+
+            #[allow(dead_code)]
+            pub fn fetch_max(&self, val: $int, order: Ordering) -> $int {
+                self.with(|a| {
+                    let prev = a.fetch_max(val, order);
+                    (prev, Some(prev.max(val)))
+                })
+            }
+        }
+    )*);
+}
+shadow_atomic! {
+    AtomicU8 u8 U8
+    AtomicU16 u16 U16
+    AtomicU32 u32 U32
+    AtomicU64 u64 U64
+}
+
+// Signed integers only need their own types for `fetch_min` and `fetch_max`,
+// which go through a compare-exchange loop on the unsigned type.
+#[cfg(feature = "nightly")]
+macro_rules! shadow_signed {
+    ($($name:ident $int:ident $unsigned:ident)*) => ($(
+        #[repr(transparent)]
+        pub struct $name(UnsafeCell<$int>);
+
+        unsafe impl Sync for $name {}
+
+        impl $name {
+            // Like `from_ptr` of the standard atomic types.
+            pub unsafe fn from_ptr<'a>(ptr: *mut $int) -> &'a $name {
+                &*(ptr as *const $name)
+            }
+
+            fn update<F: Fn($int) -> $int>(&self, order: Ordering, f: F) -> $int {
+                let bits = unsafe { &*(self as *const $name as *const $unsigned) };
+                let fetch_order = ops::failure_ordering(order);
+                let mut prev = bits.load(fetch_order);
+                loop {
+                    let next = f(prev as $int) as _;
+                    match bits.compare_exchange_weak(prev, next, order, fetch_order) {
+                        Ok(x) => return x as $int,
+                        Err(x) => prev = x,
+                    }
+                }
+            }
+
+            pub fn fetch_min(&self, val: $int, order: Ordering) -> $int {
+                self.update(order, |x| cmp::min(x, val))
+            }
+
+            pub fn fetch_max(&self, val: $int, order: Ordering) -> $int {
+                self.update(order, |x| cmp::max(x, val))
+            }
+        }
+    )*);
+}
+#[cfg(feature = "nightly")]
+shadow_signed! {
+    AtomicI8 i8 AtomicU8
+    AtomicI16 i16 AtomicU16
+    AtomicI32 i32 AtomicU32
+    AtomicI64 i64 AtomicU64
+}
+
+// Pointers use the model atomic of the integer of the same width.
+#[cfg(target_pointer_width = "32")]
+type PtrBits = AtomicU32;
+#[cfg(target_pointer_width = "32")]
+type PtrInt = u32;
+#[cfg(target_pointer_width = "64")]
+type PtrBits = AtomicU64;
+#[cfg(target_pointer_width = "64")]
+type PtrInt = u64;
+
+#[repr(transparent)]
+pub struct AtomicPtr<T>(UnsafeCell<*mut T>);
+
+unsafe impl<T> Sync for AtomicPtr<T> {}
+
+impl<T> AtomicPtr<T> {
+    pub unsafe fn from_ptr<'a>(ptr: *mut *mut T) -> &'a AtomicPtr<T> {
+        &*(ptr as *const AtomicPtr<T>)
+    }
+
+    #[inline]
+    fn bits(&self) -> &PtrBits {
+        unsafe { &*(self as *const AtomicPtr<T> as *const PtrBits) }
+    }
+
+    pub fn load(&self, order: Ordering) -> *mut T {
+        self.bits().load(order) as usize as *mut T
+    }
+
+    pub fn store(&self, val: *mut T, order: Ordering) {
+        self.bits().store(val as usize as PtrInt, order)
+    }
+
+    pub fn swap(&self, val: *mut T, order: Ordering) -> *mut T {
+        self.bits().swap(val as usize as PtrInt, order) as usize as *mut T
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<*mut T, *mut T> {
+        let (current, new) = (current as usize as PtrInt, new as usize as PtrInt);
+        self.bits()
+            .compare_exchange(current, new, success, failure)
+            .map(|x| x as usize as *mut T)
+            .map_err(|x| x as usize as *mut T)
+    }
+
+    pub fn compare_exchange_weak(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<*mut T, *mut T> {
+        let (current, new) = (current as usize as PtrInt, new as usize as PtrInt);
+        self.bits()
+            .compare_exchange_weak(current, new, success, failure)
+            .map(|x| x as usize as *mut T)
+            .map_err(|x| x as usize as *mut T)
+    }
+
+    pub fn fetch_update<F: FnMut(*mut T) -> Option<*mut T>>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<*mut T, *mut T> {
+        let mut prev = self.load(fetch_order);
+        while let Some(next) = f(prev) {
+            match self.compare_exchange_weak(prev, next, set_order, fetch_order) {
+                Ok(x) => return Ok(x),
+                Err(x) => prev = x,
+            }
+        }
+        Err(prev)
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Operations on slices of atomics.
+
+use core::sync::atomic::Ordering;
+use ops;
+use Atomic;
+
+/// Fills a slice of atomics with `val` using non-temporal stores.
+///
+/// This is meant for initializing large tables which won't be read again
+/// soon: on x86 the stores bypass the cache instead of evicting the current
+/// working set. On other architectures, and for types which have no
+/// non-temporal store instruction, this uses `Relaxed` stores.
+///
+/// The slice is borrowed mutably so no other thread can observe it while it
+/// is being filled, and an `sfence` is issued before returning. The values are
+/// therefore visible to any thread which the slice is subsequently published
+/// to, for example through a `Release` store.
+pub fn fill_streaming<T: Copy>(dst: &mut [Atomic<T>], val: T) {
+    for a in dst.iter() {
+        unsafe { ops::atomic_store_streaming(a.v.get(), val) };
+    }
+    ops::sfence();
+}
+
+// Reading several elements with one wider access would race with accesses to
+// the elements themselves, which the memory model doesn't allow even though
+// the hardware wouldn't mind, so each element is accessed on its own.
+
+/// Copies the values of a slice of atomics into `dst`, loading each element
+/// with `order`.
+///
+/// Each element is loaded atomically, so it is never torn, but the copy as a
+/// whole is not a snapshot: other threads may modify elements while it is in
+/// progress, and the result can mix their old and new values. Elements are
+/// loaded in order, so with `Acquire` everything written before the `Release`
+/// store of an element is visible once that element has been copied.
+///
+/// # Panics
+///
+/// Panics if the slices have different lengths, or if `order` is `Release`
+/// or `AcqRel`.
+///
+/// # Examples
+///
+/// ```
+/// use atomic::{slice, Atomic, Ordering};
+///
+/// let shared = [Atomic::new(1u32), Atomic::new(2), Atomic::new(3)];
+/// let mut local = [0; 3];
+/// slice::load(&shared, &mut local, Ordering::Acquire);
+/// assert_eq!(local, [1, 2, 3]);
+/// ```
+pub fn load<T: Copy>(src: &[Atomic<T>], dst: &mut [T], order: Ordering) {
+    check_lengths(src.len(), dst.len());
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = s.load(order);
+    }
+}
+
+/// Stores the values of `src` into a slice of atomics, storing each element
+/// with `order`.
+///
+/// Each element is stored atomically, but other threads may observe some
+/// elements of the slice before others are stored. Elements are stored in
+/// order.
+///
+/// # Panics
+///
+/// Panics if the slices have different lengths, or if `order` is `Acquire`
+/// or `AcqRel`.
+pub fn store<T: Copy>(dst: &[Atomic<T>], src: &[T], order: Ordering) {
+    check_lengths(src.len(), dst.len());
+    for (d, s) in dst.iter().zip(src) {
+        d.store(*s, order);
+    }
+}
+
+/// Copies the values of one slice of atomics into another, loading each
+/// element with `load_order` and storing it with `store_order`.
+///
+/// As with [`load`] and [`store`], each element is copied atomically but the
+/// copy as a whole is not.
+///
+/// # Panics
+///
+/// Panics if the slices have different lengths, if `load_order` is `Release`
+/// or `AcqRel`, or if `store_order` is `Acquire` or `AcqRel`.
+pub fn copy<T: Copy>(
+    src: &[Atomic<T>],
+    dst: &[Atomic<T>],
+    load_order: Ordering,
+    store_order: Ordering,
+) {
+    check_lengths(src.len(), dst.len());
+    for (d, s) in dst.iter().zip(src) {
+        d.store(s.load(load_order), store_order);
+    }
+}
+
+// The same message as `copy_from_slice`.
+#[inline]
+fn check_lengths(src: usize, dst: usize) {
+    if src != dst {
+        panic!(
+            "source slice length ({}) does not match destination slice length ({})",
+            src, dst
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fill_streaming;
+    use std::thread;
+    use std::vec::Vec;
+    use Atomic;
+    use Ordering::*;
+
+    #[test]
+    fn fill_then_publish() {
+        let mut a: Vec<Atomic<u32>> = (0..4096).map(|_| Atomic::new(0)).collect();
+        let mut b: Vec<Atomic<u64>> = (0..4096).map(|_| Atomic::new(0)).collect();
+        let mut c: Vec<Atomic<u8>> = (0..4096).map(|_| Atomic::new(0)).collect();
+        fill_streaming(&mut a, 0xdead_beef);
+        fill_streaming(&mut b, u64::MAX - 1);
+        fill_streaming(&mut c, 7);
+        fill_streaming(&mut [], 0u64);
+
+        let ready = Atomic::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                while !ready.load(Acquire) {}
+                assert!(a.iter().all(|x| x.load(Relaxed) == 0xdead_beef));
+                assert!(b.iter().all(|x| x.load(Relaxed) == u64::MAX - 1));
+                assert!(c.iter().all(|x| x.load(Relaxed) == 7));
+            });
+            ready.store(true, Release);
+        });
+    }
+
+    // Each element holds a generation in every byte, so that a torn element
+    // shows up as bytes from different generations.
+    fn pattern(gen: u8) -> u64 {
+        u64::from(gen) * 0x0101_0101_0101_0101
+    }
+
+    fn check_untorn(values: &[u64]) {
+        for &v in values {
+            assert_eq!(v, pattern(v as u8), "torn element {:#x}", v);
+        }
+    }
+
+    #[test]
+    fn concurrent_copies_dont_tear_elements() {
+        const LEN: usize = 64;
+        let rounds = if cfg!(miri) { 20 } else { 2000 };
+        let shared: Vec<Atomic<u64>> = (0..LEN).map(|_| Atomic::new(0)).collect();
+        let copy: Vec<Atomic<u64>> = (0..LEN).map(|_| Atomic::new(0)).collect();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for gen in 1..=rounds {
+                    super::store(&shared, &[pattern(gen as u8); LEN], Release);
+                }
+            });
+            s.spawn(|| {
+                for _ in 0..rounds {
+                    super::copy(&shared, &copy, Acquire, Release);
+                }
+            });
+            s.spawn(|| {
+                let mut local = [0; LEN];
+                for _ in 0..rounds {
+                    super::load(&shared, &mut local, Acquire);
+                    check_untorn(&local);
+                    super::load(&copy, &mut local, Acquire);
+                    check_untorn(&local);
+                }
+            });
+        });
+    }
+
+    // Elements which take the fallback lock.
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn fallback_elements() {
+        let shared: Vec<Atomic<[u32; 3]>> = (0..16).map(|_| Atomic::new([0; 3])).collect();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for gen in 1..=100u32 {
+                    super::store(&shared, &[[gen; 3]; 16], Relaxed);
+                }
+            });
+            s.spawn(|| {
+                let mut local = [[0; 3]; 16];
+                for _ in 0..100 {
+                    super::load(&shared, &mut local, Relaxed);
+                    assert!(local.iter().all(|e| e[0] == e[1] && e[1] == e[2]));
+                }
+            });
+        });
+        let mut local = [[0; 3]; 16];
+        super::load(&shared, &mut local, SeqCst);
+        assert_eq!(local, [[100; 3]; 16]);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "source slice length (2) does not match destination slice length (3)"
+    )]
+    fn load_length_mismatch() {
+        let src = [Atomic::new(1u8), Atomic::new(2)];
+        super::load(&src, &mut [0; 3], Relaxed);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "source slice length (1) does not match destination slice length (0)"
+    )]
+    fn store_length_mismatch() {
+        super::store(&[], &[1u32], Relaxed);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "source slice length (0) does not match destination slice length (1)"
+    )]
+    fn copy_length_mismatch() {
+        super::copy(&[], &[Atomic::new(1u16)], Relaxed, Relaxed);
+    }
+}
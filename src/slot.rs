@@ -0,0 +1,282 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#[cfg(not(any(loom, shuttle)))]
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::Ordering;
+use fallback;
+#[cfg(any(loom, shuttle))]
+use shadow::ShadowCell;
+#[cfg(feature = "std")]
+use std::panic::RefUnwindSafe;
+
+/// A value which can be moved in and out atomically, for types which aren't
+/// `Copy`.
+///
+/// `Atomic<T>` requires `T: Copy` since native atomic instructions duplicate
+/// the bytes of the value. An `AtomicSlot` instead protects its value with the
+/// lock table used by `Atomic<T>` for types which aren't lock-free, so it can
+/// hold values which own resources, such as a `Box` or a `Vec`, and move them
+/// in and out with `swap`, or clone them with `load`.
+///
+/// Every operation takes the lock, even for types which would be lock-free in
+/// an `Atomic`, so it is sequentially consistent whatever the ordering passed
+/// to it. The orderings are only taken for symmetry with `Atomic`, whose
+/// operations on types which aren't lock-free ignore them in the same way.
+/// The lock is only held to move the value or clone it: a value replaced by
+/// `store` is dropped after the lock is released.
+///
+/// This type isn't available with the `no-fallback` feature, which removes
+/// the lock table.
+///
+/// # Examples
+///
+/// ```
+/// use atomic::{AtomicSlot, Ordering};
+///
+/// let config = AtomicSlot::new(vec![String::from("a")]);
+/// let old = config.swap(vec![String::from("b"), String::from("c")], Ordering::AcqRel);
+/// assert_eq!(old, ["a"]);
+/// assert_eq!(config.load(Ordering::Acquire).len(), 2);
+/// config.store(Vec::new(), Ordering::Release);
+/// assert!(config.into_inner().is_empty());
+/// ```
+pub struct AtomicSlot<T> {
+    #[cfg(not(any(loom, shuttle)))]
+    v: UnsafeCell<T>,
+    #[cfg(any(loom, shuttle))]
+    v: ShadowCell<T>,
+}
+
+// Like a `Mutex<T>`, an `AtomicSlot<T>` only ever gives one thread access to
+// the value at a time, so it can be shared as long as the value can be sent.
+unsafe impl<T: Send> Sync for AtomicSlot<T> {}
+
+// A `clone` which panics in `load` leaves the value as it was.
+#[cfg(feature = "std")]
+impl<T: RefUnwindSafe> RefUnwindSafe for AtomicSlot<T> {}
+
+impl<T: Default> Default for AtomicSlot<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Clone + fmt::Debug> fmt::Debug for AtomicSlot<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("AtomicSlot")
+            .field(&self.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+impl<T> AtomicSlot<T> {
+    /// Creates a new `AtomicSlot`.
+    #[inline]
+    #[cfg(not(any(loom, shuttle)))]
+    pub const fn new(v: T) -> AtomicSlot<T> {
+        AtomicSlot {
+            v: UnsafeCell::new(v),
+        }
+    }
+
+    /// Creates a new `AtomicSlot`.
+    ///
+    /// Under loom or shuttle this isn't a `const fn`, like `Atomic::new`.
+    #[inline]
+    #[cfg(any(loom, shuttle))]
+    pub fn new(v: T) -> AtomicSlot<T> {
+        AtomicSlot {
+            v: ShadowCell::new_locked(v),
+        }
+    }
+
+    /// Returns a mutable reference to the value.
+    ///
+    /// This is safe because the mutable reference guarantees that no other
+    /// threads are concurrently accessing the value.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.v.get() }
+    }
+
+    /// Consumes the slot and returns the contained value.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+
+    /// Returns a clone of the value.
+    ///
+    /// The value is cloned while the lock is held, so `clone` should be
+    /// short and must not access this slot or other values which take the
+    /// fallback lock. If it panics, the lock is released and the value is
+    /// left unchanged.
+    #[inline]
+    pub fn load(&self, _order: Ordering) -> T
+    where
+        T: Clone,
+    {
+        unsafe { fallback::atomic_clone(self.v.get()) }
+    }
+
+    /// Stores a value into the slot, dropping the previous one.
+    ///
+    /// The previous value is dropped after the lock has been released.
+    #[inline]
+    pub fn store(&self, val: T, order: Ordering) {
+        drop(self.swap(val, order));
+    }
+
+    /// Stores a value into the slot, returning the previous value.
+    #[inline]
+    pub fn swap(&self, val: T, _order: Ordering) -> T {
+        unsafe { fallback::atomic_swap(self.v.get(), val) }
+    }
+
+    /// Takes the value out of the slot, leaving `T::default()` in its place.
+    #[inline]
+    pub fn take(&self, order: Ordering) -> T
+    where
+        T: Default,
+    {
+        self.swap(T::default(), order)
+    }
+}
+
+#[cfg(all(test, not(any(loom, shuttle))))]
+mod tests {
+    use super::AtomicSlot;
+    use core::sync::atomic::Ordering::*;
+    use std::boxed::Box;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use std::vec::Vec;
+
+    // Counts live values, and panics when cloned if asked to.
+    struct Tracked<'a> {
+        id: usize,
+        live: &'a AtomicUsize,
+        panic_on_clone: bool,
+    }
+
+    impl<'a> Tracked<'a> {
+        fn new(id: usize, live: &'a AtomicUsize) -> Tracked<'a> {
+            live.fetch_add(1, Relaxed);
+            Tracked {
+                id,
+                live,
+                panic_on_clone: false,
+            }
+        }
+    }
+
+    impl<'a> Clone for Tracked<'a> {
+        fn clone(&self) -> Self {
+            assert!(!self.panic_on_clone, "clone panicked");
+            Tracked::new(self.id, self.live)
+        }
+    }
+
+    impl<'a> Drop for Tracked<'a> {
+        fn drop(&mut self) {
+            self.live.fetch_sub(1, Relaxed);
+        }
+    }
+
+    #[test]
+    fn non_copy_values() {
+        let s = AtomicSlot::new(Box::new(1));
+        assert_eq!(*s.swap(Box::new(2), AcqRel), 1);
+        assert_eq!(*s.load(Acquire), 2);
+        s.store(Box::new(3), Release);
+        assert_eq!(format!("{:?}", s), "AtomicSlot(3)");
+        assert_eq!(*s.take(AcqRel), 3);
+        let mut s = AtomicSlot::<Vec<u8>>::default();
+        s.get_mut().push(4);
+        assert_eq!(s.into_inner(), [4]);
+    }
+
+    #[test]
+    fn drops_are_balanced() {
+        let live = AtomicUsize::new(0);
+        {
+            let s = AtomicSlot::new(Tracked::new(0, &live));
+            s.store(Tracked::new(1, &live), SeqCst);
+            assert_eq!(live.load(Relaxed), 1);
+            let old = s.swap(Tracked::new(2, &live), SeqCst);
+            assert_eq!(old.id, 1);
+            drop(old);
+            let c = s.load(SeqCst);
+            assert_eq!((c.id, live.load(Relaxed)), (2, 2));
+        }
+        assert_eq!(live.load(Relaxed), 0);
+        let s = AtomicSlot::new(Tracked::new(3, &live));
+        assert_eq!(s.into_inner().id, 3);
+        assert_eq!(live.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn panicking_clone() {
+        let live = AtomicUsize::new(0);
+        let mut t = Tracked::new(0, &live);
+        t.panic_on_clone = true;
+        let s = AtomicSlot::new(t);
+        let r = panic::catch_unwind(AssertUnwindSafe(|| s.load(SeqCst)));
+        assert!(r.is_err());
+        // The value is untouched and the lock was released.
+        assert_eq!(live.load(Relaxed), 1);
+        let old = s.swap(Tracked::new(1, &live), SeqCst);
+        assert!(old.panic_on_clone);
+        drop(old);
+        drop(s);
+        assert_eq!(live.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn concurrent_swaps() {
+        const THREADS: usize = 4;
+        const ITERS: usize = 1000;
+        let live = AtomicUsize::new(0);
+        let s = AtomicSlot::new(Tracked::new(0, &live));
+        let mut seen: Vec<usize> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    let (s, live) = (&s, &live);
+                    scope.spawn(move || {
+                        let mut seen = Vec::new();
+                        for i in 0..ITERS {
+                            let id = 1 + t * ITERS + i;
+                            if i % 3 == 0 {
+                                s.store(Tracked::new(id, live), Release);
+                                s.load(Acquire);
+                            } else {
+                                seen.push(s.swap(Tracked::new(id, live), AcqRel).id);
+                            }
+                        }
+                        seen
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        // Every value was swapped out at most once.
+        let swaps = seen.len();
+        seen.sort_unstable();
+        seen.dedup();
+        assert_eq!(seen.len(), swaps);
+        assert_eq!(live.load(Relaxed), 1);
+        drop(s);
+        assert_eq!(live.load(Relaxed), 0);
+    }
+}
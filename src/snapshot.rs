@@ -0,0 +1,306 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use core::hint;
+use error::{Error, ErrorKind};
+use Atomic;
+use Ordering;
+
+const VALUE_BITS: u32 = 48;
+const VALUE_MASK: u64 = (1 << VALUE_BITS) - 1;
+
+#[inline]
+fn pack(value: u64, stamp: u16) -> u64 {
+    value | (u64::from(stamp) << VALUE_BITS)
+}
+
+#[inline]
+fn unpack(word: u64) -> (u64, u16) {
+    (word & VALUE_MASK, (word >> VALUE_BITS) as u16)
+}
+
+/// A 48-bit value and a 16-bit stamp packed into an `Atomic<u64>`.
+///
+/// Every modification increments the stamp, which allows `snapshot` to detect
+/// that a cell has been modified between two reads even if it was changed
+/// back to the same value. The stamp wraps around after 65536 modifications,
+/// so a change is only missed if a cell is modified exactly a multiple of
+/// 65536 times between two consecutive reads of the same snapshot attempt.
+pub struct StampedU64 {
+    word: Atomic<u64>,
+}
+
+impl StampedU64 {
+    /// The largest value which can be stored in a `StampedU64`.
+    pub const MAX_VALUE: u64 = VALUE_MASK;
+
+    /// Creates a new `StampedU64` with a stamp of zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is larger than `MAX_VALUE`.
+    #[inline]
+    pub fn new(value: u64) -> StampedU64 {
+        match StampedU64::try_new(value) {
+            Ok(cell) => cell,
+            Err(_) => panic!("value does not fit in 48 bits"),
+        }
+    }
+
+    /// Creates a new `StampedU64` with a stamp of zero, or returns a
+    /// `ValueTooLarge` error carrying `value` if it is larger than
+    /// `MAX_VALUE`.
+    #[inline]
+    pub fn try_new(value: u64) -> Result<StampedU64, Error<u64>> {
+        if value > VALUE_MASK {
+            return Err(Error::with_payload(ErrorKind::ValueTooLarge, value));
+        }
+        Ok(StampedU64 {
+            word: Atomic::new(pack(value, 0)),
+        })
+    }
+
+    /// Loads the current value.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> u64 {
+        unpack(self.word.load(order)).0
+    }
+
+    /// Loads the current value along with its stamp.
+    #[inline]
+    pub fn load_stamped(&self, order: Ordering) -> (u64, u16) {
+        unpack(self.word.load(order))
+    }
+
+    /// Stores a value, incrementing the stamp.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is larger than `MAX_VALUE`.
+    #[inline]
+    pub fn store(&self, value: u64, order: Ordering) {
+        self.swap(value, order);
+    }
+
+    /// Stores a value, incrementing the stamp, and returns the previous value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is larger than `MAX_VALUE`.
+    #[inline]
+    pub fn swap(&self, value: u64, order: Ordering) -> u64 {
+        assert!(value <= VALUE_MASK, "value does not fit in 48 bits");
+        self.update(order, |_| value)
+    }
+
+    /// Adds to the current value, wrapping around at `MAX_VALUE`, and returns
+    /// the previous value.
+    #[inline]
+    pub fn fetch_add(&self, val: u64, order: Ordering) -> u64 {
+        self.update(order, |old| old.wrapping_add(val) & VALUE_MASK)
+    }
+
+    /// Consumes the cell and returns the contained value.
+    #[inline]
+    pub fn into_inner(self) -> u64 {
+        unpack(self.word.into_inner()).0
+    }
+
+    fn update<F: Fn(u64) -> u64>(&self, order: Ordering, f: F) -> u64 {
+        let mut current = self.word.load(Ordering::Relaxed);
+        loop {
+            let (value, stamp) = unpack(current);
+            let new = pack(f(value), stamp.wrapping_add(1));
+            match self
+                .word
+                .compare_exchange_weak(current, new, order, Ordering::Relaxed)
+            {
+                Ok(_) => return value,
+                Err(x) => current = x,
+            }
+        }
+    }
+}
+
+impl Default for StampedU64 {
+    #[inline]
+    fn default() -> Self {
+        StampedU64::new(0)
+    }
+}
+
+impl fmt::Debug for StampedU64 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (value, stamp) = self.load_stamped(Ordering::SeqCst);
+        f.debug_struct("StampedU64")
+            .field("value", &value)
+            .field("stamp", &stamp)
+            .finish()
+    }
+}
+
+fn collect<const N: usize>(cells: &[&StampedU64; N]) -> [u64; N] {
+    let mut words = [0; N];
+    for (word, cell) in words.iter_mut().zip(cells.iter()) {
+        *word = cell.word.load(Ordering::SeqCst);
+    }
+    words
+}
+
+/// Attempts to take a consistent snapshot of several cells, giving up with a
+/// `WouldBlock` error after `max_attempts` failed attempts.
+///
+/// See `snapshot` for details.
+pub fn try_snapshot<const N: usize>(
+    cells: &[&StampedU64; N],
+    max_attempts: usize,
+) -> Result<[u64; N], Error> {
+    let mut previous = collect(cells);
+    let mut spins = 1u32;
+    for _ in 0..max_attempts {
+        let current = collect(cells);
+        if current == previous {
+            let mut values = [0; N];
+            for (value, &word) in values.iter_mut().zip(current.iter()) {
+                *value = unpack(word).0;
+            }
+            return Ok(values);
+        }
+        previous = current;
+
+        // Give the writers some room before trying again.
+        for _ in 0..spins {
+            hint::spin_loop();
+        }
+        if spins < 1 << 10 {
+            spins <<= 1;
+        }
+    }
+    Err(Error::new(ErrorKind::WouldBlock))
+}
+
+/// Takes a consistent snapshot of several cells without taking a lock.
+///
+/// This uses the double-collect algorithm: all cells are read repeatedly
+/// until two consecutive reads of every cell (including its stamp) agree. At
+/// that point no cell was modified between the two reads, so all the values
+/// were present at the same time and the snapshot is linearizable. All the
+/// reads are `SeqCst`.
+///
+/// The snapshot is obstruction-free rather than wait-free: if writers keep
+/// modifying the cells faster than they can be read, this function keeps
+/// retrying, with an increasing backoff between attempts. Use `try_snapshot`
+/// to bound the number of attempts instead.
+pub fn snapshot<const N: usize>(cells: &[&StampedU64; N]) -> [u64; N] {
+    loop {
+        if let Ok(values) = try_snapshot(cells, usize::MAX) {
+            return values;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{snapshot, try_snapshot, StampedU64};
+    use error::ErrorKind;
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+    use Ordering::*;
+
+    #[test]
+    fn stamped_u64() {
+        let a = StampedU64::new(5);
+        assert_eq!(a.load_stamped(SeqCst), (5, 0));
+        a.store(5, SeqCst);
+        assert_eq!(a.load_stamped(SeqCst), (5, 1));
+        assert_eq!(a.swap(7, SeqCst), 5);
+        assert_eq!(a.fetch_add(StampedU64::MAX_VALUE, SeqCst), 7);
+        assert_eq!(a.load_stamped(SeqCst), (6, 3));
+        assert_eq!(format!("{:?}", a), "StampedU64 { value: 6, stamp: 3 }");
+        assert_eq!(a.into_inner(), 6);
+    }
+
+    #[test]
+    #[should_panic(expected = "value does not fit in 48 bits")]
+    fn stamped_u64_too_large() {
+        StampedU64::new(1 << 48);
+    }
+
+    #[test]
+    fn stamped_u64_try_new() {
+        assert_eq!(
+            StampedU64::try_new(StampedU64::MAX_VALUE)
+                .unwrap()
+                .into_inner(),
+            StampedU64::MAX_VALUE
+        );
+        let err = StampedU64::try_new(1 << 48).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ValueTooLarge);
+        assert_eq!(err.into_payload(), 1 << 48);
+    }
+
+    #[test]
+    fn stamp_wraps() {
+        let a = StampedU64::new(0);
+        for _ in 0..0x10000 {
+            a.fetch_add(1, Relaxed);
+        }
+        assert_eq!(a.load_stamped(SeqCst), (0x10000, 0));
+    }
+
+    #[test]
+    fn quiescent_snapshot() {
+        let cells = [StampedU64::new(1), StampedU64::new(2), StampedU64::new(3)];
+        assert_eq!(snapshot(&[&cells[0], &cells[1], &cells[2]]), [1, 2, 3]);
+        assert_eq!(
+            try_snapshot(&[&cells[0], &cells[1], &cells[2]], 1),
+            Ok([1, 2, 3])
+        );
+        assert_eq!(
+            try_snapshot(&[&cells[0]], 0).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn snapshots_are_linearizable() {
+        // The writer increments the cells in order, one round at a time, so at
+        // any instant the cells are non-increasing and the first and last
+        // cells differ by at most one. A torn read would see a later cell
+        // ahead of an earlier one.
+        const N: usize = 6;
+        let cells: [StampedU64; N] = Default::default();
+        let refs = [
+            &cells[0], &cells[1], &cells[2], &cells[3], &cells[4], &cells[5],
+        ];
+        let done = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..20_000 {
+                    for cell in cells.iter() {
+                        cell.fetch_add(1, SeqCst);
+                    }
+                }
+                done.store(true, SeqCst);
+            });
+            for _ in 0..2 {
+                s.spawn(|| {
+                    while !done.load(SeqCst) {
+                        let snap = snapshot(&refs);
+                        for pair in snap.windows(2) {
+                            assert!(pair[0] >= pair[1], "torn snapshot: {:?}", snap);
+                        }
+                        assert!(snap[0] - snap[N - 1] <= 1, "torn snapshot: {:?}", snap);
+                    }
+                });
+            }
+        });
+        assert_eq!(snapshot(&refs), [20_000; N]);
+    }
+}
@@ -0,0 +1,332 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Stale-read detection for `Relaxed` loads.
+//
+// Every instrumented atomic gets an entry in a small global table holding its
+// address and a modification counter, which is bumped after every write made
+// through this crate. Each thread remembers, per instrumented atomic, the
+// value it last loaded, the modification count at the time it first saw that
+// value and how many loads in a row returned it. If a thread keeps seeing the
+// same value for more than `max_loads` loads while the atomic was modified
+// more than `max_gap` times, the value it is reading is stale and a
+// diagnostic is reported.
+//
+// A thread only looks at the modification counter after it has performed its
+// load, so a healthy reader which happens to race with a writer may see the
+// counter move ahead of the value for a short while. The gap threshold absorbs
+// that window, and the load threshold makes sure a single unlucky load never
+// triggers the diagnostic. Writers which repeatedly store the same value look
+// exactly like stale reads, so they shouldn't be instrumented.
+
+use core::cell::RefCell;
+use core::mem;
+use core::panic::Location;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use error::{Error, ErrorKind};
+use misuse::{self, Misuse};
+use std::vec::Vec;
+
+pub const MAX_INSTRUMENTED: usize = 64;
+const MAX_STREAKS: usize = 16;
+
+struct Entry {
+    addr: AtomicUsize,
+    modifications: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_ENTRY: Entry = Entry {
+    addr: AtomicUsize::new(0),
+    modifications: AtomicUsize::new(0),
+};
+
+static ENTRIES: [Entry; MAX_INSTRUMENTED] = [EMPTY_ENTRY; MAX_INSTRUMENTED];
+
+// Number of instrumented atomics, used to skip the table lookup entirely when
+// nothing is instrumented.
+static INSTRUMENTED: AtomicUsize = AtomicUsize::new(0);
+
+static MAX_LOADS: AtomicUsize = AtomicUsize::new(1000);
+static MAX_GAP: AtomicUsize = AtomicUsize::new(16);
+
+struct Streak {
+    addr: usize,
+    hash: u64,
+    first_modification: usize,
+    loads: usize,
+}
+
+thread_local! {
+    static STREAKS: RefCell<Vec<Streak>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Sets the thresholds used by the stale-read detector.
+///
+/// A diagnostic is reported when a thread performs more than `max_loads`
+/// consecutive `Relaxed` loads which observe the same value while the atomic
+/// is modified more than `max_gap` times. The defaults are 1000 loads and 16
+/// modifications.
+pub fn set_staleness_thresholds(max_loads: usize, max_gap: usize) {
+    MAX_LOADS.store(max_loads, Ordering::Relaxed);
+    MAX_GAP.store(max_gap, Ordering::Relaxed);
+}
+
+fn find(addr: usize) -> Option<&'static Entry> {
+    if INSTRUMENTED.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    ENTRIES
+        .iter()
+        .find(|e| e.addr.load(Ordering::Relaxed) == addr)
+}
+
+pub fn enable(addr: usize) -> Result<(), Error> {
+    if find(addr).is_some() {
+        return Ok(());
+    }
+    for entry in ENTRIES.iter() {
+        if entry
+            .addr
+            .compare_exchange(0, addr, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            entry.modifications.store(0, Ordering::Relaxed);
+            INSTRUMENTED.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+    }
+    Err(Error::new(ErrorKind::CapacityExhausted))
+}
+
+pub fn disable(addr: usize) {
+    if let Some(entry) = find(addr) {
+        entry.addr.store(0, Ordering::Relaxed);
+        INSTRUMENTED.fetch_sub(1, Ordering::Relaxed);
+    }
+    STREAKS.with(|s| s.borrow_mut().retain(|s| s.addr != addr));
+}
+
+#[inline]
+pub fn record_modification(addr: usize) {
+    if let Some(entry) = find(addr) {
+        entry.modifications.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+fn modifications(addr: usize) -> usize {
+    find(addr).unwrap().modifications.load(Ordering::Relaxed)
+}
+
+// Records a modification when dropped, which lets the ops functions account
+// for a write after it has been performed.
+pub struct Modification(pub usize);
+
+impl Drop for Modification {
+    #[inline]
+    fn drop(&mut self) {
+        record_modification(self.0);
+    }
+}
+
+// FNV-1a over the bytes of the loaded value.
+fn hash<T>(val: &T) -> u64 {
+    let bytes = unsafe { slice::from_raw_parts(val as *const T as *const u8, mem::size_of::<T>()) };
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub fn check_load<T>(addr: usize, val: &T, location: &'static Location<'static>) {
+    let entry = match find(addr) {
+        Some(entry) => entry,
+        None => return,
+    };
+    let modifications = entry.modifications.load(Ordering::Acquire);
+    let hash = hash(val);
+
+    let stale = STREAKS.with(|streaks| {
+        let mut streaks = streaks.borrow_mut();
+        let i = match streaks.iter().position(|s| s.addr == addr) {
+            Some(i) => i,
+            None => {
+                if streaks.len() == MAX_STREAKS {
+                    streaks.remove(0);
+                }
+                streaks.push(Streak {
+                    addr,
+                    hash,
+                    first_modification: modifications,
+                    loads: 0,
+                });
+                streaks.len() - 1
+            }
+        };
+        let streak = &mut streaks[i];
+        if streak.hash != hash {
+            streak.hash = hash;
+            streak.first_modification = modifications;
+            streak.loads = 0;
+        }
+        streak.loads += 1;
+
+        let gap = modifications.wrapping_sub(streak.first_modification);
+        if streak.loads > MAX_LOADS.load(Ordering::Relaxed) && gap > MAX_GAP.load(Ordering::Relaxed)
+        {
+            let stale = Misuse::StaleRead {
+                location,
+                loads: streak.loads,
+                modifications: gap,
+            };
+            // Start a new streak so the diagnostic only fires once.
+            streak.first_modification = modifications;
+            streak.loads = 0;
+            Some(stale)
+        } else {
+            None
+        }
+    });
+
+    // Report outside of the thread-local borrow in case the handler loads
+    // from an instrumented atomic itself.
+    if let Some(stale) = stale {
+        misuse::report(stale);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{modifications, set_staleness_thresholds};
+    use core::ptr;
+    use core::sync::atomic::{AtomicUsize, Ordering as StdOrdering};
+    use misuse::{set_misuse_handler, Misuse};
+    use std::sync::Mutex;
+    use std::thread;
+    use Atomic;
+    use Ordering::*;
+
+    // The thresholds and the handler are global, so these tests must not run
+    // concurrently.
+    static SERIAL: Mutex<()> = Mutex::new(());
+    static STALE_REPORTS: AtomicUsize = AtomicUsize::new(0);
+    static STALE_LINE: AtomicUsize = AtomicUsize::new(0);
+
+    fn record(misuse: &Misuse) {
+        match *misuse {
+            Misuse::StaleRead { location, .. } => {
+                STALE_LINE.store(location.line() as usize, StdOrdering::SeqCst);
+                STALE_REPORTS.fetch_add(1, StdOrdering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn stale_spin_loop_is_reported() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        set_misuse_handler(record);
+        set_staleness_thresholds(4, 2);
+        STALE_REPORTS.store(0, StdOrdering::SeqCst);
+
+        let a = Atomic::new(0u32);
+        a.enable_staleness_check();
+        let mut line = 0;
+        for i in 1..10 {
+            a.store(i, Relaxed);
+            // Simulate a reader which is served an outdated value by writing
+            // the old value back behind the detector's back.
+            unsafe { ptr::write(a.v.get(), 0) };
+            line = line!() + 1;
+            assert_eq!(a.load(Relaxed), 0);
+        }
+        a.disable_staleness_check();
+
+        assert_eq!(STALE_REPORTS.load(StdOrdering::SeqCst), 1);
+        assert_eq!(STALE_LINE.load(StdOrdering::SeqCst), line as usize);
+    }
+
+    #[test]
+    fn healthy_spin_loop_is_silent() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        set_misuse_handler(record);
+        set_staleness_thresholds(4, 2);
+        STALE_REPORTS.store(0, StdOrdering::SeqCst);
+
+        let a = Atomic::new(0u32);
+        a.enable_staleness_check();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=10_000 {
+                    a.store(i, Relaxed);
+                }
+            });
+            while a.load(Relaxed) != 10_000 {}
+        });
+        a.disable_staleness_check();
+
+        assert_eq!(STALE_REPORTS.load(StdOrdering::SeqCst), 0);
+    }
+
+    #[test]
+    fn saturated_ops_do_not_store() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+
+        let a = Atomic::new(u32::MAX - 2);
+        a.enable_staleness_check();
+        for _ in 0..100 {
+            a.fetch_saturating_add(1, Relaxed);
+        }
+        assert_eq!(modifications(a.as_ptr() as usize), 2);
+        a.fetch_saturating_sub(0, Relaxed);
+        assert_eq!(modifications(a.as_ptr() as usize), 2);
+        a.disable_staleness_check();
+
+        // u128 takes the fallback path.
+        let b = Atomic::new(3u128);
+        b.enable_staleness_check();
+        for _ in 0..100 {
+            b.fetch_saturating_sub(1, Relaxed);
+        }
+        assert_eq!(modifications(b.as_ptr() as usize), 3);
+        b.disable_staleness_check();
+    }
+
+    #[test]
+    fn clamp_in_range_does_not_store() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+
+        let a = Atomic::new(5i64);
+        a.enable_staleness_check();
+        for _ in 0..100 {
+            a.fetch_clamp(0, 10, Relaxed);
+            a.fetch_clamp(5, 5, Relaxed);
+        }
+        assert_eq!(modifications(a.as_ptr() as usize), 0);
+        a.fetch_clamp(6, 10, Relaxed);
+        assert_eq!(modifications(a.as_ptr() as usize), 1);
+        a.disable_staleness_check();
+    }
+
+    #[test]
+    fn store_max_min_dominated_does_not_store() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+
+        let a = Atomic::new(5u16);
+        a.enable_staleness_check();
+        for _ in 0..100 {
+            a.store_max(5, Relaxed);
+            a.store_max(1, Relaxed);
+            a.store_min(5, Relaxed);
+            a.store_min(9, Relaxed);
+        }
+        assert_eq!(modifications(a.as_ptr() as usize), 0);
+        a.store_max(6, Relaxed);
+        assert_eq!(modifications(a.as_ptr() as usize), 1);
+        a.disable_staleness_check();
+    }
+}
@@ -0,0 +1,339 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use ops;
+use Atomic;
+use Ordering;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A type which can be stored in an [`AtomicStamped`].
+///
+/// This trait is sealed. Types of up to 4 bytes are packed with a `u32` stamp
+/// into a `u64`, and 8-byte types with a `u64` stamp into a `u128`. It is
+/// implemented for the primitive integers and floats of up to 8 bytes, `bool`
+/// and `char`.
+pub trait StampedValue: Copy + sealed::Sealed {
+    /// The type of the stamp stored alongside the value.
+    type Stamp: Copy + Eq + Default + fmt::Debug;
+    #[doc(hidden)]
+    type Word: Copy;
+    #[doc(hidden)]
+    fn pack(self, stamp: Self::Stamp) -> Self::Word;
+    #[doc(hidden)]
+    fn unpack(word: Self::Word) -> (Self, Self::Stamp);
+    #[doc(hidden)]
+    fn next_stamp(stamp: Self::Stamp) -> Self::Stamp;
+}
+
+// The value is stored in the low half of the word as the unsigned integer of
+// the same size, and the stamp in the high half. A word is only ever built by
+// `pack`, so the bits of the value are always valid for its type.
+macro_rules! stamped_value {
+    ($word:ident $stamp:ident $shift:expr; $($t:ident $bits:ident)*) => ($(
+        impl sealed::Sealed for $t {}
+
+        impl StampedValue for $t {
+            type Stamp = $stamp;
+            type Word = $word;
+
+            #[inline]
+            fn pack(self, stamp: $stamp) -> $word {
+                let bits: $bits = unsafe { ops::transmute_bits(self) };
+                bits as $word | ($word::from(stamp) << $shift)
+            }
+
+            #[inline]
+            fn unpack(word: $word) -> ($t, $stamp) {
+                let value = unsafe { ops::transmute_bits(word as $bits) };
+                (value, (word >> $shift) as $stamp)
+            }
+
+            #[inline]
+            fn next_stamp(stamp: $stamp) -> $stamp {
+                stamp.wrapping_add(1)
+            }
+        }
+    )*);
+}
+
+stamped_value!(u64 u32 32; u8 u8 i8 u8 bool u8 u16 u16 i16 u16 u32 u32 i32 u32 f32 u32 char u32);
+stamped_value!(u128 u64 64; u64 u64 i64 u64 f64 u64);
+#[cfg(target_pointer_width = "32")]
+stamped_value!(u64 u32 32; usize u32 isize u32);
+#[cfg(target_pointer_width = "64")]
+stamped_value!(u128 u64 64; usize u64 isize u64);
+
+/// A value paired with a stamp which is incremented by every modification.
+///
+/// Comparing the stamp as well as the value lets `compare_exchange` detect
+/// that the value was changed and then changed back since it was loaded (the
+/// ABA problem), which a plain compare-exchange of the value would miss.
+///
+/// The value and the stamp are packed into a single word: a value of up to 4
+/// bytes with a `u32` stamp into 8 bytes, and an 8-byte value with a `u64`
+/// stamp into 16 bytes. Where the target has no lock-free atomics of that
+/// size, the operations go through the fallback lock like those of any other
+/// `Atomic`, which `is_lock_free` reports.
+///
+/// The stamp wraps around after 2<sup>32</sup> or 2<sup>64</sup>
+/// modifications, so a change is only missed if the value is modified exactly
+/// a multiple of that many times between a load and a compare-exchange.
+///
+/// Values are compared by their bits, so `0.0` and `-0.0` are different
+/// values while a NaN is equal to itself.
+pub struct AtomicStamped<T: StampedValue> {
+    word: Atomic<T::Word>,
+}
+
+impl<T: StampedValue + Default> Default for AtomicStamped<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: StampedValue + fmt::Debug> fmt::Debug for AtomicStamped<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (value, stamp) = self.load(Ordering::SeqCst);
+        f.debug_struct("AtomicStamped")
+            .field("value", &value)
+            .field("stamp", &stamp)
+            .finish()
+    }
+}
+
+impl<T: StampedValue> AtomicStamped<T> {
+    /// Creates a new `AtomicStamped` with a stamp of zero.
+    #[inline]
+    pub fn new(value: T) -> AtomicStamped<T> {
+        Self::with_stamp(value, T::Stamp::default())
+    }
+
+    /// Creates a new `AtomicStamped` with the given stamp.
+    #[inline]
+    pub fn with_stamp(value: T, stamp: T::Stamp) -> AtomicStamped<T> {
+        AtomicStamped {
+            word: Atomic::new(value.pack(stamp)),
+        }
+    }
+
+    /// Checks if the operations on this type are lock-free.
+    #[inline]
+    pub fn is_lock_free() -> bool {
+        Atomic::<T::Word>::is_lock_free()
+    }
+
+    /// Loads the value along with its stamp.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> (T, T::Stamp) {
+        T::unpack(self.word.load(order))
+    }
+
+    /// Stores a value, incrementing the stamp.
+    #[inline]
+    pub fn store(&self, value: T, order: Ordering) {
+        self.swap(value, order);
+    }
+
+    /// Stores a value, incrementing the stamp, and returns the previous value
+    /// along with its stamp.
+    #[inline]
+    pub fn swap(&self, value: T, order: Ordering) -> (T, T::Stamp) {
+        let mut current = self.word.load(Ordering::Relaxed);
+        loop {
+            let (_, stamp) = T::unpack(current);
+            let new = value.pack(T::next_stamp(stamp));
+            match self
+                .word
+                .compare_exchange_weak(current, new, order, Ordering::Relaxed)
+            {
+                Ok(_) => return T::unpack(current),
+                Err(x) => current = x,
+            }
+        }
+    }
+
+    /// Stores `new` if the value and the stamp are both equal to `current`,
+    /// incrementing the stamp.
+    ///
+    /// The return value is a result indicating whether the new value was
+    /// written, and containing the previous value and stamp.
+    #[inline]
+    pub fn compare_exchange(
+        &self,
+        current: (T, T::Stamp),
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(T, T::Stamp), (T, T::Stamp)> {
+        self.word
+            .compare_exchange(
+                current.0.pack(current.1),
+                new.pack(T::next_stamp(current.1)),
+                success,
+                failure,
+            )
+            .map(T::unpack)
+            .map_err(T::unpack)
+    }
+
+    /// Stores `new` if the value and the stamp are both equal to `current`,
+    /// incrementing the stamp.
+    ///
+    /// Unlike `compare_exchange`, this function is allowed to spuriously fail
+    /// even when the comparison succeeds, which can result in more efficient
+    /// code on some platforms.
+    #[inline]
+    pub fn compare_exchange_weak(
+        &self,
+        current: (T, T::Stamp),
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(T, T::Stamp), (T, T::Stamp)> {
+        self.word
+            .compare_exchange_weak(
+                current.0.pack(current.1),
+                new.pack(T::next_stamp(current.1)),
+                success,
+                failure,
+            )
+            .map(T::unpack)
+            .map_err(T::unpack)
+    }
+
+    /// Consumes the cell and returns the contained value and stamp.
+    #[inline]
+    pub fn into_inner(self) -> (T, T::Stamp) {
+        T::unpack(self.word.into_inner())
+    }
+}
+
+#[cfg(all(test, not(any(loom, shuttle))))]
+mod tests {
+    use super::AtomicStamped;
+    use std::thread;
+    use Atomic;
+    use Ordering::*;
+
+    #[test]
+    fn stamped() {
+        let a = AtomicStamped::new(5u32);
+        assert_eq!(a.load(SeqCst), (5, 0));
+        a.store(5, SeqCst);
+        assert_eq!(a.load(SeqCst), (5, 1));
+        assert_eq!(a.swap(7, SeqCst), (5, 1));
+        assert_eq!(a.compare_exchange((7, 1), 8, SeqCst, SeqCst), Err((7, 2)));
+        assert_eq!(a.compare_exchange((7, 2), 8, SeqCst, SeqCst), Ok((7, 2)));
+        assert_eq!(format!("{:?}", a), "AtomicStamped { value: 8, stamp: 3 }");
+        assert_eq!(a.into_inner(), (8, 3));
+
+        let b = AtomicStamped::<bool>::default();
+        assert_eq!(b.swap(true, SeqCst), (false, 0));
+        assert_eq!(b.load(SeqCst), (true, 1));
+
+        let c = AtomicStamped::new('a');
+        c.store('é', SeqCst);
+        assert_eq!(c.load(SeqCst), ('é', 1));
+
+        let d = AtomicStamped::new(-1i16);
+        assert_eq!(d.load(SeqCst), (-1, 0));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn wide_values() {
+        let a = AtomicStamped::with_stamp(u64::MAX, u64::MAX - 1);
+        assert_eq!(a.swap(1, SeqCst), (u64::MAX, u64::MAX - 1));
+        assert_eq!(a.load(SeqCst), (1, u64::MAX));
+        assert_eq!(
+            a.compare_exchange((1, u64::MAX), 2, SeqCst, SeqCst),
+            Ok((1, u64::MAX))
+        );
+        assert_eq!(a.load(SeqCst), (2, 0));
+        assert_eq!(
+            AtomicStamped::<u64>::is_lock_free(),
+            Atomic::<u128>::is_lock_free()
+        );
+
+        let f = AtomicStamped::new(0.0f64);
+        assert_eq!(
+            f.compare_exchange((-0.0, 0), 1.0, SeqCst, SeqCst),
+            Err((0.0, 0))
+        );
+        assert_eq!(
+            f.compare_exchange((0.0, 0), 1.5, SeqCst, SeqCst),
+            Ok((0.0, 0))
+        );
+        assert_eq!(f.load(SeqCst), (1.5, 1));
+    }
+
+    #[test]
+    fn stamp_wraps() {
+        let a = AtomicStamped::with_stamp(1u8, u32::MAX);
+        a.store(2, SeqCst);
+        assert_eq!(a.load(SeqCst), (2, 0));
+        assert_eq!(a.compare_exchange((2, 0), 3, SeqCst, SeqCst), Ok((2, 0)));
+        assert_eq!(a.load(SeqCst), (3, 1));
+    }
+
+    // The value goes from A to B and back to A between a load and a
+    // compare-exchange. A plain compare-exchange can't tell, while the stamp
+    // has moved on.
+    #[test]
+    fn detects_aba() {
+        let plain = Atomic::new(1u32);
+        let stamped = AtomicStamped::new(1u32);
+        let seen = plain.load(SeqCst);
+        let seen_stamped = stamped.load(SeqCst);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                plain.store(2, SeqCst);
+                plain.store(1, SeqCst);
+                stamped.store(2, SeqCst);
+                stamped.store(1, SeqCst);
+            });
+        });
+
+        assert_eq!(plain.compare_exchange(seen, 3, SeqCst, SeqCst), Ok(1));
+        assert_eq!(
+            stamped.compare_exchange(seen_stamped, 3, SeqCst, SeqCst),
+            Err((1, 2))
+        );
+        assert_eq!(
+            stamped.compare_exchange((1, 2), 3, SeqCst, SeqCst),
+            Ok((1, 2))
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-fallback"))]
+    fn concurrent_increments() {
+        let a = AtomicStamped::new(0u64);
+        let rounds = if cfg!(miri) { 100 } else { 10_000 };
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..rounds {
+                        let mut current = a.load(Relaxed);
+                        while let Err(x) =
+                            a.compare_exchange_weak(current, current.0 + 1, AcqRel, Relaxed)
+                        {
+                            current = x;
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(a.load(SeqCst), (4 * rounds, 4 * rounds));
+    }
+}
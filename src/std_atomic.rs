@@ -0,0 +1,189 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Conversions between `Atomic<T>` and the standard atomic types.
+//
+// The by-value conversions exist whenever the standard type does. Converting
+// references relies on `Atomic<T>` having the layout of `T`, and the standard
+// types having the layout of their integer except for alignment: a reference
+// to a standard atomic can always be turned into one to an `Atomic`, but the
+// other way only works if the two have the same alignment. That is not the
+// case for 64-bit integers on 32-bit x86, for example.
+//
+// Both views use the same native instructions, since the types involved are
+// only ever lock-free (the 64-bit one on 32-bit x86 is lock-free at addresses
+// where `AtomicU64` can be, see `ops::atomic_is_lock_free_at`). With
+// `--cfg force_fallback` every `Atomic` takes the fallback lock, so the
+// reference conversions don't exist.
+
+use core::sync::atomic;
+use Atomic;
+
+macro_rules! std_atomic {
+    ($t:ty, $std:ident, $has:meta, $aligned:meta) => {
+        #[cfg($has)]
+        impl Atomic<$t> {
+            #[doc = concat!("Returns the `Atomic<", stringify!($t), ">` view of an `", stringify!($std), "`.")]
+            ///
+            /// Operations through either reference are atomic with respect to
+            /// each other.
+            #[doc = include_str!("doc_examples/from_std.md")]
+            #[inline]
+            #[cfg(not(any(loom, shuttle, force_fallback)))]
+            pub fn from_std(v: &atomic::$std) -> &Atomic<$t> {
+                unsafe { &*(v as *const atomic::$std as *const Atomic<$t>) }
+            }
+
+            #[doc = concat!("Returns the `", stringify!($std), "` view of this `Atomic`.")]
+            ///
+            /// This is only available on targets where both types have the
+            /// same alignment. Operations through either reference are atomic
+            /// with respect to each other.
+            #[doc = include_str!("doc_examples/as_std.md")]
+            #[inline]
+            #[cfg(all($aligned, not(any(loom, shuttle, force_fallback))))]
+            pub fn as_std(&self) -> &atomic::$std {
+                unsafe { &*(self.as_ptr() as *const atomic::$std) }
+            }
+        }
+
+        #[cfg($has)]
+        impl From<Atomic<$t>> for atomic::$std {
+            #[inline]
+            fn from(a: Atomic<$t>) -> Self {
+                atomic::$std::new(a.into_inner())
+            }
+        }
+
+        #[cfg($has)]
+        impl From<atomic::$std> for Atomic<$t> {
+            #[inline]
+            fn from(a: atomic::$std) -> Self {
+                Atomic::new(a.into_inner())
+            }
+        }
+    };
+}
+
+std_atomic!(bool, AtomicBool, has_atomic_8, has_atomic_equal_alignment_8);
+std_atomic!(u8, AtomicU8, has_atomic_8, has_atomic_equal_alignment_8);
+std_atomic!(i8, AtomicI8, has_atomic_8, has_atomic_equal_alignment_8);
+std_atomic!(u16, AtomicU16, has_atomic_16, has_atomic_equal_alignment_16);
+std_atomic!(i16, AtomicI16, has_atomic_16, has_atomic_equal_alignment_16);
+std_atomic!(u32, AtomicU32, has_atomic_32, has_atomic_equal_alignment_32);
+std_atomic!(i32, AtomicI32, has_atomic_32, has_atomic_equal_alignment_32);
+std_atomic!(u64, AtomicU64, has_atomic_64, has_atomic_equal_alignment_64);
+std_atomic!(i64, AtomicI64, has_atomic_64, has_atomic_equal_alignment_64);
+std_atomic!(usize, AtomicUsize, has_atomic_ptr, has_atomic_equal_alignment_ptr);
+std_atomic!(isize, AtomicIsize, has_atomic_ptr, has_atomic_equal_alignment_ptr);
+
+#[cfg(has_atomic_ptr)]
+impl<T> Atomic<*mut T> {
+    /// Returns the `Atomic<*mut T>` view of an `AtomicPtr<T>`.
+    ///
+    /// Operations through either reference are atomic with respect to each
+    /// other.
+    #[inline]
+    #[cfg(not(any(loom, shuttle, force_fallback)))]
+    pub fn from_std(v: &atomic::AtomicPtr<T>) -> &Atomic<*mut T> {
+        unsafe { &*(v as *const atomic::AtomicPtr<T> as *const Atomic<*mut T>) }
+    }
+
+    /// Returns the `AtomicPtr<T>` view of this `Atomic`.
+    ///
+    /// Operations through either reference are atomic with respect to each
+    /// other.
+    #[inline]
+    #[cfg(all(has_atomic_equal_alignment_ptr, not(any(loom, shuttle, force_fallback))))]
+    pub fn as_std(&self) -> &atomic::AtomicPtr<T> {
+        unsafe { &*(self.as_ptr() as *const atomic::AtomicPtr<T>) }
+    }
+}
+
+#[cfg(has_atomic_ptr)]
+impl<T> From<Atomic<*mut T>> for atomic::AtomicPtr<T> {
+    #[inline]
+    fn from(a: Atomic<*mut T>) -> Self {
+        atomic::AtomicPtr::new(a.into_inner())
+    }
+}
+
+#[cfg(has_atomic_ptr)]
+impl<T> From<atomic::AtomicPtr<T>> for Atomic<*mut T> {
+    #[inline]
+    fn from(a: atomic::AtomicPtr<T>) -> Self {
+        Atomic::new(a.into_inner())
+    }
+}
+
+#[cfg(all(test, not(any(loom, shuttle, force_fallback))))]
+mod tests {
+    use core::ptr;
+    use core::sync::atomic::Ordering::*;
+    #[cfg(all(has_atomic_64, has_atomic_equal_alignment_64))]
+    use core::sync::atomic::AtomicI64;
+    use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize};
+    use std::thread;
+    use Atomic;
+
+    #[test]
+    fn reference_views() {
+        let a = Atomic::new(5u32);
+        a.as_std().fetch_add(3, SeqCst);
+        assert_eq!(a.load(SeqCst), 8);
+        a.fetch_sub(1, SeqCst);
+        assert_eq!(a.as_std().load(SeqCst), 7);
+
+        let s = AtomicBool::new(false);
+        Atomic::<bool>::from_std(&s).store(true, SeqCst);
+        assert!(s.load(SeqCst));
+        s.store(false, SeqCst);
+        assert!(!Atomic::<bool>::from_std(&s).load(SeqCst));
+
+        #[cfg(all(has_atomic_64, has_atomic_equal_alignment_64))]
+        {
+            let s = AtomicI64::new(-1);
+            assert_eq!(Atomic::<i64>::from_std(&s).fetch_min(-10, SeqCst), -1);
+            assert_eq!(s.load(SeqCst), -10);
+        }
+
+        let mut x = 3;
+        let p = Atomic::new(ptr::null_mut::<i32>());
+        p.as_std().store(&mut x, SeqCst);
+        assert_eq!(unsafe { *p.load(SeqCst) }, 3);
+    }
+
+    #[test]
+    fn concurrent_views() {
+        let s = AtomicUsize::new(0);
+        let a = Atomic::<usize>::from_std(&s);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..1000 {
+                    s.fetch_add(1, Relaxed);
+                }
+            });
+            scope.spawn(|| {
+                for _ in 0..1000 {
+                    a.fetch_add(1, Relaxed);
+                }
+            });
+        });
+        assert_eq!(a.load(SeqCst), 2000);
+    }
+
+    #[test]
+    fn by_value() {
+        let s: AtomicU32 = Atomic::new(7u32).into();
+        assert_eq!(s.load(SeqCst), 7);
+        let a: Atomic<u32> = s.into();
+        assert_eq!(a.into_inner(), 7);
+        let mut x = 1u8;
+        let p: AtomicPtr<u8> = Atomic::new(&mut x as *mut u8).into();
+        assert_eq!(Atomic::<*mut u8>::from(p).into_inner(), &mut x as *mut u8);
+    }
+}
@@ -0,0 +1,332 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+use core::mem;
+use core::ptr;
+use ops;
+use Atomic;
+use Ordering;
+
+/// An atomic pointer with a small tag packed into its low bits.
+///
+/// The low bits of a pointer to `T` are always zero because of its alignment,
+/// so `TAG_BITS` of them (`log2(align_of::<T>())`) are used to store a tag
+/// alongside the pointer. The pointer and the tag are updated together in a
+/// single atomic operation, which is typically used to attach a version
+/// counter to the head of a lock-free stack to guard against the ABA problem.
+///
+/// The pointer keeps its provenance while it is tagged.
+pub struct AtomicTaggedPtr<T> {
+    p: Atomic<*mut T>,
+}
+
+// Like `AtomicPtr`, an `AtomicTaggedPtr` can be shared freely since
+// dereferencing the pointer it returns is unsafe.
+unsafe impl<T> Send for AtomicTaggedPtr<T> {}
+unsafe impl<T> Sync for AtomicTaggedPtr<T> {}
+
+impl<T> Default for AtomicTaggedPtr<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(ptr::null_mut(), 0)
+    }
+}
+
+impl<T> fmt::Debug for AtomicTaggedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (ptr, tag) = self.load(Ordering::SeqCst);
+        f.debug_struct("AtomicTaggedPtr")
+            .field("ptr", &ptr)
+            .field("tag", &tag)
+            .finish()
+    }
+}
+
+impl<T> AtomicTaggedPtr<T> {
+    /// The number of tag bits available.
+    pub const TAG_BITS: u32 = mem::align_of::<T>().trailing_zeros();
+
+    /// The largest tag which can be stored.
+    pub const MAX_TAG: usize = mem::align_of::<T>() - 1;
+
+    /// Creates a new `AtomicTaggedPtr`.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if `ptr` is not aligned for `T` or if `tag` is
+    /// larger than `MAX_TAG`.
+    #[inline]
+    pub fn new(ptr: *mut T, tag: usize) -> AtomicTaggedPtr<T> {
+        AtomicTaggedPtr {
+            p: Atomic::new(Self::compose(ptr, tag)),
+        }
+    }
+
+    #[inline]
+    fn compose(ptr: *mut T, tag: usize) -> *mut T {
+        debug_assert!(
+            ptr as usize & Self::MAX_TAG == 0,
+            "pointer is not aligned for its type"
+        );
+        debug_assert!(
+            tag <= Self::MAX_TAG,
+            "tag does not fit in the alignment bits"
+        );
+        ptr.wrapping_byte_add(tag)
+    }
+
+    #[inline]
+    fn decompose(p: *mut T) -> (*mut T, usize) {
+        let tag = p as usize & Self::MAX_TAG;
+        (p.wrapping_byte_sub(tag), tag)
+    }
+
+    /// Loads the pointer and the tag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> (*mut T, usize) {
+        Self::decompose(self.p.load(order))
+    }
+
+    /// Stores a pointer and a tag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`. In debug builds, also
+    /// panics if `ptr` is not aligned for `T` or if `tag` is larger than
+    /// `MAX_TAG`.
+    #[inline]
+    pub fn store(&self, ptr: *mut T, tag: usize, order: Ordering) {
+        self.p.store(Self::compose(ptr, tag), order)
+    }
+
+    /// Stores a pointer and a tag, returning the previous ones.
+    #[inline]
+    pub fn swap(&self, ptr: *mut T, tag: usize, order: Ordering) -> (*mut T, usize) {
+        Self::decompose(self.p.swap(Self::compose(ptr, tag), order))
+    }
+
+    /// Stores `new` if the current pointer and tag are both equal to
+    /// `current`.
+    ///
+    /// The return value is a result indicating whether the new value was
+    /// written and containing the previous pointer and tag.
+    #[inline]
+    pub fn compare_exchange(
+        &self,
+        current: (*mut T, usize),
+        new: (*mut T, usize),
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(*mut T, usize), (*mut T, usize)> {
+        match self.p.compare_exchange(
+            Self::compose(current.0, current.1),
+            Self::compose(new.0, new.1),
+            success,
+            failure,
+        ) {
+            Ok(p) => Ok(Self::decompose(p)),
+            Err(p) => Err(Self::decompose(p)),
+        }
+    }
+
+    /// Stores `new` if the current pointer and tag are both equal to
+    /// `current`.
+    ///
+    /// Unlike `compare_exchange`, this function is allowed to spuriously fail
+    /// even when the comparison succeeds, which can result in more efficient
+    /// code on some platforms.
+    #[inline]
+    pub fn compare_exchange_weak(
+        &self,
+        current: (*mut T, usize),
+        new: (*mut T, usize),
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(*mut T, usize), (*mut T, usize)> {
+        match self.p.compare_exchange_weak(
+            Self::compose(current.0, current.1),
+            Self::compose(new.0, new.1),
+            success,
+            failure,
+        ) {
+            Ok(p) => Ok(Self::decompose(p)),
+            Err(p) => Err(Self::decompose(p)),
+        }
+    }
+
+    /// Replaces the tag, leaving the pointer unchanged, and returns the
+    /// previous pointer and tag.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if `tag` is larger than `MAX_TAG`.
+    #[inline]
+    pub fn fetch_set_tag(&self, tag: usize, order: Ordering) -> (*mut T, usize) {
+        debug_assert!(
+            tag <= Self::MAX_TAG,
+            "tag does not fit in the alignment bits"
+        );
+        let p = unsafe {
+            ops::atomic_ptr_update(self.p.v.get(), order, |p| {
+                Self::decompose(p).0.wrapping_byte_add(tag)
+            })
+        };
+        Self::decompose(p)
+    }
+
+    /// Clears the tag, leaving the pointer unchanged, and returns the previous
+    /// pointer and tag.
+    #[inline]
+    pub fn fetch_clear_tag(&self, order: Ordering) -> (*mut T, usize) {
+        Self::decompose(self.p.fetch_and(!Self::MAX_TAG, order))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AtomicTaggedPtr;
+    use core::ptr;
+    use core::sync::atomic::AtomicPtr;
+    use std::thread;
+    use std::vec::Vec;
+    use Ordering::*;
+
+    #[test]
+    fn tagged_ptr() {
+        let mut x = 0u64;
+        let p = &mut x as *mut u64;
+        assert_eq!(AtomicTaggedPtr::<u64>::TAG_BITS, 3);
+        assert_eq!(AtomicTaggedPtr::<u64>::MAX_TAG, 7);
+        assert_eq!(AtomicTaggedPtr::<u8>::TAG_BITS, 0);
+
+        let a = AtomicTaggedPtr::new(p, 5);
+        assert_eq!(a.load(SeqCst), (p, 5));
+        assert_eq!(a.fetch_set_tag(2, SeqCst), (p, 5));
+        assert_eq!(a.fetch_clear_tag(SeqCst), (p, 2));
+        assert_eq!(a.swap(p, 7, SeqCst), (p, 0));
+        assert_eq!(
+            a.compare_exchange((p, 6), (ptr::null_mut(), 1), SeqCst, SeqCst),
+            Err((p, 7))
+        );
+        assert_eq!(
+            a.compare_exchange((p, 7), (p, 1), SeqCst, SeqCst),
+            Ok((p, 7))
+        );
+        a.store(p, 3, SeqCst);
+
+        // Writing through a loaded pointer is fine: the tag doesn't cost the
+        // pointer its provenance.
+        unsafe { *a.load(SeqCst).0 = 42 };
+        assert_eq!(x, 42);
+
+        let b = AtomicTaggedPtr::<u64>::default();
+        assert_eq!(b.load(SeqCst), (ptr::null_mut(), 0));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "tag does not fit in the alignment bits")]
+    fn tag_out_of_range() {
+        AtomicTaggedPtr::<u32>::new(ptr::null_mut(), 4);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "pointer is not aligned for its type")]
+    fn misaligned_pointer() {
+        let x = 0u32;
+        let p = (&x as *const u32 as *mut u32).wrapping_byte_add(1);
+        AtomicTaggedPtr::new(p, 0);
+    }
+
+    // The alignment leaves 6 tag bits for the version counter.
+    #[repr(align(64))]
+    struct Node {
+        next: AtomicPtr<Node>,
+    }
+
+    // A Treiber stack whose head carries a version counter in its tag. Nodes
+    // are constantly popped and pushed back, so without the counter a pop
+    // could succeed with a stale `next` pointer when the same node is back at
+    // the top of the stack.
+    struct Stack {
+        head: AtomicTaggedPtr<Node>,
+    }
+
+    impl Stack {
+        fn push(&self, node: *mut Node) {
+            let mut head = self.head.load(Relaxed);
+            loop {
+                unsafe { (*node).next.store(head.0, Relaxed) };
+                let new = (node, (head.1 + 1) & AtomicTaggedPtr::<Node>::MAX_TAG);
+                match self.head.compare_exchange_weak(head, new, Release, Relaxed) {
+                    Ok(_) => return,
+                    Err(x) => head = x,
+                }
+            }
+        }
+
+        fn pop(&self) -> Option<*mut Node> {
+            let mut head = self.head.load(Acquire);
+            loop {
+                if head.0.is_null() {
+                    return None;
+                }
+                let next = unsafe { (*head.0).next.load(Relaxed) };
+                let new = (next, (head.1 + 1) & AtomicTaggedPtr::<Node>::MAX_TAG);
+                match self.head.compare_exchange_weak(head, new, Acquire, Acquire) {
+                    Ok(_) => return Some(head.0),
+                    Err(x) => head = x,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn treiber_stack() {
+        const NODES: usize = 8;
+        let mut nodes: Vec<Node> = (0..NODES)
+            .map(|_| Node {
+                next: AtomicPtr::new(ptr::null_mut()),
+            })
+            .collect();
+        let base = nodes.as_mut_ptr();
+        let stack = Stack {
+            head: AtomicTaggedPtr::default(),
+        };
+        for i in 0..NODES {
+            stack.push(base.wrapping_add(i));
+        }
+
+        let rounds = if cfg!(miri) { 100 } else { 10_000 };
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..rounds {
+                        let a = stack.pop().unwrap();
+                        let b = stack.pop().unwrap();
+                        stack.push(a);
+                        stack.push(b);
+                    }
+                });
+            }
+        });
+
+        let mut seen = Vec::new();
+        while let Some(node) = stack.pop() {
+            seen.push(node as usize);
+        }
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), NODES);
+    }
+}
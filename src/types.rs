@@ -0,0 +1,109 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Aliases for atomic types which have no counterpart in `core::sync::atomic`.
+//!
+//! These are plain type aliases, so all of the methods are documented on the
+//! corresponding `Atomic<T>`. Whether they are lock-free depends on the
+//! target: the 128-bit types in particular go through the fallback lock
+//! unless the `nightly` feature is enabled on a target with 128-bit atomics.
+
+use Atomic;
+
+/// An `f32` which can be safely shared between threads.
+///
+/// Besides the operations every `Atomic<T>` has, like `fetch_update`, this
+/// supports `fetch_add`, `fetch_sub`, `fetch_mul`, `fetch_div`, `fetch_min`
+/// and `fetch_max`. The arithmetic is done with a compare-exchange loop on the
+/// bits of the value.
+///
+/// # Examples
+///
+/// ```
+/// use atomic::{AtomicF32, Ordering};
+///
+/// let level = AtomicF32::new(0.5);
+/// assert_eq!(level.fetch_add(0.25, Ordering::Relaxed), 0.5);
+/// assert_eq!(level.fetch_sub(1.0, Ordering::Relaxed), 0.75);
+/// assert_eq!(level.fetch_max(0.0, Ordering::Relaxed), -0.25);
+/// assert_eq!(level.fetch_min(-2.0, Ordering::Relaxed), 0.0);
+/// assert_eq!(level.fetch_mul(3.0, Ordering::Relaxed), -2.0);
+/// assert_eq!(level.fetch_div(-2.0, Ordering::Relaxed), -6.0);
+/// let halve = |x: f32| Some(x / 2.0);
+/// assert_eq!(level.fetch_update(Ordering::AcqRel, Ordering::Acquire, halve), Ok(3.0));
+/// assert_eq!(level.into_inner(), 1.5);
+/// ```
+pub type AtomicF32 = Atomic<f32>;
+
+/// An `f64` which can be safely shared between threads.
+///
+/// This supports the same operations as [`AtomicF32`].
+///
+/// # Examples
+///
+/// ```
+/// use atomic::{AtomicF64, Ordering};
+///
+/// let mean = AtomicF64::new(0.0);
+/// for sample in [1.0, 2.0, 6.0] {
+///     mean.fetch_add(sample / 3.0, Ordering::Relaxed);
+/// }
+/// assert_eq!(mean.load(Ordering::Relaxed), 3.0);
+/// assert_eq!(mean.fetch_min(f64::NAN, Ordering::Relaxed), 3.0);
+/// assert_eq!(mean.swap(-1.5, Ordering::Relaxed), 3.0);
+/// assert_eq!(mean.fetch_max(1.0, Ordering::Relaxed), -1.5);
+/// assert_eq!(mean.into_inner(), 1.0);
+/// ```
+pub type AtomicF64 = Atomic<f64>;
+
+/// A `u128` which can be safely shared between threads.
+///
+/// This supports the same operations as the other unsigned `Atomic<T>`
+/// integers, including `fetch_add`, `fetch_sub`, the bitwise operations,
+/// `fetch_min`, `fetch_max` and `fetch_update`.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(not(feature = "no-fallback"))] {
+/// use atomic::{AtomicU128, Ordering};
+///
+/// let ids = AtomicU128::new(u64::MAX as u128);
+/// assert_eq!(ids.fetch_add(1, Ordering::Relaxed), u64::MAX as u128);
+/// assert_eq!(ids.load(Ordering::Relaxed), 1 << 64);
+/// assert_eq!(ids.fetch_or(1, Ordering::Relaxed), 1 << 64);
+/// assert_eq!(ids.fetch_max(u128::MAX, Ordering::Relaxed), (1 << 64) + 1);
+/// let rotate = |x: u128| Some(x.rotate_left(1));
+/// assert_eq!(ids.fetch_update(Ordering::AcqRel, Ordering::Acquire, rotate), Ok(u128::MAX));
+/// assert_eq!(ids.fetch_min(7, Ordering::Relaxed), u128::MAX);
+/// assert_eq!(ids.into_inner(), 7);
+/// # }
+/// ```
+pub type AtomicU128 = Atomic<u128>;
+
+/// An `i128` which can be safely shared between threads.
+///
+/// This supports the same operations as the other signed `Atomic<T>`
+/// integers, including `fetch_add`, `fetch_sub`, the bitwise operations,
+/// `fetch_min`, `fetch_max` and `fetch_update`.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(not(feature = "no-fallback"))] {
+/// use atomic::{AtomicI128, Ordering};
+///
+/// let balance = AtomicI128::new(0);
+/// assert_eq!(balance.fetch_sub(i64::MAX as i128 * 4, Ordering::Relaxed), 0);
+/// assert_eq!(balance.fetch_min(0, Ordering::Relaxed), i64::MAX as i128 * -4);
+/// assert_eq!(balance.fetch_max(-1, Ordering::Relaxed), i64::MAX as i128 * -4);
+/// let withdraw = |x: i128| if x >= 10 { Some(x - 10) } else { None };
+/// assert_eq!(balance.fetch_update(Ordering::AcqRel, Ordering::Acquire, withdraw), Err(-1));
+/// assert_eq!(balance.into_inner(), -1);
+/// # }
+/// ```
+pub type AtomicI128 = Atomic<i128>;
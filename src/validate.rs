@@ -0,0 +1,461 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Validity checks of the values returned by loads, swaps, compare-exchanges
+// and updates, for the `validate` feature.
+//
+// The operations are performed on the memory as a `MaybeUninit<T>`, and its
+// bytes are checked before they become a `T`: checking a `bool` which has
+// already been loaded as one would be folded away by the optimizer, which
+// knows that it is 0 or 1. There is no way to specialize the operations for the
+// types which implement `AtomicValidate`, so the standard types are recognized
+// by name, and other types are looked up by name in the validators registered
+// with `register_validator`.
+
+use core::any::type_name;
+use core::mem::{self, MaybeUninit};
+use core::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU128,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize,
+};
+use core::ptr;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use ops;
+use std::sync::{PoisonError, RwLock};
+use std::vec::Vec;
+use PrimitiveInteger;
+
+/// A type whose bit patterns can be checked for validity, so that loads from
+/// an `Atomic` of it can detect corrupted values.
+///
+/// With the `validate` feature, the value returned by every load, swap,
+/// compare-exchange and update of an `Atomic<T>` is checked with `is_valid` before it is
+/// used as a `T`, and the operation panics with the offending bytes if it
+/// isn't valid. A value which isn't valid can only get into an `Atomic`
+/// through `as_ptr`, `from_ptr` or memory shared with other code, and using
+/// it would be undefined behavior far away from where it was written.
+///
+/// This is implemented for `bool`, `char` and the `NonZero` integers, which
+/// are always checked. Other types are checked once they are registered with
+/// `register_validator`. The `atomic_validate!` macro declares a fieldless
+/// enum together with an implementation of this trait.
+///
+/// # Safety
+///
+/// `is_valid` must return `false` for every bit pattern which isn't a valid
+/// value of `Self`, since the bytes are used as a `Self` when it returns
+/// `true`. It is given all the bytes of the value, so `Self` must not have
+/// any padding.
+pub unsafe trait AtomicValidate: Copy {
+    /// Returns whether `bytes`, which has the size of `Self`, is a valid value
+    /// of `Self`.
+    fn is_valid(bytes: &[u8]) -> bool;
+}
+
+unsafe impl AtomicValidate for bool {
+    #[inline]
+    fn is_valid(bytes: &[u8]) -> bool {
+        matches!(bytes, [0] | [1])
+    }
+}
+
+unsafe impl AtomicValidate for char {
+    #[inline]
+    fn is_valid(bytes: &[u8]) -> bool {
+        match *bytes {
+            [a, b, c, d] => char::from_u32(u32::from_ne_bytes([a, b, c, d])).is_some(),
+            _ => false,
+        }
+    }
+}
+
+macro_rules! non_zero {
+    ($($t:ty)*) => ($(
+        unsafe impl AtomicValidate for $t {
+            #[inline]
+            fn is_valid(bytes: &[u8]) -> bool {
+                bytes.len() == mem::size_of::<$t>() && bytes.iter().any(|&b| b != 0)
+            }
+        }
+    )*);
+}
+non_zero!(NonZeroU8 NonZeroU16 NonZeroU32 NonZeroU64 NonZeroU128 NonZeroUsize);
+non_zero!(NonZeroI8 NonZeroI16 NonZeroI32 NonZeroI64 NonZeroI128 NonZeroIsize);
+
+type Validator = fn(&[u8]) -> bool;
+
+static VALIDATORS: RwLock<Vec<(&str, Validator)>> = RwLock::new(Vec::new());
+
+// Number of registered validators, used to skip the lock entirely when there
+// are none.
+static REGISTERED: AtomicUsize = AtomicUsize::new(0);
+
+/// Checks the values of every `Atomic<T>` with `T::is_valid`, with the
+/// `validate` feature.
+///
+/// `bool`, `char` and the `NonZero` integers are always checked. Other types
+/// which implement `AtomicValidate` are only checked once they have been
+/// registered, which is usually done at the start of `main`. Registering a
+/// type again does nothing.
+#[doc = include_str!("doc_examples/register_validator.md")]
+pub fn register_validator<T: AtomicValidate>() {
+    let name = type_name::<T>();
+    let mut validators = VALIDATORS.write().unwrap_or_else(PoisonError::into_inner);
+    if validators.iter().all(|&(n, _)| n != name) {
+        validators.push((name, T::is_valid));
+        REGISTERED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn validator<T>() -> Option<Validator> {
+    let name = type_name::<T>();
+    macro_rules! standard {
+        ($($t:ty)*) => ($(
+            if mem::size_of::<T>() == mem::size_of::<$t>() && name == type_name::<$t>() {
+                return Some(<$t as AtomicValidate>::is_valid);
+            }
+        )*);
+    }
+    standard!(bool char);
+    standard!(NonZeroU8 NonZeroU16 NonZeroU32 NonZeroU64 NonZeroU128 NonZeroUsize);
+    standard!(NonZeroI8 NonZeroI16 NonZeroI32 NonZeroI64 NonZeroI128 NonZeroIsize);
+    if REGISTERED.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    let validators = VALIDATORS.read().unwrap_or_else(PoisonError::into_inner);
+    validators
+        .iter()
+        .find(|&&(n, _)| n == name)
+        .map(|&(_, is_valid)| is_valid)
+}
+
+#[inline]
+#[track_caller]
+fn check<T>(val: MaybeUninit<T>) -> T {
+    if let Some(is_valid) = validator::<T>() {
+        let bytes =
+            unsafe { slice::from_raw_parts(val.as_ptr() as *const u8, mem::size_of::<T>()) };
+        assert!(
+            is_valid(bytes),
+            "Atomic<{}> contains an invalid value: {:02x?}",
+            type_name::<T>(),
+            bytes
+        );
+    }
+    unsafe { val.assume_init() }
+}
+
+#[inline]
+fn uninit<T>(dst: *mut T) -> *mut MaybeUninit<T> {
+    dst as *mut MaybeUninit<T>
+}
+
+// The operations of `ops` which return values, with the values checked.
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_load<T>(dst: *mut T, order: Ordering) -> T {
+    check(ops::atomic_load(uninit(dst), order))
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_load_consume<T>(dst: *mut T) -> T {
+    check(ops::atomic_load_consume(uninit(dst)))
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_try_load<T>(dst: *mut T, order: Ordering) -> Option<T> {
+    ops::atomic_try_load(uninit(dst), order).map(check)
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_swap<T>(dst: *mut T, val: T, order: Ordering) -> T {
+    check(ops::atomic_swap(uninit(dst), MaybeUninit::new(val), order))
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_try_swap<T>(dst: *mut T, val: T, order: Ordering) -> Result<T, T> {
+    match ops::atomic_try_swap(uninit(dst), MaybeUninit::new(val), order) {
+        Ok(prev) => Ok(check(prev)),
+        // The value which couldn't be stored is the caller's own.
+        Err(val) => Err(val.assume_init()),
+    }
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_compare_exchange<T>(
+    dst: *mut T,
+    current: T,
+    new: T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    match ops::atomic_compare_exchange(
+        uninit(dst),
+        MaybeUninit::new(current),
+        MaybeUninit::new(new),
+        success,
+        failure,
+    ) {
+        Ok(prev) => Ok(check(prev)),
+        Err(prev) => Err(check(prev)),
+    }
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_compare_exchange_weak<T>(
+    dst: *mut T,
+    current: T,
+    new: T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    match ops::atomic_compare_exchange_weak(
+        uninit(dst),
+        MaybeUninit::new(current),
+        MaybeUninit::new(new),
+        success,
+        failure,
+    ) {
+        Ok(prev) => Ok(check(prev)),
+        Err(prev) => Err(check(prev)),
+    }
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_compare_exchange_ref<T: Copy>(
+    dst: *mut T,
+    current: &T,
+    new: &T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    match ops::atomic_compare_exchange_ref(
+        uninit(dst),
+        &MaybeUninit::new(*current),
+        &MaybeUninit::new(*new),
+        success,
+        failure,
+    ) {
+        Ok(prev) => Ok(check(prev)),
+        Err(prev) => Err(check(prev)),
+    }
+}
+
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_compare_exchange_in_place<T: Copy>(
+    dst: *mut T,
+    current: &mut T,
+    new: &T,
+    success: Ordering,
+    failure: Ordering,
+) -> bool {
+    let mut prev = MaybeUninit::new(*current);
+    let stored = ops::atomic_compare_exchange_in_place(
+        uninit(dst),
+        &mut prev,
+        &MaybeUninit::new(*new),
+        success,
+        failure,
+    );
+    *current = check(prev);
+    stored
+}
+
+// Every value which is returned has been passed to `f` first, so it has
+// already been checked.
+#[inline]
+#[track_caller]
+pub unsafe fn atomic_fetch_update<T: Copy, F: FnMut(T) -> Option<T>>(
+    dst: *mut T,
+    set_order: Ordering,
+    fetch_order: Ordering,
+    mut f: F,
+) -> Result<T, T> {
+    let f = |prev| f(check(prev)).map(MaybeUninit::new);
+    match ops::atomic_fetch_update(uninit(dst), set_order, fetch_order, f) {
+        Ok(prev) => Ok(prev.assume_init()),
+        Err(prev) => Err(prev.assume_init()),
+    }
+}
+
+// Used by `atomic_validate!`, where the representation of the enum is known.
+#[doc(hidden)]
+pub fn is_discriminant<R: PrimitiveInteger>(bytes: &[u8], discriminants: &[R]) -> bool {
+    // Every bit pattern is a valid primitive integer.
+    bytes.len() == mem::size_of::<R>()
+        && discriminants.contains(&unsafe { ptr::read_unaligned(bytes.as_ptr() as *const R) })
+}
+
+/// Declares a fieldless enum which implements `AtomicValidate`.
+///
+/// The enum is written as usual, except that its representation follows its
+/// name, like `enum State: u8 { ... }`, and becomes its `#[repr]`. A value is
+/// valid if it is the discriminant of one of the variants. The enum still
+/// needs to be registered with `register_validator` for its values to be
+/// checked.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate atomic;
+///
+/// atomic_validate! {
+///     #[derive(Copy, Clone, Debug, PartialEq)]
+///     pub enum State: u8 {
+///         Idle,
+///         Busy = 4,
+///         Done,
+///     }
+/// }
+///
+/// fn main() {
+///     use atomic::AtomicValidate;
+///
+///     assert!(State::is_valid(&[5]));
+///     assert!(!State::is_valid(&[1]));
+/// }
+/// ```
+#[macro_export]
+macro_rules! atomic_validate {
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident: $repr:ident {
+            $($(#[$variant_attr:meta])* $variant:ident $(= $value:expr)?),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[repr($repr)]
+        $vis enum $name {
+            $($(#[$variant_attr])* $variant $(= $value)?,)*
+        }
+
+        unsafe impl $crate::AtomicValidate for $name {
+            fn is_valid(bytes: &[u8]) -> bool {
+                $crate::__is_discriminant::<$repr>(bytes, &[$($name::$variant as $repr),*])
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::register_validator;
+    use core::num::NonZeroU32;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::string::String;
+    use Atomic;
+    use Ordering::*;
+
+    atomic_validate! {
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        enum State: u16 {
+            Idle,
+            Busy = 7,
+            Done,
+        }
+    }
+
+    // The panic message of `f`.
+    fn panic_message<F: FnOnce()>(f: F) -> String {
+        let payload = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_err();
+        *payload.downcast::<String>().unwrap()
+    }
+
+    #[test]
+    fn valid_values() {
+        let a = Atomic::new(false);
+        assert!(!a.swap(true, SeqCst));
+        assert_eq!(a.compare_exchange(false, true, SeqCst, SeqCst), Err(true));
+        assert_eq!(a.try_load(SeqCst), Some(true));
+        let c = Atomic::new('a');
+        while c.compare_exchange_weak('a', 'é', SeqCst, SeqCst).is_err() {}
+        assert_eq!(c.load_consume(), 'é');
+        let n = Atomic::new(NonZeroU32::new(1).unwrap());
+        assert_eq!(n.load(SeqCst).get(), 1);
+
+        register_validator::<State>();
+        register_validator::<State>();
+        let s = Atomic::new(State::Idle);
+        s.store(State::Done, SeqCst);
+        assert_eq!(s.try_swap(State::Busy, SeqCst), Ok(State::Done));
+        assert_eq!(s.load(SeqCst), State::Busy);
+    }
+
+    #[test]
+    fn invalid_bool() {
+        let a = Atomic::new(false);
+        unsafe { *(a.as_ptr() as *mut u8) = 2 };
+        assert_eq!(
+            panic_message(|| {
+                a.load(SeqCst);
+            }),
+            "Atomic<bool> contains an invalid value: [02]"
+        );
+        assert!(panic_message(|| {
+            a.swap(true, SeqCst);
+        })
+        .contains("[02]"));
+        // The value was replaced before it was checked.
+        assert!(a.load(SeqCst));
+    }
+
+    #[test]
+    fn invalid_char() {
+        let a = Atomic::new('a');
+        unsafe { *(a.as_ptr() as *mut u32) = 0xd800 };
+        assert_eq!(
+            panic_message(|| {
+                let _ = a.compare_exchange('a', 'b', SeqCst, SeqCst);
+            }),
+            format!(
+                "Atomic<char> contains an invalid value: {:02x?}",
+                0xd800u32.to_ne_bytes()
+            )
+        );
+        assert!(panic_message(|| {
+            a.load_consume();
+        })
+        .starts_with("Atomic<char> contains an invalid value"));
+    }
+
+    #[test]
+    fn invalid_non_zero() {
+        let a = Atomic::new(NonZeroU32::new(1).unwrap());
+        unsafe { *(a.as_ptr() as *mut u32) = 0 };
+        assert!(panic_message(|| {
+            a.try_load(Relaxed);
+        })
+        .contains("[00, 00, 00, 00]"));
+    }
+
+    #[test]
+    fn invalid_enum() {
+        register_validator::<State>();
+        let a = Atomic::new(State::Busy);
+        unsafe { *(a.as_ptr() as *mut u16) = 3 };
+        let message = panic_message(|| {
+            a.load(Acquire);
+        });
+        assert!(message.contains("State> contains an invalid value"));
+        assert!(message.ends_with(&format!("{:02x?}", 3u16.to_ne_bytes())));
+        assert!(panic_message(|| {
+            let _ = a.compare_exchange_weak(State::Idle, State::Done, SeqCst, SeqCst);
+        })
+        .contains("State> contains an invalid value"));
+    }
+}
@@ -0,0 +1,414 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Atomic loads and stores which the compiler neither removes nor merges, for
+// memory shared with a device or another agent.
+//
+// The optimizer may merge two atomic loads of the same location or drop a
+// store which is overwritten, while volatile accesses are kept but are data
+// races when something else writes at the same time. These are a single load
+// or store instruction in inline assembly, which the compiler can't look
+// into, with the barriers the ordering needs around it. They follow the
+// mappings the compiler uses for the standard atomic types:
+//
+// - x86 and x86_64: `mov`, which already has acquire and release semantics,
+//   and `xchg` for `SeqCst` stores.
+// - AArch64: `ldr` and `str`, or `ldar` and `stlr` for the other orderings.
+// - ARM (ARMv6-M and ARMv7 or later): `ldr` and `str`, with a `dmb` after
+//   acquire loads and before release stores, and after `SeqCst` stores. The
+//   barrier is `dmb sy` rather than the compiler's `dmb ish`: M-profile
+//   processors only have that one, and unlike `ish` it covers devices.
+// - RISC-V: loads and stores with `fence`s around them.
+//
+// The assembly isn't marked as leaving memory alone, so it is also a compiler
+// barrier. Only integers of up to the pointer size are a single instruction,
+// so other types fail to compile. Under loom, shuttle and Miri, which can't
+// run the assembly, these are the usual atomic loads and stores.
+#![cfg_attr(any(loom, shuttle, miri), allow(unused_macros))]
+
+use core::marker::PhantomData;
+use core::mem;
+use core::sync::atomic::Ordering;
+use ops;
+#[cfg(feature = "staleness-check")]
+use staleness;
+use Atomic;
+
+// Checked before the size dispatch, like the lock-free check of the
+// `no-fallback` feature.
+struct Volatile<T>(PhantomData<T>);
+
+impl<T> Volatile<T> {
+    const CHECK: () = assert!(
+        matches!(mem::size_of::<T>(), 1 | 2 | 4 | 8)
+            && mem::size_of::<T>() <= mem::size_of::<usize>()
+            && mem::align_of::<T>() >= mem::size_of::<T>(),
+        "Atomic<T> has no volatile loads and stores for this type"
+    );
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+macro_rules! imp {
+    ($($t:ident $load:ident $store:ident $class:ident $v:literal $ptr:literal)*) => ($(
+        // Every `mov` load is an acquire load, and is `SeqCst` since the
+        // stores are.
+        #[inline(always)]
+        pub unsafe fn $load(src: *const $t, _order: Ordering) -> $t {
+            let val: $t;
+            asm!(
+                concat!("mov ", $v, ", ", $ptr, " ptr [{p}]"),
+                p = in(reg) src,
+                v = lateout($class) val,
+                options(nostack, preserves_flags),
+            );
+            val
+        }
+
+        #[inline(always)]
+        pub unsafe fn $store(dst: *mut $t, val: $t, order: Ordering) {
+            match order {
+                Ordering::SeqCst => asm!(
+                    concat!("xchg ", $ptr, " ptr [{p}], ", $v),
+                    p = in(reg) dst,
+                    v = inout($class) val => _,
+                    options(nostack, preserves_flags),
+                ),
+                _ => asm!(
+                    concat!("mov ", $ptr, " ptr [{p}], ", $v),
+                    p = in(reg) dst,
+                    v = in($class) val,
+                    options(nostack, preserves_flags),
+                ),
+            }
+        }
+    )*);
+}
+
+#[cfg(target_arch = "aarch64")]
+macro_rules! imp {
+    ($($t:ident $load:ident $store:ident $ldr:literal $ldar:literal $str:literal $stlr:literal
+       $v:literal)*) => ($(
+        #[inline(always)]
+        pub unsafe fn $load(src: *const $t, order: Ordering) -> $t {
+            let val: $t;
+            match order {
+                Ordering::Relaxed => asm!(
+                    concat!($ldr, " ", $v, ", [{p}]"),
+                    p = in(reg) src,
+                    v = lateout(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+                _ => asm!(
+                    concat!($ldar, " ", $v, ", [{p}]"),
+                    p = in(reg) src,
+                    v = lateout(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+            }
+            val
+        }
+
+        #[inline(always)]
+        pub unsafe fn $store(dst: *mut $t, val: $t, order: Ordering) {
+            match order {
+                Ordering::Relaxed => asm!(
+                    concat!($str, " ", $v, ", [{p}]"),
+                    p = in(reg) dst,
+                    v = in(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+                _ => asm!(
+                    concat!($stlr, " ", $v, ", [{p}]"),
+                    p = in(reg) dst,
+                    v = in(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+            }
+        }
+    )*);
+}
+
+#[cfg(target_arch = "arm")]
+macro_rules! imp {
+    ($($t:ident $load:ident $store:ident $ldr:literal $str:literal)*) => ($(
+        #[inline(always)]
+        pub unsafe fn $load(src: *const $t, order: Ordering) -> $t {
+            let val: $t;
+            match order {
+                Ordering::Relaxed => asm!(
+                    concat!($ldr, " {v}, [{p}]"),
+                    p = in(reg) src,
+                    v = lateout(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+                _ => asm!(
+                    concat!($ldr, " {v}, [{p}]"),
+                    "dmb sy",
+                    p = in(reg) src,
+                    v = lateout(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+            }
+            val
+        }
+
+        #[inline(always)]
+        pub unsafe fn $store(dst: *mut $t, val: $t, order: Ordering) {
+            match order {
+                Ordering::Relaxed => asm!(
+                    concat!($str, " {v}, [{p}]"),
+                    p = in(reg) dst,
+                    v = in(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+                Ordering::SeqCst => asm!(
+                    "dmb sy",
+                    concat!($str, " {v}, [{p}]"),
+                    "dmb sy",
+                    p = in(reg) dst,
+                    v = in(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+                _ => asm!(
+                    "dmb sy",
+                    concat!($str, " {v}, [{p}]"),
+                    p = in(reg) dst,
+                    v = in(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+            }
+        }
+    )*);
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+macro_rules! imp {
+    ($($t:ident $load:ident $store:ident $l:literal $s:literal)*) => ($(
+        #[inline(always)]
+        pub unsafe fn $load(src: *const $t, order: Ordering) -> $t {
+            let val: $t;
+            match order {
+                Ordering::Relaxed => asm!(
+                    concat!($l, " {v}, 0({p})"),
+                    p = in(reg) src,
+                    v = lateout(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+                Ordering::SeqCst => asm!(
+                    "fence rw, rw",
+                    concat!($l, " {v}, 0({p})"),
+                    "fence r, rw",
+                    p = in(reg) src,
+                    v = lateout(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+                _ => asm!(
+                    concat!($l, " {v}, 0({p})"),
+                    "fence r, rw",
+                    p = in(reg) src,
+                    v = lateout(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+            }
+            val
+        }
+
+        #[inline(always)]
+        pub unsafe fn $store(dst: *mut $t, val: $t, order: Ordering) {
+            match order {
+                Ordering::Relaxed => asm!(
+                    concat!($s, " {v}, 0({p})"),
+                    p = in(reg) dst,
+                    v = in(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+                _ => asm!(
+                    "fence rw, w",
+                    concat!($s, " {v}, 0({p})"),
+                    p = in(reg) dst,
+                    v = in(reg) val,
+                    options(nostack, preserves_flags),
+                ),
+            }
+        }
+    )*);
+}
+
+#[cfg(not(any(loom, shuttle, miri)))]
+mod imp {
+    use core::arch::asm;
+    use core::sync::atomic::Ordering;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    imp! {
+        u8 load_u8 store_u8 reg_byte "{v}" "byte"
+        u16 load_u16 store_u16 reg "{v:x}" "word"
+        u32 load_u32 store_u32 reg "{v:e}" "dword"
+    }
+    #[cfg(target_arch = "x86_64")]
+    imp! {
+        u64 load_u64 store_u64 reg "{v:r}" "qword"
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    imp! {
+        u8 load_u8 store_u8 "ldrb" "ldarb" "strb" "stlrb" "{v:w}"
+        u16 load_u16 store_u16 "ldrh" "ldarh" "strh" "stlrh" "{v:w}"
+        u32 load_u32 store_u32 "ldr" "ldar" "str" "stlr" "{v:w}"
+        u64 load_u64 store_u64 "ldr" "ldar" "str" "stlr" "{v:x}"
+    }
+
+    #[cfg(target_arch = "arm")]
+    imp! {
+        u8 load_u8 store_u8 "ldrb" "strb"
+        u16 load_u16 store_u16 "ldrh" "strh"
+        u32 load_u32 store_u32 "ldr" "str"
+    }
+
+    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    imp! {
+        u8 load_u8 store_u8 "lbu" "sb"
+        u16 load_u16 store_u16 "lhu" "sh"
+        u32 load_u32 store_u32 "lw" "sw"
+    }
+    #[cfg(target_arch = "riscv64")]
+    imp! {
+        u64 load_u64 store_u64 "ld" "sd"
+    }
+}
+
+#[inline]
+unsafe fn load<T>(src: *mut T, order: Ordering) -> T {
+    #[allow(clippy::let_unit_value)]
+    let () = Volatile::<T>::CHECK;
+    let order = ops::check_load_ordering(order);
+    #[cfg(not(any(loom, shuttle, miri)))]
+    match mem::size_of::<T>() {
+        1 => ops::transmute_bits(imp::load_u8(src as *const u8, order)),
+        2 => ops::transmute_bits(imp::load_u16(src as *const u16, order)),
+        4 => ops::transmute_bits(imp::load_u32(src as *const u32, order)),
+        #[cfg(target_pointer_width = "64")]
+        8 => ops::transmute_bits(imp::load_u64(src as *const u64, order)),
+        _ => unreachable!(),
+    }
+    #[cfg(any(loom, shuttle, miri))]
+    ops::atomic_load(src, order)
+}
+
+#[inline]
+unsafe fn store<T>(dst: *mut T, val: T, order: Ordering) {
+    #[allow(clippy::let_unit_value)]
+    let () = Volatile::<T>::CHECK;
+    let order = ops::check_store_ordering(order);
+    #[cfg(feature = "staleness-check")]
+    let _m = staleness::Modification(dst as usize);
+    #[cfg(not(any(loom, shuttle, miri)))]
+    match mem::size_of::<T>() {
+        1 => imp::store_u8(dst as *mut u8, ops::transmute_bits(val), order),
+        2 => imp::store_u16(dst as *mut u16, ops::transmute_bits(val), order),
+        4 => imp::store_u32(dst as *mut u32, ops::transmute_bits(val), order),
+        #[cfg(target_pointer_width = "64")]
+        8 => imp::store_u64(dst as *mut u64, ops::transmute_bits(val), order),
+        _ => unreachable!(),
+    }
+    #[cfg(any(loom, shuttle, miri))]
+    ops::atomic_store(dst, val, order)
+}
+
+impl<T: Copy> Atomic<T> {
+    /// Loads a value from the `Atomic` with exactly one access to memory,
+    /// which the compiler can neither remove nor merge with other accesses.
+    ///
+    /// This is for memory which is also accessed by something the compiler
+    /// doesn't know about, like a device, where a volatile read would be a
+    /// data race and an ordinary atomic load may be merged with another one.
+    /// The load is a single instruction in inline assembly, and also keeps
+    /// the compiler from moving other memory accesses across it.
+    ///
+    /// This is only available on x86, x86_64, AArch64, RISC-V, and ARM from
+    /// ARMv6-M and ARMv7 on. `T` must be 1, 2, 4 or 8 bytes, no bigger than a
+    /// pointer, and aligned to its size, so that the value is loaded by a
+    /// single instruction; other types fail to build.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[doc = include_str!("doc_examples/load_volatile.md")]
+    #[inline]
+    pub fn load_volatile(&self, order: Ordering) -> T {
+        unsafe { load(self.v.get(), order) }
+    }
+
+    /// Stores a value into the `Atomic` with exactly one access to memory,
+    /// which the compiler can neither remove nor merge with other accesses.
+    ///
+    /// This is the store of `load_volatile`, with the same requirements on
+    /// `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn store_volatile(&self, val: T, order: Ordering) {
+        unsafe { store(self.v.get(), val, order) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use Atomic;
+    use Ordering::*;
+
+    #[test]
+    fn widths() {
+        let a = Atomic::new(0xffu8);
+        a.store_volatile(0x12, Relaxed);
+        assert_eq!(a.load_volatile(Acquire), 0x12);
+        let b = Atomic::new(-1i16);
+        b.store_volatile(-2, Release);
+        assert_eq!(b.load_volatile(SeqCst), -2);
+        let c = Atomic::new(1.5f32);
+        c.store_volatile(-2.5, SeqCst);
+        assert_eq!(c.load_volatile(Relaxed), -2.5);
+        let d = Atomic::new(u64::MAX);
+        d.store_volatile(1 << 40, SeqCst);
+        assert_eq!(d.load_volatile(SeqCst), 1 << 40);
+        assert_eq!(d.load(SeqCst), 1 << 40);
+        let e = Atomic::new(true);
+        e.store_volatile(false, Relaxed);
+        assert!(!e.load_volatile(Relaxed));
+    }
+
+    // Only the bytes of the value are written.
+    #[test]
+    fn neighbours() {
+        let a = [Atomic::new(1u16), Atomic::new(2), Atomic::new(3)];
+        a[1].store_volatile(0xabcd, SeqCst);
+        let values: [u16; 3] = [a[0].load(SeqCst), a[1].load(SeqCst), a[2].load(SeqCst)];
+        assert_eq!(values, [1, 0xabcd, 3]);
+    }
+
+    // A value published with a release store is seen by an acquire load which
+    // reads the flag, like with the ordinary operations.
+    #[test]
+    fn message_passing() {
+        let rounds = if cfg!(miri) { 10 } else { 1000 };
+        for _ in 0..rounds {
+            let data = Atomic::new(0u32);
+            let ready = Atomic::new(false);
+            thread::scope(|s| {
+                s.spawn(|| {
+                    data.store_volatile(42, Relaxed);
+                    ready.store_volatile(true, Release);
+                });
+                while !ready.load_volatile(Acquire) {}
+                assert_eq!(data.load_volatile(Relaxed), 42);
+            });
+        }
+    }
+}
@@ -0,0 +1,945 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Blocking until an atomic changes, in the style of futexes.
+//
+// Types which the OS can wait on directly use its address-based wait
+// primitive: `futex` on Linux, `__ulock_wait` on macOS and `WaitOnAddress` on
+// Windows. The kernel compares the value with the expected one before going
+// to sleep, so a notification sent after the value was changed can't be lost.
+//
+// Every other type, including the ones going through the fallback lock, uses
+// a global table of condition variables keyed by address. A waiter compares
+// the value while holding the bucket's mutex and only releases it once it is
+// queued on the condition variable, and notifiers lock the same mutex before
+// signalling, which gives the same guarantee. Buckets are shared between
+// addresses, so the table always wakes every waiter in the bucket and lets
+// them go back to sleep if their own value didn't change.
+//
+// Timeouts are turned into a deadline up front, and every time a thread wakes
+// up with the value unchanged it sleeps again for whatever is left. The OS
+// primitives take a relative timeout with a limited range, so a long timeout
+// is simply split into several sleeps, and a deadline too far away to be
+// represented as an `Instant` is treated as no deadline at all.
+//
+// With the `async` feature, tasks waiting in `wait_async` register a `Waker`
+// in another table keyed by address, which `atomic_notify` also goes through.
+//
+// Under loom and shuttle none of this is used; see `atomic_wait` below.
+#![cfg_attr(any(loom, shuttle), allow(dead_code, unused_imports))]
+
+use core::sync::atomic::Ordering;
+use ops;
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+use {
+    core::future::Future,
+    core::pin::Pin,
+    core::sync::atomic::AtomicUsize,
+    core::task::{Context, Poll, Waker},
+    std::vec::Vec,
+    Atomic,
+};
+
+/// The result of a timed wait on an `Atomic`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[must_use]
+pub enum WaitResult {
+    /// The value was observed to be different from the expected one.
+    Changed,
+    /// The timeout elapsed while the value was still the expected one.
+    TimedOut,
+}
+
+impl WaitResult {
+    /// Returns whether the wait timed out.
+    #[inline]
+    pub fn timed_out(self) -> bool {
+        self == WaitResult::TimedOut
+    }
+}
+
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "s390x"
+    )
+))]
+mod imp {
+    use core::ffi::c_long;
+    use core::mem;
+    use core::ptr;
+    use ops;
+    use std::time::Duration;
+
+    #[cfg(target_arch = "x86_64")]
+    const SYS_FUTEX: c_long = 202;
+    #[cfg(any(target_arch = "x86", target_arch = "arm"))]
+    const SYS_FUTEX: c_long = 240;
+    #[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+    const SYS_FUTEX: c_long = 98;
+    #[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+    const SYS_FUTEX: c_long = 221;
+    #[cfg(target_arch = "s390x")]
+    const SYS_FUTEX: c_long = 238;
+
+    const FUTEX_WAIT_PRIVATE: i32 = 128;
+    const FUTEX_WAKE_PRIVATE: i32 = 129;
+
+    // `time_t` is a `long` for every architecture using this syscall number,
+    // including the 32-bit ones.
+    #[repr(C)]
+    struct Timespec {
+        tv_sec: c_long,
+        tv_nsec: c_long,
+    }
+
+    extern "C" {
+        fn syscall(num: c_long, ...) -> c_long;
+    }
+
+    #[inline]
+    pub fn supported<T>() -> bool {
+        mem::size_of::<T>() == 4 && ops::atomic_is_lock_free::<T>()
+    }
+
+    #[inline]
+    pub unsafe fn wait<T>(dst: *mut T, expected: T, timeout: Option<Duration>) {
+        let expected: u32 = ops::transmute_bits(expected);
+        let ts = timeout.map(|t| Timespec {
+            // Timeouts which don't fit just end the sleep early.
+            tv_sec: t.as_secs().min(c_long::MAX as u64) as c_long,
+            tv_nsec: t.subsec_nanos() as c_long,
+        });
+        let ts = ts.as_ref().map_or(ptr::null(), |ts| ts as *const Timespec);
+        // EAGAIN (value changed), EINTR and ETIMEDOUT are all fine, the caller
+        // checks the value and the deadline again either way.
+        syscall(SYS_FUTEX, dst, FUTEX_WAIT_PRIVATE, expected, ts);
+    }
+
+    #[inline]
+    pub unsafe fn wake<T>(dst: *mut T, all: bool) {
+        let count = if all { i32::MAX } else { 1 };
+        syscall(SYS_FUTEX, dst, FUTEX_WAKE_PRIVATE, count);
+    }
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos"
+))]
+mod imp {
+    use core::ffi::{c_int, c_void};
+    use core::mem;
+    use ops;
+    use std::time::Duration;
+
+    const UL_COMPARE_AND_WAIT: u32 = 1;
+    const ULF_WAKE_ALL: u32 = 0x100;
+
+    // Private but stable system calls, which std uses for the same purpose.
+    extern "C" {
+        fn __ulock_wait(operation: u32, addr: *mut c_void, value: u64, timeout: u32) -> c_int;
+        fn __ulock_wake(operation: u32, addr: *mut c_void, wake_value: u64) -> c_int;
+    }
+
+    #[inline]
+    pub fn supported<T>() -> bool {
+        mem::size_of::<T>() == 4 && ops::atomic_is_lock_free::<T>()
+    }
+
+    #[inline]
+    pub unsafe fn wait<T>(dst: *mut T, expected: T, timeout: Option<Duration>) {
+        let expected: u32 = ops::transmute_bits(expected);
+        // The timeout is in microseconds, and 0 means no timeout, so it is
+        // rounded up to at least 1.
+        let timeout = timeout.map_or(0, |t| {
+            let micros = t.as_micros() + (t.subsec_nanos() % 1000 != 0) as u128;
+            micros.clamp(1, u32::MAX as u128) as u32
+        });
+        __ulock_wait(
+            UL_COMPARE_AND_WAIT,
+            dst as *mut c_void,
+            expected as u64,
+            timeout,
+        );
+    }
+
+    #[inline]
+    pub unsafe fn wake<T>(dst: *mut T, all: bool) {
+        let op = if all {
+            UL_COMPARE_AND_WAIT | ULF_WAKE_ALL
+        } else {
+            UL_COMPARE_AND_WAIT
+        };
+        __ulock_wake(op, dst as *mut c_void, 0);
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use core::ffi::c_void;
+    use core::mem;
+    use ops;
+    use std::time::Duration;
+
+    const INFINITE: u32 = 0xffff_ffff;
+
+    #[link(name = "synchronization")]
+    extern "system" {
+        fn WaitOnAddress(
+            address: *const c_void,
+            compare_address: *const c_void,
+            address_size: usize,
+            milliseconds: u32,
+        ) -> i32;
+        fn WakeByAddressSingle(address: *const c_void);
+        fn WakeByAddressAll(address: *const c_void);
+    }
+
+    #[inline]
+    pub fn supported<T>() -> bool {
+        matches!(mem::size_of::<T>(), 1 | 2 | 4 | 8) && ops::atomic_is_lock_free::<T>()
+    }
+
+    #[inline]
+    pub unsafe fn wait<T>(dst: *mut T, expected: T, timeout: Option<Duration>) {
+        // The timeout is in milliseconds, rounded up so that the thread doesn't
+        // keep waking up just before the deadline. `INFINITE` itself must be
+        // avoided for finite timeouts.
+        let timeout = timeout.map_or(INFINITE, |t| {
+            let millis = t.as_millis() + (t.subsec_nanos() % 1_000_000 != 0) as u128;
+            millis.min(INFINITE as u128 - 1) as u32
+        });
+        WaitOnAddress(
+            dst as *const c_void,
+            &expected as *const T as *const c_void,
+            mem::size_of::<T>(),
+            timeout,
+        );
+    }
+
+    #[inline]
+    pub unsafe fn wake<T>(dst: *mut T, all: bool) {
+        if all {
+            WakeByAddressAll(dst as *const c_void);
+        } else {
+            WakeByAddressSingle(dst as *const c_void);
+        }
+    }
+}
+
+#[cfg(not(any(
+    all(
+        any(target_os = "linux", target_os = "android"),
+        any(
+            target_arch = "x86",
+            target_arch = "x86_64",
+            target_arch = "arm",
+            target_arch = "aarch64",
+            target_arch = "riscv64",
+            target_arch = "powerpc",
+            target_arch = "powerpc64",
+            target_arch = "s390x"
+        )
+    ),
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    target_os = "watchos",
+    windows
+)))]
+mod imp {
+    use std::time::Duration;
+
+    #[inline]
+    pub fn supported<T>() -> bool {
+        false
+    }
+
+    pub unsafe fn wait<T>(_dst: *mut T, _expected: T, _timeout: Option<Duration>) {
+        unreachable!()
+    }
+
+    pub unsafe fn wake<T>(_dst: *mut T, _all: bool) {
+        unreachable!()
+    }
+}
+
+struct Bucket {
+    mutex: Mutex<()>,
+    condvar: Condvar,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const BUCKET: Bucket = Bucket {
+    mutex: Mutex::new(()),
+    condvar: Condvar::new(),
+};
+
+static BUCKETS: [Bucket; 64] = [BUCKET; 64];
+
+#[inline]
+fn bucket_for(addr: usize) -> &'static Bucket {
+    &BUCKETS[(addr >> 2) % BUCKETS.len()]
+}
+
+// Returns the time left until `deadline`, or `None` if it has passed.
+#[inline]
+fn remaining(deadline: Instant) -> Option<Duration> {
+    let now = Instant::now();
+    if now >= deadline {
+        None
+    } else {
+        Some(deadline - now)
+    }
+}
+
+// Loom and shuttle can't see threads blocked in the kernel or on a condition
+// variable, so under them waiting is a spin loop which yields to the scheduler.
+// Timed waits give up as soon as they would block, which is one of the
+// outcomes a real timeout can have, and notifications have nothing to do.
+#[inline]
+#[cfg(any(loom, shuttle))]
+pub unsafe fn atomic_wait<T: Copy>(
+    dst: *mut T,
+    expected: T,
+    order: Ordering,
+    deadline: Option<Instant>,
+) -> WaitResult {
+    while ops::same_bytes(&ops::atomic_load(dst, order), &expected) {
+        if deadline.is_some() {
+            return WaitResult::TimedOut;
+        }
+        ::shadow::yield_now();
+    }
+    WaitResult::Changed
+}
+
+#[inline]
+#[cfg(any(loom, shuttle))]
+pub unsafe fn atomic_notify<T>(_dst: *mut T, _all: bool) {}
+
+#[inline]
+#[cfg(not(any(loom, shuttle)))]
+pub unsafe fn atomic_wait<T: Copy>(
+    dst: *mut T,
+    expected: T,
+    order: Ordering,
+    deadline: Option<Instant>,
+) -> WaitResult {
+    if imp::supported::<T>() {
+        while ops::same_bytes(&ops::atomic_load(dst, order), &expected) {
+            let timeout = match deadline.map(remaining) {
+                Some(None) => return WaitResult::TimedOut,
+                Some(timeout) => timeout,
+                None => None,
+            };
+            imp::wait(dst, expected, timeout);
+        }
+    } else {
+        let bucket = bucket_for(dst as usize);
+        let mut guard = bucket.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+        while ops::same_bytes(&ops::atomic_load(dst, order), &expected) {
+            guard = match deadline.map(remaining) {
+                Some(None) => return WaitResult::TimedOut,
+                Some(Some(timeout)) => {
+                    let result = bucket.condvar.wait_timeout(guard, timeout);
+                    result.unwrap_or_else(PoisonError::into_inner).0
+                }
+                None => bucket
+                    .condvar
+                    .wait(guard)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+    WaitResult::Changed
+}
+
+#[inline]
+#[cfg(not(any(loom, shuttle)))]
+pub unsafe fn atomic_notify<T>(dst: *mut T, all: bool) {
+    if imp::supported::<T>() {
+        imp::wake(dst, all);
+    } else {
+        let bucket = bucket_for(dst as usize);
+        drop(bucket.mutex.lock().unwrap_or_else(PoisonError::into_inner));
+        bucket.condvar.notify_all();
+    }
+    #[cfg(feature = "async")]
+    wake_tasks(dst as usize, all);
+}
+
+// Tasks waiting in `wait_async`, which works like `atomic_wait` with the bucket
+// mutex. A task registers its waker under the mutex before comparing the value
+// one last time, and notifiers take the waker out of the list under the same
+// mutex, so a notification sent after the value was changed either finds the
+// waker or is followed by the comparison.
+//
+// Every waiter has an id, since the future can move between polls. Notifiers
+// remove the wakers they wake, so a future whose entry is gone was woken and
+// hasn't looked at the value yet. If it is dropped then, it passes the wakeup
+// on to another task, so that a `notify_one` isn't lost.
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+struct Task {
+    addr: usize,
+    id: usize,
+    waker: Waker,
+}
+
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+#[allow(clippy::declare_interior_mutable_const)]
+const TASK_BUCKET: Mutex<Vec<Task>> = Mutex::new(Vec::new());
+
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+static TASK_BUCKETS: [Mutex<Vec<Task>>; 64] = [TASK_BUCKET; 64];
+
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+static NEXT_TASK_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[inline]
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+fn tasks_for(addr: usize) -> std::sync::MutexGuard<'static, Vec<Task>> {
+    let bucket = &TASK_BUCKETS[(addr >> 2) % TASK_BUCKETS.len()];
+    bucket.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// The wakers are called after the mutex is released, since a waker may poll
+// the future right away.
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+fn wake_tasks(addr: usize, all: bool) {
+    let mut woken = Vec::new();
+    {
+        let mut tasks = tasks_for(addr);
+        let mut i = 0;
+        while i < tasks.len() {
+            if tasks[i].addr == addr {
+                woken.push(tasks.swap_remove(i).waker);
+                if !all {
+                    break;
+                }
+            } else {
+                i += 1;
+            }
+        }
+    }
+    for waker in woken {
+        waker.wake();
+    }
+}
+
+/// The future returned by `Atomic::wait_async`.
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+pub struct WaitFuture<'a, T: Copy> {
+    atomic: &'a Atomic<T>,
+    expected: T,
+    order: Ordering,
+    // The id of the waker in the table, if one was registered since the
+    // value was last compared.
+    id: Option<usize>,
+}
+
+// Nothing is pinned in place: the table only refers to the future by its id.
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+impl<'a, T: Copy> Unpin for WaitFuture<'a, T> {}
+
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+impl<'a, T: Copy> WaitFuture<'a, T> {
+    #[inline]
+    pub fn new(atomic: &'a Atomic<T>, expected: T, order: Ordering) -> Self {
+        let order = ops::check_load_ordering(order);
+        WaitFuture {
+            atomic,
+            expected,
+            order,
+            id: None,
+        }
+    }
+
+    #[inline]
+    fn addr(&self) -> usize {
+        self.atomic.v.get() as usize
+    }
+
+    #[inline]
+    fn changed(&self) -> Option<T> {
+        let val = unsafe { ops::atomic_load(self.atomic.v.get(), self.order) };
+        if ops::same_bytes(&val, &self.expected) {
+            None
+        } else {
+            Some(val)
+        }
+    }
+
+    fn register(&mut self, waker: &Waker) {
+        let addr = self.addr();
+        let mut tasks = tasks_for(addr);
+        if let Some(id) = self.id {
+            if let Some(task) = tasks.iter_mut().find(|task| task.id == id) {
+                if !task.waker.will_wake(waker) {
+                    task.waker = waker.clone();
+                }
+                return;
+            }
+        }
+        let id = NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed);
+        tasks.push(Task {
+            addr,
+            id,
+            waker: waker.clone(),
+        });
+        self.id = Some(id);
+    }
+
+    // Returns whether the waker had already been taken out by a notifier.
+    fn unregister(&mut self) -> bool {
+        match self.id.take() {
+            Some(id) => {
+                let mut tasks = tasks_for(self.addr());
+                match tasks.iter().position(|task| task.id == id) {
+                    Some(i) => {
+                        tasks.swap_remove(i);
+                        false
+                    }
+                    None => true,
+                }
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+impl<'a, T: Copy> Future for WaitFuture<'a, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let this = self.get_mut();
+        if let Some(val) = this.changed() {
+            this.unregister();
+            return Poll::Ready(val);
+        }
+        this.register(cx.waker());
+        match this.changed() {
+            Some(val) => {
+                this.unregister();
+                Poll::Ready(val)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(all(feature = "async", not(any(loom, shuttle))))]
+impl<'a, T: Copy> Drop for WaitFuture<'a, T> {
+    fn drop(&mut self) {
+        if self.unregister() {
+            wake_tasks(self.addr(), false);
+        }
+    }
+}
+
+// Parking for the fallback lock, with buckets like the ones for the types
+// which the OS can't wait on. `blocked` is checked under the bucket's mutex,
+// so a thread which makes it false and then calls `unpark_all` with the same
+// key always wakes the parked thread. The lock word isn't read through `ops`,
+// which could take the fallback lock itself.
+//
+// The table is separate from `BUCKETS`, since `atomic_wait` may take the
+// fallback lock while it holds one of those.
+#[cfg(not(any(no_atomic_cas, single_threaded)))]
+static LOCK_BUCKETS: [Bucket; 64] = [BUCKET; 64];
+
+#[cfg(not(any(no_atomic_cas, single_threaded)))]
+pub fn park_while<F: Fn() -> bool>(key: usize, blocked: F) {
+    let bucket = &LOCK_BUCKETS[key % LOCK_BUCKETS.len()];
+    let mut guard = bucket.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+    while blocked() {
+        guard = bucket
+            .condvar
+            .wait(guard)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+#[cfg(not(any(no_atomic_cas, single_threaded)))]
+pub fn unpark_all(key: usize) {
+    let bucket = &LOCK_BUCKETS[key % LOCK_BUCKETS.len()];
+    drop(bucket.mutex.lock().unwrap_or_else(PoisonError::into_inner));
+    bucket.condvar.notify_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{imp, WaitResult};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use Atomic;
+    use Ordering::*;
+
+    #[test]
+    fn latch() {
+        let latch = Atomic::new(1u32);
+        let done = Atomic::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                latch.wait(1, Acquire);
+                assert!(done.load(Relaxed));
+            });
+            thread::sleep(Duration::from_millis(10));
+            done.store(true, Relaxed);
+            latch.store(0, Release);
+            latch.notify_one();
+        });
+    }
+
+    #[test]
+    fn wait_returns_immediately() {
+        let a = Atomic::new(5u32);
+        a.wait(4, SeqCst);
+        let b = Atomic::new([1u8; 3]);
+        b.wait([0; 3], SeqCst);
+    }
+
+    #[test]
+    fn table_path() {
+        // Neither of these can be waited on by any OS.
+        assert!(!imp::supported::<[u8; 3]>());
+        assert!(!imp::supported::<[u64; 4]>());
+
+        let a = Atomic::new([0u8; 3]);
+        let b = Atomic::new([0u64; 4]);
+        thread::scope(|s| {
+            s.spawn(|| a.wait([0; 3], Acquire));
+            s.spawn(|| b.wait([0; 4], Acquire));
+            thread::sleep(Duration::from_millis(10));
+            a.store([1; 3], Release);
+            a.notify_one();
+            b.store([2; 4], Release);
+            b.notify_one();
+        });
+    }
+
+    fn notify_all_stress<T>(zero: T, one: T)
+    where
+        T: Copy + Send + Sync + PartialEq + ::core::fmt::Debug,
+    {
+        let rounds = if cfg!(miri) { 2 } else { 50 };
+        for _ in 0..rounds {
+            let gate = Atomic::new(zero);
+            let woken = Atomic::new(0u32);
+            thread::scope(|s| {
+                for _ in 0..16 {
+                    s.spawn(|| {
+                        gate.wait(zero, Acquire);
+                        assert_eq!(gate.load(Relaxed), one);
+                        woken.fetch_add(1, Relaxed);
+                    });
+                }
+                thread::yield_now();
+                gate.store(one, Release);
+                gate.notify_all();
+            });
+            assert_eq!(woken.load(SeqCst), 16);
+        }
+    }
+
+    #[test]
+    fn notify_all_many_waiters() {
+        notify_all_stress(0u32, 1);
+        notify_all_stress(0u8, 1);
+        notify_all_stress(0u64, 1);
+        notify_all_stress([0u16; 7], [1; 7]);
+    }
+
+    // Both an OS-supported type and one which goes through the table.
+    fn timed_out_promptly<T: Copy + Send + Sync>(val: T) {
+        let a = Atomic::new(val);
+        let start = Instant::now();
+        let res = a.wait_timeout(val, Acquire, Duration::from_millis(50));
+        let elapsed = start.elapsed();
+        assert_eq!(res, WaitResult::TimedOut);
+        assert!(elapsed >= Duration::from_millis(50), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert_eq!(
+            a.wait_deadline(val, Acquire, deadline),
+            WaitResult::TimedOut
+        );
+        assert!(Instant::now() >= deadline);
+    }
+
+    #[test]
+    fn wait_timeout_elapses() {
+        timed_out_promptly(7u32);
+        timed_out_promptly([7u8; 3]);
+    }
+
+    #[test]
+    fn wait_timeout_edge_cases() {
+        let a = Atomic::new(1u32);
+        let b = Atomic::new([1u16; 5]);
+
+        // Zero timeouts and past deadlines only look at the value.
+        assert!(a.wait_timeout(1, SeqCst, Duration::ZERO).timed_out());
+        assert!(b.wait_timeout([1; 5], SeqCst, Duration::ZERO).timed_out());
+        assert_eq!(
+            a.wait_timeout(0, SeqCst, Duration::ZERO),
+            WaitResult::Changed
+        );
+        let past = Instant::now();
+        thread::sleep(Duration::from_millis(1));
+        assert!(a.wait_deadline(1, SeqCst, past).timed_out());
+        assert!(b.wait_deadline([1; 5], SeqCst, past).timed_out());
+        assert_eq!(b.wait_deadline([0; 5], SeqCst, past), WaitResult::Changed);
+
+        // A timeout which can't be represented as a deadline never elapses.
+        assert_eq!(
+            a.wait_timeout(0, SeqCst, Duration::MAX),
+            WaitResult::Changed
+        );
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                a.store(2, Release);
+                a.notify_one();
+                b.store([2; 5], Release);
+                b.notify_one();
+            });
+            assert_eq!(
+                a.wait_timeout(1, Acquire, Duration::MAX),
+                WaitResult::Changed
+            );
+            let far = Duration::from_secs(u32::MAX as u64 * 1000);
+            assert_eq!(b.wait_timeout([1; 5], Acquire, far), WaitResult::Changed);
+        });
+    }
+
+    #[test]
+    fn wait_timeout_notified() {
+        let a = Atomic::new(0u32);
+        let b = Atomic::new(0u64);
+        let c = Atomic::new([0u8; 3]);
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                a.store(1, Release);
+                a.notify_one();
+                b.store(1, Release);
+                b.notify_all();
+                c.store([1; 3], Release);
+                c.notify_all();
+            });
+            let timeout = Duration::from_secs(60);
+            assert_eq!(a.wait_timeout(0, Acquire, timeout), WaitResult::Changed);
+            let deadline = Instant::now() + timeout;
+            assert_eq!(b.wait_deadline(0, Acquire, deadline), WaitResult::Changed);
+            assert_eq!(
+                c.wait_deadline([0; 3], Acquire, deadline),
+                WaitResult::Changed
+            );
+            assert!(Instant::now() < deadline);
+        });
+    }
+
+    #[test]
+    fn zero_sized() {
+        // The only value never changes, so waiting always times out.
+        let a = Atomic::new(());
+        a.notify_one();
+        a.notify_all();
+        let timeout = Duration::from_millis(1);
+        assert_eq!(a.wait_timeout((), SeqCst, timeout), WaitResult::TimedOut);
+    }
+
+    #[cfg(feature = "async")]
+    mod tasks {
+        use super::super::tasks_for;
+        use core::future::Future;
+        use core::pin::pin;
+        use core::task::{Context, Poll, Waker};
+        use std::boxed::Box;
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::Arc;
+        use std::task::Wake;
+        use std::thread::{self, Thread};
+        use std::time::Duration;
+        use Atomic;
+        use Ordering::*;
+
+        struct Unparker(Thread);
+
+        impl Wake for Unparker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        // The smallest possible executor, polling on the current thread.
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let mut future = pin!(future);
+            let waker = Waker::from(Arc::new(Unparker(thread::current())));
+            let mut cx = Context::from_waker(&waker);
+            loop {
+                if let Poll::Ready(val) = future.as_mut().poll(&mut cx) {
+                    return val;
+                }
+                thread::park();
+            }
+        }
+
+        #[derive(Default)]
+        struct Counter(AtomicUsize);
+
+        impl Wake for Counter {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, SeqCst);
+            }
+        }
+
+        impl Counter {
+            fn woken(&self) -> usize {
+                self.0.load(SeqCst)
+            }
+        }
+
+        fn registered<T: Copy>(a: &Atomic<T>) -> usize {
+            let addr = a.v.get() as usize;
+            let tasks = tasks_for(addr);
+            tasks.iter().filter(|task| task.addr == addr).count()
+        }
+
+        #[test]
+        fn wait_async() {
+            // An OS-supported type and one which goes through the table.
+            let a = Atomic::new(0u32);
+            let b = Atomic::new([0u8; 3]);
+            thread::scope(|s| {
+                s.spawn(|| {
+                    thread::sleep(Duration::from_millis(10));
+                    a.store(1, Release);
+                    a.notify_one();
+                    thread::sleep(Duration::from_millis(10));
+                    b.store([2; 3], Release);
+                    b.notify_all();
+                });
+                assert_eq!(block_on(a.wait_async(0, Acquire)), 1);
+                assert_eq!(block_on(b.wait_async([0; 3], Acquire)), [2; 3]);
+            });
+            assert_eq!(block_on(a.wait_async(0, Acquire)), 1);
+            assert_eq!(registered(&a) + registered(&b), 0);
+        }
+
+        #[test]
+        fn dropped_before_notify() {
+            let a = Atomic::new(0u32);
+            let counter = Arc::new(Counter::default());
+            let waker = Waker::from(counter.clone());
+            let mut cx = Context::from_waker(&waker);
+
+            let mut future = Box::pin(a.wait_async(0, Acquire));
+            assert_eq!(future.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(future.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(registered(&a), 1);
+            drop(future);
+            assert_eq!(registered(&a), 0);
+
+            a.store(1, Release);
+            a.notify_all();
+            assert_eq!(counter.woken(), 0);
+        }
+
+        #[test]
+        fn spurious_wakeup() {
+            let a = Atomic::new(0u32);
+            let counter = Arc::new(Counter::default());
+            let waker = Waker::from(counter.clone());
+            let mut cx = Context::from_waker(&waker);
+
+            // A notification without a change only makes it register again.
+            let mut future = pin!(a.wait_async(0, Acquire));
+            assert_eq!(future.as_mut().poll(&mut cx), Poll::Pending);
+            a.notify_one();
+            assert_eq!(counter.woken(), 1);
+            assert_eq!(registered(&a), 0);
+            assert_eq!(future.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(registered(&a), 1);
+            a.store(3, Release);
+            a.notify_one();
+            assert_eq!(future.as_mut().poll(&mut cx), Poll::Ready(3));
+            assert_eq!(counter.woken(), 2);
+            assert_eq!(registered(&a), 0);
+        }
+
+        #[test]
+        fn notify_one_passed_on() {
+            let a = Atomic::new(0u32);
+            let first = Arc::new(Counter::default());
+            let second = Arc::new(Counter::default());
+            let first_waker = Waker::from(first.clone());
+            let second_waker = Waker::from(second.clone());
+
+            let mut f1 = Box::pin(a.wait_async(0, Acquire));
+            let mut f2 = Box::pin(a.wait_async(0, Acquire));
+            let poll = f1.as_mut().poll(&mut Context::from_waker(&first_waker));
+            assert_eq!(poll, Poll::Pending);
+            let poll = f2.as_mut().poll(&mut Context::from_waker(&second_waker));
+            assert_eq!(poll, Poll::Pending);
+
+            a.store(1, Release);
+            a.notify_one();
+            assert_eq!(first.woken() + second.woken(), 1);
+
+            // Whichever was woken is dropped without looking at the value.
+            let (woken, other) = if first.woken() == 1 {
+                (f1, f2)
+            } else {
+                (f2, f1)
+            };
+            drop(woken);
+            assert_eq!(first.woken() + second.woken(), 2);
+            drop(other);
+            assert_eq!(registered(&a), 0);
+        }
+
+        #[test]
+        fn many_tasks() {
+            let rounds = if cfg!(miri) { 2 } else { 20 };
+            for _ in 0..rounds {
+                let gate = Atomic::new(0u64);
+                thread::scope(|s| {
+                    for _ in 0..8 {
+                        s.spawn(|| assert_eq!(block_on(gate.wait_async(0, Acquire)), 1));
+                    }
+                    thread::yield_now();
+                    gate.store(1, Release);
+                    gate.notify_all();
+                });
+                assert_eq!(registered(&gate), 0);
+            }
+        }
+
+        #[test]
+        #[should_panic(expected = "there is no such thing as a release load")]
+        fn invalid_ordering() {
+            let a = Atomic::new(0u32);
+            let _future = a.wait_async(0, Release);
+        }
+    }
+}
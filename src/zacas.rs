@@ -0,0 +1,287 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// 8-byte atomics on riscv32 through the `amocas.d` instruction of the Zacas
+// extension, which compares and swaps a pair of registers.
+//
+// Bare-metal targets can't detect the extension, so the build script only
+// enables this module when it is enabled at compile time, with
+// `-C target-feature=+zacas`. `amocas.d` is the only 64-bit atomic instruction
+// on riscv32: loads are a compare-exchange which writes back the value it
+// found, and everything else is a compare-exchange loop, like for
+// `cmpxchg16b`. The orderings map to the `aq` and `rl` bits of the
+// instruction. The ops module has already checked them.
+//
+// The types have the same interface as the native types of the ops module.
+// Only `amocas_d` is specific to riscv32: the tests build the module on the
+// host, where it is a 64-bit compare-exchange of the standard library, so
+// that everything around the instruction is tested there.
+
+#![cfg_attr(not(zacas_64), allow(dead_code))]
+
+#[cfg(zacas_64)]
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::sync::atomic::Ordering;
+#[cfg(zacas_64)]
+use ops;
+
+// `rd` and `rs2` of `amocas.d` are the even registers of a pair, holding the
+// low half of the value, which rules out letting the compiler choose them.
+#[cfg(zacas_64)]
+macro_rules! amocas_d {
+    ($suffix:expr, $dst:expr, $current:expr, $new:expr) => {{
+        let (lo, hi): (u32, u32);
+        asm!(
+            concat!("amocas.d", $suffix, " a0, a2, ({dst})"),
+            dst = in(reg) $dst,
+            inout("a0") $current as u32 => lo,
+            inout("a1") ($current >> 32) as u32 => hi,
+            in("a2") $new as u32,
+            in("a3") ($new >> 32) as u32,
+            options(nostack),
+        );
+        u64::from(lo) | (u64::from(hi) << 32)
+    }};
+}
+
+// Replaces the value at `dst` with `new` if it is `current`, and returns the
+// value it found.
+#[cfg(zacas_64)]
+#[inline]
+unsafe fn amocas_d(dst: *mut u64, current: u64, new: u64, order: Ordering) -> u64 {
+    match order {
+        Ordering::Relaxed => amocas_d!("", dst, current, new),
+        Ordering::Acquire => amocas_d!(".aq", dst, current, new),
+        Ordering::Release => amocas_d!(".rl", dst, current, new),
+        _ => amocas_d!(".aqrl", dst, current, new),
+    }
+}
+
+#[cfg(not(zacas_64))]
+#[inline]
+unsafe fn amocas_d(dst: *mut u64, current: u64, new: u64, order: Ordering) -> u64 {
+    use core::sync::atomic::AtomicU64;
+    let failure = match order {
+        Ordering::Release => Ordering::Relaxed,
+        Ordering::AcqRel => Ordering::Acquire,
+        order => order,
+    };
+    match AtomicU64::from_ptr(dst).compare_exchange(current, new, order, failure) {
+        Ok(x) | Err(x) => x,
+    }
+}
+
+// `amocas.d` has a single ordering, which covers both of those of a
+// compare-exchange.
+#[inline]
+fn cas_ordering(success: Ordering, failure: Ordering) -> Ordering {
+    match (success, failure) {
+        (_, Ordering::SeqCst) => Ordering::SeqCst,
+        (Ordering::Relaxed, Ordering::Acquire) => Ordering::Acquire,
+        (Ordering::Release, Ordering::Acquire) => Ordering::AcqRel,
+        (success, _) => success,
+    }
+}
+
+macro_rules! atomic_int {
+    ($($name:ident $t:ident)*) => ($(
+        #[repr(transparent)]
+        pub struct $name(UnsafeCell<$t>);
+
+        // The ops module only uses `AtomicI64` for its minimum and maximum.
+        #[allow(dead_code)]
+        impl $name {
+            #[inline]
+            pub unsafe fn from_ptr<'a>(p: *mut $t) -> &'a $name {
+                &*(p as *const $name)
+            }
+
+            #[inline]
+            fn compare_exchange_raw(&self, current: $t, new: $t, order: Ordering) -> $t {
+                let p = self.0.get() as *mut u64;
+                unsafe { amocas_d(p, current as u64, new as u64, order) as $t }
+            }
+
+            // Starts from a guess of zero, since a failed compare-exchange
+            // returns the current value anyway.
+            #[inline]
+            fn update<F: Fn($t) -> $t>(&self, f: F, order: Ordering) -> $t {
+                let mut prev = 0;
+                loop {
+                    let found = self.compare_exchange_raw(prev, f(prev), order);
+                    if found == prev {
+                        return prev;
+                    }
+                    prev = found;
+                }
+            }
+
+            #[inline]
+            pub fn load(&self, order: Ordering) -> $t {
+                self.compare_exchange_raw(0, 0, order)
+            }
+
+            #[inline]
+            pub fn store(&self, val: $t, order: Ordering) {
+                self.update(|_| val, order);
+            }
+
+            #[inline]
+            pub fn swap(&self, val: $t, order: Ordering) -> $t {
+                self.update(|_| val, order)
+            }
+
+            #[inline]
+            pub fn compare_exchange(
+                &self,
+                current: $t,
+                new: $t,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$t, $t> {
+                let found = self.compare_exchange_raw(current, new, cas_ordering(success, failure));
+                if found == current {
+                    Ok(found)
+                } else {
+                    Err(found)
+                }
+            }
+
+            // `amocas.d` never fails spuriously.
+            #[inline]
+            pub fn compare_exchange_weak(
+                &self,
+                current: $t,
+                new: $t,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$t, $t> {
+                self.compare_exchange(current, new, success, failure)
+            }
+
+            #[inline]
+            pub fn fetch_add(&self, val: $t, order: Ordering) -> $t {
+                self.update(|x| x.wrapping_add(val), order)
+            }
+
+            #[inline]
+            pub fn fetch_sub(&self, val: $t, order: Ordering) -> $t {
+                self.update(|x| x.wrapping_sub(val), order)
+            }
+
+            #[inline]
+            pub fn fetch_and(&self, val: $t, order: Ordering) -> $t {
+                self.update(|x| x & val, order)
+            }
+
+            #[inline]
+            pub fn fetch_or(&self, val: $t, order: Ordering) -> $t {
+                self.update(|x| x | val, order)
+            }
+
+            #[inline]
+            pub fn fetch_xor(&self, val: $t, order: Ordering) -> $t {
+                self.update(|x| x ^ val, order)
+            }
+
+            #[inline]
+            pub fn fetch_min(&self, val: $t, order: Ordering) -> $t {
+                self.update(|x| x.min(val), order)
+            }
+
+            #[inline]
+            pub fn fetch_max(&self, val: $t, order: Ordering) -> $t {
+                self.update(|x| x.max(val), order)
+            }
+        }
+    )*);
+}
+
+atomic_int! {
+    AtomicU64 u64
+    AtomicI64 i64
+}
+
+// Building for a target with the extension checks that 8-byte values are
+// dispatched here.
+#[cfg(zacas_64)]
+const _: () = assert!(ops::atomic_is_lock_free::<u64>() && ops::atomic_is_lock_free::<i64>());
+
+#[cfg(test)]
+mod tests {
+    use super::{cas_ordering, AtomicI64, AtomicU64};
+    use core::sync::atomic::Ordering::*;
+    use std::thread;
+
+    #[test]
+    fn orderings() {
+        let all = [Relaxed, Release, Acquire, AcqRel, SeqCst];
+        let failures = [Relaxed, Acquire, SeqCst];
+        let expected = [
+            [Relaxed, Acquire, SeqCst],
+            [Release, AcqRel, SeqCst],
+            [Acquire, Acquire, SeqCst],
+            [AcqRel, AcqRel, SeqCst],
+            [SeqCst, SeqCst, SeqCst],
+        ];
+        for (&success, row) in all.iter().zip(&expected) {
+            for (&failure, &order) in failures.iter().zip(row) {
+                assert_eq!(cas_ordering(success, failure), order);
+            }
+        }
+    }
+
+    #[test]
+    fn operations() {
+        let mut x = u64::MAX - 1;
+        let a = unsafe { AtomicU64::from_ptr(&mut x) };
+        assert_eq!(a.load(SeqCst), u64::MAX - 1);
+        assert_eq!(a.fetch_add(3, AcqRel), u64::MAX - 1);
+        assert_eq!(a.fetch_sub(1 << 32, Release), 1);
+        assert_eq!(a.swap(7, SeqCst), 1u64.wrapping_sub(1 << 32));
+        assert_eq!(a.fetch_or(1 << 40, Relaxed), 7);
+        assert_eq!(a.fetch_and(!1, Acquire), 1 << 40 | 7);
+        assert_eq!(a.fetch_xor(0xff, SeqCst), 1 << 40 | 6);
+        assert_eq!(a.fetch_max(u64::MAX / 2, SeqCst), 1 << 40 | 0xf9);
+        assert_eq!(a.fetch_min(5, SeqCst), u64::MAX / 2);
+        assert_eq!(a.compare_exchange(6, 8, SeqCst, SeqCst), Err(5));
+        assert_eq!(a.compare_exchange(5, 8, Release, Relaxed), Ok(5));
+        assert_eq!(a.compare_exchange_weak(8, 0, Relaxed, Acquire), Ok(8));
+        a.store(1 << 63, Release);
+        assert_eq!(a.load(Relaxed), 1 << 63);
+        // A load is a compare-exchange of zero with zero, which must leave
+        // both zero and other values alone.
+        a.store(0, SeqCst);
+        assert_eq!(a.load(Acquire), 0);
+        assert_eq!(x, 0);
+
+        let mut y = -1i64;
+        let b = unsafe { AtomicI64::from_ptr(&mut y) };
+        assert_eq!(b.fetch_min(i64::MIN, SeqCst), -1);
+        assert_eq!(b.fetch_max(3, SeqCst), i64::MIN);
+        assert_eq!(b.fetch_sub(4, SeqCst), 3);
+        assert_eq!(b.load(SeqCst), -1);
+    }
+
+    #[test]
+    fn contention() {
+        let mut x = 0u64;
+        let p = &mut x as *mut u64 as usize;
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(move || {
+                    let a = unsafe { AtomicU64::from_ptr(p as *mut u64) };
+                    for _ in 0..1000 {
+                        a.fetch_add((1 << 32) | 1, Relaxed);
+                    }
+                });
+            }
+        });
+        assert_eq!(x, (4000 << 32) | 4000);
+    }
+}
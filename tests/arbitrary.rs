@@ -0,0 +1,96 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Builds structures with atomic fields from fuzzer-style input, and checks
+// that they hold the values the same input produces for the plain types.
+#![cfg(feature = "arbitrary")]
+
+extern crate arbitrary;
+extern crate atomic;
+
+use arbitrary::{Arbitrary, Unstructured};
+use atomic::{Atomic, AtomicArray, AtomicFlag, AtomicSlot, CachePadded};
+use std::sync::atomic::Ordering::*;
+
+struct Fields {
+    count: Atomic<u32>,
+    enabled: Atomic<bool>,
+    ratio: Atomic<f64>,
+    // Too big to be lock-free.
+    key: Atomic<[u8; 24]>,
+    counters: AtomicArray<i16, 3>,
+    padded: CachePadded<Atomic<u64>>,
+    flag: AtomicFlag,
+    name: AtomicSlot<String>,
+}
+
+type Plain = (u32, bool, f64, [u8; 24], [i16; 3], u64, bool, String);
+
+impl<'a> Arbitrary<'a> for Fields {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Fields {
+            count: u.arbitrary()?,
+            enabled: u.arbitrary()?,
+            ratio: u.arbitrary()?,
+            key: u.arbitrary()?,
+            counters: u.arbitrary()?,
+            padded: u.arbitrary()?,
+            flag: u.arbitrary()?,
+            name: u.arbitrary()?,
+        })
+    }
+}
+
+// xorshift64*, to get varied input without depending on a random crate.
+fn next(state: &mut u64) -> u64 {
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+#[test]
+fn fields_match_source() {
+    let mut state = 0x9e37_79b9_7f4a_7c15;
+    for len in 0..512 {
+        let bytes: Vec<u8> = (0..len).map(|_| next(&mut state) as u8).collect();
+        let fields = Fields::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+        let plain = Plain::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+        assert_eq!(fields.count.load(SeqCst), plain.0);
+        assert_eq!(fields.enabled.load(SeqCst), plain.1);
+        // Compared by their bits, since the input can make NaNs.
+        assert_eq!(fields.ratio.load(SeqCst).to_bits(), plain.2.to_bits());
+        assert_eq!(fields.key.load(SeqCst), plain.3);
+        assert_eq!(fields.counters.iter_load(SeqCst), plain.4);
+        assert_eq!(fields.padded.load(SeqCst), plain.5);
+        assert_eq!(fields.flag.test(SeqCst), plain.6);
+        assert_eq!(fields.name.into_inner(), plain.7);
+    }
+}
+
+#[test]
+fn size_hints_are_delegated() {
+    assert_eq!(Atomic::<u32>::size_hint(0), u32::size_hint(0));
+    assert_eq!(Atomic::<[u8; 24]>::size_hint(0), <[u8; 24]>::size_hint(0));
+    assert_eq!(
+        AtomicArray::<i16, 3>::size_hint(0),
+        <[i16; 3]>::size_hint(0)
+    );
+    assert_eq!(AtomicFlag::size_hint(0), bool::size_hint(0));
+    assert_eq!(
+        AtomicSlot::<String>::try_size_hint(0).unwrap(),
+        String::try_size_hint(0).unwrap()
+    );
+}
+
+#[test]
+fn take_rest() {
+    let bytes = [1, 2, 3, 4, 5, 6, 7, 8];
+    let a = Atomic::<u64>::arbitrary_take_rest(Unstructured::new(&bytes)).unwrap();
+    let x = u64::arbitrary_take_rest(Unstructured::new(&bytes)).unwrap();
+    assert_eq!(a.into_inner(), x);
+}
@@ -0,0 +1,24 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Checks that `assert_lock_free!` accepts lock-free payloads and rejects
+// others at compile time.
+//
+// The test cases are built for the host, and the passing one expects 64-bit
+// atomics, so this only runs on x86_64. The expected compiler output depends
+// on the compiler version, so it can be updated with `TRYBUILD=overwrite`.
+// Miri can't run the compiler.
+#![cfg(all(target_arch = "x86_64", not(miri)))]
+
+extern crate trybuild;
+
+#[test]
+fn assert_lock_free() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/assert_lock_free/lock_free.rs");
+    t.compile_fail("tests/assert_lock_free/not_lock_free.rs");
+}
@@ -0,0 +1,13 @@
+#[macro_use]
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+
+assert_lock_free!(u64);
+
+static COUNTER: Atomic<u64> = Atomic::new(0);
+
+fn main() {
+    assert_lock_free!(u32, "checked inside a function");
+    COUNTER.fetch_add(1, Ordering::Relaxed);
+}
@@ -0,0 +1,10 @@
+#[macro_use]
+extern crate atomic;
+
+#[derive(Copy, Clone)]
+struct Big([u64; 4]);
+
+assert_lock_free!(Big);
+assert_lock_free!([u8; 32], "the payload must fit in a native atomic");
+
+fn main() {}
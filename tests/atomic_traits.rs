@@ -0,0 +1,117 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Uses `Atomic<T>` the way downstream code generic over the atomic-traits
+// traits would, side by side with the standard atomic types.
+#![cfg(feature = "atomic-traits")]
+
+extern crate atomic;
+extern crate atomic_traits;
+
+use atomic::Atomic;
+use atomic_traits::fetch::{Add, Nand, Update};
+use atomic_traits::{AsPtr, Bitwise, FromPtr, NumOps};
+use std::sync::atomic::Ordering::*;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32};
+use std::thread;
+
+fn count<A>(threads: usize, increments: u32) -> u32
+where
+    A: atomic_traits::Atomic<Type = u32> + Add<Type = u32> + Sync,
+{
+    let a = A::new(0);
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                for _ in 0..increments {
+                    a.fetch_add(1, Relaxed);
+                }
+            });
+        }
+    });
+    a.into_inner()
+}
+
+fn exercise_u32<A>(x: u32, y: u32) -> u32
+where
+    A: atomic_traits::Atomic<Type = u32> + NumOps + Bitwise + AsPtr,
+{
+    let a = A::new(x);
+    assert_eq!(a.load(SeqCst), x);
+    a.store(y, SeqCst);
+    assert_eq!(a.swap(x, SeqCst), y);
+    assert_eq!(a.compare_exchange(x, y, SeqCst, SeqCst), Ok(x));
+    assert_eq!(a.compare_exchange(x, y, SeqCst, SeqCst), Err(y));
+    assert_eq!(a.fetch_max(x, SeqCst), y);
+    assert_eq!(a.fetch_min(x, SeqCst), y);
+    assert_eq!(a.fetch_update(SeqCst, SeqCst, |_| None), Err(x));
+    assert_eq!(a.fetch_update(SeqCst, SeqCst, |_| Some(y)), Ok(x));
+    assert_eq!(a.fetch_and(x, SeqCst), y);
+    assert_eq!(a.fetch_or(y, SeqCst), y & x);
+    assert_eq!(a.fetch_xor(x, SeqCst), y);
+    assert_eq!(a.fetch_nand(y, SeqCst), y ^ x);
+    assert_eq!(unsafe { *a.as_ptr() }, !((y ^ x) & y));
+    a.into_inner()
+}
+
+fn exercise_i64<A: atomic_traits::Atomic<Type = i64> + NumOps + Nand<Type = i64>>() -> i64 {
+    let a = A::new(-5);
+    assert_eq!(a.fetch_max(-7, SeqCst), -5);
+    assert_eq!(a.fetch_min(-7, SeqCst), -5);
+    assert_eq!(a.fetch_sub(1, SeqCst), -7);
+    assert_eq!(a.fetch_nand(-1, SeqCst), -8);
+    a.into_inner()
+}
+
+fn exercise_bool<A: atomic_traits::Atomic<Type = bool> + Bitwise + Update<Type = bool>>() {
+    let a = A::new(true);
+    assert!(a.fetch_nand(true, SeqCst));
+    assert!(!a.fetch_or(true, SeqCst));
+    assert!(a.fetch_xor(true, SeqCst));
+    assert!(!a.fetch_and(true, SeqCst));
+    assert_eq!(a.fetch_update(SeqCst, SeqCst, |x| Some(!x)), Ok(false));
+    assert!(a.into_inner());
+}
+
+#[test]
+fn interchangeable_with_std() {
+    assert_eq!(count::<AtomicU32>(4, 1000), 4000);
+    assert_eq!(count::<Atomic<u32>>(4, 1000), 4000);
+
+    assert_eq!(exercise_u32::<AtomicU32>(0b0110, 0b1100), !0b1000);
+    assert_eq!(exercise_u32::<Atomic<u32>>(0b0110, 0b1100), !0b1000);
+    assert_eq!(exercise_i64::<AtomicI64>(), 7);
+    assert_eq!(exercise_i64::<Atomic<i64>>(), 7);
+
+    exercise_bool::<AtomicBool>();
+    exercise_bool::<Atomic<bool>>();
+}
+
+#[test]
+fn pointer() {
+    let mut x = 1u8;
+    let mut y = 2u8;
+    let (px, py): (*mut u8, *mut u8) = (&mut x, &mut y);
+    let a = <Atomic<*mut u8> as atomic_traits::Atomic>::new(px);
+    let f = |p: *mut u8| if p == px { Some(py) } else { None };
+    assert_eq!(Update::fetch_update(&a, SeqCst, SeqCst, f), Ok(px));
+    assert_eq!(Update::fetch_update(&a, SeqCst, SeqCst, f), Err(py));
+    assert_eq!(atomic_traits::Atomic::load(&a, SeqCst), py);
+}
+
+fn bump_in_place<A: FromPtr<Type = u32> + NumOps>(x: &mut u32) {
+    let a = unsafe { A::from_ptr(x) };
+    a.fetch_add(1, SeqCst);
+}
+
+#[test]
+fn from_ptr() {
+    let mut x = 1;
+    bump_in_place::<AtomicU32>(&mut x);
+    bump_in_place::<Atomic<u32>>(&mut x);
+    assert_eq!(x, 3);
+}
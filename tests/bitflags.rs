@@ -0,0 +1,107 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Uses `Atomic<T>` with flags generated by `bitflags!`.
+#![cfg(feature = "bitflags")]
+
+extern crate atomic;
+#[macro_use]
+extern crate bitflags;
+
+use atomic::Atomic;
+use std::sync::atomic::Ordering::*;
+use std::thread;
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct Flags: u32 {
+        const A = 1 << 0;
+        const B = 1 << 1;
+        const C = 1 << 2;
+        const D = 1 << 3;
+    }
+}
+
+#[test]
+fn insert_remove_toggle() {
+    let a = Atomic::new(Flags::A);
+    assert_eq!(a.fetch_insert(Flags::B | Flags::C, SeqCst), Flags::A);
+    assert_eq!(
+        a.fetch_remove(Flags::A | Flags::D, SeqCst),
+        Flags::A | Flags::B | Flags::C
+    );
+    assert_eq!(
+        a.fetch_toggle(Flags::C | Flags::D, SeqCst),
+        Flags::B | Flags::C
+    );
+    assert_eq!(a.load(SeqCst), Flags::B | Flags::D);
+    assert!(a.load_contains(Flags::B | Flags::D, SeqCst));
+    assert!(!a.load_contains(Flags::A | Flags::B, SeqCst));
+    assert!(a.load_contains(Flags::empty(), SeqCst));
+}
+
+// Bits which aren't defined flags are kept, like `from_bits_retain` does.
+#[test]
+fn unknown_bits_retained() {
+    let unknown = Flags::from_bits_retain(1 << 8);
+    let a = Atomic::new(Flags::A | unknown);
+    assert_eq!(a.fetch_insert(Flags::B, SeqCst).bits(), 1 << 8 | 1);
+    assert_eq!(a.fetch_remove(Flags::A, SeqCst).bits(), 1 << 8 | 0b11);
+    assert_eq!(
+        a.fetch_toggle(Flags::from_bits_retain(1 << 9), SeqCst)
+            .bits(),
+        1 << 8 | 0b10
+    );
+    assert_eq!(a.load(SeqCst).bits(), 0b11 << 8 | 0b10);
+    assert_eq!(a.fetch_remove(unknown, SeqCst).bits(), 0b11 << 8 | 0b10);
+    assert_eq!(a.load(SeqCst).bits(), 1 << 9 | 0b10);
+    assert!(a.load_contains(Flags::from_bits_retain(1 << 9), SeqCst));
+}
+
+// Each thread owns one flag and sets and clears it repeatedly, so every other
+// flag must be untouched by its updates, and each flag ends up in the state
+// its thread left it in.
+#[test]
+fn concurrent_insert_remove() {
+    let a = Atomic::new(Flags::empty());
+    let flags = [Flags::A, Flags::B, Flags::C, Flags::D];
+    thread::scope(|s| {
+        for (i, &flag) in flags.iter().enumerate() {
+            let a = &a;
+            s.spawn(move || {
+                for _ in 0..1000 {
+                    assert!(!a.fetch_insert(flag, AcqRel).contains(flag));
+                    assert!(a.fetch_remove(flag, AcqRel).contains(flag));
+                    assert!(!a.fetch_toggle(flag, AcqRel).contains(flag));
+                    assert!(a.fetch_toggle(flag, AcqRel).contains(flag));
+                }
+                if i % 2 == 0 {
+                    a.fetch_insert(flag, AcqRel);
+                }
+            });
+        }
+    });
+    assert_eq!(a.load(SeqCst), Flags::A | Flags::C);
+}
+
+// 128-bit flags are only lock-free on some targets, and take the fallback lock
+// elsewhere.
+#[test]
+fn wide_flags() {
+    bitflags! {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        struct Wide: u128 {
+            const LOW = 1;
+            const HIGH = 1 << 127;
+        }
+    }
+
+    let a = Atomic::new(Wide::LOW);
+    assert_eq!(a.fetch_insert(Wide::HIGH, SeqCst), Wide::LOW);
+    assert_eq!(a.fetch_toggle(Wide::all(), SeqCst), Wide::all());
+    assert_eq!(a.load(SeqCst), Wide::empty());
+}
@@ -0,0 +1,71 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Checks the tables of the build script for targets which can't be tested on
+// the host, by giving it the environment Cargo gives it for those targets.
+//
+// Cargo doesn't pass the unstable `target_has_atomic_load_store` and
+// `target_has_atomic_primitive_alignment` cfgs on to build scripts, so on
+// targets without a compare-exchange the tables are all there is.
+
+// The build script is included rather than declared as a module, so that its
+// functions can stay private.
+#[allow(dead_code)]
+mod build {
+    include!("../build.rs");
+
+    fn set_target(target: &str, arch: &str, pointer_width: &str) {
+        for var in &[
+            "CARGO_CFG_TARGET_HAS_ATOMIC",
+            "CARGO_CFG_TARGET_HAS_ATOMIC_LOAD_STORE",
+            "CARGO_CFG_TARGET_HAS_ATOMIC_PRIMITIVE_ALIGNMENT",
+            "CARGO_CFG_TARGET_HAS_ATOMIC_EQUAL_ALIGNMENT",
+            "CARGO_CFG_TARGET_FEATURE",
+            "CARGO_CFG_TARGET_OS",
+            "CARGO_CFG_NO_ATOMIC_CAS",
+        ] {
+            env::remove_var(var);
+        }
+        env::set_var("TARGET", target);
+        env::set_var("CARGO_CFG_TARGET_ARCH", arch);
+        env::set_var("CARGO_CFG_TARGET_POINTER_WIDTH", pointer_width);
+    }
+
+    fn strings(widths: &[&str]) -> Vec<String> {
+        widths.iter().map(|w| w.to_string()).collect()
+    }
+
+    // Everything is in one test, since tests running in parallel would see
+    // each other's environment.
+    #[test]
+    fn sixteen_bit_targets() {
+        // AVR has no compare-exchange, and loads and stores of up to 16 bits,
+        // which are only aligned to a byte like everything else.
+        set_target("avr-none", "avr", "16");
+        assert!(target_atomic_widths().is_empty());
+        assert_eq!(target_load_store_widths(), strings(&["8", "16"]));
+        assert_eq!(
+            equal_alignment_widths(&strings(&["8", "16"]), "16"),
+            strings(&["8"])
+        );
+
+        // MSP430 has no atomics at all.
+        set_target("msp430-none-elf", "msp430", "16");
+        assert!(target_atomic_widths().is_empty());
+        assert!(target_load_store_widths().is_empty());
+
+        // The compiler's answer wins where there is one.
+        env::set_var("CARGO_CFG_TARGET_HAS_ATOMIC_LOAD_STORE", "8,16,ptr");
+        assert_eq!(target_load_store_widths(), strings(&["8", "16"]));
+
+        // `--cfg no_atomic_cas` on a 16-bit target only keeps loads and stores
+        // of up to 16 bits.
+        set_target("msp430-none-elf", "msp430", "16");
+        env::set_var("CARGO_CFG_NO_ATOMIC_CAS", "");
+        assert_eq!(target_load_store_widths(), strings(&["8", "16"]));
+    }
+}
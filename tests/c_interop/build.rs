@@ -0,0 +1,33 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Writes `atomic::ffi::C_HEADER` to `atomic.h` and compiles `race.c` against
+// it, and tells the tests whether it has the 64-bit functions. The build
+// script runs on the host, so this is only right when the tests are built for
+// the host too.
+
+extern crate atomic;
+extern crate cc;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-changed=race.c");
+    println!("cargo:rustc-check-cfg=cfg(atomic_u64)");
+    if atomic::ffi::C_HEADER.contains("#define ATOMIC_RS_U64") {
+        println!("cargo:rustc-cfg=atomic_u64");
+    }
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out_dir.join("atomic.h"), atomic::ffi::C_HEADER).unwrap();
+    cc::Build::new()
+        .file("race.c")
+        .include(&out_dir)
+        .flag_if_supported("-std=c11")
+        .compile("race");
+}
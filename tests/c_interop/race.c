@@ -0,0 +1,87 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The C side of the tests: the same struct as `Shared` in src/lib.rs, with
+// functions which operate on it with the C11 atomics or with the functions
+// exported by `atomic::ffi`.
+
+#include <stddef.h>
+
+#include "atomic.h"
+
+struct shared {
+    _Atomic uint32_t counter;
+    _Atomic uint8_t flag;
+    _Atomic uint16_t bits;
+#ifdef ATOMIC_RS_U64
+    _Atomic uint64_t total;
+#endif
+    uint32_t data;
+};
+
+void c_layout(size_t out[6]) {
+    out[0] = sizeof(struct shared);
+    out[1] = _Alignof(struct shared);
+    out[2] = offsetof(struct shared, flag);
+    out[3] = offsetof(struct shared, bits);
+#ifdef ATOMIC_RS_U64
+    out[4] = offsetof(struct shared, total);
+#else
+    out[4] = 0;
+#endif
+    out[5] = offsetof(struct shared, data);
+}
+
+void c_increment(struct shared *s, uint32_t n) {
+    for (uint32_t i = 0; i < n; i++) {
+        atomic_fetch_add_explicit(&s->counter, 1, memory_order_relaxed);
+        atomic_fetch_xor_explicit(&s->bits, (uint16_t)(1u << (i % 16)), memory_order_acq_rel);
+#ifdef ATOMIC_RS_U64
+        atomic_fetch_add_explicit(&s->total, 1ull << 32, memory_order_seq_cst);
+#endif
+    }
+}
+
+// A compare-exchange loop, which has to retry whenever Rust got in first.
+void c_cas_increment(struct shared *s, uint32_t n) {
+    for (uint32_t i = 0; i < n; i++) {
+        uint32_t current = atomic_load_explicit(&s->counter, memory_order_relaxed);
+        while (!atomic_compare_exchange_weak_explicit(&s->counter, &current, current + 1,
+                                                      memory_order_acq_rel,
+                                                      memory_order_relaxed)) {
+        }
+    }
+}
+
+// The same increments through the functions exported by Rust.
+void c_increment_via_rust(struct shared *s, uint32_t n) {
+    for (uint32_t i = 0; i < n; i++) {
+        uint32_t current = atomic_u32_load(&s->counter, memory_order_consume);
+        if (i % 2 == 0) {
+            atomic_u32_fetch_add(&s->counter, 1, memory_order_relaxed);
+        } else {
+            while (!atomic_u32_compare_exchange_strong(&s->counter, &current, current + 1,
+                                                       memory_order_seq_cst,
+                                                       memory_order_acquire)) {
+            }
+        }
+    }
+}
+
+// Publishes `data` with a release store of the flag.
+void c_publish(struct shared *s, uint32_t value) {
+    s->data = value;
+    atomic_store_explicit(&s->flag, 1, memory_order_release);
+}
+
+// Waits for the flag with acquire loads, and returns the data published with
+// it.
+uint32_t c_receive(struct shared *s) {
+    while (atomic_load_explicit(&s->flag, memory_order_acquire) == 0) {
+    }
+    return s->data;
+}
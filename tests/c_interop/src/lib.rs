@@ -0,0 +1,144 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Checks that `Atomic<T>` and C11 `_Atomic` types can operate on the same
+// memory: the C functions in race.c run on some threads while Rust operates
+// on the same struct on others, and the results must add up.
+
+extern crate atomic;
+
+use atomic::Atomic;
+use std::cell::UnsafeCell;
+
+// `struct shared` in race.c.
+#[repr(C)]
+pub struct Shared {
+    pub counter: Atomic<u32>,
+    pub flag: Atomic<u8>,
+    pub bits: Atomic<u16>,
+    #[cfg(atomic_u64)]
+    pub total: Atomic<u64>,
+    pub data: UnsafeCell<u32>,
+}
+
+unsafe impl Sync for Shared {}
+
+extern "C" {
+    pub fn c_layout(out: *mut [usize; 6]);
+    pub fn c_increment(s: *const Shared, n: u32);
+    pub fn c_cas_increment(s: *const Shared, n: u32);
+    pub fn c_increment_via_rust(s: *const Shared, n: u32);
+    pub fn c_publish(s: *const Shared, value: u32);
+    pub fn c_receive(s: *const Shared) -> u32;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atomic::Ordering::*;
+    use std::mem;
+    use std::thread;
+
+    const ROUNDS: u32 = 100_000;
+
+    fn shared() -> Shared {
+        Shared {
+            counter: Atomic::new(0),
+            flag: Atomic::new(0),
+            bits: Atomic::new(0),
+            #[cfg(atomic_u64)]
+            total: Atomic::new(0),
+            data: UnsafeCell::new(0),
+        }
+    }
+
+    #[test]
+    fn layout() {
+        let mut c = [0; 6];
+        unsafe { c_layout(&mut c) };
+        let s = shared();
+        let base = &s as *const Shared as usize;
+        let offset = |p: *const u8| p as usize - base;
+        #[cfg(atomic_u64)]
+        let total = offset(&s.total as *const Atomic<u64> as *const u8);
+        #[cfg(not(atomic_u64))]
+        let total = 0;
+        assert_eq!(
+            c,
+            [
+                mem::size_of::<Shared>(),
+                mem::align_of::<Shared>(),
+                offset(&s.flag as *const Atomic<u8> as *const u8),
+                offset(&s.bits as *const Atomic<u16> as *const u8),
+                total,
+                offset(s.data.get() as *const u8),
+            ]
+        );
+    }
+
+    // Rust and C increment the same counters, and the C side uses plain
+    // read-modify-writes, compare-exchange loops and the functions exported
+    // by Rust.
+    #[test]
+    fn race() {
+        let s = shared();
+        thread::scope(|scope| {
+            scope.spawn(|| unsafe { c_increment(&s, ROUNDS) });
+            scope.spawn(|| unsafe { c_cas_increment(&s, ROUNDS) });
+            scope.spawn(|| unsafe { c_increment_via_rust(&s, ROUNDS) });
+            scope.spawn(|| {
+                for i in 0..ROUNDS {
+                    s.counter.fetch_add(1, Relaxed);
+                    s.bits.fetch_xor(1 << (i % 16), AcqRel);
+                    #[cfg(atomic_u64)]
+                    s.total.fetch_add(1, SeqCst);
+                }
+            });
+            scope.spawn(|| {
+                for _ in 0..ROUNDS {
+                    let mut current = s.counter.load(Relaxed);
+                    while let Err(x) =
+                        s.counter
+                            .compare_exchange_weak(current, current + 1, AcqRel, Relaxed)
+                    {
+                        current = x;
+                    }
+                }
+            });
+        });
+        assert_eq!(s.counter.load(SeqCst), 5 * ROUNDS);
+        // Each bit was flipped an even number of times by each side.
+        assert_eq!(s.bits.load(SeqCst), 0);
+        #[cfg(atomic_u64)]
+        assert_eq!(
+            s.total.load(SeqCst),
+            (u64::from(ROUNDS) << 32) | u64::from(ROUNDS)
+        );
+    }
+
+    // A release store in C synchronizes with an acquire load in Rust, and the
+    // other way around.
+    #[test]
+    fn message_passing() {
+        for i in 0..1000 {
+            let s = shared();
+            thread::scope(|scope| {
+                scope.spawn(|| unsafe { c_publish(&s, i) });
+                while s.flag.load(Acquire) == 0 {}
+                assert_eq!(unsafe { *s.data.get() }, i);
+            });
+
+            let s = shared();
+            thread::scope(|scope| {
+                let receiver = scope.spawn(|| unsafe { c_receive(&s) });
+                unsafe { *s.data.get() = i };
+                s.flag.store(1, Release);
+                assert_eq!(receiver.join().unwrap(), i);
+            });
+        }
+    }
+}
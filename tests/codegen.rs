@@ -0,0 +1,191 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Checks that the alternative backends of the ops module compile down to the
+// same instructions as the standard atomic types, and that volatile accesses
+// are never merged, by building `examples/codegen.rs` in release mode and
+// reading its assembly.
+//
+// Run with `cargo test --test codegen`, and with `--features portable-atomic`
+// or `cargo +nightly test --features nightly --test codegen` for the backends.
+// The nightly run also builds the example for AArch64, with `-Z build-std`,
+// which needs the `rust-src` component. The example is
+// built in its own target directory for each target and set of features, so
+// this takes a while the first time. Miri can't run cargo.
+#![cfg(all(target_arch = "x86_64", not(miri)))]
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Builds the example with `features` and returns its assembly.
+fn assembly(features: &str) -> String {
+    assembly_for(None, features)
+}
+
+// Builds the example for `target`, or the host if it is `None`, and returns
+// its assembly. Other targets build the standard library themselves, so that
+// they don't need to be installed.
+fn assembly_for(target: Option<&str>, features: &str) -> String {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = if features.is_empty() {
+        "default"
+    } else {
+        features
+    };
+    let target_dir = root
+        .join("target")
+        .join("codegen")
+        .join(target.unwrap_or("host"))
+        .join(dir);
+    let mut command = Command::new(env!("CARGO"));
+    command.current_dir(root).args(["rustc", "--release"]);
+    if !features.is_empty() {
+        command.args(["--features", features]);
+    }
+    if let Some(target) = target {
+        command.args(["--target", target, "-Z", "build-std"]);
+    }
+    command
+        .args(["--example", "codegen", "--target-dir"])
+        .arg(&target_dir)
+        .args(["--", "--emit", "asm"]);
+    if target.is_none() {
+        command.args(["-C", "llvm-args=-x86-asm-syntax=intel"]);
+    }
+    assert!(command.status().unwrap().success());
+
+    let examples = match target {
+        Some(target) => target_dir.join(target),
+        None => target_dir,
+    }
+    .join("release")
+    .join("examples");
+    let asm = fs::read_dir(&examples)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "s"))
+        .max_by_key(|path| fs::metadata(path).unwrap().modified().unwrap())
+        .expect("no assembly was emitted");
+    fs::read_to_string(asm).unwrap()
+}
+
+// Returns the instructions of the function `name` in `asm`, without directives,
+// labels and the comments around inline assembly.
+fn instructions(asm: &str, name: &str) -> Vec<String> {
+    let start = format!("{}:", name);
+    asm.lines()
+        .map(str::trim)
+        .skip_while(|line| *line != start)
+        .skip(1)
+        .take_while(|line| *line != ".cfi_endproc")
+        .filter(|line| {
+            !line.is_empty()
+                && !line.starts_with('.')
+                && !line.starts_with('#')
+                && !line.starts_with("//")
+                && !line.ends_with(':')
+        })
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect()
+}
+
+#[test]
+#[cfg(feature = "nightly")]
+fn load_relaxed_u32_is_a_single_mov() {
+    assert_eq!(
+        instructions(&assembly("nightly"), "load_relaxed_u32"),
+        ["mov eax, dword ptr [rdi]", "ret"]
+    );
+}
+
+// The default build uses the standard atomic types whether or not the
+// `portable-atomic` feature exists, and the types of `portable-atomic` compile
+// to the same instructions for every width which x86_64 has natively.
+#[test]
+#[cfg(feature = "portable-atomic")]
+fn portable_atomic_matches_the_default_build() {
+    let default = assembly("");
+    assert_eq!(
+        instructions(&default, "load_relaxed_u32"),
+        ["mov eax, dword ptr [rdi]", "ret"]
+    );
+    assert_eq!(
+        instructions(&default, "store_release_u32"),
+        ["mov dword ptr [rdi], esi", "ret"]
+    );
+    assert_eq!(
+        instructions(&default, "fetch_add_relaxed_u64"),
+        ["mov rax, rsi", "lock xadd qword ptr [rdi], rax", "ret"]
+    );
+
+    let portable = assembly("portable-atomic");
+    for name in &[
+        "load_relaxed_u32",
+        "store_release_u32",
+        "swap_acq_rel_u64",
+        "compare_exchange_u32",
+        "fetch_add_relaxed_u64",
+    ] {
+        assert!(
+            !instructions(&default, name).is_empty(),
+            "{} is missing",
+            name
+        );
+        assert_eq!(
+            instructions(&default, name),
+            instructions(&portable, name),
+            "{}",
+            name
+        );
+    }
+}
+
+// Two volatile loads of the same value are two loads, even though the second
+// could be replaced by the first, and so are two volatile stores of it.
+#[test]
+fn volatile_accesses_are_not_merged() {
+    let asm = assembly("");
+    let loads = instructions(&asm, "load_volatile_twice_u32");
+    assert_eq!(
+        loads
+            .iter()
+            .filter(|i| i.starts_with("mov") && i.ends_with("dword ptr [rdi]"))
+            .count(),
+        2,
+        "{:?}",
+        loads
+    );
+    assert_eq!(
+        instructions(&asm, "store_volatile_twice_u64"),
+        [
+            "mov qword ptr [rdi], rsi",
+            "mov qword ptr [rdi], rsi",
+            "ret"
+        ]
+    );
+}
+
+#[test]
+#[cfg(feature = "nightly")]
+fn aarch64_volatile_accesses_are_not_merged() {
+    let asm = assembly_for(Some("aarch64-unknown-linux-gnu"), "");
+    let loads = instructions(&asm, "load_volatile_twice_u32");
+    assert_eq!(
+        loads
+            .iter()
+            .filter(|i| i.starts_with("ldar w") && i.ends_with("[x0]"))
+            .count(),
+        2,
+        "{:?}",
+        loads
+    );
+    assert_eq!(
+        instructions(&asm, "store_volatile_twice_u64"),
+        ["str x1, [x0]", "str x1, [x0]", "ret"]
+    );
+}
@@ -0,0 +1,50 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The functions whose code tests/symbols.rs looks at. `main` calls them
+// through `black_box`, so that they are linked into the binary.
+
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+use std::hint::black_box;
+
+#[no_mangle]
+pub fn codegen_load(a: &Atomic<u32>) -> u32 {
+    a.load(Ordering::Relaxed)
+}
+
+#[no_mangle]
+pub fn codegen_store(a: &Atomic<u32>, val: u32) {
+    a.store(val, Ordering::Relaxed)
+}
+
+#[no_mangle]
+pub fn codegen_fetch_add(a: &Atomic<u32>, val: u32) -> u32 {
+    a.fetch_add(val, Ordering::Relaxed)
+}
+
+#[cfg(feature = "oversized")]
+#[no_mangle]
+pub fn codegen_oversized_load(a: &Atomic<[u32; 3]>) -> [u32; 3] {
+    a.load(Ordering::Relaxed)
+}
+
+fn main() {
+    let a = Atomic::new(1u32);
+    black_box(codegen_store as fn(&Atomic<u32>, u32))(&a, 2);
+    black_box(codegen_fetch_add as fn(&Atomic<u32>, u32) -> u32)(&a, 3);
+    println!("{}", black_box(codegen_load as fn(&Atomic<u32>) -> u32)(&a));
+    #[cfg(feature = "oversized")]
+    {
+        let b = Atomic::new([1u32; 3]);
+        println!(
+            "{:?}",
+            black_box(codegen_oversized_load as fn(&Atomic<[u32; 3]>) -> [u32; 3])(&b)
+        );
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Builds src/main.rs at opt-level 0 and 3 and looks at the symbols of the
+// binary: the fallback lock must not be linked into a binary which only uses
+// lock-free types, even without optimizations, and with them the functions
+// must be the bare atomic instructions.
+
+extern crate object;
+
+use object::{Object, ObjectSymbol};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Returns the name and size of every symbol defined in the binary.
+fn build(opt_level: u32, features: &str) -> Vec<(String, u64)> {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join(format!("opt-level-{}{}", opt_level, features));
+    let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .arg("build")
+        .arg("--quiet")
+        .arg("--manifest-path")
+        .arg(manifest_dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .arg("--features")
+        .arg(features)
+        .arg("--config")
+        .arg(format!("profile.dev.opt-level={}", opt_level))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let data = fs::read(target_dir.join("debug").join("atomic-codegen")).unwrap();
+    let file = object::File::parse(&*data).unwrap();
+    file.symbols()
+        .filter(|s| s.is_definition())
+        .map(|s| (s.name().unwrap().to_string(), s.size()))
+        .collect()
+}
+
+// Mangled names spell out each path segment with its length.
+fn has_fallback(symbols: &[(String, u64)]) -> bool {
+    symbols
+        .iter()
+        .any(|(name, _)| name.contains("6atomic8fallback"))
+}
+
+fn size(symbols: &[(String, u64)], name: &str) -> u64 {
+    symbols.iter().find(|(n, _)| n == name).unwrap().1
+}
+
+#[test]
+fn no_fallback_at_opt_level_0() {
+    let symbols = build(0, "");
+    assert!(!has_fallback(&symbols));
+    assert!(has_fallback(&build(0, "oversized")));
+}
+
+#[test]
+fn no_fallback_at_opt_level_3() {
+    let symbols = build(3, "");
+    assert!(!has_fallback(&symbols));
+    assert!(has_fallback(&build(3, "oversized")));
+
+    // `mov` and `ret`, or `lock xadd` with a `mov` on each side and `ret`.
+    if cfg!(target_arch = "x86_64") {
+        assert!(size(&symbols, "codegen_load") <= 4);
+        assert!(size(&symbols, "codegen_store") <= 4);
+        assert!(size(&symbols, "codegen_fetch_add") <= 12);
+    }
+}
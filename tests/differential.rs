@@ -0,0 +1,255 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Runs random sequences of operations on `Atomic<u32>` and `Atomic<u64>` and
+// on the standard atomic types, and checks that every operation returns the
+// same value.
+//
+// Normally `Atomic` uses the native instructions for these types. Running the
+// tests again with `RUSTFLAGS="--cfg force_fallback" cargo test --test
+// differential` sends every operation through the fallback lock instead, so
+// that it is checked against the same reference.
+
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64};
+use std::thread;
+
+const ORDERINGS: [Ordering; 5] = [
+    Ordering::Relaxed,
+    Ordering::Release,
+    Ordering::Acquire,
+    Ordering::AcqRel,
+    Ordering::SeqCst,
+];
+
+// A xorshift generator, so that a failing sequence can be replayed from its
+// seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn ordering(&mut self) -> Ordering {
+        ORDERINGS[self.below(ORDERINGS.len())]
+    }
+
+    // Orderings which a load, or the failure of a compare-exchange, accepts.
+    fn load_ordering(&mut self) -> Ordering {
+        [Ordering::Relaxed, Ordering::Acquire, Ordering::SeqCst][self.below(3)]
+    }
+
+    fn store_ordering(&mut self) -> Ordering {
+        [Ordering::Relaxed, Ordering::Release, Ordering::SeqCst][self.below(3)]
+    }
+}
+
+macro_rules! differential {
+    ($name:ident $t:ident $std:ident) => {
+        fn $name(seed: u64, steps: usize) {
+            let mut rng = Rng(seed);
+            let a = Atomic::<$t>::new(0);
+            let b = $std::new(0);
+            for step in 0..steps {
+                // Mostly small values, so that the compare-exchanges and the
+                // minimum and maximum often see equal ones.
+                let val = if rng.below(2) == 0 {
+                    rng.below(4) as $t
+                } else {
+                    rng.next() as $t
+                };
+                let current = b.load(Ordering::Relaxed);
+                let op = rng.below(14);
+                let (got, expected) = match op {
+                    0 => {
+                        let order = rng.load_ordering();
+                        (a.load(order), b.load(order))
+                    }
+                    1 => {
+                        let order = rng.store_ordering();
+                        a.store(val, order);
+                        b.store(val, order);
+                        (a.load(Ordering::Relaxed), b.load(Ordering::Relaxed))
+                    }
+                    2 => {
+                        let order = rng.ordering();
+                        (a.swap(val, order), b.swap(val, order))
+                    }
+                    // The expected value is the current one half of the time,
+                    // to check the previous value on success as well as on
+                    // failure.
+                    3 | 4 => {
+                        let expected = if op == 3 { current } else { val };
+                        let (success, failure) = (rng.ordering(), rng.load_ordering());
+                        let x = a.compare_exchange(expected, val ^ 1, success, failure);
+                        let y = b.compare_exchange(expected, val ^ 1, success, failure);
+                        assert_eq!(x, y, "compare_exchange, seed {} step {}", seed, step);
+                        (x.unwrap_or_else(|x| x), y.unwrap_or_else(|y| y))
+                    }
+                    // A weak compare-exchange may fail spuriously, which shows
+                    // as a failure returning the expected value. It is then
+                    // retried.
+                    5 => {
+                        let expected = if rng.below(2) == 0 { current } else { val };
+                        let (success, failure) = (rng.ordering(), rng.load_ordering());
+                        let x = loop {
+                            match a.compare_exchange_weak(expected, val, success, failure) {
+                                Err(x) if x == expected => continue,
+                                x => break x,
+                            }
+                        };
+                        let y = loop {
+                            match b.compare_exchange_weak(expected, val, success, failure) {
+                                Err(y) if y == expected => continue,
+                                y => break y,
+                            }
+                        };
+                        assert_eq!(x, y, "compare_exchange_weak, seed {} step {}", seed, step);
+                        (x.unwrap_or_else(|x| x), y.unwrap_or_else(|y| y))
+                    }
+                    6 => {
+                        let order = rng.ordering();
+                        (a.fetch_add(val, order), b.fetch_add(val, order))
+                    }
+                    7 => {
+                        let order = rng.ordering();
+                        (a.fetch_sub(val, order), b.fetch_sub(val, order))
+                    }
+                    8 => {
+                        let order = rng.ordering();
+                        (a.fetch_and(val, order), b.fetch_and(val, order))
+                    }
+                    9 => {
+                        let order = rng.ordering();
+                        (a.fetch_or(val, order), b.fetch_or(val, order))
+                    }
+                    10 => {
+                        let order = rng.ordering();
+                        (a.fetch_xor(val, order), b.fetch_xor(val, order))
+                    }
+                    11 => {
+                        let order = rng.ordering();
+                        (a.fetch_min(val, order), b.fetch_min(val, order))
+                    }
+                    12 => {
+                        let order = rng.ordering();
+                        (a.fetch_max(val, order), b.fetch_max(val, order))
+                    }
+                    // Only updates odd values, so that it fails as well.
+                    _ => {
+                        let (set, fetch) = (rng.ordering(), rng.load_ordering());
+                        let f = |x: $t| {
+                            if x % 2 == 1 {
+                                Some(x.wrapping_mul(val))
+                            } else {
+                                None
+                            }
+                        };
+                        let x = a.fetch_update(set, fetch, f);
+                        let y = b.fetch_update(set, fetch, f);
+                        assert_eq!(x, y, "fetch_update, seed {} step {}", seed, step);
+                        (x.unwrap_or_else(|x| x), y.unwrap_or_else(|y| y))
+                    }
+                };
+                assert_eq!(
+                    got, expected,
+                    "operation {}, seed {} step {}",
+                    op, seed, step
+                );
+                assert_eq!(
+                    a.load(Ordering::Relaxed),
+                    b.load(Ordering::Relaxed),
+                    "value after operation {}, seed {} step {}",
+                    op,
+                    seed,
+                    step
+                );
+            }
+        }
+    };
+}
+
+differential!(differential_u32 u32 AtomicU32);
+differential!(differential_u64 u64 AtomicU64);
+
+fn steps() -> usize {
+    if cfg!(miri) {
+        200
+    } else {
+        10_000
+    }
+}
+
+#[test]
+fn path() {
+    assert_eq!(Atomic::<u32>::is_lock_free(), !cfg!(force_fallback));
+    assert_eq!(Atomic::<u32>::IS_LOCK_FREE, !cfg!(force_fallback));
+    if cfg!(force_fallback) {
+        assert!(!Atomic::<u64>::is_lock_free());
+        assert!(!Atomic::<usize>::is_lock_free());
+        assert!(!Atomic::<bool>::is_lock_free());
+    }
+}
+
+#[test]
+fn u32_sequences() {
+    for seed in 1..=16 {
+        differential_u32(seed, steps());
+    }
+}
+
+#[test]
+fn u64_sequences() {
+    for seed in 1..=16u64 {
+        differential_u64(seed * 0x9e37_79b9, steps());
+    }
+}
+
+// Concurrent increments and bit flips, whose final result doesn't depend on
+// how they interleave.
+#[test]
+fn concurrent() {
+    let a = Atomic::<u64>::new(0);
+    let b = AtomicU64::new(0);
+    let rounds = if cfg!(miri) { 100 } else { 10_000 };
+    thread::scope(|s| {
+        for i in 0..4u64 {
+            let (a, b) = (&a, &b);
+            s.spawn(move || {
+                let mut rng = Rng(i + 1);
+                for _ in 0..rounds {
+                    let bit = 1 << (32 + rng.below(32));
+                    a.fetch_add(1, Ordering::Relaxed);
+                    b.fetch_add(1, Ordering::Relaxed);
+                    a.fetch_xor(bit, Ordering::AcqRel);
+                    b.fetch_xor(bit, Ordering::AcqRel);
+                    let mut current = a.load(Ordering::Relaxed);
+                    while let Err(x) = a.compare_exchange_weak(
+                        current,
+                        current.wrapping_add(1 << 16),
+                        Ordering::SeqCst,
+                        Ordering::Relaxed,
+                    ) {
+                        current = x;
+                    }
+                    b.fetch_add(1 << 16, Ordering::SeqCst);
+                }
+            });
+        }
+    });
+    assert_eq!(a.into_inner(), b.into_inner());
+}
@@ -0,0 +1,66 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// These tests live in their own process so that no other test can touch the
+// fallback lock table while the counters are being checked.
+#![cfg(feature = "fallback-stats")]
+
+extern crate atomic;
+
+use atomic::{fallback_stats, reset_fallback_stats, Atomic, Ordering::*};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+struct Triple(u32, u32, u32);
+
+fn acquisitions(slot: usize) -> usize {
+    fallback_stats()
+        .find(|s| s.slot == slot)
+        .unwrap()
+        .acquisitions
+}
+
+#[test]
+fn fallback_stats_count_acquisitions() {
+    let a = Atomic::new(Triple::default());
+    let slot = a.fallback_slot().unwrap();
+    let before = acquisitions(slot);
+
+    a.store(Triple(1, 2, 3), SeqCst);
+    assert_eq!(a.load(SeqCst), Triple(1, 2, 3));
+    assert_eq!(a.swap(Triple(4, 5, 6), SeqCst), Triple(1, 2, 3));
+    assert!(a.compare_exchange(Triple(0, 0, 0), Triple(7, 8, 9), SeqCst, SeqCst).is_err());
+    assert!(a.compare_exchange(Triple(4, 5, 6), Triple(7, 8, 9), SeqCst, SeqCst).is_ok());
+    assert_eq!(acquisitions(slot) - before, 5);
+
+    // A try_ operation which finds the lock held doesn't count.
+    let before = acquisitions(slot);
+    let result = a.fetch_update_both(SeqCst, SeqCst, |x| {
+        assert_eq!(a.try_load(SeqCst), None);
+        Some(x)
+    });
+    assert!(result.is_ok());
+    assert_eq!(acquisitions(slot) - before, 1);
+
+    reset_fallback_stats();
+    assert!(fallback_stats().all(|s| s.acquisitions == 0 && s.contended == 0 && s.spins == 0));
+    assert_eq!(fallback_stats().count(), fallback_stats().last().unwrap().slot + 1);
+}
+
+#[test]
+fn slot_count_follows_configuration() {
+    let locks: usize = env!("ATOMIC_FALLBACK_LOCKS").parse().unwrap();
+    assert_eq!(fallback_stats().count(), locks);
+
+    // The atomics of an array are spread over the whole table.
+    let atomics: Vec<Atomic<[u8; 3]>> = (0..locks * 4).map(|_| Atomic::default()).collect();
+    let mut used = vec![false; locks];
+    for a in &atomics {
+        used[a.fallback_slot().unwrap()] = true;
+    }
+    let used = used.iter().filter(|&&u| u).count();
+    assert!(used * 2 >= locks, "{} of {} slots used", used, locks);
+}
@@ -0,0 +1,159 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Checks the layout guarantee of `Atomic<T>` and the conversions built on it.
+#![cfg(not(any(loom, shuttle)))]
+
+extern crate atomic;
+#[cfg(feature = "bytemuck")]
+extern crate bytemuck;
+#[cfg(feature = "zerocopy")]
+extern crate zerocopy;
+
+use atomic::{Atomic, Ordering::*};
+#[cfg(any(feature = "bytemuck", feature = "zerocopy"))]
+use atomic::{AtomicArray, CachePadded};
+#[cfg(feature = "bytemuck")]
+use bytemuck::Zeroable;
+use std::mem::{align_of, size_of};
+#[cfg(feature = "zerocopy")]
+use zerocopy::{FromBytes, FromZeros, IntoBytes, KnownLayout};
+use std::thread;
+
+macro_rules! assert_same_layout {
+    ($($t:ty)*) => {$(
+        const _: () = assert!(size_of::<Atomic<$t>>() == size_of::<$t>());
+        const _: () = assert!(align_of::<Atomic<$t>>() == align_of::<$t>());
+    )*};
+}
+
+// Including types which aren't lock-free, and those whose alignment is
+// smaller than their size.
+assert_same_layout! { () u8 u16 u32 u64 u128 usize f64 char [u8; 3] [u8; 4] [u16; 5] (u8, u32) }
+
+#[test]
+fn mut_round_trips() {
+    let mut x = 5u64;
+    {
+        let a = Atomic::from_mut(&mut x);
+        assert_eq!(a.fetch_add(2, Relaxed), 5);
+        assert_eq!(*a.get_mut(), 7);
+    }
+    assert_eq!(x, 7);
+
+    let mut buf = [0u32; 16];
+    {
+        let atomics = Atomic::from_mut_slice(&mut buf);
+        thread::scope(|s| {
+            for t in 0..4u32 {
+                let atomics = &*atomics;
+                s.spawn(move || {
+                    for a in atomics {
+                        a.fetch_add(t + 1, Relaxed);
+                    }
+                });
+            }
+        });
+        let values = Atomic::get_mut_slice(atomics);
+        assert_eq!(values, [10; 16]);
+        values[0] = 1;
+    }
+    assert_eq!(buf[0], 1);
+
+    // Types which aren't lock-free take the same path.
+    #[cfg(not(feature = "no-fallback"))]
+    {
+        let mut triples = [[1u8; 3]; 4];
+        Atomic::from_mut_slice(&mut triples)[2].store([7; 3], SeqCst);
+        assert_eq!(triples[2], [7; 3]);
+    }
+}
+
+#[test]
+fn from_ptr() {
+    let mut values = vec![0u64; 8];
+    let ptr = values.as_mut_ptr();
+    thread::scope(|s| {
+        for i in 0..8 {
+            let a = unsafe { Atomic::from_ptr(ptr.wrapping_add(i)) };
+            s.spawn(move || a.store(i as u64, Release));
+        }
+    });
+    assert_eq!(values, [0, 1, 2, 3, 4, 5, 6, 7]);
+}
+
+#[test]
+#[cfg(feature = "bytemuck")]
+fn zeroed() {
+    let table: Box<[Atomic<u64>]> = bytemuck::zeroed_slice_box(1000);
+    assert!(table.iter().all(|a| a.load(Relaxed) == 0));
+    let a: Atomic<[u16; 5]> = Zeroable::zeroed();
+    assert_eq!(a.load(Relaxed), [0; 5]);
+    let array: Box<AtomicArray<u32, 64>> = bytemuck::zeroed_box();
+    assert_eq!(array.iter_load(Relaxed), [0; 64]);
+    let padded: CachePadded<Atomic<i32>> = Zeroable::zeroed();
+    assert_eq!(padded.load(Relaxed), 0);
+}
+
+// The header of a ring buffer in memory shared with another process.
+#[cfg(feature = "zerocopy")]
+#[derive(FromBytes, IntoBytes, KnownLayout)]
+#[repr(C)]
+struct RingHeader {
+    head: Atomic<u32>,
+    tail: Atomic<u32>,
+    dropped: Atomic<u32>,
+    flags: Atomic<u32>,
+}
+
+#[test]
+#[cfg(feature = "zerocopy")]
+fn zerocopy_overlay() {
+    #[repr(C, align(4))]
+    struct Buffer([u8; 32]);
+
+    let mut buffer = Buffer([0; 32]);
+    buffer.0[..4].copy_from_slice(&7u32.to_ne_bytes());
+    {
+        let (header, _) = RingHeader::mut_from_prefix(&mut buffer.0).unwrap();
+        let header = &*header;
+        assert_eq!(header.head.load(Relaxed), 7);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        header.tail.fetch_add(1, Relaxed);
+                    }
+                    header.flags.fetch_or(1, Relaxed);
+                });
+            }
+        });
+        header.head.store(9, Release);
+    }
+    assert_eq!(buffer.0[..4], 9u32.to_ne_bytes());
+    assert_eq!(buffer.0[4..8], 400u32.to_ne_bytes());
+    assert_eq!(buffer.0[8..12], 0u32.to_ne_bytes());
+    assert_eq!(buffer.0[12..16], 1u32.to_ne_bytes());
+
+    // The alignment is checked.
+    assert!(RingHeader::mut_from_prefix(&mut buffer.0[1..]).is_err());
+}
+
+#[test]
+#[cfg(feature = "zerocopy")]
+fn zerocopy_zeroed() {
+    let a = Atomic::<[u16; 5]>::new_zeroed();
+    assert_eq!(a.load(Relaxed), [0; 5]);
+    let mut b = Atomic::<u64>::read_from_bytes(&5u64.to_ne_bytes()).unwrap();
+    assert_eq!(b.load(Relaxed), 5);
+    b.as_mut_bytes().copy_from_slice(&6u64.to_ne_bytes());
+    assert_eq!(b.load(Relaxed), 6);
+    let array = AtomicArray::<u32, 64>::new_zeroed();
+    assert_eq!(array.iter_load(Relaxed), [0; 64]);
+    let padded = CachePadded::<Atomic<i32>>::new_zeroed();
+    assert_eq!(padded.load(Relaxed), 0);
+}
@@ -0,0 +1,131 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Model checks code built on `Atomic<T>` with loom, both for lock-free types
+// and for ones which go through the fallback lock.
+//
+// Run with `RUSTFLAGS="--cfg loom" cargo test --test loom --release`.
+#![cfg(loom)]
+
+extern crate atomic;
+extern crate loom;
+
+use atomic::{Atomic, AtomicSlot, Ordering};
+use loom::cell::UnsafeCell;
+use loom::sync::Arc;
+use loom::thread;
+
+fn wait_for(turn: &Atomic<u32>, value: u32, order: Ordering) {
+    while turn.load(order) != value {
+        thread::yield_now();
+    }
+}
+
+// Two threads take turns incrementing a non-atomic counter, handing it over
+// through `turn`. Loom reports a data race unless the handover synchronizes.
+fn handoff(release: Ordering, acquire: Ordering) {
+    loom::model(move || {
+        let turn = Arc::new(Atomic::new(0u32));
+        let data = Arc::new(UnsafeCell::new(0u32));
+        let other = {
+            let (turn, data) = (turn.clone(), data.clone());
+            thread::spawn(move || {
+                for round in 0..2 {
+                    wait_for(&turn, 2 * round + 1, acquire);
+                    data.with_mut(|d| unsafe { *d += 1 });
+                    turn.store(2 * round + 2, release);
+                }
+            })
+        };
+        for round in 0..2 {
+            wait_for(&turn, 2 * round, acquire);
+            data.with_mut(|d| unsafe { *d += 1 });
+            turn.store(2 * round + 1, release);
+        }
+        other.join().unwrap();
+        assert_eq!(data.with(|d| unsafe { *d }), 4);
+    });
+}
+
+#[test]
+fn handoff_release_acquire() {
+    handoff(Ordering::Release, Ordering::Acquire);
+}
+
+#[test]
+#[should_panic]
+fn handoff_relaxed_is_caught() {
+    handoff(Ordering::Relaxed, Ordering::Relaxed);
+}
+
+// Too big to be lock-free, so every operation goes through the fallback lock.
+type Payload = [u32; 3];
+
+#[test]
+fn fallback_concurrent_updates() {
+    assert!(!Atomic::<Payload>::is_lock_free());
+    loom::model(|| {
+        let a = Arc::new(Atomic::new([0u32; 3]));
+        let threads: Vec<_> = (0..2)
+            .map(|i| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    let update = |mut x: Payload| {
+                        x[i] += 1;
+                        x[2] += 1;
+                        Some(x)
+                    };
+                    a.update_and_fetch(Ordering::AcqRel, Ordering::Acquire, update)
+                        .unwrap();
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(a.load(Ordering::Relaxed), [1, 1, 2]);
+    });
+}
+
+#[test]
+fn fallback_publish() {
+    loom::model(|| {
+        let a = Arc::new(Atomic::new([0u32; 3]));
+        let data = Arc::new(UnsafeCell::new(0u32));
+        let producer = {
+            let (a, data) = (a.clone(), data.clone());
+            thread::spawn(move || {
+                data.with_mut(|d| unsafe { *d = 7 });
+                a.store([1, 2, 3], Ordering::Release);
+            })
+        };
+        if a.load(Ordering::Acquire) == [1, 2, 3] {
+            assert_eq!(data.with(|d| unsafe { *d }), 7);
+        }
+        producer.join().unwrap();
+        assert_eq!(Arc::try_unwrap(a).unwrap().into_inner(), [1, 2, 3]);
+    });
+}
+
+// `AtomicSlot` always takes the lock, even for a `Box` which is the size of a
+// pointer. Each value is moved out exactly once.
+#[test]
+fn slot_swaps() {
+    loom::model(|| {
+        let s = Arc::new(AtomicSlot::new(Box::new(0u32)));
+        let other = {
+            let s = s.clone();
+            thread::spawn(move || *s.swap(Box::new(1), Ordering::AcqRel))
+        };
+        let mine = *s.swap(Box::new(2), Ordering::AcqRel);
+        let theirs = other.join().unwrap();
+        let last = *Arc::try_unwrap(s).unwrap().into_inner();
+        let mut all = [mine, theirs, last];
+        all.sort();
+        assert_eq!(all, [0, 1, 2]);
+    });
+}
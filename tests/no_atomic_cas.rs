@@ -0,0 +1,132 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Checks the dispatch on targets without a compare-exchange, like Cortex-M0:
+// loads and stores are native, while everything else runs in a critical
+// section.
+//
+// `--cfg no_atomic_cas` makes the host look like such a target, so run with
+// `RUSTFLAGS="--cfg no_atomic_cas" cargo test --features critical-section
+// --test no_atomic_cas`.
+#![cfg(all(no_atomic_cas, feature = "critical-section"))]
+
+extern crate atomic;
+extern crate critical_section;
+
+use atomic::{Atomic, Ordering};
+use std::hint;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering as StdOrdering};
+use std::thread;
+use std::time::Duration;
+
+// A critical section which keeps out other threads, like masking interrupts
+// keeps out everything else on a single core. The tests never nest them.
+struct HostCriticalSection;
+critical_section::set_impl!(HostCriticalSection);
+
+static LOCKED: AtomicBool = AtomicBool::new(false);
+
+unsafe impl critical_section::Impl for HostCriticalSection {
+    unsafe fn acquire() -> critical_section::RawRestoreState {
+        while LOCKED
+            .compare_exchange_weak(false, true, StdOrdering::Acquire, StdOrdering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+    }
+
+    unsafe fn release(_: critical_section::RawRestoreState) {
+        LOCKED.store(false, StdOrdering::Release);
+    }
+}
+
+// Up to the pointer width, loads and stores are native while nothing else is.
+const _: () = {
+    assert!(!Atomic::<u8>::IS_LOCK_FREE);
+    assert!(Atomic::<u8>::LOAD_STORE_LOCK_FREE);
+    assert!(!Atomic::<u32>::IS_LOCK_FREE);
+    assert!(Atomic::<u32>::LOAD_STORE_LOCK_FREE);
+    assert!(Atomic::<usize>::LOAD_STORE_LOCK_FREE);
+    assert!(!Atomic::<[u8; 3]>::LOAD_STORE_LOCK_FREE);
+    assert!(Atomic::<()>::IS_LOCK_FREE);
+    // The native types need values aligned to their size, which integers and
+    // pointers aren't on AVR, where everything is aligned to a byte.
+    assert!(Atomic::<u16>::LOAD_STORE_LOCK_FREE);
+    assert!(!Atomic::<[u8; 2]>::LOAD_STORE_LOCK_FREE);
+    assert!(!Atomic::<[u8; mem::size_of::<usize>()]>::LOAD_STORE_LOCK_FREE);
+};
+
+#[test]
+fn is_lock_free() {
+    assert!(!Atomic::<u32>::is_lock_free());
+    assert!(!atomic::ops::atomic_is_lock_free::<usize>());
+    assert!(atomic::ops::atomic_is_load_store_lock_free::<usize>());
+}
+
+#[test]
+fn loads_and_stores_skip_the_critical_section() {
+    let a = Atomic::new(1u32);
+    critical_section::with(|_| {
+        // Another thread can load and store while this one is in the critical
+        // section, and a try_load never fails.
+        thread::scope(|s| {
+            s.spawn(|| {
+                a.store(2, Ordering::Release);
+                assert_eq!(a.load(Ordering::Acquire), 2);
+                assert_eq!(a.try_load(Ordering::Acquire), Some(2));
+            });
+        });
+    });
+    assert_eq!(a.load(Ordering::Acquire), 2);
+}
+
+#[test]
+fn read_modify_writes_wait_for_the_critical_section() {
+    static A: Atomic<u32> = Atomic::new(0);
+    let t = critical_section::with(|_| {
+        let t = thread::spawn(|| A.fetch_add(1, Ordering::AcqRel));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(A.load(Ordering::Acquire), 0);
+        t
+    });
+    assert_eq!(t.join().unwrap(), 0);
+    assert_eq!(A.load(Ordering::Acquire), 1);
+}
+
+#[test]
+fn under_aligned_stores_wait_for_the_critical_section() {
+    static A: Atomic<[u8; 2]> = Atomic::new([0; 2]);
+    let t = critical_section::with(|_| {
+        let t = thread::spawn(|| A.store([1, 2], Ordering::Release));
+        thread::sleep(Duration::from_millis(50));
+        // The store is still waiting.
+        assert_eq!(unsafe { A.as_ptr().read() }, [0; 2]);
+        t
+    });
+    t.join().unwrap();
+    assert_eq!(A.load(Ordering::Acquire), [1, 2]);
+}
+
+#[test]
+fn read_modify_writes_are_atomic() {
+    let a = Atomic::new(0u32);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    a.fetch_add(1, Ordering::Relaxed);
+                    let _ = a.compare_exchange(0, 0, Ordering::AcqRel, Ordering::Acquire);
+                }
+            });
+        }
+    });
+    assert_eq!(a.load(Ordering::Relaxed), 4000);
+    assert_eq!(a.swap(5, Ordering::AcqRel), 4000);
+    assert_eq!(a.load(Ordering::Relaxed), 5);
+}
@@ -0,0 +1,23 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Checks that with the `no-fallback` feature, atomics which aren't lock-free
+// are rejected at compile time while lock-free ones still work.
+//
+// Run with `cargo test --features no-fallback --test no_fallback`. The
+// expected compiler output depends on the compiler version, so it can be
+// updated with `TRYBUILD=overwrite`.
+#![cfg(feature = "no-fallback")]
+
+extern crate trybuild;
+
+#[test]
+fn not_lock_free_is_a_compile_error() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/no_fallback/lock_free.rs");
+    t.compile_fail("tests/no_fallback/not_lock_free.rs");
+}
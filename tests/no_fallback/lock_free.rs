@@ -0,0 +1,15 @@
+extern crate atomic;
+
+use atomic::{Atomic, InlineAtomic, Ordering};
+
+fn main() {
+    assert!(Atomic::<u32>::is_lock_free());
+    let a = Atomic::new(1u32);
+    assert_eq!(a.fetch_add(2, Ordering::Relaxed), 1);
+    let b = Atomic::new(());
+    b.store((), Ordering::Relaxed);
+
+    // `InlineAtomic` doesn't need the lock table.
+    let c = InlineAtomic::new([0u64; 4]);
+    assert_eq!(c.swap([1; 4], Ordering::AcqRel), [0; 4]);
+}
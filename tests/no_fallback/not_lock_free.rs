@@ -0,0 +1,8 @@
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+
+fn main() {
+    let a = Atomic::new([0u8; 24]);
+    a.store([1; 24], Ordering::Relaxed);
+}
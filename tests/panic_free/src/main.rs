@@ -0,0 +1,187 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The operations which must not panic, for a lock-free type and for one which
+// takes the fallback lock. The orderings are arguments, so that the checks
+// for invalid ones can't be optimized away.
+//
+// With the `check` feature, the panic handler calls a function which isn't
+// defined anywhere, so the binary only links if no panic is reachable from
+// `main`. tests/link.rs builds it that way.
+
+#![no_std]
+#![no_main]
+
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+use core::hint::black_box;
+use core::panic::PanicInfo;
+
+type Big = [u32; 3];
+
+#[no_mangle]
+pub fn panic_free_load(a: &Atomic<u32>, order: Ordering) -> u32 {
+    a.load(order)
+}
+
+#[no_mangle]
+pub fn panic_free_store(a: &Atomic<u32>, val: u32, order: Ordering) {
+    a.store(val, order)
+}
+
+#[no_mangle]
+pub fn panic_free_swap(a: &Atomic<u32>, val: u32, order: Ordering) -> u32 {
+    a.swap(val, order)
+}
+
+#[no_mangle]
+pub fn panic_free_compare_exchange(
+    a: &Atomic<u32>,
+    current: u32,
+    new: u32,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<u32, u32> {
+    a.compare_exchange(current, new, success, failure)
+}
+
+#[no_mangle]
+pub fn panic_free_compare_exchange_weak(
+    a: &Atomic<u32>,
+    current: u32,
+    new: u32,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<u32, u32> {
+    a.compare_exchange_weak(current, new, success, failure)
+}
+
+#[no_mangle]
+pub fn panic_free_fetch_add(a: &Atomic<u32>, val: u32, order: Ordering) -> u32 {
+    a.fetch_add(val, order)
+}
+
+#[no_mangle]
+pub fn panic_free_fetch_sub(a: &Atomic<u32>, val: u32, order: Ordering) -> u32 {
+    a.fetch_sub(val, order)
+}
+
+#[no_mangle]
+pub fn panic_free_fetch_and(a: &Atomic<u32>, val: u32, order: Ordering) -> u32 {
+    a.fetch_and(val, order)
+}
+
+#[no_mangle]
+pub fn panic_free_fetch_or(a: &Atomic<u32>, val: u32, order: Ordering) -> u32 {
+    a.fetch_or(val, order)
+}
+
+#[no_mangle]
+pub fn panic_free_fetch_xor(a: &Atomic<u32>, val: u32, order: Ordering) -> u32 {
+    a.fetch_xor(val, order)
+}
+
+#[no_mangle]
+pub fn panic_free_fetch_max(a: &Atomic<u32>, val: u32, order: Ordering) -> u32 {
+    a.fetch_max(val, order)
+}
+
+#[no_mangle]
+pub fn panic_free_fetch_min(a: &Atomic<u32>, val: u32, order: Ordering) -> u32 {
+    a.fetch_min(val, order)
+}
+
+#[no_mangle]
+pub fn panic_free_fallback_load(a: &Atomic<Big>, order: Ordering) -> Big {
+    a.load(order)
+}
+
+#[no_mangle]
+pub fn panic_free_fallback_store(a: &Atomic<Big>, val: Big, order: Ordering) {
+    a.store(val, order)
+}
+
+#[no_mangle]
+pub fn panic_free_fallback_swap(a: &Atomic<Big>, val: Big, order: Ordering) -> Big {
+    a.swap(val, order)
+}
+
+#[no_mangle]
+pub fn panic_free_fallback_compare_exchange(
+    a: &Atomic<Big>,
+    current: Big,
+    new: Big,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<Big, Big> {
+    a.compare_exchange(current, new, success, failure)
+}
+
+#[no_mangle]
+pub fn panic_free_fallback_fetch_add(a: &Atomic<u128>, val: u128, order: Ordering) -> u128 {
+    a.fetch_add(val, order)
+}
+
+type Load<T> = fn(&Atomic<T>, Ordering) -> T;
+type Store<T> = fn(&Atomic<T>, T, Ordering);
+type Rmw<T> = fn(&Atomic<T>, T, Ordering) -> T;
+type Cas<T> = fn(&Atomic<T>, T, T, Ordering, Ordering) -> Result<T, T>;
+
+#[no_mangle]
+pub extern "C" fn main(_argc: i32, _argv: *const *const u8) -> i32 {
+    let order = black_box(Ordering::SeqCst);
+    let a = Atomic::new(1u32);
+    black_box(panic_free_load as Load<u32>)(&a, order);
+    black_box(panic_free_store as Store<u32>)(&a, 2, order);
+    black_box(panic_free_swap as Rmw<u32>)(&a, 3, order);
+    let _ = black_box(panic_free_compare_exchange as Cas<u32>)(&a, 3, 4, order, order);
+    let _ = black_box(panic_free_compare_exchange_weak as Cas<u32>)(&a, 4, 5, order, order);
+    let rmws: [Rmw<u32>; 7] = [
+        panic_free_fetch_add,
+        panic_free_fetch_sub,
+        panic_free_fetch_and,
+        panic_free_fetch_or,
+        panic_free_fetch_xor,
+        panic_free_fetch_max,
+        panic_free_fetch_min,
+    ];
+    for f in rmws {
+        black_box(f)(&a, 6, order);
+    }
+
+    let b = Atomic::new([1u32; 3]);
+    black_box(panic_free_fallback_load as Load<Big>)(&b, order);
+    black_box(panic_free_fallback_store as Store<Big>)(&b, [2; 3], order);
+    black_box(panic_free_fallback_swap as Rmw<Big>)(&b, [3; 3], order);
+    let cas = black_box(panic_free_fallback_compare_exchange as Cas<Big>);
+    let _ = cas(&b, [3; 3], [4; 3], order, order);
+    let c = Atomic::new(1u128);
+    black_box(panic_free_fallback_fetch_add as Rmw<u128>)(&c, 7, order);
+    0
+}
+
+// The binary starts from the C runtime, and never unwinds: the precompiled
+// `core` still refers to the personality function, which is never called.
+#[link(name = "c")]
+extern "C" {}
+
+#[no_mangle]
+pub extern "C" fn rust_eh_personality() {}
+
+#[panic_handler]
+fn panic(_: &PanicInfo) -> ! {
+    #[cfg(feature = "check")]
+    {
+        extern "C" {
+            fn atomic_panic_is_reachable() -> !;
+        }
+        unsafe { atomic_panic_is_reachable() }
+    }
+    #[cfg(not(feature = "check"))]
+    loop {}
+}
@@ -0,0 +1,51 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Builds src/main.rs with the `check` feature, whose panic handler refers to
+// an undefined symbol: the link fails if any panic is left in the operations.
+// The standard atomic types only lose their checks of the orderings when they
+// are optimized, so this is done at the opt-levels used for firmware.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn links(opt_level: &str) -> bool {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join(format!("opt-level-{}", opt_level.trim_matches('"')));
+    Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .arg("build")
+        .arg("--quiet")
+        .arg("--release")
+        .arg("--manifest-path")
+        .arg(manifest_dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .arg("--features")
+        .arg("check")
+        .arg("--config")
+        .arg(format!("profile.release.opt-level={}", opt_level))
+        .status()
+        .unwrap()
+        .success()
+}
+
+#[test]
+fn no_panics_at_opt_level_3() {
+    assert!(links("3"));
+}
+
+#[test]
+fn no_panics_at_opt_level_s() {
+    assert!(links("\"s\""));
+}
+
+#[test]
+fn no_panics_at_opt_level_z() {
+    assert!(links("\"z\""));
+}
@@ -0,0 +1,92 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Uses `Atomic<T>` the way downstream code generic over `Radium` would.
+#![cfg(feature = "radium")]
+
+extern crate atomic;
+extern crate radium;
+
+use atomic::Atomic;
+use radium::Radium;
+use std::cell::Cell;
+use std::ptr;
+use std::sync::atomic::Ordering::*;
+
+fn exercise_u32<R: Radium<Item = u32>>() -> u32 {
+    let r = R::new(0b1100);
+    assert_eq!(r.fetch_and(0b1010, SeqCst), 0b1100);
+    assert_eq!(r.fetch_nand(0b1111, SeqCst), 0b1000);
+    assert_eq!(r.fetch_or(0b0001, SeqCst), !0b1000);
+    assert_eq!(r.fetch_xor(!0, SeqCst), !0b1000);
+    assert_eq!(r.load(SeqCst), 0b1000);
+    assert_eq!(r.fetch_add(2, SeqCst), 8);
+    assert_eq!(r.fetch_sub(20, SeqCst), 10);
+    assert_eq!(r.fetch_max(3, SeqCst), 10u32.wrapping_sub(20));
+    r.store(5, SeqCst);
+    assert_eq!(r.fetch_max(7, SeqCst), 5);
+    assert_eq!(r.fetch_min(6, SeqCst), 7);
+    assert_eq!(r.swap(9, SeqCst), 6);
+    assert_eq!(r.compare_exchange(1, 2, SeqCst, SeqCst), Err(9));
+    assert_eq!(r.compare_exchange(9, 10, SeqCst, SeqCst), Ok(9));
+    let mut res = r.compare_exchange_weak(10, 11, SeqCst, SeqCst);
+    while res == Err(10) {
+        res = r.compare_exchange_weak(10, 11, SeqCst, SeqCst);
+    }
+    assert_eq!(res, Ok(10));
+    assert_eq!(r.fetch_update(SeqCst, SeqCst, |x| Some(x * 2)), Ok(11));
+    assert_eq!(r.fetch_update(SeqCst, SeqCst, |_| None), Err(22));
+    R::fence(SeqCst);
+    r.into_inner()
+}
+
+fn exercise_signed<R: Radium<Item = i64>>() {
+    let r = R::new(-5);
+    assert_eq!(r.fetch_max(-7, SeqCst), -5);
+    assert_eq!(r.fetch_min(-7, SeqCst), -5);
+    assert_eq!(r.fetch_nand(-1, SeqCst), -7);
+    assert_eq!(r.load(SeqCst), 6);
+}
+
+fn exercise_bool<R: Radium<Item = bool>>() {
+    let r = R::new(true);
+    assert!(r.fetch_nand(true, SeqCst));
+    assert!(!r.fetch_or(true, SeqCst));
+    assert!(r.fetch_xor(true, SeqCst));
+    assert!(!r.fetch_and(true, SeqCst));
+    assert!(!r.load(SeqCst));
+}
+
+fn exercise_ptr<R: Radium<Item = *mut u8>>(p: *mut u8) {
+    let mut r = R::new(ptr::null_mut());
+    assert_eq!(r.swap(p, SeqCst), ptr::null_mut());
+    assert_eq!(r.compare_exchange(p, ptr::null_mut(), SeqCst, SeqCst), Ok(p));
+    *r.get_mut() = p;
+    assert_eq!(r.load(SeqCst), p);
+}
+
+#[test]
+fn generic_over_radium() {
+    assert_eq!(exercise_u32::<Atomic<u32>>(), 22);
+    assert_eq!(exercise_u32::<Cell<u32>>(), 22);
+    exercise_signed::<Atomic<i64>>();
+    exercise_signed::<Cell<i64>>();
+    exercise_bool::<Atomic<bool>>();
+    exercise_bool::<Cell<bool>>();
+
+    let mut x = 0u8;
+    exercise_ptr::<Atomic<*mut u8>>(&mut x);
+    exercise_ptr::<Cell<*mut u8>>(&mut x);
+}
+
+#[test]
+#[allow(deprecated)]
+fn compare_and_swap() {
+    let a: Atomic<u16> = Radium::new(1);
+    assert_eq!(Radium::compare_and_swap(&a, 1, 2, AcqRel), 1);
+    assert_eq!(Radium::compare_and_swap(&a, 1, 3, Release), 2);
+}
@@ -0,0 +1,103 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Runs code built on `Atomic<T>` under randomized shuttle schedules, both for
+// lock-free types and for ones which go through the fallback lock.
+//
+// Run with `RUSTFLAGS="--cfg shuttle" cargo test --test shuttle --release`.
+#![cfg(shuttle)]
+
+extern crate atomic;
+extern crate shuttle;
+
+use atomic::{Atomic, Ordering};
+use shuttle::sync::Arc;
+use shuttle::thread;
+
+const THREADS: usize = 4;
+const UPDATES: u32 = 5;
+const ITERATIONS: usize = 1000;
+
+// Too big to be lock-free, so every operation goes through the fallback lock.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Counters {
+    even: u32,
+    odd: u32,
+    total: u32,
+}
+
+#[test]
+fn fallback_fetch_update() {
+    assert!(!Atomic::<Counters>::is_lock_free());
+    shuttle::check_random(
+        || {
+            let zero = Counters {
+                even: 0,
+                odd: 0,
+                total: 0,
+            };
+            let a = Arc::new(Atomic::new(zero));
+            let threads: Vec<_> = (0..THREADS)
+                .map(|i| {
+                    let a = a.clone();
+                    thread::spawn(move || {
+                        for _ in 0..UPDATES {
+                            let update = |mut c: Counters| {
+                                if i % 2 == 0 {
+                                    c.even += 1;
+                                } else {
+                                    c.odd += 1;
+                                }
+                                c.total += 1;
+                                Ok::<_, ()>(c)
+                            };
+                            a.try_fetch_update(Ordering::AcqRel, Ordering::Acquire, update)
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for t in threads {
+                t.join().unwrap();
+            }
+            let half = THREADS as u32 / 2 * UPDATES;
+            let expected = Counters {
+                even: half,
+                odd: half,
+                total: 2 * half,
+            };
+            assert_eq!(a.load(Ordering::SeqCst), expected);
+        },
+        ITERATIONS,
+    );
+}
+
+#[test]
+fn lock_free_fetch_add() {
+    assert!(Atomic::<u32>::is_lock_free());
+    shuttle::check_random(
+        || {
+            let a = Arc::new(Atomic::new(0u32));
+            let threads: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let a = a.clone();
+                    thread::spawn(move || {
+                        for _ in 0..UPDATES {
+                            a.fetch_add(1, Ordering::SeqCst);
+                        }
+                    })
+                })
+                .collect();
+            for t in threads {
+                t.join().unwrap();
+            }
+            let a = Arc::try_unwrap(a).unwrap();
+            assert_eq!(a.into_inner(), THREADS as u32 * UPDATES);
+        },
+        ITERATIONS,
+    );
+}
@@ -0,0 +1,82 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Hammers an atomic which goes through the fallback lock from many threads.
+// This passes in a normal build too, but is mostly meant to be run under
+// ThreadSanitizer, which must not report any races:
+//
+// RUSTFLAGS="-Zsanitizer=thread" cargo +nightly test -Zbuild-std \
+//     --target x86_64-unknown-linux-gnu --test tsan
+#![cfg(not(feature = "no-fallback"))]
+
+extern crate atomic;
+
+use atomic::{Atomic, Ordering};
+use std::thread;
+
+const THREADS: usize = 8;
+const ITERATIONS: usize = if cfg!(miri) { 100 } else { 10_000 };
+
+// Every value ever stored has all bytes equal, so a torn read shows up as a
+// value with different bytes.
+fn check(value: [u8; 32]) -> u8 {
+    assert!(
+        value.iter().all(|&b| b == value[0]),
+        "torn value {:?}",
+        value
+    );
+    value[0]
+}
+
+fn bump(value: [u8; 32]) -> [u8; 32] {
+    [check(value).wrapping_add(1); 32]
+}
+
+fn hammer(a: &Atomic<[u8; 32]>, increments: &Atomic<usize>, thread: usize) {
+    for i in 0..ITERATIONS {
+        match (thread + i) % 4 {
+            0 => {
+                check(a.load(Ordering::Acquire));
+            }
+            1 => {
+                let current = a.load(Ordering::Relaxed);
+                let new = bump(current);
+                if a.compare_exchange(current, new, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    increments.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            2 => {
+                let update = |x| Ok::<_, ()>(bump(x));
+                a.try_fetch_update(Ordering::AcqRel, Ordering::Acquire, update)
+                    .unwrap();
+                increments.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {
+                // Writing back the value which was read keeps the count intact.
+                let current = a.load(Ordering::Acquire);
+                let _ = a.compare_exchange(current, current, Ordering::AcqRel, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[test]
+fn fallback_hammer() {
+    assert!(!Atomic::<[u8; 32]>::is_lock_free());
+    let a = Atomic::new([0u8; 32]);
+    let increments = Atomic::new(0usize);
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let (a, increments) = (&a, &increments);
+            s.spawn(move || hammer(a, increments, t));
+        }
+    });
+    let expected = increments.into_inner() as u8;
+    assert_eq!(a.into_inner(), [expected; 32]);
+}
@@ -0,0 +1,22 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Checks that the `*_with` methods accept the orderings which are valid for
+// each operation and reject the others at compile time.
+//
+// The expected compiler output depends on the compiler version, so it can be
+// updated with `TRYBUILD=overwrite`. Miri can't run the compiler.
+#![cfg(not(miri))]
+
+extern crate trybuild;
+
+#[test]
+fn typed_ordering() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/typed_ordering/valid.rs");
+    t.compile_fail("tests/typed_ordering/invalid.rs");
+}
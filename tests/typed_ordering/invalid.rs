@@ -0,0 +1,13 @@
+extern crate atomic;
+
+use atomic::order::{AcqRel, Acquire, Relaxed, Release};
+use atomic::Atomic;
+
+fn main() {
+    let a = Atomic::new(0u32);
+    a.load_with(Release);
+    a.load_with(AcqRel);
+    a.store_with(1, Acquire);
+    let _ = a.compare_exchange_with(0, 1, Acquire, Release);
+    let _ = a.compare_exchange_weak_with(0, 1, Relaxed, AcqRel);
+}
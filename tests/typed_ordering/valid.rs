@@ -0,0 +1,24 @@
+extern crate atomic;
+
+use atomic::order::{AcqRel, Acquire, LoadOrdering, Relaxed, Release, RmwOrdering, SeqCst};
+use atomic::Atomic;
+
+// Generic code can forward the orderings it is given.
+fn increment<S: RmwOrdering, F: LoadOrdering>(a: &Atomic<u32>, success: S, failure: F) {
+    let mut cur = a.load_with(failure);
+    while let Err(x) = a.compare_exchange_weak_with(cur, cur + 1, success, failure) {
+        cur = x;
+    }
+}
+
+fn main() {
+    let a = Atomic::new(0u32);
+    a.store_with(1, Release);
+    a.store_with(2, Relaxed);
+    a.store_with(3, SeqCst);
+    assert_eq!(a.swap_with(4, AcqRel), 3);
+    increment(&a, Release, Acquire);
+    increment(&a, AcqRel, SeqCst);
+    assert_eq!(a.compare_exchange_with(6, 7, Acquire, Relaxed), Ok(6));
+    assert_eq!(a.load_with(Acquire), 7);
+}
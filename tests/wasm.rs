@@ -0,0 +1,84 @@
+// Copyright 2016 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Checks the single-threaded mode of wasm without the `atomics` target
+// feature, where the values which take the fallback lock elsewhere are read
+// and written in place.
+//
+// Run with `wasm-bindgen-test-runner` as the runner of the target:
+// `CARGO_TARGET_WASM32_UNKNOWN_UNKNOWN_RUNNER=wasm-bindgen-test-runner
+// cargo test --target wasm32-unknown-unknown --test wasm`.
+#![cfg(all(target_family = "wasm", not(target_feature = "atomics")))]
+
+#[macro_use]
+extern crate atomic;
+extern crate wasm_bindgen_test;
+
+use atomic::{Atomic, Ordering::*};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+// Too big for any native atomic, and not aligned to its size either.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Payload {
+    id: u32,
+    words: [u64; 3],
+    tag: u8,
+}
+
+const A: Payload = Payload {
+    id: 1,
+    words: [2, 3, 4],
+    tag: 5,
+};
+
+const B: Payload = Payload {
+    id: 6,
+    words: [7, 8, 9],
+    tag: 10,
+};
+
+assert_lock_free!(Payload);
+assert_lock_free!([u8; 3]);
+assert_lock_free!(u128);
+
+#[wasm_bindgen_test]
+fn every_size_is_lock_free() {
+    assert!(Atomic::<Payload>::is_lock_free());
+    assert!(Atomic::<[u64; 64]>::is_lock_free());
+}
+
+#[wasm_bindgen_test]
+fn payload_operations() {
+    let a = Atomic::new(A);
+    assert_eq!(a.load(SeqCst), A);
+    a.store(B, Release);
+    assert_eq!(a.swap(A, AcqRel), B);
+    assert_eq!(a.compare_exchange(B, B, SeqCst, Relaxed), Err(A));
+    assert_eq!(a.compare_exchange(A, B, SeqCst, Relaxed), Ok(A));
+    let bump = |mut x: Payload| {
+        x.words[1] += 1;
+        Some(x)
+    };
+    let (prev, new) = a.fetch_update_both(SeqCst, SeqCst, bump).unwrap();
+    assert_eq!((prev.words, new.words), ([7, 8, 9], [7, 9, 9]));
+    assert_eq!(a.load(Acquire), new);
+    assert_eq!(a.into_inner().tag, 10);
+}
+
+#[wasm_bindgen_test]
+fn wide_integers() {
+    let a = Atomic::new(u128::MAX);
+    assert_eq!(a.fetch_add(2, SeqCst), u128::MAX);
+    assert_eq!(a.fetch_sub(1 << 100, SeqCst), 1);
+    assert_eq!(a.fetch_max(7, SeqCst), 1u128.wrapping_sub(1 << 100));
+    assert_eq!(a.load(SeqCst), 1u128.wrapping_sub(1 << 100));
+
+    let b = Atomic::new(-5i128);
+    assert_eq!(b.fetch_min(i128::MIN, SeqCst), -5);
+    assert_eq!(b.fetch_xor(-1, SeqCst), i128::MIN);
+    assert_eq!(b.load(SeqCst), i128::MAX);
+}